@@ -89,13 +89,22 @@ impl ApacheToVeloServeConverter {
 
         Ok(VirtualHostConfig {
             domain,
+            aliases: apache.server_names.iter().skip(1).cloned().collect(),
+            default_server: Default::default(),
             root,
             platform: Some(platform),
             ssl_certificate,
             ssl_certificate_key,
             cache: None,
+            compression: None,
             index: vec!["index.php".to_string(), "index.html".to_string()],
             error_pages: std::collections::HashMap::new(),
+            try_files: Vec::new(),
+            cron: Vec::new(),
+            location: Vec::new(),
+            rewrites: Vec::new(),
+            // Sites migrated from Apache usually rely on their .htaccess files
+            htaccess: true,
         })
     }
 
@@ -178,6 +187,14 @@ impl ApacheToVeloServeConverter {
                 vhost.platform.as_deref().unwrap_or("generic"),
             ));
 
+            if !vhost.aliases.is_empty() {
+                let aliases: Vec<String> =
+                    vhost.aliases.iter().map(|a| format!("\"{}\"", a)).collect();
+                output.push_str(&format!("aliases = [{}]\n", aliases.join(", ")));
+            }
+            if vhost.htaccess {
+                output.push_str("htaccess = true\n");
+            }
             if let Some(ref cert) = vhost.ssl_certificate {
                 output.push_str(&format!("ssl_certificate = \"{}\"\n", cert));
             }
@@ -8,7 +8,7 @@
 //! - SSLEngine, SSLCertificateFile, SSLCertificateKeyFile
 //! - php_admin_value, php_admin_flag
 //! - DirectoryIndex, ErrorLog, CustomLog
-//! - <Directory>, <IfModule>, <Files>
+//! - <Directory>, <IfModule>, <Files> (other blocks are kept generically)
 
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
@@ -78,6 +78,12 @@ pub enum ApacheDirective {
         pattern: String,
         content: Vec<ApacheDirective>,
     },
+    /// Any other block (<FilesMatch>, <Location>, <RequireAll>, ...)
+    Block {
+        name: String,
+        args: Vec<String>,
+        content: Vec<ApacheDirective>,
+    },
     /// Simple key-value directive
     Simple { name: String, value: String },
     /// Comment line
@@ -176,4 +182,58 @@ mod tests {
             Some(PathBuf::from("/etc/ssl/certs/example.crt"))
         );
     }
+
+    #[test]
+    fn test_parse_nested_blocks() {
+        let content = r#"
+<IfModule mod_rewrite.c>
+    RewriteEngine On
+    RewriteCond %{REQUEST_FILENAME} !-f \
+        [OR]
+    <FilesMatch "\.(ini|log)$">
+        Require all denied
+    </FilesMatch>
+</IfModule>
+"#;
+
+        let directives = ApacheConfigParser::new().parse_directives(content).unwrap();
+        assert_eq!(directives.len(), 1);
+
+        let ApacheDirective::IfModule { module, content } = &directives[0] else {
+            panic!("expected IfModule, got {:?}", directives[0]);
+        };
+        assert_eq!(module, "mod_rewrite.c");
+        assert_eq!(content.len(), 3);
+        assert!(matches!(
+            &content[1],
+            ApacheDirective::Simple { value, .. } if value.contains("!-f") && value.ends_with("[OR]")
+        ));
+        assert!(matches!(
+            &content[2],
+            ApacheDirective::Block { name, args, content }
+                if name == "FilesMatch" && args[0] == r"\.(ini|log)$" && content.len() == 1
+        ));
+    }
+
+    #[test]
+    fn test_convert_keeps_server_alias() {
+        let config = r#"
+<VirtualHost *:80>
+    ServerName example.com
+    ServerAlias www.example.com *.example.org
+    DocumentRoot /var/www/html
+</VirtualHost>
+"#;
+
+        let apache_config = ApacheConfig::from_str(config).unwrap();
+        let converter = ApacheToVeloServeConverter::new();
+        let converted = converter.convert(&apache_config);
+
+        let vhost = &converted.virtualhost[0];
+        assert_eq!(vhost.domain, "example.com");
+        assert_eq!(vhost.aliases, vec!["www.example.com", "*.example.org"]);
+        assert!(converter
+            .to_toml_vhosts_only(&apache_config)
+            .contains(r#"aliases = ["www.example.com", "*.example.org"]"#));
+    }
 }
@@ -52,13 +52,73 @@ impl ApacheConfigParser {
     /// Parse configuration from string content
     pub fn parse(&self, content: &str) -> ParseResult<ApacheConfig> {
         let mut config = ApacheConfig::default();
-        let mut lines = content.lines().peekable();
-        let mut line_number = 0;
 
-        while let Some(line) = lines.next() {
-            line_number += 1;
+        for directive in self.parse_directives(content)? {
+            self.collect_global(&mut config, &directive);
+            config.global_directives.push(directive);
+        }
+
+        Ok(config)
+    }
+
+    /// Parse content into a directive tree without interpreting it.
+    ///
+    /// Used for `.htaccess` files, which have no virtual hosts.
+    pub fn parse_directives(&self, content: &str) -> ParseResult<Vec<ApacheDirective>> {
+        let lines = logical_lines(content);
+        let mut pos = 0;
+        self.parse_block(&lines, &mut pos, None, 0)
+    }
+
+    /// Pick virtual hosts, includes and modules out of a top-level directive
+    fn collect_global(&self, config: &mut ApacheConfig, directive: &ApacheDirective) {
+        match directive {
+            ApacheDirective::VirtualHost { addresses, content } => {
+                if let Ok(vhost) = self.parse_virtual_host(addresses, content) {
+                    config.virtual_hosts.push(vhost);
+                }
+            }
+            // Virtual hosts are commonly wrapped in <IfModule mod_ssl.c>
+            ApacheDirective::IfModule { content, .. } => {
+                for inner in content {
+                    if matches!(inner, ApacheDirective::VirtualHost { .. }) {
+                        self.collect_global(config, inner);
+                    }
+                }
+            }
+            ApacheDirective::Simple { name, value } => match name.as_str() {
+                "Include" | "IncludeOptional" => {
+                    if self.expand_includes {
+                        config.includes.push(PathBuf::from(value));
+                    }
+                }
+                "LoadModule" => {
+                    let parts: Vec<&str> = value.split_whitespace().collect();
+                    if parts.len() >= 2 {
+                        config
+                            .modules
+                            .push((parts[0].to_string(), PathBuf::from(parts[1])));
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    /// Parse directives until the closing tag of `closing` (or end of input)
+    fn parse_block(
+        &self,
+        lines: &[(usize, String)],
+        pos: &mut usize,
+        closing: Option<&str>,
+        depth: usize,
+    ) -> ParseResult<Vec<ApacheDirective>> {
+        let mut directives = Vec::new();
+
+        while let Some((line_number, line)) = lines.get(*pos) {
+            *pos += 1;
 
-            // Skip empty lines and comments (but keep them for context)
             let trimmed = line.trim();
             if trimmed.is_empty() {
                 continue;
@@ -66,41 +126,39 @@ impl ApacheConfigParser {
 
             // Handle comments
             if trimmed.starts_with('#') {
-                config
-                    .global_directives
-                    .push(ApacheDirective::Comment(trimmed.to_string()));
+                directives.push(ApacheDirective::Comment(trimmed.to_string()));
+                continue;
+            }
+
+            // Block end
+            if let Some(rest) = trimmed.strip_prefix("</") {
+                let name = rest.trim_end_matches('>').trim().to_lowercase();
+                if closing == Some(name.as_str()) {
+                    return Ok(directives);
+                }
+                if self.verbose {
+                    eprintln!(
+                        "Warning at line {}: unexpected closing tag </{}>",
+                        line_number, name
+                    );
+                }
                 continue;
             }
 
             // Parse directive
             match self.parse_line(trimmed) {
-                Ok(directive) => {
-                    // Extract virtual hosts
-                    if let ApacheDirective::VirtualHost { addresses, content } = &directive {
-                        if let Ok(vhost) = self.parse_virtual_host(addresses, content) {
-                            config.virtual_hosts.push(vhost);
-                        }
-                    } else if let ApacheDirective::Simple { name, value } = &directive {
-                        // Handle global directives
-                        match name.as_str() {
-                            "Include" | "IncludeOptional" => {
-                                if self.expand_includes {
-                                    config.includes.push(PathBuf::from(value));
-                                }
-                            }
-                            "LoadModule" => {
-                                let parts: Vec<&str> = value.split_whitespace().collect();
-                                if parts.len() >= 2 {
-                                    config
-                                        .modules
-                                        .push((parts[0].to_string(), PathBuf::from(parts[1])));
-                                }
-                            }
-                            _ => {}
+                Ok(mut directive) => {
+                    if trimmed.starts_with('<') {
+                        if depth + 1 > MAX_NESTING_DEPTH {
+                            return Err(ApacheParseError::NestingTooDeep {
+                                max_depth: MAX_NESTING_DEPTH,
+                            });
                         }
+                        let name = block_name(trimmed);
+                        let inner = self.parse_block(lines, pos, Some(&name), depth + 1)?;
+                        set_block_content(&mut directive, inner);
                     }
-
-                    config.global_directives.push(directive);
+                    directives.push(directive);
                 }
                 Err(e) => {
                     if self.verbose {
@@ -111,7 +169,13 @@ impl ApacheConfigParser {
             }
         }
 
-        Ok(config)
+        if let Some(name) = closing {
+            if self.verbose {
+                eprintln!("Warning: block <{}> is not closed", name);
+            }
+        }
+
+        Ok(directives)
     }
 
     /// Parse a single line into a directive
@@ -133,10 +197,10 @@ impl ApacheConfigParser {
         Ok(ApacheDirective::Simple { name, value })
     }
 
-    /// Parse block directive start
+    /// Parse block directive start; the content is filled in by `parse_block`
     fn parse_block_start(&self, line: &str) -> ParseResult<ApacheDirective> {
         // Extract block type and arguments
-        let end_pos = line.find('>').ok_or(ApacheParseError::UnclosedBlock)?;
+        let end_pos = line.rfind('>').ok_or(ApacheParseError::UnclosedBlock)?;
         let inner = &line[1..end_pos];
 
         let parts: Vec<&str> = inner.split_whitespace().collect();
@@ -145,39 +209,36 @@ impl ApacheConfigParser {
         }
 
         let block_type = parts[0].to_lowercase();
+        let argument = || unquote(parts[1..].join(" ").as_str()).to_string();
 
-        // For now, return a simplified version
-        // In full implementation, we'd parse the entire block content
         match block_type.as_str() {
             "virtualhost" => {
                 let addresses = parts[1..].iter().map(|s| s.to_string()).collect();
                 Ok(ApacheDirective::VirtualHost {
                     addresses,
-                    content: vec![], // Would be filled by parsing block content
+                    content: vec![],
                 })
             }
             "directory" => {
-                let path = parts.get(1).unwrap_or(&"/").to_string();
+                let path = parts.get(1).map(|p| unquote(p)).unwrap_or("/").to_string();
                 Ok(ApacheDirective::Directory {
                     path,
                     content: vec![],
                 })
             }
-            "ifmodule" => {
-                let module = parts.get(1).unwrap_or(&"").to_string();
-                Ok(ApacheDirective::IfModule {
-                    module,
-                    content: vec![],
-                })
-            }
-            "files" => {
-                let pattern = parts.get(1).unwrap_or(&"").to_string();
-                Ok(ApacheDirective::Files {
-                    pattern,
-                    content: vec![],
-                })
-            }
-            _ => Err(ApacheParseError::UnknownBlock(block_type)),
+            "ifmodule" => Ok(ApacheDirective::IfModule {
+                module: argument(),
+                content: vec![],
+            }),
+            "files" => Ok(ApacheDirective::Files {
+                pattern: argument(),
+                content: vec![],
+            }),
+            _ => Ok(ApacheDirective::Block {
+                name: parts[0].to_string(),
+                args: parts[1..].iter().map(|p| unquote(p).to_string()).collect(),
+                content: vec![],
+            }),
         }
     }
 
@@ -271,6 +332,63 @@ impl ApacheConfigParser {
     }
 }
 
+/// Maximum `<Block>` nesting depth
+const MAX_NESTING_DEPTH: usize = 16;
+
+/// Join `\`-continued lines, keeping the number of the first physical line
+fn logical_lines(content: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+
+    for (index, line) in content.lines().enumerate() {
+        let (number, mut text) = pending.take().unwrap_or_else(|| (index + 1, String::new()));
+        match line.trim_end().strip_suffix('\\') {
+            Some(head) => {
+                text.push_str(head);
+                text.push(' ');
+                pending = Some((number, text));
+            }
+            None => {
+                text.push_str(line);
+                lines.push((number, text));
+            }
+        }
+    }
+    if let Some(last) = pending {
+        lines.push(last);
+    }
+
+    lines
+}
+
+/// Lowercased block name of a `<Name args>` line
+fn block_name(line: &str) -> String {
+    line.trim_start_matches('<')
+        .split(|c: char| c.is_whitespace() || c == '>')
+        .next()
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+fn set_block_content(directive: &mut ApacheDirective, inner: Vec<ApacheDirective>) {
+    match directive {
+        ApacheDirective::VirtualHost { content, .. }
+        | ApacheDirective::Directory { content, .. }
+        | ApacheDirective::IfModule { content, .. }
+        | ApacheDirective::Files { content, .. }
+        | ApacheDirective::Block { content, .. } => *content = inner,
+        ApacheDirective::Simple { .. } | ApacheDirective::Comment(_) => {}
+    }
+}
+
+/// Strip one pair of surrounding double quotes
+pub fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
 impl Default for ApacheConfigParser {
     fn default() -> Self {
         Self::new()
@@ -0,0 +1,194 @@
+//! Circuit Breaker
+//!
+//! Stops calls into a failing cache layer. After `failure_threshold`
+//! consecutive failures the breaker opens and the layer is skipped; the
+//! owner probes it in the background and moves the breaker to half-open
+//! once it answers. Half-open lets traffic through again: a failure reopens
+//! the breaker, `success_threshold` consecutive successes close it.
+
+use parking_lot::Mutex;
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl BreakerState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Closed => "closed",
+            Self::Open => "open",
+            Self::HalfOpen => "half_open",
+        }
+    }
+}
+
+struct BreakerInner {
+    state: BreakerState,
+    /// Consecutive failures while closed
+    failures: u32,
+    /// Consecutive successes while half-open
+    successes: u32,
+    changed_at: Option<SystemTime>,
+}
+
+pub struct CircuitBreaker {
+    name: &'static str,
+    /// 0 disables the breaker
+    failure_threshold: u32,
+    success_threshold: u32,
+    inner: Mutex<BreakerInner>,
+    opened: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &'static str, failure_threshold: u32, success_threshold: u32) -> Self {
+        Self {
+            name,
+            failure_threshold,
+            success_threshold: success_threshold.max(1),
+            inner: Mutex::new(BreakerInner {
+                state: BreakerState::Closed,
+                failures: 0,
+                successes: 0,
+                changed_at: None,
+            }),
+            opened: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.inner.lock().state
+    }
+
+    /// Whether a call may go to the layer; rejections are counted
+    pub fn allow(&self) -> bool {
+        if self.state() == BreakerState::Open {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    /// Record the outcome of a call; returns true when it opened the breaker
+    pub fn record(&self, ok: bool) -> bool {
+        if self.failure_threshold == 0 {
+            return false;
+        }
+
+        let mut inner = self.inner.lock();
+        match (inner.state, ok) {
+            (BreakerState::Closed, true) => inner.failures = 0,
+            (BreakerState::Closed, false) => {
+                inner.failures += 1;
+                if inner.failures >= self.failure_threshold {
+                    warn!(
+                        "{} circuit breaker opened after {} consecutive failures",
+                        self.name, inner.failures
+                    );
+                    self.transition(&mut inner, BreakerState::Open);
+                    return true;
+                }
+            }
+            (BreakerState::HalfOpen, true) => {
+                inner.successes += 1;
+                if inner.successes >= self.success_threshold {
+                    info!("{} circuit breaker closed", self.name);
+                    self.transition(&mut inner, BreakerState::Closed);
+                }
+            }
+            (BreakerState::HalfOpen, false) => {
+                warn!(
+                    "{} circuit breaker reopened: half-open call failed",
+                    self.name
+                );
+                self.transition(&mut inner, BreakerState::Open);
+                return true;
+            }
+            // Calls started before the breaker opened
+            (BreakerState::Open, _) => {}
+        }
+        false
+    }
+
+    /// Let traffic through again after a successful probe
+    pub fn half_open(&self) {
+        let mut inner = self.inner.lock();
+        if inner.state == BreakerState::Open {
+            info!("{} circuit breaker half-open: probe succeeded", self.name);
+            self.transition(&mut inner, BreakerState::HalfOpen);
+        }
+    }
+
+    pub fn stats(&self) -> serde_json::Value {
+        let inner = self.inner.lock();
+        json!({
+            "enabled": self.failure_threshold > 0,
+            "state": inner.state.as_str(),
+            "consecutive_failures": inner.failures,
+            "opened": self.opened.load(Ordering::Relaxed),
+            "rejected": self.rejected.load(Ordering::Relaxed),
+            "changed_at": inner.changed_at.map(|at| {
+                at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+            }),
+        })
+    }
+
+    fn transition(&self, inner: &mut BreakerInner, to: BreakerState) {
+        if to == BreakerState::Open {
+            self.opened.fetch_add(1, Ordering::Relaxed);
+        }
+        inner.state = to;
+        inner.failures = 0;
+        inner.successes = 0;
+        inner.changed_at = Some(SystemTime::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breaker_transitions() {
+        let breaker = CircuitBreaker::new("test", 3, 2);
+
+        assert!(!breaker.record(false));
+        assert!(!breaker.record(true));
+        assert!(!breaker.record(false));
+        assert!(!breaker.record(false));
+        assert!(breaker.record(false));
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(!breaker.allow());
+        assert_eq!(breaker.stats()["rejected"], 1);
+
+        breaker.half_open();
+        assert!(breaker.allow());
+        assert!(breaker.record(false));
+        assert_eq!(breaker.state(), BreakerState::Open);
+
+        breaker.half_open();
+        breaker.record(true);
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        breaker.record(true);
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert_eq!(breaker.stats()["opened"], 2);
+    }
+
+    #[test]
+    fn test_disabled_breaker_never_opens() {
+        let breaker = CircuitBreaker::new("test", 0, 1);
+        for _ in 0..10 {
+            assert!(!breaker.record(false));
+        }
+        assert!(breaker.allow());
+    }
+}
@@ -0,0 +1,847 @@
+//! Disk Cache Layer
+//!
+//! One file per key under hashed shard directories
+//! (`entries/<aa>/<bb>/<id>.bin`), written to `tmp/` and renamed into place.
+//! A redb index next to them maps keys to their file, size and tags, so
+//! lookups, tag and prefix purges and size-based eviction never scan the
+//! entry files. The index is the source of truth: a file it does not
+//! reference is garbage, and an indexed entry whose file is missing or
+//! damaged is a miss.
+//!
+//! The directory belongs to one server process; redb locks the index.
+
+use super::{CacheEntry, PersistedEntry, PersistentCacheLayer, DISK_ENTRY_VERSION};
+use async_trait::async_trait;
+use lru::LruCache;
+use parking_lot::Mutex;
+use redb::{
+    Database, Durability, MultimapTableDefinition, ReadableTable, ReadableTableMetadata,
+    TableDefinition, WriteTransaction,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tracing::{debug, info, warn};
+
+/// Key -> bincode `IndexRecord`
+const ENTRIES: TableDefinition<&str, &[u8]> = TableDefinition::new("entries");
+/// Tag -> keys
+const TAGS: MultimapTableDefinition<&str, &str> = MultimapTableDefinition::new("tags");
+const META: TableDefinition<&str, u64> = TableDefinition::new("meta");
+const NEXT_FILE_ID: &str = "next_file_id";
+
+/// Entry file header: magic, format, flags, CRC32 of the payload
+const FILE_MAGIC: &[u8; 4] = b"VSCE";
+const FILE_FORMAT: u8 = 1;
+const FILE_HEADER_LEN: usize = 10;
+const FLAG_ZSTD: u8 = 1;
+
+/// Payloads smaller than this are stored uncompressed
+const COMPRESS_MIN_BYTES: usize = 512;
+const ZSTD_LEVEL: i32 = 3;
+
+/// Eviction frees space down to this share of the size limit
+const EVICT_TARGET_PERCENT: u64 = 90;
+
+#[derive(Serialize, Deserialize)]
+struct IndexRecord {
+    /// Path relative to the entries directory
+    file: String,
+    size: u64,
+    tags: Vec<String>,
+    stored_at_epoch_secs: u64,
+}
+
+struct LruState {
+    /// Keys from least to most recently used, with their file size
+    order: LruCache<String, u64>,
+    bytes: u64,
+}
+
+impl LruState {
+    fn forget(&mut self, key: &str) {
+        if let Some(size) = self.order.pop(key) {
+            self.bytes = self.bytes.saturating_sub(size);
+        }
+    }
+}
+
+pub(super) struct DiskCacheLayer {
+    store: Arc<DiskStore>,
+}
+
+struct DiskStore {
+    root: PathBuf,
+    entries_dir: PathBuf,
+    tmp_dir: PathBuf,
+    index: Database,
+    /// 0 means unlimited
+    max_bytes: u64,
+    compression: bool,
+    /// Held across index writes so the LRU state follows the index
+    lru: Mutex<LruState>,
+    next_tmp: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl DiskCacheLayer {
+    /// Open (or create) the cache directory, recovering from an unclean
+    /// shutdown
+    pub(super) fn open(
+        path: impl AsRef<Path>,
+        max_bytes: u64,
+        compression: bool,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            store: Arc::new(DiskStore::open(path.as_ref(), max_bytes, compression)?),
+        })
+    }
+
+    #[cfg(test)]
+    pub(super) fn entry_path(&self, key: &str) -> Option<PathBuf> {
+        self.store.entry_path(key)
+    }
+
+    async fn run<T, F>(&self, op: F) -> io::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&DiskStore) -> io::Result<T> + Send + 'static,
+    {
+        let store = Arc::clone(&self.store);
+        tokio::task::spawn_blocking(move || op(&store))
+            .await
+            .map_err(io::Error::other)?
+    }
+}
+
+#[async_trait]
+impl PersistentCacheLayer for DiskCacheLayer {
+    async fn get(&self, key: &str) -> io::Result<Option<CacheEntry>> {
+        let key = key.to_string();
+        let persisted = self.run(move |store| store.get(&key)).await?;
+        Ok(persisted.map(CacheEntry::from_persisted))
+    }
+
+    async fn set(&self, key: &str, entry: &CacheEntry) -> io::Result<()> {
+        let key = key.to_string();
+        let mut persisted = entry.to_persisted();
+        persisted.key = key.clone();
+        self.run(move |store| store.set(&key, &persisted)).await
+    }
+
+    async fn remove(&self, key: &str) -> io::Result<bool> {
+        let key = key.to_string();
+        self.run(move |store| {
+            store
+                .remove_keys(vec![key], Durability::Immediate)
+                .map(|removed| removed > 0)
+        })
+        .await
+    }
+
+    async fn purge_by_tag(&self, tag: &str) -> io::Result<usize> {
+        let tag = tag.to_string();
+        self.run(move |store| store.purge_by_tag(&tag)).await
+    }
+
+    async fn purge_by_prefix(&self, prefix: &str) -> io::Result<usize> {
+        let prefix = prefix.to_string();
+        self.run(move |store| store.purge_by_prefix(&prefix)).await
+    }
+
+    async fn purge_all(&self) -> io::Result<usize> {
+        self.run(|store| store.purge_all()).await
+    }
+
+    async fn probe(&self) -> io::Result<()> {
+        self.run(|store| {
+            if !fs::metadata(&store.entries_dir)?.is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "cache directory is missing",
+                ));
+            }
+            store.index.begin_read().map_err(index_error)?;
+            Ok(())
+        })
+        .await
+    }
+
+    fn usage(&self) -> Option<serde_json::Value> {
+        let lru = self.store.lru.lock();
+        Some(json!({
+            "entries": lru.order.len(),
+            "bytes": lru.bytes,
+            "max_bytes": self.store.max_bytes,
+            "evictions": self.store.evictions.load(Ordering::Relaxed),
+        }))
+    }
+}
+
+impl DiskStore {
+    fn open(root: &Path, max_bytes: u64, compression: bool) -> io::Result<Self> {
+        let entries_dir = root.join("entries");
+        let tmp_dir = root.join("tmp");
+        fs::create_dir_all(&entries_dir)?;
+        // Half-written entries and purged directories of the last run
+        if tmp_dir.exists() {
+            fs::remove_dir_all(&tmp_dir)?;
+        }
+        fs::create_dir_all(&tmp_dir)?;
+        remove_legacy_entries(root)?;
+
+        // redb rolls back to the last durable commit after a crash
+        let index = Database::create(root.join("index.redb")).map_err(index_error)?;
+        let txn = index.begin_write().map_err(index_error)?;
+        txn.open_table(ENTRIES).map_err(index_error)?;
+        txn.open_multimap_table(TAGS).map_err(index_error)?;
+        txn.open_table(META).map_err(index_error)?;
+        txn.commit().map_err(index_error)?;
+
+        let started = SystemTime::now();
+        let (lru, referenced) = load_index(&index)?;
+        let store = Self {
+            root: root.to_path_buf(),
+            entries_dir,
+            tmp_dir,
+            index,
+            max_bytes,
+            compression,
+            lru: Mutex::new(lru),
+            next_tmp: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        };
+
+        // Files written before a crash but never indexed
+        let entries_dir = store.entries_dir.clone();
+        std::thread::Builder::new()
+            .name("veloserve-disk-sweep".to_string())
+            .spawn(move || sweep_orphans(&entries_dir, &referenced, started))?;
+
+        let mut lru = store.lru.lock();
+        info!(
+            "Disk cache at {}: {} entries, {} bytes",
+            store.root.display(),
+            lru.order.len(),
+            lru.bytes
+        );
+        store.evict(&mut lru)?;
+        drop(lru);
+        Ok(store)
+    }
+
+    fn get(&self, key: &str) -> io::Result<Option<PersistedEntry>> {
+        let txn = self.index.begin_read().map_err(index_error)?;
+        let table = txn.open_table(ENTRIES).map_err(index_error)?;
+        let Some(record) = table.get(key).map_err(index_error)? else {
+            return Ok(None);
+        };
+        let record = decode_record(record.value())?;
+        drop(table);
+        drop(txn);
+
+        let path = self.entries_dir.join(&record.file);
+        match read_entry_file(&path) {
+            Ok(Some(entry)) if entry.version == DISK_ENTRY_VERSION => {
+                self.lru.lock().order.promote(key);
+                Ok(Some(entry))
+            }
+            Ok(_) => Ok(None),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                debug!("Disk cache file of {} is missing; dropping it", key);
+                self.remove_keys(vec![key.to_string()], Durability::Eventual)?;
+                Ok(None)
+            }
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                warn!("Dropping damaged disk cache entry {}: {}", key, err);
+                self.remove_keys(vec![key.to_string()], Durability::Eventual)?;
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn set(&self, key: &str, entry: &PersistedEntry) -> io::Result<()> {
+        let bytes = encode_entry_file(entry, self.compression)?;
+        let size = bytes.len() as u64;
+        let tmp_path = self.tmp_dir.join(format!(
+            "{}-{}.tmp",
+            std::process::id(),
+            self.next_tmp.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&tmp_path, &bytes)?;
+
+        let mut lru = self.lru.lock();
+        let result = self.commit_entry(key, entry, size, &tmp_path);
+        let replaced = match result {
+            Ok(replaced) => replaced,
+            Err(err) => {
+                let _ = fs::remove_file(&tmp_path);
+                return Err(err);
+            }
+        };
+        if let Some(old) = replaced {
+            remove_entry_file(&self.entries_dir.join(old.file));
+        }
+        lru.forget(key);
+        lru.order.put(key.to_string(), size);
+        lru.bytes += size;
+        self.evict(&mut lru)
+    }
+
+    /// Move the written file into its shard and index it; returns the
+    /// record it replaced
+    fn commit_entry(
+        &self,
+        key: &str,
+        entry: &PersistedEntry,
+        size: u64,
+        tmp_path: &Path,
+    ) -> io::Result<Option<IndexRecord>> {
+        let mut txn = self.index.begin_write().map_err(index_error)?;
+        // Losing a recent write to a crash only costs a miss
+        txn.set_durability(Durability::Eventual);
+
+        let file_id = {
+            let mut meta = txn.open_table(META).map_err(index_error)?;
+            let id = meta
+                .get(NEXT_FILE_ID)
+                .map_err(index_error)?
+                .map(|value| value.value())
+                .unwrap_or(0);
+            meta.insert(NEXT_FILE_ID, id + 1).map_err(index_error)?;
+            id
+        };
+        let file = shard_file_name(key, file_id);
+        let path = self.entries_dir.join(&file);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(tmp_path, &path)?;
+
+        let record = IndexRecord {
+            file,
+            size,
+            tags: entry.tags.clone(),
+            stored_at_epoch_secs: entry.created_at_epoch_secs,
+        };
+        let replaced = {
+            let mut entries = txn.open_table(ENTRIES).map_err(index_error)?;
+            let replaced = entries
+                .insert(key, encode_record(&record)?.as_slice())
+                .map_err(index_error)?
+                .map(|old| decode_record(old.value()))
+                .transpose()?;
+            let mut tags = txn.open_multimap_table(TAGS).map_err(index_error)?;
+            if let Some(old) = &replaced {
+                for tag in &old.tags {
+                    tags.remove(tag.as_str(), key).map_err(index_error)?;
+                }
+            }
+            for tag in &record.tags {
+                tags.insert(tag.as_str(), key).map_err(index_error)?;
+            }
+            replaced
+        };
+        if let Err(err) = txn.commit() {
+            remove_entry_file(&path);
+            return Err(index_error(err));
+        }
+        Ok(replaced)
+    }
+
+    /// Drop keys from the index, then their files
+    fn remove_keys(&self, keys: Vec<String>, durability: Durability) -> io::Result<usize> {
+        if keys.is_empty() {
+            return Ok(0);
+        }
+        let mut lru = self.lru.lock();
+        let mut txn = self.index.begin_write().map_err(index_error)?;
+        txn.set_durability(durability);
+        let removed = remove_records(&txn, &keys)?;
+        txn.commit().map_err(index_error)?;
+
+        for (key, record) in &removed {
+            lru.forget(key);
+            remove_entry_file(&self.entries_dir.join(&record.file));
+        }
+        Ok(removed.len())
+    }
+
+    fn purge_by_tag(&self, tag: &str) -> io::Result<usize> {
+        let txn = self.index.begin_read().map_err(index_error)?;
+        let tags = txn.open_multimap_table(TAGS).map_err(index_error)?;
+        let mut keys = Vec::new();
+        for key in tags.get(tag).map_err(index_error)? {
+            keys.push(key.map_err(index_error)?.value().to_string());
+        }
+        drop(tags);
+        drop(txn);
+        // Purged content must not come back after a crash
+        self.remove_keys(keys, Durability::Immediate)
+    }
+
+    fn purge_by_prefix(&self, prefix: &str) -> io::Result<usize> {
+        let txn = self.index.begin_read().map_err(index_error)?;
+        let entries = txn.open_table(ENTRIES).map_err(index_error)?;
+        let mut keys = Vec::new();
+        for item in entries.range(prefix..).map_err(index_error)? {
+            let (key, _) = item.map_err(index_error)?;
+            let key = key.value();
+            if !key.starts_with(prefix) {
+                break;
+            }
+            keys.push(key.to_string());
+        }
+        drop(entries);
+        drop(txn);
+        self.remove_keys(keys, Durability::Immediate)
+    }
+
+    fn purge_all(&self) -> io::Result<usize> {
+        let mut lru = self.lru.lock();
+        let txn = self.index.begin_write().map_err(index_error)?;
+        let removed = txn
+            .open_table(ENTRIES)
+            .map_err(index_error)?
+            .len()
+            .map_err(index_error)? as usize;
+        txn.delete_table(ENTRIES).map_err(index_error)?;
+        txn.delete_multimap_table(TAGS).map_err(index_error)?;
+        txn.open_table(ENTRIES).map_err(index_error)?;
+        txn.open_multimap_table(TAGS).map_err(index_error)?;
+        txn.commit().map_err(index_error)?;
+
+        lru.order.clear();
+        lru.bytes = 0;
+        // Swap the whole tree out instead of deleting file by file
+        let purged = self.tmp_dir.join(format!(
+            "purged-{}",
+            self.next_tmp.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::rename(&self.entries_dir, &purged)?;
+        fs::create_dir_all(&self.entries_dir)?;
+        drop(lru);
+        fs::remove_dir_all(&purged)?;
+        Ok(removed)
+    }
+
+    /// Drop least recently used entries until the cache is back under
+    /// `EVICT_TARGET_PERCENT` of its limit
+    fn evict(&self, lru: &mut LruState) -> io::Result<()> {
+        if self.max_bytes == 0 || lru.bytes <= self.max_bytes {
+            return Ok(());
+        }
+        let target = self.max_bytes / 100 * EVICT_TARGET_PERCENT;
+        let mut victims = Vec::new();
+        while lru.bytes > target {
+            let Some((key, size)) = lru.order.pop_lru() else {
+                break;
+            };
+            lru.bytes = lru.bytes.saturating_sub(size);
+            victims.push(key);
+        }
+
+        let mut txn = self.index.begin_write().map_err(index_error)?;
+        txn.set_durability(Durability::Eventual);
+        let removed = remove_records(&txn, &victims)?;
+        txn.commit().map_err(index_error)?;
+        for (_, record) in &removed {
+            remove_entry_file(&self.entries_dir.join(&record.file));
+        }
+        self.evictions
+            .fetch_add(removed.len() as u64, Ordering::Relaxed);
+        debug!(
+            "Disk cache evicted {} entries, {} bytes left",
+            removed.len(),
+            lru.bytes
+        );
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn entry_path(&self, key: &str) -> Option<PathBuf> {
+        let txn = self.index.begin_read().ok()?;
+        let table = txn.open_table(ENTRIES).ok()?;
+        let record = decode_record(table.get(key).ok()??.value()).ok()?;
+        Some(self.entries_dir.join(record.file))
+    }
+}
+
+/// Remove keys and their tag rows; returns what was indexed for them
+fn remove_records(
+    txn: &WriteTransaction,
+    keys: &[String],
+) -> io::Result<Vec<(String, IndexRecord)>> {
+    let mut entries = txn.open_table(ENTRIES).map_err(index_error)?;
+    let mut tags = txn.open_multimap_table(TAGS).map_err(index_error)?;
+    let mut removed = Vec::new();
+    for key in keys {
+        let Some(record) = entries.remove(key.as_str()).map_err(index_error)? else {
+            continue;
+        };
+        let record = decode_record(record.value())?;
+        for tag in &record.tags {
+            tags.remove(tag.as_str(), key.as_str())
+                .map_err(index_error)?;
+        }
+        removed.push((key.clone(), record));
+    }
+    Ok(removed)
+}
+
+/// Seed the LRU order by age and collect the files the index references
+fn load_index(index: &Database) -> io::Result<(LruState, HashSet<String>)> {
+    let txn = index.begin_read().map_err(index_error)?;
+    let table = txn.open_table(ENTRIES).map_err(index_error)?;
+    let mut records = Vec::new();
+    for item in table.iter().map_err(index_error)? {
+        let (key, record) = item.map_err(index_error)?;
+        records.push((key.value().to_string(), decode_record(record.value())?));
+    }
+    records.sort_by_key(|(_, record)| record.stored_at_epoch_secs);
+
+    let mut lru = LruState {
+        order: LruCache::unbounded(),
+        bytes: 0,
+    };
+    let mut referenced = HashSet::with_capacity(records.len());
+    for (key, record) in records {
+        lru.bytes += record.size;
+        lru.order.put(key, record.size);
+        referenced.insert(record.file);
+    }
+    Ok((lru, referenced))
+}
+
+/// Delete entry files older than `before` that the index does not know
+fn sweep_orphans(entries_dir: &Path, referenced: &HashSet<String>, before: SystemTime) {
+    let mut removed = 0;
+    let mut pending = vec![entries_dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(listing) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for item in listing.flatten() {
+            let path = item.path();
+            let Ok(metadata) = item.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let Ok(relative) = path.strip_prefix(entries_dir) else {
+                continue;
+            };
+            let known = relative
+                .to_str()
+                .is_some_and(|relative| referenced.contains(relative));
+            let older = metadata
+                .modified()
+                .map(|modified| modified < before)
+                .unwrap_or(false);
+            if !known && older && fs::remove_file(&path).is_ok() {
+                removed += 1;
+            }
+        }
+    }
+    if removed > 0 {
+        info!("Disk cache removed {} unindexed files", removed);
+    }
+}
+
+/// Flat entry files of the layout before the index; anything else in the
+/// directory is left alone
+fn remove_legacy_entries(root: &Path) -> io::Result<()> {
+    let mut removed = 0;
+    for item in fs::read_dir(root)? {
+        let path = item?.path();
+        if path.is_file() && is_legacy_entry(&path) {
+            fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    if removed > 0 {
+        info!("Disk cache removed {} entries of the old layout", removed);
+    }
+    Ok(())
+}
+
+/// An old-layout entry is stored under the escaped form of the key it holds
+fn is_legacy_entry(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    if !name.ends_with(".bin") {
+        return false;
+    }
+    let Ok(bytes) = fs::read(path) else {
+        return false;
+    };
+    bincode::deserialize::<PersistedEntry>(&bytes)
+        .is_ok_and(|entry| legacy_file_name(&entry.key) == name)
+}
+
+/// Name the old layout gave a key's file: `[A-Za-z0-9._-]` kept, other
+/// bytes as two hex digits
+fn legacy_file_name(key: &str) -> String {
+    let mut name = String::with_capacity(key.len() * 2 + 4);
+    for byte in key.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.') {
+            name.push(byte as char);
+        } else {
+            name.push_str(&format!("{:02x}", byte));
+        }
+    }
+    name.push_str(".bin");
+    name
+}
+
+/// `<aa>/<bb>/<id>.bin`, sharded by the key's hash
+fn shard_file_name(key: &str, file_id: u64) -> String {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    let hash = hasher.finish();
+    format!(
+        "{:02x}/{:02x}/{:016x}.bin",
+        hash >> 56,
+        (hash >> 48) & 0xff,
+        file_id
+    )
+}
+
+fn remove_entry_file(path: &Path) {
+    if let Err(err) = fs::remove_file(path) {
+        if err.kind() != io::ErrorKind::NotFound {
+            warn!(
+                "Failed to remove disk cache file {}: {}",
+                path.display(),
+                err
+            );
+        }
+    }
+}
+
+pub(super) fn encode_entry_file(entry: &PersistedEntry, compression: bool) -> io::Result<Vec<u8>> {
+    let mut payload = bincode::serialize(entry).map_err(invalid_data)?;
+    let mut flags = 0;
+    if compression && payload.len() >= COMPRESS_MIN_BYTES {
+        let compressed = zstd::bulk::compress(&payload, ZSTD_LEVEL)?;
+        if compressed.len() < payload.len() {
+            payload = compressed;
+            flags |= FLAG_ZSTD;
+        }
+    }
+
+    let mut bytes = Vec::with_capacity(FILE_HEADER_LEN + payload.len());
+    bytes.extend_from_slice(FILE_MAGIC);
+    bytes.push(FILE_FORMAT);
+    bytes.push(flags);
+    bytes.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+/// `None` for files of another format; `InvalidData` for damaged ones
+pub(super) fn read_entry_file(path: &Path) -> io::Result<Option<PersistedEntry>> {
+    let bytes = fs::read(path)?;
+    if bytes.len() < FILE_HEADER_LEN || &bytes[..4] != FILE_MAGIC {
+        return Err(invalid_data("bad entry header"));
+    }
+    if bytes[4] != FILE_FORMAT {
+        return Ok(None);
+    }
+    let flags = bytes[5];
+    let crc = u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]);
+    let payload = &bytes[FILE_HEADER_LEN..];
+    if crc32fast::hash(payload) != crc {
+        return Err(invalid_data("entry checksum mismatch"));
+    }
+
+    let entry = if flags & FLAG_ZSTD != 0 {
+        let payload = zstd::stream::decode_all(payload)?;
+        bincode::deserialize(&payload)
+    } else {
+        bincode::deserialize(payload)
+    };
+    entry.map(Some).map_err(invalid_data)
+}
+
+fn encode_record(record: &IndexRecord) -> io::Result<Vec<u8>> {
+    bincode::serialize(record).map_err(invalid_data)
+}
+
+fn decode_record(bytes: &[u8]) -> io::Result<IndexRecord> {
+    bincode::deserialize(bytes).map_err(invalid_data)
+}
+
+fn invalid_data(err: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+fn index_error(err: impl Into<redb::Error>) -> io::Error {
+    match err.into() {
+        redb::Error::Io(err) => err,
+        err => io::Error::other(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{CacheLifetime, ResponseMeta};
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    fn entry(data: &[u8], tags: &[&str]) -> CacheEntry {
+        CacheEntry::new(
+            data.to_vec(),
+            "text/html".to_string(),
+            ResponseMeta::default(),
+            tags.iter().map(|tag| tag.to_string()).collect(),
+            CacheLifetime::from_ttl(Duration::from_secs(60)),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_sharded_entries_and_indexed_purges() {
+        let dir = tempdir().unwrap();
+        let layer = DiskCacheLayer::open(dir.path(), 0, true).unwrap();
+
+        let big = vec![b'x'; 4096];
+        layer
+            .set("page:a.com:/", &entry(&big, &["home"]))
+            .await
+            .unwrap();
+        layer
+            .set("page:a.com:/shop", &entry(b"shop", &["shop", "catalog"]))
+            .await
+            .unwrap();
+        layer
+            .set("page:b.com:/", &entry(b"b", &["catalog"]))
+            .await
+            .unwrap();
+
+        let path = layer.store.entry_path("page:a.com:/").unwrap();
+        let relative = path.strip_prefix(dir.path().join("entries")).unwrap();
+        assert_eq!(relative.components().count(), 3);
+        // Compressed on disk, intact when read back
+        assert!(fs::metadata(&path).unwrap().len() < 1024);
+        let hit = layer.get("page:a.com:/").await.unwrap().unwrap();
+        assert_eq!(hit.data, big);
+        assert_eq!(hit.tags, vec!["home".to_string()]);
+
+        assert_eq!(layer.purge_by_tag("catalog").await.unwrap(), 2);
+        assert!(layer.get("page:a.com:/shop").await.unwrap().is_none());
+        assert!(layer.get("page:b.com:/").await.unwrap().is_none());
+        assert_eq!(layer.purge_by_tag("shop").await.unwrap(), 0);
+
+        layer
+            .set("page:a.com:/shop", &entry(b"shop", &[]))
+            .await
+            .unwrap();
+        assert_eq!(layer.purge_by_prefix("page:a.com:").await.unwrap(), 2);
+        assert!(!path.exists());
+        assert_eq!(layer.usage().unwrap()["entries"], 0);
+        assert_eq!(layer.usage().unwrap()["bytes"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_size_limit_evicts_least_recently_used() {
+        let dir = tempdir().unwrap();
+        let page = vec![b'p'; 1000];
+        let size = encode_entry_file(&entry(&page, &[]).to_persisted(), false)
+            .unwrap()
+            .len() as u64;
+        let layer = DiskCacheLayer::open(dir.path(), size * 5 / 2, false).unwrap();
+
+        layer.set("a", &entry(&page, &[])).await.unwrap();
+        layer.set("b", &entry(&page, &[])).await.unwrap();
+        assert!(layer.get("a").await.unwrap().is_some());
+        layer.set("c", &entry(&page, &[])).await.unwrap();
+
+        assert!(layer.get("a").await.unwrap().is_some());
+        assert!(layer.get("b").await.unwrap().is_none());
+        assert!(layer.get("c").await.unwrap().is_some());
+        let usage = layer.usage().unwrap();
+        assert_eq!(usage["evictions"], 1);
+        assert!(usage["bytes"].as_u64().unwrap() <= size * 5 / 2);
+    }
+
+    #[tokio::test]
+    async fn test_purge_all_and_reopen() {
+        let dir = tempdir().unwrap();
+        let layer = DiskCacheLayer::open(dir.path(), 0, true).unwrap();
+        layer.set("a", &entry(b"a", &["t"])).await.unwrap();
+        layer.set("b", &entry(b"b", &["t"])).await.unwrap();
+        drop(layer);
+
+        let layer = DiskCacheLayer::open(dir.path(), 0, true).unwrap();
+        assert_eq!(layer.usage().unwrap()["entries"], 2);
+        assert_eq!(layer.purge_all().await.unwrap(), 2);
+        assert!(layer.get("a").await.unwrap().is_none());
+        assert_eq!(layer.purge_by_tag("t").await.unwrap(), 0);
+        layer.set("c", &entry(b"c", &[])).await.unwrap();
+        assert!(layer.get("c").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_recovery_after_unclean_shutdown() {
+        let dir = tempdir().unwrap();
+        let layer = DiskCacheLayer::open(dir.path(), 0, false).unwrap();
+        layer.set("kept", &entry(b"kept", &[])).await.unwrap();
+        layer.set("damaged", &entry(b"damaged", &[])).await.unwrap();
+        let damaged = layer.store.entry_path("damaged").unwrap();
+        drop(layer);
+
+        // A torn write, a leftover temp file, an unindexed entry file and
+        // a file of the old flat layout
+        let mut bytes = fs::read(&damaged).unwrap();
+        bytes.truncate(bytes.len() - 2);
+        fs::write(&damaged, bytes).unwrap();
+        let tmp_file = dir.path().join("tmp").join("1-1.tmp");
+        fs::write(&tmp_file, b"partial").unwrap();
+        let orphan = dir.path().join("entries").join("00").join("00");
+        fs::create_dir_all(&orphan).unwrap();
+        let orphan = orphan.join("ffffffffffffffff.bin");
+        fs::write(&orphan, b"orphan").unwrap();
+        let mut old_entry = entry(b"legacy", &[]).to_persisted();
+        old_entry.key = "page:example.com:/".to_string();
+        let legacy = dir.path().join("page3aexample.com3a2f.bin");
+        fs::write(&legacy, bincode::serialize(&old_entry).unwrap()).unwrap();
+        // Other files sharing the directory are not ours
+        let foreign = dir.path().join("backup.bin");
+        fs::write(&foreign, bincode::serialize(&old_entry).unwrap()).unwrap();
+        let unrelated = dir.path().join("page3aexample.com3a2fother.bin");
+        fs::write(&unrelated, b"not an entry").unwrap();
+
+        let layer = DiskCacheLayer::open(dir.path(), 0, false).unwrap();
+        assert!(!tmp_file.exists());
+        assert!(!legacy.exists());
+        assert!(foreign.exists());
+        assert!(unrelated.exists());
+        assert_eq!(
+            layer.get("kept").await.unwrap().unwrap().data,
+            b"kept".to_vec()
+        );
+        assert!(layer.get("damaged").await.unwrap().is_none());
+        assert!(layer.store.entry_path("damaged").is_none());
+
+        for _ in 0..100 {
+            if !orphan.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!orphan.exists());
+    }
+}
@@ -0,0 +1,897 @@
+//! Memcached L2 Layer
+//!
+//! Memcached storage over the meta text protocol (memcached 1.6+). Keys are
+//! spread over the configured servers with a consistent hash ring, so
+//! adding or removing a server only moves that server's share of the keys.
+//!
+//! Memcached cannot list keys, so purges bump generation counters instead
+//! of deleting entries. Every entry records the generations of the
+//! namespaces it belongs to (the whole cache, each of its tags and each
+//! `:`/`/`-separated prefix of its key) and is ignored once one of them
+//! moves on. Purges therefore report no count. A prefix purge that does
+//! not end on a separator also matches keys that continue the last segment
+//! (`/bl` covers `/blog`), so it bumps the enclosing namespace instead.
+//!
+//! Keys live under `<key_prefix>:v1:`:
+//! - `e:<hash>`, the entry, which carries its key to rule out collisions
+//! - `g:<hash>`, a generation counter
+
+use super::{now_epoch_secs, to_io_error, CacheEntry, PersistedEntry, PersistentCacheLayer};
+
+use async_trait::async_trait;
+use futures::future::try_join_all;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+use tracing::debug;
+
+const MEMCACHED_ENTRY_VERSION: u8 = 1;
+const MEMCACHED_DEFAULT_PORT: u16 = 11211;
+const MEMCACHED_COMPRESSION_THRESHOLD_BYTES: usize = 1024;
+/// Longer expiries are sent as a Unix timestamp, as memcached expects
+const MEMCACHED_MAX_RELATIVE_TTL_SECS: u64 = 30 * 24 * 3600;
+/// Points per server on the hash ring
+const RING_POINTS_PER_SERVER: usize = 160;
+const MAX_IDLE_CONNECTIONS: usize = 16;
+/// Prefixes with more separators than this are not tracked; purging one
+/// invalidates the whole cache instead
+const MAX_PREFIX_DEPTH: usize = 16;
+const PREFIX_SEPARATORS: [char; 2] = [':', '/'];
+
+#[derive(Serialize, Deserialize)]
+struct MemcachedValue {
+    version: u8,
+    key: String,
+    /// Generations of `namespaces(key, tags)` when the entry was stored
+    generations: Vec<u64>,
+    compressed: bool,
+    /// bincode `PersistedEntry`
+    entry: Vec<u8>,
+}
+
+/// One reply of the meta protocol
+#[derive(Debug, PartialEq, Eq)]
+enum MetaReply {
+    /// `VA` with its data block
+    Value(Vec<u8>),
+    /// `HD`, `EN`, `NF`, `NS`, `EX` or `MN`
+    Status(String),
+    /// `ERROR`, `CLIENT_ERROR ...` or `SERVER_ERROR ...`
+    Error(String),
+}
+
+impl MetaReply {
+    /// Client errors leave the connection in an unknown state
+    fn breaks_connection(&self) -> bool {
+        matches!(self, Self::Error(line) if !line.starts_with("SERVER_ERROR"))
+    }
+
+    fn into_result(self) -> io::Result<Self> {
+        match self {
+            Self::Error(line) => Err(to_io_error(format!("memcached: {}", line))),
+            reply => Ok(reply),
+        }
+    }
+}
+
+/// Consistent hash ring over the server list
+struct HashRing {
+    points: Vec<(u64, usize)>,
+}
+
+impl HashRing {
+    fn new(servers: &[String]) -> Self {
+        let mut points = Vec::with_capacity(servers.len() * RING_POINTS_PER_SERVER);
+        for (index, server) in servers.iter().enumerate() {
+            for point in 0..RING_POINTS_PER_SERVER {
+                points.push((stable_hash(&format!("{}-{}", server, point)), index));
+            }
+        }
+        points.sort_unstable();
+        Self { points }
+    }
+
+    fn server_for(&self, key: &str) -> usize {
+        let hash = stable_hash(key);
+        let slot = self.points.partition_point(|(point, _)| *point < hash);
+        self.points[slot % self.points.len()].1
+    }
+}
+
+struct MemcachedServer {
+    addr: String,
+    idle: Mutex<Vec<BufStream<TcpStream>>>,
+}
+
+impl MemcachedServer {
+    /// Send `commands` in one write and read a reply for each
+    async fn execute(&self, commands: &[Vec<u8>]) -> io::Result<Vec<MetaReply>> {
+        let pooled = self.idle.lock().pop();
+        let retry = pooled.is_some();
+        let conn = match pooled {
+            Some(conn) => conn,
+            None => self.connect().await?,
+        };
+        match self.execute_on(conn, commands).await {
+            // The server may have closed an idle connection
+            Err(err) if retry => {
+                debug!(
+                    "Retrying memcached {} on a new connection: {}",
+                    self.addr, err
+                );
+                let conn = self.connect().await?;
+                self.execute_on(conn, commands).await
+            }
+            result => result,
+        }
+    }
+
+    async fn execute_on(
+        &self,
+        mut conn: BufStream<TcpStream>,
+        commands: &[Vec<u8>],
+    ) -> io::Result<Vec<MetaReply>> {
+        for command in commands {
+            conn.write_all(command).await?;
+        }
+        conn.flush().await?;
+
+        let mut replies = Vec::with_capacity(commands.len());
+        let mut reusable = true;
+        for _ in commands {
+            let reply = read_reply(&mut conn).await?;
+            reusable &= !reply.breaks_connection();
+            replies.push(reply);
+        }
+        if reusable {
+            let mut idle = self.idle.lock();
+            if idle.len() < MAX_IDLE_CONNECTIONS {
+                idle.push(conn);
+            }
+        }
+        Ok(replies)
+    }
+
+    async fn connect(&self) -> io::Result<BufStream<TcpStream>> {
+        let stream = TcpStream::connect(&self.addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(BufStream::new(stream))
+    }
+}
+
+pub(super) struct MemcachedCacheLayer {
+    servers: Vec<MemcachedServer>,
+    ring: HashRing,
+    namespace: String,
+    timeout: Duration,
+}
+
+impl MemcachedCacheLayer {
+    /// Servers are `host[:port]`; connections are opened on first use
+    pub(super) fn new(servers: &[String], key_prefix: &str, timeout: Duration) -> io::Result<Self> {
+        let servers: Vec<String> = servers
+            .iter()
+            .map(|server| server.trim())
+            .filter(|server| !server.is_empty())
+            .map(|server| {
+                if server
+                    .rsplit_once(':')
+                    .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+                {
+                    server.to_string()
+                } else {
+                    format!("{}:{}", server, MEMCACHED_DEFAULT_PORT)
+                }
+            })
+            .collect();
+        if servers.is_empty() {
+            return Err(to_io_error("no memcached servers configured"));
+        }
+        if key_prefix.is_empty()
+            || key_prefix.len() > 64
+            || key_prefix
+                .chars()
+                .any(|ch| ch.is_whitespace() || ch.is_control())
+        {
+            return Err(to_io_error(format!(
+                "invalid memcached key prefix {:?}",
+                key_prefix
+            )));
+        }
+
+        Ok(Self {
+            ring: HashRing::new(&servers),
+            servers: servers
+                .into_iter()
+                .map(|addr| MemcachedServer {
+                    addr,
+                    idle: Mutex::new(Vec::new()),
+                })
+                .collect(),
+            namespace: format!("{}:v1", key_prefix),
+            timeout,
+        })
+    }
+
+    fn entry_key(&self, key: &str) -> String {
+        format!("{}:e:{:016x}", self.namespace, stable_hash(key))
+    }
+
+    fn generation_key(&self, namespace: &str) -> String {
+        format!("{}:g:{:016x}", self.namespace, stable_hash(namespace))
+    }
+
+    /// Run `op`, bounded by the configured timeout
+    async fn run<T>(&self, op: impl Future<Output = io::Result<T>>) -> io::Result<T> {
+        tokio::time::timeout(self.timeout, op).await.map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("memcached operation timed out after {:?}", self.timeout),
+            )
+        })?
+    }
+
+    /// Send each `(key, command)` to the server owning the key, one
+    /// pipelined batch per server; replies come back in request order
+    async fn dispatch(&self, requests: Vec<(String, Vec<u8>)>) -> io::Result<Vec<MetaReply>> {
+        let total = requests.len();
+        let mut batches: Vec<Vec<(usize, Vec<u8>)>> = vec![Vec::new(); self.servers.len()];
+        for (index, (key, command)) in requests.into_iter().enumerate() {
+            batches[self.ring.server_for(&key)].push((index, command));
+        }
+
+        let results = try_join_all(
+            batches
+                .into_iter()
+                .enumerate()
+                .filter(|(_, batch)| !batch.is_empty())
+                .map(|(server, batch)| async move {
+                    let (indexes, commands): (Vec<usize>, Vec<Vec<u8>>) = batch.into_iter().unzip();
+                    let replies = self.servers[server].execute(&commands).await?;
+                    Ok::<_, io::Error>(indexes.into_iter().zip(replies))
+                }),
+        )
+        .await?;
+
+        let mut replies: Vec<Option<MetaReply>> = (0..total).map(|_| None).collect();
+        for (index, reply) in results.into_iter().flatten() {
+            replies[index] = Some(reply);
+        }
+        replies
+            .into_iter()
+            .map(|reply| reply.ok_or_else(|| to_io_error("memcached reply missing")))
+            .collect()
+    }
+
+    /// Current generation of each namespace, starting missing counters at
+    /// the current time so a recreated counter never repeats an old value
+    async fn generations(&self, namespaces: &[String]) -> io::Result<Vec<u64>> {
+        let requests = namespaces
+            .iter()
+            .map(|namespace| {
+                let key = self.generation_key(namespace);
+                let command = format!("ma {} N0 J{} D0 v\r\n", key, initial_generation());
+                (key, command.into_bytes())
+            })
+            .collect();
+        self.dispatch(requests)
+            .await?
+            .into_iter()
+            .map(parse_generation)
+            .collect()
+    }
+
+    /// Move a namespace to a new generation, orphaning its entries
+    async fn bump(&self, namespace: &str) -> io::Result<()> {
+        let key = self.generation_key(namespace);
+        let command = format!("ma {} N0 J{} v\r\n", key, initial_generation());
+        let reply = self.dispatch(vec![(key, command.into_bytes())]).await?;
+        reply
+            .into_iter()
+            .try_for_each(|reply| parse_generation(reply).map(|_| ()))
+    }
+
+    fn serialize_entry(
+        key: &str,
+        entry: &CacheEntry,
+        generations: Vec<u64>,
+    ) -> io::Result<Vec<u8>> {
+        let mut persisted = entry.to_persisted();
+        persisted.key = key.to_string();
+        let mut payload = bincode::serialize(&persisted).map_err(to_io_error)?;
+        let mut compressed = false;
+        if payload.len() >= MEMCACHED_COMPRESSION_THRESHOLD_BYTES {
+            let smaller = zstd::bulk::compress(&payload, 3)?;
+            if smaller.len() < payload.len() {
+                payload = smaller;
+                compressed = true;
+            }
+        }
+
+        bincode::serialize(&MemcachedValue {
+            version: MEMCACHED_ENTRY_VERSION,
+            key: key.to_string(),
+            generations,
+            compressed,
+            entry: payload,
+        })
+        .map_err(to_io_error)
+    }
+
+    fn deserialize_entry(key: &str, raw: &[u8]) -> Option<(CacheEntry, Vec<u64>)> {
+        let value: MemcachedValue = bincode::deserialize(raw).ok()?;
+        if value.version != MEMCACHED_ENTRY_VERSION || value.key != key {
+            return None;
+        }
+        let payload = if value.compressed {
+            zstd::stream::decode_all(value.entry.as_slice()).ok()?
+        } else {
+            value.entry
+        };
+        let persisted: PersistedEntry = bincode::deserialize(&payload).ok()?;
+        Some((CacheEntry::from_persisted(persisted), value.generations))
+    }
+}
+
+#[async_trait]
+impl PersistentCacheLayer for MemcachedCacheLayer {
+    async fn get(&self, key: &str) -> io::Result<Option<CacheEntry>> {
+        self.run(async {
+            let entry_key = self.entry_key(key);
+            let command = format!("mg {} v\r\n", entry_key).into_bytes();
+            let reply = self.dispatch(vec![(entry_key, command)]).await?;
+            let Some(MetaReply::Value(raw)) = reply
+                .into_iter()
+                .next()
+                .map(MetaReply::into_result)
+                .transpose()?
+            else {
+                return Ok(None);
+            };
+            let Some((entry, stored)) = Self::deserialize_entry(key, &raw) else {
+                return Ok(None);
+            };
+
+            let current = self.generations(&namespaces(key, &entry.tags)).await?;
+            Ok((current == stored).then_some(entry))
+        })
+        .await
+    }
+
+    async fn set(&self, key: &str, entry: &CacheEntry) -> io::Result<()> {
+        self.run(async {
+            // Read first: a purge racing this write leaves the entry stale
+            let generations = self.generations(&namespaces(key, &entry.tags)).await?;
+            let value = Self::serialize_entry(key, entry, generations)?;
+            let entry_key = self.entry_key(key);
+            let mut command = format!(
+                "ms {} {} T{}\r\n",
+                entry_key,
+                value.len(),
+                expiry(entry.retention().as_secs())
+            )
+            .into_bytes();
+            command.extend_from_slice(&value);
+            command.extend_from_slice(b"\r\n");
+
+            match self.dispatch(vec![(entry_key, command)]).await?.pop() {
+                Some(MetaReply::Error(line)) if line.contains("too large") => {
+                    debug!(
+                        "Memcached refused {} ({} bytes): {}",
+                        key,
+                        value.len(),
+                        line
+                    );
+                    Ok(())
+                }
+                Some(reply) => reply.into_result().map(|_| ()),
+                None => Err(to_io_error("memcached reply missing")),
+            }
+        })
+        .await
+    }
+
+    async fn remove(&self, key: &str) -> io::Result<bool> {
+        self.run(async {
+            let entry_key = self.entry_key(key);
+            let command = format!("md {}\r\n", entry_key).into_bytes();
+            let reply = self.dispatch(vec![(entry_key, command)]).await?.pop();
+            match reply.map(MetaReply::into_result).transpose()? {
+                Some(MetaReply::Status(status)) => Ok(status == "HD"),
+                _ => Ok(false),
+            }
+        })
+        .await
+    }
+
+    async fn purge_by_tag(&self, tag: &str) -> io::Result<usize> {
+        self.run(async {
+            self.bump(&tag_namespace(tag)).await?;
+            Ok(0)
+        })
+        .await
+    }
+
+    async fn purge_by_prefix(&self, prefix: &str) -> io::Result<usize> {
+        let Some(namespace) = prefix_purge_namespace(prefix) else {
+            debug!(
+                "Memcached prefix purge of {:?} invalidates everything",
+                prefix
+            );
+            return self.purge_all().await;
+        };
+        self.run(async {
+            self.bump(&namespace).await?;
+            Ok(0)
+        })
+        .await
+    }
+
+    async fn purge_all(&self) -> io::Result<usize> {
+        self.run(async {
+            self.bump(ALL_NAMESPACE).await?;
+            Ok(0)
+        })
+        .await
+    }
+
+    async fn probe(&self) -> io::Result<()> {
+        self.run(async {
+            try_join_all(self.servers.iter().map(|server| async move {
+                match server.execute(&[b"mn\r\n".to_vec()]).await?.pop() {
+                    Some(MetaReply::Status(status)) if status == "MN" => Ok(()),
+                    reply => Err(to_io_error(format!(
+                        "memcached {} answered {:?}",
+                        server.addr, reply
+                    ))),
+                }
+            }))
+            .await
+            .map(|_| ())
+        })
+        .await
+    }
+}
+
+const ALL_NAMESPACE: &str = "a";
+
+fn tag_namespace(tag: &str) -> String {
+    format!("t:{}", tag)
+}
+
+fn prefix_namespace(prefix: &str) -> String {
+    format!("p:{}", prefix)
+}
+
+/// Namespace shared by every key starting with `prefix`: the prefix itself
+/// when it ends on a separator, otherwise the one before its last segment.
+/// `None` when only the whole cache covers it.
+fn prefix_purge_namespace(prefix: &str) -> Option<String> {
+    let boundary = if prefix.ends_with(PREFIX_SEPARATORS) {
+        prefix
+    } else {
+        &prefix[..prefix.rfind(PREFIX_SEPARATORS)?]
+    };
+    let boundary = boundary.trim_end_matches(PREFIX_SEPARATORS);
+    if boundary.is_empty() || separator_count(boundary) >= MAX_PREFIX_DEPTH {
+        return None;
+    }
+    Some(prefix_namespace(boundary))
+}
+
+fn separator_count(value: &str) -> usize {
+    value.matches(PREFIX_SEPARATORS).count()
+}
+
+/// Namespaces an entry belongs to: everything, each key prefix ending
+/// before a separator (and the key itself) and each tag
+fn namespaces(key: &str, tags: &[String]) -> Vec<String> {
+    let mut prefixes: Vec<&str> = key
+        .match_indices(PREFIX_SEPARATORS)
+        .map(|(index, _)| &key[..index])
+        .chain(std::iter::once(key.trim_end_matches(PREFIX_SEPARATORS)))
+        .filter(|prefix| {
+            !prefix.is_empty()
+                && !prefix.ends_with(PREFIX_SEPARATORS)
+                && separator_count(prefix) < MAX_PREFIX_DEPTH
+        })
+        .collect();
+    prefixes.dedup();
+
+    std::iter::once(ALL_NAMESPACE.to_string())
+        .chain(prefixes.into_iter().map(prefix_namespace))
+        .chain(tags.iter().map(|tag| tag_namespace(tag)))
+        .collect()
+}
+
+fn parse_generation(reply: MetaReply) -> io::Result<u64> {
+    match reply.into_result()? {
+        MetaReply::Value(raw) => std::str::from_utf8(&raw)
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .ok_or_else(|| to_io_error("memcached generation is not a number")),
+        reply => Err(to_io_error(format!(
+            "unexpected memcached generation reply {:?}",
+            reply
+        ))),
+    }
+}
+
+fn initial_generation() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+fn expiry(ttl_secs: u64) -> u64 {
+    let ttl_secs = ttl_secs.max(1);
+    if ttl_secs > MEMCACHED_MAX_RELATIVE_TTL_SECS {
+        now_epoch_secs() + ttl_secs
+    } else {
+        ttl_secs
+    }
+}
+
+/// FNV-1a with a final mix; stable across builds, unlike `DefaultHasher`,
+/// so every server in a group agrees on keys and placement
+fn stable_hash(value: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in value.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^ (hash >> 33)
+}
+
+async fn read_reply(conn: &mut BufStream<TcpStream>) -> io::Result<MetaReply> {
+    let mut line = Vec::new();
+    if conn.read_until(b'\n', &mut line).await? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "memcached closed the connection",
+        ));
+    }
+    let line = String::from_utf8_lossy(&line).trim_end().to_string();
+    let mut tokens = line.split_whitespace();
+    match tokens.next() {
+        Some("VA") => {
+            let size: usize = tokens
+                .next()
+                .and_then(|size| size.parse().ok())
+                .ok_or_else(|| to_io_error(format!("bad memcached reply {:?}", line)))?;
+            let mut data = vec![0; size + 2];
+            conn.read_exact(&mut data).await?;
+            data.truncate(size);
+            Ok(MetaReply::Value(data))
+        }
+        Some(code @ ("HD" | "EN" | "NF" | "NS" | "EX" | "MN")) => {
+            Ok(MetaReply::Status(code.to_string()))
+        }
+        Some(code) if code == "ERROR" || code.ends_with("_ERROR") => Ok(MetaReply::Error(line)),
+        _ => Err(to_io_error(format!("bad memcached reply {:?}", line))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{CacheLifetime, ResponseMeta};
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use tokio::io::AsyncBufRead;
+    use tokio::net::TcpListener;
+
+    /// Stand-in for memcached speaking the meta commands the layer uses
+    struct FakeMemcached {
+        items: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+        addr: SocketAddr,
+    }
+
+    impl FakeMemcached {
+        async fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let items = Arc::new(Mutex::new(HashMap::new()));
+            let shared = Arc::clone(&items);
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let items = Arc::clone(&shared);
+                    tokio::spawn(async move {
+                        let _ = serve(BufStream::new(stream), items).await;
+                    });
+                }
+            });
+            Self { items, addr }
+        }
+
+        fn len(&self) -> usize {
+            self.items.lock().len()
+        }
+    }
+
+    async fn serve(
+        mut conn: BufStream<TcpStream>,
+        items: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    ) -> io::Result<()> {
+        loop {
+            let Some(line) = next_line(&mut conn).await? else {
+                return Ok(());
+            };
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let flag = |name: char| {
+                tokens[2..]
+                    .iter()
+                    .find_map(|token| token.strip_prefix(name))
+                    .map(str::to_string)
+            };
+            let reply = match tokens[0] {
+                "mn" => b"MN\r\n".to_vec(),
+                "mg" => match items.lock().get(tokens[1]) {
+                    Some(value) => value_reply(value),
+                    None => b"EN\r\n".to_vec(),
+                },
+                "ms" => {
+                    let size: usize = tokens[2].parse().unwrap();
+                    let mut data = vec![0; size + 2];
+                    conn.read_exact(&mut data).await?;
+                    data.truncate(size);
+                    items.lock().insert(tokens[1].to_string(), data);
+                    b"HD\r\n".to_vec()
+                }
+                "md" => match items.lock().remove(tokens[1]) {
+                    Some(_) => b"HD\r\n".to_vec(),
+                    None => b"NF\r\n".to_vec(),
+                },
+                "ma" => {
+                    let mut items = items.lock();
+                    let value = match items.get(tokens[1]) {
+                        Some(value) => {
+                            let current: u64 = String::from_utf8_lossy(value).parse().unwrap();
+                            let delta: u64 = flag('D').map_or(1, |delta| delta.parse().unwrap());
+                            Some(current + delta)
+                        }
+                        None => flag('N')
+                            .and(flag('J'))
+                            .map(|initial| initial.parse().unwrap()),
+                    };
+                    match value {
+                        Some(value) => {
+                            let value = value.to_string().into_bytes();
+                            items.insert(tokens[1].to_string(), value.clone());
+                            value_reply(&value)
+                        }
+                        None => b"NF\r\n".to_vec(),
+                    }
+                }
+                _ => b"ERROR\r\n".to_vec(),
+            };
+            conn.write_all(&reply).await?;
+            conn.flush().await?;
+        }
+    }
+
+    async fn next_line(conn: &mut (impl AsyncBufRead + Unpin)) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if conn.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(line.trim_end().to_string()))
+    }
+
+    fn value_reply(value: &[u8]) -> Vec<u8> {
+        let mut reply = format!("VA {}\r\n", value.len()).into_bytes();
+        reply.extend_from_slice(value);
+        reply.extend_from_slice(b"\r\n");
+        reply
+    }
+
+    fn entry(data: &[u8], tags: &[&str]) -> CacheEntry {
+        CacheEntry::new(
+            data.to_vec(),
+            "text/html".to_string(),
+            ResponseMeta::default(),
+            tags.iter().map(|tag| tag.to_string()).collect(),
+            CacheLifetime::from_ttl(Duration::from_secs(60)),
+        )
+    }
+
+    #[test]
+    fn test_namespaces_follow_key_segments() {
+        let namespaces = namespaces("page:example.com:/blog/post:q:a=1", &["post-1".to_string()]);
+        assert_eq!(
+            namespaces,
+            vec![
+                "a",
+                "p:page",
+                "p:page:example.com",
+                "p:page:example.com:/blog",
+                "p:page:example.com:/blog/post",
+                "p:page:example.com:/blog/post:q",
+                "p:page:example.com:/blog/post:q:a=1",
+                "t:post-1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_prefix_purges_use_an_enclosing_namespace() {
+        let purge = |prefix| prefix_purge_namespace(prefix);
+        assert_eq!(
+            purge("page:example.com:/blog/").as_deref(),
+            Some("p:page:example.com:/blog")
+        );
+        assert_eq!(
+            purge("page:example.com:/blog").as_deref(),
+            Some("p:page:example.com")
+        );
+        assert_eq!(
+            purge("page:example.com:/bl").as_deref(),
+            Some("p:page:example.com")
+        );
+        assert_eq!(purge("page:").as_deref(), Some("p:page"));
+        assert_eq!(purge("pa"), None);
+        assert_eq!(purge(""), None);
+
+        // Every key starting with the prefix belongs to the namespace
+        for (prefix, key) in [
+            ("page:example.com:/bl", "page:example.com:/blog/post"),
+            ("page:example.com:/blog", "page:example.com:/blogger"),
+            (
+                "page:example.com:/blog/",
+                "page:example.com:/blog/post:q:a=1",
+            ),
+        ] {
+            assert!(key.starts_with(prefix));
+            assert!(namespaces(key, &[]).contains(&purge(prefix).unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_ring_moves_few_keys_when_a_server_joins() {
+        let three: Vec<String> = (1..=3).map(|n| format!("10.0.0.{}:11211", n)).collect();
+        let mut four = three.clone();
+        four.push("10.0.0.4:11211".to_string());
+        let (before, after) = (HashRing::new(&three), HashRing::new(&four));
+
+        let keys: Vec<String> = (0..2000)
+            .map(|n| format!("page:example.com:/{}", n))
+            .collect();
+        let moved = keys
+            .iter()
+            .filter(|key| before.server_for(key) != after.server_for(key))
+            .count();
+        // About a quarter of the keys move, all of them to the new server
+        assert!(moved > 300 && moved < 700, "moved {}", moved);
+        assert!(keys
+            .iter()
+            .filter(|key| before.server_for(key) != after.server_for(key))
+            .all(|key| after.server_for(key) == 3));
+    }
+
+    #[tokio::test]
+    async fn test_memcached_layer_roundtrip_and_purges() {
+        let first = FakeMemcached::start().await;
+        let second = FakeMemcached::start().await;
+        let servers = vec![first.addr.to_string(), second.addr.to_string()];
+        let layer = MemcachedCacheLayer::new(&servers, "test", Duration::from_secs(2)).unwrap();
+        layer.probe().await.unwrap();
+
+        let big = vec![b'x'; 8192];
+        for n in 0..20 {
+            layer
+                .set(&format!("page:a.com:/{}", n), &entry(&big, &["a"]))
+                .await
+                .unwrap();
+        }
+        layer
+            .set("page:a.com:/blog/one", &entry(b"one", &["post-1"]))
+            .await
+            .unwrap();
+        layer
+            .set("page:a.com:/blog/two:q:p=2", &entry(b"two", &["post-2"]))
+            .await
+            .unwrap();
+        layer
+            .set("page:b.com:/", &entry(b"b", &["post-1"]))
+            .await
+            .unwrap();
+        assert!(first.len() > 0 && second.len() > 0);
+
+        let hit = layer.get("page:a.com:/3").await.unwrap().unwrap();
+        assert_eq!(hit.data, big);
+        assert_eq!(hit.tags, vec!["a".to_string()]);
+
+        assert_eq!(layer.purge_by_tag("post-1").await.unwrap(), 0);
+        assert!(layer.get("page:a.com:/blog/one").await.unwrap().is_none());
+        assert!(layer.get("page:b.com:/").await.unwrap().is_none());
+        assert!(layer
+            .get("page:a.com:/blog/two:q:p=2")
+            .await
+            .unwrap()
+            .is_some());
+
+        layer.purge_by_prefix("page:a.com:/blog/").await.unwrap();
+        assert!(layer
+            .get("page:a.com:/blog/two:q:p=2")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(layer.get("page:a.com:/3").await.unwrap().is_some());
+
+        // Stored after the purge: a new generation
+        layer
+            .set("page:a.com:/blog/one", &entry(b"again", &["post-1"]))
+            .await
+            .unwrap();
+        assert_eq!(
+            layer
+                .get("page:a.com:/blog/one")
+                .await
+                .unwrap()
+                .unwrap()
+                .data,
+            b"again".to_vec()
+        );
+
+        assert!(layer.remove("page:a.com:/blog/one").await.unwrap());
+        assert!(!layer.remove("page:a.com:/blog/one").await.unwrap());
+
+        // Mid-segment prefixes widen to the host rather than miss `/blogger`
+        layer
+            .set("page:a.com:/blogger", &entry(b"blogger", &[]))
+            .await
+            .unwrap();
+        layer.purge_by_prefix("page:a.com:/blo").await.unwrap();
+        assert!(layer.get("page:a.com:/blogger").await.unwrap().is_none());
+        assert!(layer.get("page:a.com:/3").await.unwrap().is_none());
+
+        layer.set("page:a.com:/3", &entry(b"3", &[])).await.unwrap();
+        layer.purge_by_prefix("page:a.com:").await.unwrap();
+        assert!(layer.get("page:a.com:/3").await.unwrap().is_none());
+
+        layer.set("page:c.com:/", &entry(b"c", &[])).await.unwrap();
+        layer.purge_all().await.unwrap();
+        assert!(layer.get("page:c.com:/").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_lost_generation_invalidates_entries() {
+        let server = FakeMemcached::start().await;
+        let layer =
+            MemcachedCacheLayer::new(&[server.addr.to_string()], "test", Duration::from_secs(2))
+                .unwrap();
+        layer
+            .set("page:a.com:/", &entry(b"a", &["t"]))
+            .await
+            .unwrap();
+        assert!(layer.get("page:a.com:/").await.unwrap().is_some());
+
+        // An evicted counter restarts at a new value
+        let tag_key = layer.generation_key(&tag_namespace("t"));
+        assert!(server.items.lock().remove(&tag_key).is_some());
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        assert!(layer.get("page:a.com:/").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_server_fails_fast() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let layer =
+            MemcachedCacheLayer::new(&[addr.to_string()], "test", Duration::from_millis(500))
+                .unwrap();
+        assert!(layer.get("page:a.com:/").await.is_err());
+        assert!(layer.probe().await.is_err());
+    }
+}
@@ -2,106 +2,234 @@
 //!
 //! Multi-layer caching system for VeloServe.
 
-use crate::config::{CacheConfig, CacheStorage};
+use crate::config::{CacheConfig, CacheStorage, ContentEncoding};
+use async_trait::async_trait;
 use dashmap::DashMap;
-use flate2::read::GzDecoder;
-use flate2::write::GzEncoder;
-use flate2::Compression;
 use lru::LruCache;
 use parking_lot::Mutex;
-use redis::{Client, Commands, Connection};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::fs;
-use std::io::{Read, Write};
 use std::num::NonZeroUsize;
-use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
 use tracing::{debug, info, warn};
 
+mod breaker;
+mod disk_layer;
+mod memcached_layer;
+mod private;
+mod redis_layer;
+
+pub use private::{PrivateCache, PrivateSession};
+
+use breaker::{BreakerState, CircuitBreaker};
+
+use disk_layer::DiskCacheLayer;
+use memcached_layer::MemcachedCacheLayer;
+use redis_layer::RedisCacheLayer;
+
 #[derive(Clone)]
 struct CacheEntry {
     data: Vec<u8>,
     content_type: String,
+    status: u16,
+    /// Response headers replayed on hits
+    headers: Vec<(String, String)>,
+    /// Strong validator derived from the content (not persisted)
+    etag: String,
+    /// Compressed copies of `data`, added on first request (L1 only)
+    variants: Vec<(ContentEncoding, Vec<u8>)>,
     tags: Vec<String>,
     created_at_epoch_secs: u64,
+    /// End of the stale-while-revalidate window
     ttl: Duration,
+    /// End of freshness
     stale_after: Duration,
+    /// How long after `stale_after` the entry may stand in for origin errors
+    stale_if_error: Duration,
 }
 
 impl CacheEntry {
     fn new(
         data: Vec<u8>,
         content_type: String,
+        meta: ResponseMeta,
         tags: Vec<String>,
-        ttl: Duration,
-        stale_after: Duration,
+        lifetime: CacheLifetime,
     ) -> Self {
         Self {
+            etag: content_etag(&data, &content_type),
+            variants: Vec::new(),
             data,
             content_type,
+            status: meta.status,
+            headers: meta.headers,
             tags,
             created_at_epoch_secs: now_epoch_secs(),
-            ttl,
-            stale_after,
+            ttl: lifetime.ttl,
+            stale_after: lifetime.stale_after,
+            stale_if_error: lifetime.stale_if_error,
         }
     }
 
     fn from_persisted(persisted: PersistedEntry) -> Self {
         Self {
+            etag: content_etag(&persisted.data, &persisted.content_type),
+            variants: Vec::new(),
             data: persisted.data,
             content_type: persisted.content_type,
+            status: persisted.status,
+            headers: persisted.headers,
             tags: persisted.tags,
             created_at_epoch_secs: persisted.created_at_epoch_secs,
             ttl: Duration::from_secs(persisted.ttl_seconds),
             stale_after: Duration::from_secs(persisted.stale_after_seconds),
+            stale_if_error: Duration::from_secs(persisted.stale_if_error_seconds),
         }
     }
 
     fn to_persisted(&self) -> PersistedEntry {
         PersistedEntry {
+            version: DISK_ENTRY_VERSION,
             key: String::new(),
             data: self.data.clone(),
             content_type: self.content_type.clone(),
+            status: self.status,
+            headers: self.headers.clone(),
             tags: self.tags.clone(),
             created_at_epoch_secs: self.created_at_epoch_secs,
             ttl_seconds: self.ttl.as_secs(),
             stale_after_seconds: self.stale_after.as_secs(),
+            stale_if_error_seconds: self.stale_if_error.as_secs(),
         }
     }
 
+    fn to_page(&self, encoding: Option<ContentEncoding>) -> CachedPage {
+        let variant = encoding.and_then(|encoding| {
+            self.variants
+                .iter()
+                .find(|(current, _)| *current == encoding)
+                .cloned()
+        });
+        CachedPage {
+            data: self.data.clone(),
+            content_type: self.content_type.clone(),
+            status: self.status,
+            headers: self.headers.clone(),
+            etag: self.etag.clone(),
+            created_at: UNIX_EPOCH + Duration::from_secs(self.created_at_epoch_secs),
+            variant,
+        }
+    }
+
+    /// Memory held by the entry, identity body plus every variant
+    fn size(&self) -> u64 {
+        let variants: usize = self.variants.iter().map(|(_, data)| data.len()).sum();
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|(name, value)| name.len() + value.len())
+            .sum();
+        (self.data.len() + variants + headers) as u64
+    }
+
     fn age_seconds(&self) -> u64 {
         now_epoch_secs().saturating_sub(self.created_at_epoch_secs)
     }
 
-    fn is_expired(&self) -> bool {
-        self.age_seconds() > self.ttl.as_secs()
+    /// How long the entry is worth keeping at all
+    fn retention(&self) -> Duration {
+        self.ttl.max(self.stale_after + self.stale_if_error)
     }
 
-    fn is_stale(&self) -> bool {
-        self.age_seconds() > self.stale_after.as_secs()
+    /// `None` once the entry is past every window
+    fn freshness(&self) -> Option<Freshness> {
+        let age = self.age_seconds();
+        if age <= self.stale_after.as_secs() {
+            Some(Freshness::Fresh)
+        } else if age <= self.ttl.as_secs() {
+            Some(Freshness::Stale)
+        } else if age <= self.retention().as_secs() {
+            Some(Freshness::StaleIfError)
+        } else {
+            None
+        }
     }
 }
 
+/// How a cache hit may be used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Freshness {
+    /// Serve as a HIT
+    Fresh,
+    /// Serve as STALE while one background request refreshes it
+    Stale,
+    /// Serve only when the origin fails (stale-if-error)
+    StaleIfError,
+}
+
+/// A cache hit with the validators needed to answer revalidations
+#[derive(Debug, Clone)]
+pub struct CachedPage {
+    pub data: Vec<u8>,
+    pub content_type: String,
+    pub status: u16,
+    /// Stored response headers, without Content-Type and validators
+    pub headers: Vec<(String, String)>,
+    /// Strong entity tag (without quotes)
+    pub etag: String,
+    /// When the entry was stored, used as Last-Modified
+    pub created_at: SystemTime,
+    /// Stored compressed copy for the requested encoding
+    pub variant: Option<(ContentEncoding, Vec<u8>)>,
+}
+
+/// Status and headers stored alongside a cached body
+#[derive(Debug, Clone)]
+pub struct ResponseMeta {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Default for ResponseMeta {
+    fn default() -> Self {
+        Self {
+            status: 200,
+            headers: Vec::new(),
+        }
+    }
+}
+
+/// Entry lifetime: fresh until `stale_after`, served stale while being
+/// revalidated until `ttl`, and kept for origin errors `stale_if_error`
+/// past `stale_after`
 #[derive(Debug, Clone, Copy)]
 pub struct CacheLifetime {
     pub ttl: Duration,
     pub stale_after: Duration,
+    pub stale_if_error: Duration,
 }
 
 impl CacheLifetime {
     pub fn new(ttl: Duration, stale_after: Duration) -> Self {
         let stale_after = stale_after.min(ttl);
-        Self { ttl, stale_after }
-    }
-
-    pub fn from_ttl(ttl: Duration) -> Self {
         Self {
             ttl,
-            stale_after: ttl,
+            stale_after,
+            stale_if_error: Duration::ZERO,
         }
     }
+
+    pub fn from_ttl(ttl: Duration) -> Self {
+        Self::new(ttl, ttl)
+    }
+
+    pub fn with_stale_if_error(mut self, stale_if_error: Duration) -> Self {
+        self.stale_if_error = stale_if_error;
+        self
+    }
 }
 
 #[derive(Default)]
@@ -122,380 +250,200 @@ struct CacheStats {
     l1: LayerStats,
     l2: LayerStats,
     size_bytes: AtomicU64,
+    revalidations: AtomicU64,
+    served_on_error: AtomicU64,
+    coalescing: CoalescingStats,
 }
 
-const REDIS_ENTRY_VERSION: u8 = 1;
-const REDIS_COMPRESSION_THRESHOLD_BYTES: usize = 1024;
-const REDIS_RETRY_ATTEMPTS: u32 = 2;
-const REDIS_TAG_INDEX_TTL_GRACE_SECS: u64 = 300;
-
-#[derive(Serialize, Deserialize)]
-struct RedisPersistedEntry {
-    version: u8,
-    content_type: String,
-    tags: Vec<String>,
-    created_at_epoch_secs: u64,
-    ttl_seconds: u64,
-    stale_after_seconds: u64,
-    compressed: bool,
-    data: Vec<u8>,
+#[derive(Default)]
+struct CoalescingStats {
+    leaders: AtomicU64,
+    followers: AtomicU64,
+    hits: AtomicU64,
+    fallbacks: AtomicU64,
+    timeouts: AtomicU64,
 }
 
-trait PersistentCacheLayer: Send + Sync {
-    fn get(&self, key: &str) -> Option<CacheEntry>;
-    fn set(&self, key: &str, entry: &CacheEntry) -> std::io::Result<()>;
-    fn remove(&self, key: &str) -> std::io::Result<()>;
-    fn purge_by_tag(&self, tag: &str) -> std::io::Result<usize>;
-    fn purge_by_prefix(&self, prefix: &str) -> std::io::Result<usize>;
-    fn purge_all(&self) -> std::io::Result<usize>;
+/// Outcome of joining the in-flight render of a cache key
+pub enum Inflight {
+    /// Render the page; followers wake when the guard is dropped
+    Leader(InflightGuard),
+    /// Another request renders the page; wait with `wait_inflight`
+    Follower(watch::Receiver<()>),
 }
 
-#[derive(Serialize, Deserialize)]
-struct PersistedEntry {
-    #[serde(default)]
+/// Held by the request rendering a key; dropping it releases the followers
+pub struct InflightGuard {
     key: String,
-    data: Vec<u8>,
-    content_type: String,
-    tags: Vec<String>,
-    created_at_epoch_secs: u64,
-    ttl_seconds: u64,
-    stale_after_seconds: u64,
+    inflight: Arc<DashMap<String, watch::Sender<()>>>,
 }
 
-struct DiskCacheLayer {
-    root: PathBuf,
-    io_lock: Mutex<()>,
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        // Dropping the sender closes every follower's receiver
+        self.inflight.remove(&self.key);
+    }
 }
 
-impl DiskCacheLayer {
-    fn new(path: impl AsRef<Path>) -> std::io::Result<Self> {
-        let root = path.as_ref().to_path_buf();
-        fs::create_dir_all(&root)?;
-        Ok(Self {
-            root,
-            io_lock: Mutex::new(()),
-        })
-    }
+/// A revalidation older than this is assumed lost and may be retried
+const REVALIDATION_TIMEOUT: Duration = Duration::from_secs(30);
 
-    fn key_path(&self, key: &str) -> PathBuf {
-        self.root
-            .join(format!("{}.bin", filesystem_safe_key(key.as_bytes())))
-    }
+const DISK_ENTRY_VERSION: u8 = 1;
 
-    fn entry_paths(&self) -> std::io::Result<Vec<PathBuf>> {
-        let mut files = Vec::new();
-        for entry in fs::read_dir(&self.root)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_file() {
-                files.push(path);
-            }
-        }
-        Ok(files)
-    }
+/// L2 storage behind the in-process cache
+#[async_trait]
+trait PersistentCacheLayer: Send + Sync {
+    async fn get(&self, key: &str) -> std::io::Result<Option<CacheEntry>>;
+    async fn set(&self, key: &str, entry: &CacheEntry) -> std::io::Result<()>;
+    /// Returns whether the key was stored
+    async fn remove(&self, key: &str) -> std::io::Result<bool>;
+    async fn purge_by_tag(&self, tag: &str) -> std::io::Result<usize>;
+    async fn purge_by_prefix(&self, prefix: &str) -> std::io::Result<usize>;
+    async fn purge_all(&self) -> std::io::Result<usize>;
 
-    fn read_entry(&self, path: &Path) -> Option<PersistedEntry> {
-        let bytes = fs::read(path).ok()?;
-        bincode::deserialize::<PersistedEntry>(&bytes).ok()
+    /// Health check while the layer's circuit breaker is open
+    async fn probe(&self) -> std::io::Result<()> {
+        self.get(L2_PROBE_KEY).await.map(|_| ())
     }
 
-    fn write_entry(&self, path: &Path, entry: &PersistedEntry) -> std::io::Result<()> {
-        let bytes = bincode::serialize(entry)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
-        fs::write(path, bytes)
+    /// Size figures for the stats, where the layer tracks them
+    fn usage(&self) -> Option<serde_json::Value> {
+        None
     }
 }
 
-impl PersistentCacheLayer for DiskCacheLayer {
-    fn get(&self, key: &str) -> Option<CacheEntry> {
-        let _guard = self.io_lock.lock();
-        let path = self.key_path(key);
-        let persisted = self.read_entry(&path)?;
-        Some(CacheEntry::from_persisted(persisted))
-    }
-
-    fn set(&self, key: &str, entry: &CacheEntry) -> std::io::Result<()> {
-        let _guard = self.io_lock.lock();
-        let path = self.key_path(key);
-        let mut persisted = entry.to_persisted();
-        persisted.key = key.to_string();
-        self.write_entry(&path, &persisted)
-    }
+const L2_PROBE_KEY: &str = "veloserve:probe";
 
-    fn remove(&self, key: &str) -> std::io::Result<()> {
-        let _guard = self.io_lock.lock();
-        let path = self.key_path(key);
-        if path.exists() {
-            fs::remove_file(path)?;
-        }
-        Ok(())
-    }
-
-    fn purge_by_tag(&self, tag: &str) -> std::io::Result<usize> {
-        let _guard = self.io_lock.lock();
-        let mut removed = 0;
-        for path in self.entry_paths()? {
-            if let Some(entry) = self.read_entry(&path) {
-                if entry.tags.iter().any(|current| current == tag) {
-                    fs::remove_file(path)?;
-                    removed += 1;
-                }
-            }
-        }
-        Ok(removed)
-    }
+/// Purges skipped while the L2 breaker was open (or that failed), kept at
+/// most this many before collapsing into a full purge
+const L2_PENDING_PURGES_MAX: usize = 1024;
 
-    fn purge_by_prefix(&self, prefix: &str) -> std::io::Result<usize> {
-        let _guard = self.io_lock.lock();
-        let mut removed = 0;
-        for path in self.entry_paths()? {
-            if let Some(entry) = self.read_entry(&path) {
-                if entry.key.starts_with(prefix) {
-                    fs::remove_file(path)?;
-                    removed += 1;
-                }
-            }
-        }
-        Ok(removed)
-    }
+/// L2 purge to replay once the layer is back
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PendingPurge {
+    Key(String),
+    Tag(String),
+    Prefix(String),
+    All,
+}
 
-    fn purge_all(&self) -> std::io::Result<usize> {
-        let _guard = self.io_lock.lock();
-        let mut removed = 0;
-        for path in self.entry_paths()? {
-            fs::remove_file(path)?;
-            removed += 1;
+impl PendingPurge {
+    async fn run(&self, storage: &dyn PersistentCacheLayer) -> std::io::Result<usize> {
+        match self {
+            Self::Key(key) => storage.remove(key).await.map(usize::from),
+            Self::Tag(tag) => storage.purge_by_tag(tag).await,
+            Self::Prefix(prefix) => storage.purge_by_prefix(prefix).await,
+            Self::All => storage.purge_all().await,
         }
-        Ok(removed)
     }
 }
 
-struct RedisCacheLayer {
-    client: Client,
-    pool: Mutex<Vec<Connection>>,
-    max_pool_size: usize,
-    namespace: String,
+/// L2 storage with the circuit breaker guarding it
+struct L2Layer {
+    storage: Box<dyn PersistentCacheLayer>,
+    breaker: CircuitBreaker,
+    /// Wait between probes while the breaker is open
+    retry_interval: Duration,
+    pending: Mutex<Vec<PendingPurge>>,
+    /// Whether a background probe task is running
+    probing: AtomicBool,
 }
 
-impl RedisCacheLayer {
-    fn new(redis_url: &str) -> std::io::Result<Self> {
-        let client = Client::open(redis_url).map_err(to_io_error)?;
-
-        Ok(Self {
-            client,
-            pool: Mutex::new(Vec::new()),
-            max_pool_size: 8,
-            namespace: "veloserve:v1".to_string(),
-        })
-    }
-
-    fn entry_key(&self, key: &str) -> String {
-        format!("{}:entry:{}", self.namespace, key)
-    }
-
-    fn tag_key(&self, tag: &str) -> String {
-        format!("{}:tag:{}", self.namespace, normalize_cache_key(tag))
-    }
-
-    fn key_index_key(&self) -> String {
-        format!("{}:keys", self.namespace)
-    }
-
-    fn acquire_connection(&self) -> std::io::Result<Connection> {
-        if let Some(conn) = self.pool.lock().pop() {
-            return Ok(conn);
-        }
-        self.client
-            .get_connection()
-            .map_err(|err| to_io_error(format!("redis connection failed: {}", err)))
-    }
-
-    fn release_connection(&self, conn: Connection) {
-        let mut pool = self.pool.lock();
-        if pool.len() < self.max_pool_size {
-            pool.push(conn);
+impl L2Layer {
+    fn new(storage: Box<dyn PersistentCacheLayer>, config: &CacheConfig) -> Self {
+        Self {
+            storage,
+            breaker: CircuitBreaker::new(
+                "cache L2",
+                config.l2_breaker_failures,
+                config.l2_breaker_successes,
+            ),
+            retry_interval: Duration::from_millis(config.l2_breaker_retry_ms),
+            pending: Mutex::new(Vec::new()),
+            probing: AtomicBool::new(false),
         }
     }
 
-    fn with_conn<T, F>(&self, mut op: F) -> std::io::Result<T>
-    where
-        F: FnMut(&mut Connection) -> redis::RedisResult<T>,
-    {
-        for attempt in 0..=REDIS_RETRY_ATTEMPTS {
-            let mut conn = self.acquire_connection()?;
-            match op(&mut conn) {
-                Ok(value) => {
-                    self.release_connection(conn);
-                    return Ok(value);
-                }
-                Err(err) => {
-                    drop(conn);
-                    if attempt == REDIS_RETRY_ATTEMPTS {
-                        return Err(to_io_error(err));
-                    }
-                }
-            }
+    /// Remember a purge that could not reach the layer
+    fn defer(&self, purge: PendingPurge) {
+        let mut pending = self.pending.lock();
+        if pending.contains(&purge) || pending.contains(&PendingPurge::All) {
+            return;
         }
-
-        Err(std::io::Error::new(
-            std::io::ErrorKind::TimedOut,
-            "redis retry budget exhausted",
-        ))
-    }
-
-    fn serialize_entry(entry: &CacheEntry) -> std::io::Result<Vec<u8>> {
-        let (compressed, data) = if entry.data.len() >= REDIS_COMPRESSION_THRESHOLD_BYTES {
-            let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
-            encoder.write_all(&entry.data)?;
-            let compressed = encoder.finish()?;
-            if compressed.len() < entry.data.len() {
-                (true, compressed)
-            } else {
-                (false, entry.data.clone())
-            }
+        if purge == PendingPurge::All || pending.len() >= L2_PENDING_PURGES_MAX {
+            pending.clear();
+            pending.push(PendingPurge::All);
         } else {
-            (false, entry.data.clone())
-        };
-
-        let persisted = RedisPersistedEntry {
-            version: REDIS_ENTRY_VERSION,
-            content_type: entry.content_type.clone(),
-            tags: entry.tags.clone(),
-            created_at_epoch_secs: entry.created_at_epoch_secs,
-            ttl_seconds: entry.ttl.as_secs(),
-            stale_after_seconds: entry.stale_after.as_secs(),
-            compressed,
-            data,
-        };
-
-        bincode::serialize(&persisted)
-            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()))
-    }
-
-    fn deserialize_entry(raw: &[u8]) -> Option<CacheEntry> {
-        let persisted: RedisPersistedEntry = bincode::deserialize(raw).ok()?;
-        if persisted.version != REDIS_ENTRY_VERSION {
-            return None;
+            pending.push(purge);
         }
-
-        let data = if persisted.compressed {
-            let mut decoder = GzDecoder::new(persisted.data.as_slice());
-            let mut out = Vec::new();
-            decoder.read_to_end(&mut out).ok()?;
-            out
-        } else {
-            persisted.data
-        };
-
-        Some(CacheEntry {
-            data,
-            content_type: persisted.content_type,
-            tags: persisted.tags,
-            created_at_epoch_secs: persisted.created_at_epoch_secs,
-            ttl: Duration::from_secs(persisted.ttl_seconds),
-            stale_after: Duration::from_secs(persisted.stale_after_seconds),
-        })
     }
 
-    fn remove_internal(&self, conn: &mut Connection, key: &str) -> redis::RedisResult<bool> {
-        let entry_key = self.entry_key(key);
-        let key_index_key = self.key_index_key();
-
-        let raw: Option<Vec<u8>> = conn.get(&entry_key)?;
-        let mut removed = false;
-
-        if let Some(raw) = raw {
-            if let Some(entry) = Self::deserialize_entry(&raw) {
-                for tag in entry.tags {
-                    let _: usize = conn.srem(self.tag_key(&tag), key)?;
-                }
+    /// Replay deferred purges; on failure the rest stay queued
+    async fn replay_pending(&self) -> std::io::Result<()> {
+        loop {
+            let Some(purge) = self.pending.lock().first().cloned() else {
+                return Ok(());
+            };
+            purge.run(self.storage.as_ref()).await?;
+            let mut pending = self.pending.lock();
+            if let Some(index) = pending.iter().position(|current| *current == purge) {
+                pending.remove(index);
             }
-            let deleted: usize = conn.del(&entry_key)?;
-            removed = deleted > 0;
         }
-
-        let _: usize = conn.srem(key_index_key, key)?;
-        Ok(removed)
-    }
-}
-
-impl PersistentCacheLayer for RedisCacheLayer {
-    fn get(&self, key: &str) -> Option<CacheEntry> {
-        let entry_key = self.entry_key(key);
-        let raw = self
-            .with_conn(|conn| conn.get::<_, Option<Vec<u8>>>(&entry_key))
-            .ok()?;
-        raw.and_then(|bytes| Self::deserialize_entry(&bytes))
-    }
-
-    fn set(&self, key: &str, entry: &CacheEntry) -> std::io::Result<()> {
-        let entry_key = self.entry_key(key);
-        let key_index_key = self.key_index_key();
-        let payload = Self::serialize_entry(entry)?;
-        let ttl_secs = entry.ttl.as_secs().max(1);
-        let tag_ttl = ttl_secs.saturating_add(REDIS_TAG_INDEX_TTL_GRACE_SECS);
-
-        self.with_conn(|conn| {
-            let _: () = conn.set_ex(&entry_key, payload.clone(), ttl_secs)?;
-            let _: usize = conn.sadd(&key_index_key, key)?;
-            for tag in &entry.tags {
-                let tag_key = self.tag_key(tag);
-                let _: usize = conn.sadd(&tag_key, key)?;
-                let _: bool = conn.expire(&tag_key, tag_ttl as i64)?;
-            }
-            Ok(())
-        })
     }
 
-    fn remove(&self, key: &str) -> std::io::Result<()> {
-        self.with_conn(|conn| self.remove_internal(conn, key).map(|_| ()))
+    /// Start the background probe unless one is running
+    fn spawn_probe(self: &Arc<Self>) {
+        if self.probing.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let layer = Arc::clone(self);
+        tokio::spawn(async move { layer.probe_until_recovered().await });
     }
 
-    fn purge_by_tag(&self, tag: &str) -> std::io::Result<usize> {
-        let tag_key = self.tag_key(tag);
-        self.with_conn(|conn| {
-            let keys: Vec<String> = conn.smembers(&tag_key)?;
-            let mut removed = 0usize;
-            for key in &keys {
-                if self.remove_internal(conn, key)? {
-                    removed += 1;
+    async fn probe_until_recovered(&self) {
+        loop {
+            tokio::time::sleep(self.retry_interval).await;
+            if self.breaker.state() == BreakerState::Open {
+                if let Err(err) = self.storage.probe().await {
+                    debug!("Cache L2 probe failed: {}", err);
+                    continue;
                 }
-            }
-            let _: usize = conn.del(&tag_key)?;
-            Ok(removed)
-        })
-    }
-
-    fn purge_by_prefix(&self, prefix: &str) -> std::io::Result<usize> {
-        let key_index_key = self.key_index_key();
-        self.with_conn(|conn| {
-            let keys: Vec<String> = conn.smembers(&key_index_key)?;
-            let mut removed = 0usize;
-            for key in keys {
-                if key.starts_with(prefix) && self.remove_internal(conn, &key)? {
-                    removed += 1;
+                // Purges missed while open go out before regular traffic
+                if let Err(err) = self.replay_pending().await {
+                    debug!("Cache L2 purge replay failed: {}", err);
+                    continue;
                 }
+                self.breaker.half_open();
             }
-            Ok(removed)
-        })
-    }
 
-    fn purge_all(&self) -> std::io::Result<usize> {
-        let key_index_key = self.key_index_key();
-        self.with_conn(|conn| {
-            let keys: Vec<String> = conn.smembers(&key_index_key)?;
-            let mut removed = 0usize;
-            for key in keys {
-                if self.remove_internal(conn, &key)? {
-                    removed += 1;
-                }
+            self.probing.store(false, Ordering::Release);
+            // Reopened while this task was finishing: keep probing
+            if self.breaker.state() != BreakerState::Open
+                || self.probing.swap(true, Ordering::AcqRel)
+            {
+                return;
             }
-            let _: usize = conn.del(&key_index_key)?;
-            Ok(removed)
-        })
+        }
     }
 }
 
+#[derive(Serialize, Deserialize)]
+struct PersistedEntry {
+    version: u8,
+    #[serde(default)]
+    key: String,
+    data: Vec<u8>,
+    content_type: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    tags: Vec<String>,
+    created_at_epoch_secs: u64,
+    ttl_seconds: u64,
+    stale_after_seconds: u64,
+    stale_if_error_seconds: u64,
+}
+
 /// Cache manager
 pub struct CacheManager {
     l1_cache: DashMap<String, CacheEntry>,
@@ -504,7 +452,13 @@ pub struct CacheManager {
     config: CacheConfig,
     stats: CacheStats,
     max_memory: u64,
-    l2_cache: Option<Box<dyn PersistentCacheLayer>>,
+    l2_cache: Option<Arc<L2Layer>>,
+    /// Keys with a background refresh in flight, and since when
+    revalidating: DashMap<String, Instant>,
+    /// Keys being rendered after a miss (request coalescing)
+    inflight: Arc<DashMap<String, watch::Sender<()>>>,
+    /// Per-session pages of logged-in visitors
+    private: PrivateCache,
 }
 
 impl CacheManager {
@@ -517,7 +471,8 @@ impl CacheManager {
             match config.storage {
                 CacheStorage::Redis => {
                     if let Some(redis_url) = config.redis_url.as_deref() {
-                        match RedisCacheLayer::new(redis_url) {
+                        let timeout = Duration::from_millis(config.redis_timeout_ms);
+                        match RedisCacheLayer::new(redis_url, &config.redis_key_prefix, timeout) {
                             Ok(layer) => Some(Box::new(layer) as Box<dyn PersistentCacheLayer>),
                             Err(err) => {
                                 warn!("Failed to initialize Redis cache layer: {}", err);
@@ -529,8 +484,24 @@ impl CacheManager {
                         None
                     }
                 }
+                CacheStorage::Memcached => {
+                    let timeout = Duration::from_millis(config.memcached_timeout_ms);
+                    match MemcachedCacheLayer::new(
+                        &config.memcached_servers,
+                        &config.memcached_key_prefix,
+                        timeout,
+                    ) {
+                        Ok(layer) => Some(Box::new(layer) as Box<dyn PersistentCacheLayer>),
+                        Err(err) => {
+                            warn!("Failed to initialize Memcached cache layer: {}", err);
+                            None
+                        }
+                    }
+                }
                 CacheStorage::Memory | CacheStorage::Disk => {
-                    match DiskCacheLayer::new(&config.disk_path) {
+                    let max_size = parse_size(&config.disk_max_size);
+                    match DiskCacheLayer::open(&config.disk_path, max_size, config.disk_compression)
+                    {
                         Ok(layer) => Some(Box::new(layer) as Box<dyn PersistentCacheLayer>),
                         Err(err) => {
                             warn!(
@@ -546,6 +517,8 @@ impl CacheManager {
             None
         };
 
+        let l2_cache = l2_cache.map(|storage| Arc::new(L2Layer::new(storage, config)));
+
         info!(
             "Initializing cache: l1_enabled={}, l2_enabled={}, storage={:?}, max_memory={}",
             config.l1_enabled,
@@ -562,9 +535,17 @@ impl CacheManager {
             stats: CacheStats::default(),
             max_memory,
             l2_cache,
+            revalidating: DashMap::new(),
+            inflight: Arc::new(DashMap::new()),
+            private: PrivateCache::new(parse_size(&config.private_memory_limit)),
         }
     }
 
+    /// Store for private (per-session) pages
+    pub fn private(&self) -> &PrivateCache {
+        &self.private
+    }
+
     /// Get an entry from cache
     pub async fn get(&self, key: &str) -> Option<Vec<u8>> {
         self.get_with_metadata(key).await.map(|(data, _)| data)
@@ -572,6 +553,34 @@ impl CacheManager {
 
     /// Get an entry and its content-type from cache
     pub async fn get_with_metadata(&self, key: &str) -> Option<(Vec<u8>, String)> {
+        self.get_page(key)
+            .await
+            .map(|page| (page.data, page.content_type))
+    }
+
+    /// Get an entry with its validators from cache
+    pub async fn get_page(&self, key: &str) -> Option<CachedPage> {
+        self.get_page_variant(key, None).await
+    }
+
+    /// Get a fresh entry, including its stored `encoding` variant if there is one
+    pub async fn get_page_variant(
+        &self,
+        key: &str,
+        encoding: Option<ContentEncoding>,
+    ) -> Option<CachedPage> {
+        match self.lookup_page(key, encoding).await {
+            Some((page, Freshness::Fresh)) => Some(page),
+            _ => None,
+        }
+    }
+
+    /// Get an entry that is fresh, stale or only usable on origin errors
+    pub async fn lookup_page(
+        &self,
+        key: &str,
+        encoding: Option<ContentEncoding>,
+    ) -> Option<(CachedPage, Freshness)> {
         if !self.config.enable {
             return None;
         }
@@ -580,62 +589,160 @@ impl CacheManager {
 
         if self.config.l1_enabled {
             if let Some(entry) = self.l1_cache.get(&key) {
-                if entry.is_expired() {
-                    drop(entry);
-                    self.remove_l1(&key).await;
-                    self.stats.l1.misses.fetch_add(1, Ordering::Relaxed);
-                } else if entry.is_stale() {
-                    drop(entry);
-                    self.remove_l1(&key).await;
-                    self.stats.l1.stale.fetch_add(1, Ordering::Relaxed);
-                    self.stats.l1.misses.fetch_add(1, Ordering::Relaxed);
-                } else {
-                    {
-                        let mut lru = self.l1_lru.lock();
-                        lru.get(&key);
+                match entry.freshness() {
+                    None => {
+                        drop(entry);
+                        self.remove_l1(&key).await;
+                        self.stats.l1.misses.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Some(freshness) => {
+                        {
+                            let mut lru = self.l1_lru.lock();
+                            lru.get(&key);
+                        }
+                        if freshness == Freshness::Fresh {
+                            self.stats.l1.hits.fetch_add(1, Ordering::Relaxed);
+                            debug!("L1 cache hit: {}", key);
+                        } else {
+                            self.stats.l1.stale.fetch_add(1, Ordering::Relaxed);
+                            debug!("L1 cache hit ({:?}): {}", freshness, key);
+                        }
+                        return Some((entry.to_page(encoding), freshness));
                     }
-                    self.stats.l1.hits.fetch_add(1, Ordering::Relaxed);
-                    debug!("L1 cache hit: {}", key);
-                    return Some((entry.data.clone(), entry.content_type.clone()));
                 }
             } else {
                 self.stats.l1.misses.fetch_add(1, Ordering::Relaxed);
             }
         }
 
-        if let Some(l2) = &self.l2_cache {
+        if let Some(l2) = self.l2() {
             let started = Instant::now();
-            if let Some(entry) = l2.get(&key) {
-                self.record_l2_op(started, true);
-                if entry.is_expired() {
-                    let _ = l2.remove(&key);
+            let result = l2.storage.get(&key).await;
+            self.record_l2_op(started, result.is_ok());
+            if let Err(err) = &result {
+                warn!("Failed to read L2 cache key {}: {}", key, err);
+            }
+            if let Ok(Some(entry)) = result {
+                let Some(freshness) = entry.freshness() else {
+                    let _ = l2.storage.remove(&key).await;
                     self.stats.l2.misses.fetch_add(1, Ordering::Relaxed);
                     return None;
-                }
+                };
 
-                if entry.is_stale() {
-                    let _ = l2.remove(&key);
+                if freshness == Freshness::Fresh {
+                    self.stats.l2.hits.fetch_add(1, Ordering::Relaxed);
+                    debug!("L2 cache hit: {}", key);
+                } else {
                     self.stats.l2.stale.fetch_add(1, Ordering::Relaxed);
-                    self.stats.l2.misses.fetch_add(1, Ordering::Relaxed);
-                    return None;
+                    debug!("L2 cache hit ({:?}): {}", freshness, key);
                 }
 
-                self.stats.l2.hits.fetch_add(1, Ordering::Relaxed);
-                debug!("L2 cache hit: {}", key);
-
+                let page = entry.to_page(None);
                 if self.config.l1_enabled {
-                    self.write_l1(&key, entry.clone()).await;
+                    self.write_l1(&key, entry).await;
                 }
 
-                return Some((entry.data, entry.content_type));
+                return Some((page, freshness));
             }
-            self.record_l2_op(started, true);
             self.stats.l2.misses.fetch_add(1, Ordering::Relaxed);
         }
 
         None
     }
 
+    /// Claim the background refresh of a stale entry
+    ///
+    /// Returns false while another refresh of `key` is in flight, so a
+    /// stale page triggers one origin request no matter how many clients
+    /// hit it.
+    pub fn try_begin_revalidation(&self, key: &str) -> bool {
+        let key = normalize_cache_key(key);
+        let now = Instant::now();
+        let mut claimed = false;
+        self.revalidating
+            .entry(key)
+            .and_modify(|since| {
+                if now.duration_since(*since) > REVALIDATION_TIMEOUT {
+                    *since = now;
+                    claimed = true;
+                }
+            })
+            .or_insert_with(|| {
+                claimed = true;
+                now
+            });
+        if claimed {
+            self.stats.revalidations.fetch_add(1, Ordering::Relaxed);
+        }
+        claimed
+    }
+
+    /// Release a refresh claimed with `try_begin_revalidation`
+    pub fn end_revalidation(&self, key: &str) {
+        self.revalidating.remove(&normalize_cache_key(key));
+    }
+
+    /// Become the leader rendering `key` after a miss, or follow the current one
+    pub fn join_inflight(&self, key: &str) -> Inflight {
+        let key = normalize_cache_key(key);
+        match self.inflight.entry(key.clone()) {
+            dashmap::mapref::entry::Entry::Occupied(entry) => {
+                self.stats
+                    .coalescing
+                    .followers
+                    .fetch_add(1, Ordering::Relaxed);
+                Inflight::Follower(entry.get().subscribe())
+            }
+            dashmap::mapref::entry::Entry::Vacant(entry) => {
+                entry.insert(watch::channel(()).0);
+                self.stats
+                    .coalescing
+                    .leaders
+                    .fetch_add(1, Ordering::Relaxed);
+                Inflight::Leader(InflightGuard {
+                    key,
+                    inflight: self.inflight.clone(),
+                })
+            }
+        }
+    }
+
+    /// Wait for the leader of a key; false when `timeout` passed first
+    pub async fn wait_inflight(
+        &self,
+        mut receiver: watch::Receiver<()>,
+        timeout: Duration,
+    ) -> bool {
+        // The leader never sends: `changed` returns once the sender is dropped
+        let finished =
+            tokio::time::timeout(timeout, async { while receiver.changed().await.is_ok() {} })
+                .await
+                .is_ok();
+        if !finished {
+            self.stats
+                .coalescing
+                .timeouts
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        finished
+    }
+
+    /// Count a follower served from the leader's result, or one that had to
+    /// render the page itself because the result was not cacheable
+    pub fn record_coalesced(&self, served_from_cache: bool) {
+        let counter = if served_from_cache {
+            &self.stats.coalescing.hits
+        } else {
+            &self.stats.coalescing.fallbacks
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a stale entry served in place of a failed origin response
+    pub fn record_served_on_error(&self) {
+        self.stats.served_on_error.fetch_add(1, Ordering::Relaxed);
+    }
+
     /// Store an entry in cache using default layer policy.
     pub async fn set(&self, key: &str, data: Vec<u8>, content_type: &str, tags: Vec<String>) {
         if !self.config.enable {
@@ -667,27 +774,42 @@ impl CacheManager {
         content_type: &str,
         tags: Vec<String>,
         lifetime: CacheLifetime,
+    ) {
+        self.set_response(
+            key,
+            data,
+            content_type,
+            ResponseMeta::default(),
+            tags,
+            lifetime,
+        )
+        .await;
+    }
+
+    /// Store a full response: body, status and headers.
+    pub async fn set_response(
+        &self,
+        key: &str,
+        data: Vec<u8>,
+        content_type: &str,
+        meta: ResponseMeta,
+        tags: Vec<String>,
+        lifetime: CacheLifetime,
     ) {
         if !self.config.enable {
             return;
         }
 
         let key = normalize_cache_key(key);
-        let entry = CacheEntry::new(
-            data,
-            content_type.to_string(),
-            tags.clone(),
-            lifetime.ttl,
-            lifetime.stale_after,
-        );
+        let entry = CacheEntry::new(data, content_type.to_string(), meta, tags.clone(), lifetime);
 
         if self.config.l1_enabled {
             self.write_l1(&key, entry.clone()).await;
         }
 
-        if let Some(l2) = &self.l2_cache {
+        if let Some(l2) = self.l2() {
             let started = Instant::now();
-            if let Err(err) = l2.set(&key, &entry) {
+            if let Err(err) = l2.storage.set(&key, &entry).await {
                 self.record_l2_op(started, false);
                 warn!("Failed to write L2 cache key {}: {}", key, err);
             } else {
@@ -698,14 +820,54 @@ impl CacheManager {
 
         self.index_tags(&key, &tags);
         debug!(
-            "Cache set: {} ({} bytes, ttl={:?}, stale_after={:?})",
+            "Cache set: {} ({} {} bytes, ttl={:?}, stale_after={:?})",
             key,
+            entry.status,
             entry.data.len(),
             entry.ttl,
             entry.stale_after
         );
     }
 
+    /// Keep a compressed copy of a cached page in L1
+    ///
+    /// Ignored when the entry was replaced since `etag` was read, or when
+    /// the variant is already stored.
+    pub async fn store_variant(
+        &self,
+        key: &str,
+        etag: &str,
+        encoding: ContentEncoding,
+        data: Vec<u8>,
+    ) {
+        if !self.config.enable || !self.config.l1_enabled {
+            return;
+        }
+
+        let key = normalize_cache_key(key);
+        let added = data.len() as u64;
+        {
+            let Some(mut entry) = self.l1_cache.get_mut(&key) else {
+                return;
+            };
+            if entry.etag != etag || entry.variants.iter().any(|(e, _)| *e == encoding) {
+                return;
+            }
+            entry.variants.push((encoding, data));
+        }
+
+        self.stats.size_bytes.fetch_add(added, Ordering::Relaxed);
+        if self.stats.size_bytes.load(Ordering::Relaxed) > self.max_memory {
+            self.evict_lru().await;
+        }
+        debug!(
+            "Cache variant stored: {} ({}, {} bytes)",
+            key,
+            encoding.as_str(),
+            added
+        );
+    }
+
     /// Remove an entry from all cache layers.
     pub async fn remove(&self, key: &str) {
         let _ = self.remove_with_count(key).await;
@@ -719,18 +881,21 @@ impl CacheManager {
             affected += 1;
         }
 
-        if let Some(l2) = &self.l2_cache {
+        if let Some(l2) = self.l2() {
             let started = Instant::now();
-            let existed_in_l2 = l2.get(&key).is_some();
-            if let Err(err) = l2.remove(&key) {
-                self.record_l2_op(started, false);
-                warn!("Failed to remove L2 cache key {}: {}", key, err);
-            } else if existed_in_l2 {
-                self.record_l2_op(started, true);
-                affected += 1;
-            } else {
-                self.record_l2_op(started, true);
+            match l2.storage.remove(&key).await {
+                Ok(existed) => {
+                    self.record_l2_op(started, true);
+                    affected += usize::from(existed);
+                }
+                Err(err) => {
+                    self.record_l2_op(started, false);
+                    warn!("Failed to remove L2 cache key {}: {}", key, err);
+                    l2.defer(PendingPurge::Key(key));
+                }
             }
+        } else {
+            self.defer_l2_purge(PendingPurge::Key(key));
         }
 
         affected
@@ -753,10 +918,11 @@ impl CacheManager {
                 }
             }
         }
+        affected += self.private.purge_tag(tag);
 
-        if let Some(l2) = &self.l2_cache {
+        if let Some(l2) = self.l2() {
             let started = Instant::now();
-            match l2.purge_by_tag(tag) {
+            match l2.storage.purge_by_tag(tag).await {
                 Ok(removed) => {
                     self.record_l2_op(started, true);
                     affected += removed;
@@ -764,8 +930,11 @@ impl CacheManager {
                 Err(err) => {
                     self.record_l2_op(started, false);
                     warn!("Failed to purge L2 tag {}: {}", tag, err);
+                    l2.defer(PendingPurge::Tag(tag.to_string()));
                 }
             }
+        } else {
+            self.defer_l2_purge(PendingPurge::Tag(tag.to_string()));
         }
 
         affected
@@ -792,10 +961,11 @@ impl CacheManager {
                 affected += 1;
             }
         }
+        affected += self.private.purge_prefix(&prefix);
 
-        if let Some(l2) = &self.l2_cache {
+        if let Some(l2) = self.l2() {
             let started = Instant::now();
-            match l2.purge_by_prefix(&prefix) {
+            match l2.storage.purge_by_prefix(&prefix).await {
                 Ok(removed) => {
                     self.record_l2_op(started, true);
                     affected += removed;
@@ -803,8 +973,11 @@ impl CacheManager {
                 Err(err) => {
                     self.record_l2_op(started, false);
                     warn!("Failed to purge L2 key prefix {}: {}", prefix, err);
+                    l2.defer(PendingPurge::Prefix(prefix));
                 }
             }
+        } else {
+            self.defer_l2_purge(PendingPurge::Prefix(prefix));
         }
 
         affected
@@ -816,6 +989,7 @@ impl CacheManager {
 
         self.l1_cache.clear();
         self.tag_index.clear();
+        self.private.clear();
 
         {
             let mut lru = self.l1_lru.lock();
@@ -825,9 +999,9 @@ impl CacheManager {
         self.stats.size_bytes.store(0, Ordering::Relaxed);
         self.stats.l1.evictions.fetch_add(1, Ordering::Relaxed);
 
-        if let Some(l2) = &self.l2_cache {
+        if let Some(l2) = self.l2() {
             let started = Instant::now();
-            match l2.purge_all() {
+            match l2.storage.purge_all().await {
                 Ok(removed) => {
                     self.record_l2_op(started, true);
                     if removed > 0 {
@@ -839,9 +1013,12 @@ impl CacheManager {
                 }
                 Err(err) => {
                     self.record_l2_op(started, false);
-                    warn!("Failed to purge all L2 entries: {}", err)
+                    warn!("Failed to purge all L2 entries: {}", err);
+                    l2.defer(PendingPurge::All);
                 }
             }
+        } else {
+            self.defer_l2_purge(PendingPurge::All);
         }
     }
 
@@ -867,6 +1044,12 @@ impl CacheManager {
             },
             "l2": {
                 "enabled": self.l2_cache.is_some(),
+                "breaker": self.l2_cache.as_ref().map(|l2| {
+                    let mut breaker = l2.breaker.stats();
+                    breaker["pending_purges"] = json!(l2.pending.lock().len());
+                    breaker
+                }),
+                "storage": self.l2_cache.as_ref().and_then(|l2| l2.storage.usage()),
                 "hits": l2_hits,
                 "misses": l2_misses,
                 "writes": self.stats.l2.writes.load(Ordering::Relaxed),
@@ -880,6 +1063,20 @@ impl CacheManager {
                     self.stats.l2.ops.load(Ordering::Relaxed)
                 )
             },
+            "stale": {
+                "revalidations": self.stats.revalidations.load(Ordering::Relaxed),
+                "revalidating": self.revalidating.len(),
+                "served_on_error": self.stats.served_on_error.load(Ordering::Relaxed)
+            },
+            "coalescing": {
+                "leaders": self.stats.coalescing.leaders.load(Ordering::Relaxed),
+                "followers": self.stats.coalescing.followers.load(Ordering::Relaxed),
+                "hits": self.stats.coalescing.hits.load(Ordering::Relaxed),
+                "fallbacks": self.stats.coalescing.fallbacks.load(Ordering::Relaxed),
+                "timeouts": self.stats.coalescing.timeouts.load(Ordering::Relaxed),
+                "in_flight": self.inflight.len()
+            },
+            "private": self.private.stats(),
             "hit_rate": hit_rate(l1_hits + l2_hits, l1_misses + l2_misses),
         })
     }
@@ -894,6 +1091,29 @@ impl CacheManager {
             self.stats.l2.errors.fetch_add(1, Ordering::Relaxed);
             self.stats.l2.fallbacks.fetch_add(1, Ordering::Relaxed);
         }
+        if let Some(l2) = &self.l2_cache {
+            if l2.breaker.record(ok) {
+                l2.spawn_probe();
+            }
+        }
+    }
+
+    /// L2 layer, unless its breaker is open (L1-only operation)
+    fn l2(&self) -> Option<&Arc<L2Layer>> {
+        let l2 = self.l2_cache.as_ref()?;
+        if l2.breaker.allow() {
+            Some(l2)
+        } else {
+            self.stats.l2.fallbacks.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+
+    /// Queue a purge the open breaker kept from reaching L2
+    fn defer_l2_purge(&self, purge: PendingPurge) {
+        if let Some(l2) = &self.l2_cache {
+            l2.defer(purge);
+        }
     }
 
     async fn remove_l1(&self, key: &str) -> bool {
@@ -902,7 +1122,7 @@ impl CacheManager {
             removed = true;
             self.stats
                 .size_bytes
-                .fetch_sub(entry.data.len() as u64, Ordering::Relaxed);
+                .fetch_sub(entry.size(), Ordering::Relaxed);
 
             for tag in &entry.tags {
                 if let Some(mut keys) = self.tag_index.get_mut(tag) {
@@ -920,11 +1140,11 @@ impl CacheManager {
     }
 
     async fn write_l1(&self, key: &str, entry: CacheEntry) {
-        let entry_size = entry.data.len() as u64;
+        let entry_size = entry.size();
         if let Some(previous) = self.l1_cache.get(key) {
             self.stats
                 .size_bytes
-                .fetch_sub(previous.data.len() as u64, Ordering::Relaxed);
+                .fetch_sub(previous.size(), Ordering::Relaxed);
         }
         if self.stats.size_bytes.load(Ordering::Relaxed) + entry_size > self.max_memory {
             self.evict_lru().await;
@@ -980,6 +1200,17 @@ impl CacheManager {
     }
 }
 
+/// Strong entity tag for cached content (without quotes)
+pub fn content_etag(data: &[u8], content_type: &str) -> String {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    content_type.hash(&mut hasher);
+    format!("p-{:016x}-{:x}", hasher.finish(), data.len())
+}
+
 /// Normalize cache key to a deterministic file-safe representation.
 pub fn normalize_cache_key(raw: &str) -> String {
     let raw = raw.trim();
@@ -996,20 +1227,11 @@ pub fn normalize_cache_key(raw: &str) -> String {
     key
 }
 
-fn filesystem_safe_key(bytes: &[u8]) -> String {
-    let mut out = String::with_capacity(bytes.len() * 2);
-    for byte in bytes {
-        let ch = *byte as char;
-        if ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | '.') {
-            out.push(ch);
-        } else {
-            out.push_str(&format!("{:02x}", byte));
-        }
-    }
-    out
-}
-
 /// Build deterministic cache key for page responses.
+///
+/// A query string is keyed as a `:q:` segment after the path, so purging
+/// the path prefix covers every query variant. The segment is an escaped
+/// form of the query (see `query_key`), so distinct queries never collide.
 pub fn build_page_cache_key(host: &str, path_and_query: &str) -> String {
     let normalized_host = host
         .trim()
@@ -1018,12 +1240,118 @@ pub fn build_page_cache_key(host: &str, path_and_query: &str) -> String {
         .unwrap_or("localhost")
         .to_ascii_lowercase();
 
-    let path = percent_encoding::percent_decode_str(path_and_query)
+    let (path, query) = match path_and_query.split_once('?') {
+        Some((path, query)) => (path, query),
+        None => (path_and_query, ""),
+    };
+    let path = percent_encoding::percent_decode_str(path)
         .decode_utf8_lossy()
         .to_string();
     let path = normalize_path(&path);
 
-    normalize_cache_key(&format!("page:{}:{}", normalized_host, path))
+    if query.is_empty() {
+        return normalize_cache_key(&format!("page:{}:{}", normalized_host, path));
+    }
+    format!(
+        "{}:q:{}",
+        normalize_cache_key(&format!("page:{}:{}", normalized_host, path)),
+        query_key(query)
+    )
+}
+
+/// Injective cache-key form of a query string. Escapes of unreserved
+/// characters are decoded and other escapes uppercased (`%7e` and `~` are
+/// the same query), then every byte outside `[A-Za-z0-9.-]` becomes `_XX`,
+/// which also keeps `&`, `=` and `%26` apart.
+fn query_key(query: &str) -> String {
+    let bytes = query.as_bytes();
+    let mut canonical = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) => {
+                canonical.push(byte);
+                i += 3;
+            }
+            Some(byte) => {
+                canonical.extend_from_slice(format!("%{:02X}", byte).as_bytes());
+                i += 3;
+            }
+            None => {
+                canonical.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    let mut key = String::with_capacity(canonical.len() * 2);
+    for byte in canonical {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'.' | b'-') {
+            key.push(byte as char);
+        } else {
+            key.push_str(&format!("_{:02X}", byte));
+        }
+    }
+    key
+}
+
+/// Cache-key form of a query string: ignored parameters dropped, the rest
+/// optionally sorted. `None` when a parameter outside a non-empty `include`
+/// list is present (the request must not be cached).
+pub fn normalize_query(
+    query: &str,
+    ignore: &[String],
+    include: &[String],
+    sort: bool,
+) -> Option<String> {
+    let mut keyed = Vec::new();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let name = query_param_name(pair);
+        if matches_query_param(&name, ignore) {
+            continue;
+        }
+        if !include.is_empty() && !matches_query_param(&name, include) {
+            return None;
+        }
+        keyed.push(pair);
+    }
+    if sort {
+        keyed.sort_unstable();
+    }
+    Some(keyed.join("&"))
+}
+
+/// Query string without the ignored parameters, original order kept
+pub fn strip_ignored_query(query: &str, ignore: &[String]) -> String {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty() && !matches_query_param(&query_param_name(pair), ignore))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn query_param_name(pair: &str) -> String {
+    let name = pair.split_once('=').map_or(pair, |(name, _)| name);
+    percent_encoding::percent_decode_str(name)
+        .decode_utf8_lossy()
+        .to_string()
+}
+
+/// Case-insensitive match against names, `prefix*` matching a prefix
+fn matches_query_param(name: &str, patterns: &[String]) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => name
+                .get(..prefix.len())
+                .is_some_and(|head| head.eq_ignore_ascii_case(prefix)),
+            None => name.eq_ignore_ascii_case(pattern),
+        })
 }
 
 /// Build scoped cache key that avoids collisions across app/site/store/variant dimensions.
@@ -1127,6 +1455,7 @@ fn parse_size(s: &str) -> u64 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
     use tempfile::tempdir;
 
     #[test]
@@ -1145,6 +1474,78 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_query_cache_keys() {
+        let ignore: Vec<String> = vec!["utm_*".into(), "gclid".into(), "fbclid".into()];
+
+        assert_eq!(
+            normalize_query(
+                "utm_source=x&page=2&UTM_Medium=y&gclid=z",
+                &ignore,
+                &[],
+                true
+            ),
+            Some("page=2".to_string())
+        );
+        assert_eq!(
+            normalize_query("sort=asc&page=2", &ignore, &[], true),
+            normalize_query("page=2&sort=asc", &ignore, &[], true)
+        );
+        assert_eq!(
+            normalize_query("sort=asc&page=2", &ignore, &[], false),
+            Some("sort=asc&page=2".to_string())
+        );
+        assert_eq!(
+            normalize_query("fbclid=1", &ignore, &[], true),
+            Some(String::new())
+        );
+
+        let include: Vec<String> = vec!["page".into()];
+        assert_eq!(
+            normalize_query("page=3&utm_campaign=a", &ignore, &include, true),
+            Some("page=3".to_string())
+        );
+        assert_eq!(
+            normalize_query("page=3&s=shoes", &ignore, &include, true),
+            None
+        );
+
+        assert_eq!(
+            strip_ignored_query("a=1&utm_source=x&b=2", &ignore),
+            "a=1&b=2"
+        );
+
+        let base = build_page_cache_key("example.com", "/catalog");
+        let paged = build_page_cache_key("example.com", "/catalog?page=2");
+        assert_eq!(paged, "page:example.com:/catalog:q:page_3D2");
+        assert!(paged.starts_with(&format!("{}:", base)));
+        assert_eq!(build_page_cache_key("example.com", "/catalog?"), base);
+
+        // Distinct queries never share a key
+        let keys: Vec<String> = [
+            "/search?s=%E6%97%A5",
+            "/search?s=%E4%B8%AD",
+            "/?a=1&b=2",
+            "/?a=1_b_2",
+            "/?a=1%26b=2",
+            "/?a=1%26b%3D2",
+            "/?a_3D1",
+        ]
+        .iter()
+        .map(|url| build_page_cache_key("example.com", url))
+        .collect();
+        for (i, key) in keys.iter().enumerate() {
+            assert!(!keys[i + 1..].contains(key), "collision on {}", key);
+            assert_eq!(&normalize_cache_key(key), key);
+        }
+
+        // Equivalent spellings of one query share a key
+        assert_eq!(
+            build_page_cache_key("example.com", "/search?s=%e6%97%a5&t=%7E"),
+            build_page_cache_key("example.com", "/search?s=%E6%97%A5&t=~")
+        );
+    }
+
     #[test]
     fn test_build_page_cache_key_scoped() {
         assert_eq!(
@@ -1159,26 +1560,6 @@ mod tests {
         );
     }
 
-    #[test]
-    fn test_redis_payload_roundtrip_with_compression() {
-        let entry = CacheEntry::new(
-            vec![b'x'; 4096],
-            "text/html".to_string(),
-            vec!["domain:example.test".to_string()],
-            Duration::from_secs(300),
-            Duration::from_secs(120),
-        );
-
-        let encoded = RedisCacheLayer::serialize_entry(&entry).unwrap();
-        let decoded = RedisCacheLayer::deserialize_entry(&encoded).unwrap();
-
-        assert_eq!(decoded.data, entry.data);
-        assert_eq!(decoded.content_type, entry.content_type);
-        assert_eq!(decoded.tags, entry.tags);
-        assert_eq!(decoded.ttl, entry.ttl);
-        assert_eq!(decoded.stale_after, entry.stale_after);
-    }
-
     #[tokio::test]
     async fn test_write_through_and_l1_hit() {
         let dir = tempdir().unwrap();
@@ -1226,6 +1607,8 @@ mod tests {
                 vec![],
             )
             .await;
+        // The disk layer belongs to one manager at a time
+        drop(writer);
 
         let reader = CacheManager::new(&config);
         let first = reader.get("page:example.com:/l2").await;
@@ -1239,6 +1622,204 @@ mod tests {
         assert!(stats["l1"]["hits"].as_u64().unwrap_or(0) >= 1);
     }
 
+    #[tokio::test]
+    async fn test_page_validators_survive_l2_reload() {
+        let dir = tempdir().unwrap();
+        let config = CacheConfig {
+            disk_path: dir.path().to_string_lossy().to_string(),
+            l1_enabled: true,
+            l2_enabled: true,
+            ..CacheConfig::default()
+        };
+
+        let writer = CacheManager::new(&config);
+        writer
+            .set(
+                "page:example.com:/etag",
+                b"<h1>etag</h1>".to_vec(),
+                "text/html",
+                vec![],
+            )
+            .await;
+        let written = writer.get_page("page:example.com:/etag").await.unwrap();
+        drop(writer);
+
+        let reader = CacheManager::new(&config);
+        let reloaded = reader.get_page("page:example.com:/etag").await.unwrap();
+
+        assert_eq!(written.etag, reloaded.etag);
+        assert_eq!(written.created_at, reloaded.created_at);
+        assert_eq!(reloaded.etag, content_etag(b"<h1>etag</h1>", "text/html"));
+        assert_ne!(reloaded.etag, content_etag(b"<h1>other</h1>", "text/html"));
+    }
+
+    #[tokio::test]
+    async fn test_response_meta_survives_l2_reload() {
+        let dir = tempdir().unwrap();
+        let config = CacheConfig {
+            disk_path: dir.path().to_string_lossy().to_string(),
+            ..CacheConfig::default()
+        };
+
+        let writer = CacheManager::new(&config);
+        writer
+            .set_response(
+                "page:example.com:/gone",
+                b"gone".to_vec(),
+                "text/plain",
+                ResponseMeta {
+                    status: 410,
+                    headers: vec![
+                        ("content-language".to_string(), "de".to_string()),
+                        ("link".to_string(), "</new>; rel=canonical".to_string()),
+                    ],
+                },
+                vec![],
+                CacheLifetime::from_ttl(Duration::from_secs(60)),
+            )
+            .await;
+        drop(writer);
+
+        let reader = CacheManager::new(&config);
+        let page = reader.get_page("page:example.com:/gone").await.unwrap();
+        assert_eq!(page.status, 410);
+        assert_eq!(page.headers.len(), 2);
+        assert_eq!(page.headers[0], ("content-language".into(), "de".into()));
+        drop(reader);
+
+        // Entries from an older format are ignored, not misread
+        let layer = DiskCacheLayer::open(dir.path(), 0, true).unwrap();
+        let path = layer.entry_path("page:example.com:/gone").unwrap();
+        let mut persisted = disk_layer::read_entry_file(&path).unwrap().unwrap();
+        persisted.version = DISK_ENTRY_VERSION + 1;
+        fs::write(
+            &path,
+            disk_layer::encode_entry_file(&persisted, true).unwrap(),
+        )
+        .unwrap();
+        drop(layer);
+        assert!(CacheManager::new(&config)
+            .get_page("page:example.com:/gone")
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_variants_count_towards_size() {
+        let cache = CacheManager::new(&CacheConfig {
+            l2_enabled: false,
+            ..CacheConfig::default()
+        });
+        let key = "page:example.com:/";
+        cache
+            .set(key, b"identity".to_vec(), "text/html", vec![])
+            .await;
+        let page = cache.get_page(key).await.unwrap();
+        assert!(page.variant.is_none());
+
+        cache
+            .store_variant(key, &page.etag, ContentEncoding::Br, b"br".to_vec())
+            .await;
+        // A replaced entry does not take variants of the old body
+        cache
+            .store_variant(key, "other", ContentEncoding::Gzip, b"gz".to_vec())
+            .await;
+        assert_eq!(cache.stats()["size_bytes"], 10);
+
+        let page = cache
+            .get_page_variant(key, Some(ContentEncoding::Br))
+            .await
+            .unwrap();
+        assert_eq!(page.variant, Some((ContentEncoding::Br, b"br".to_vec())));
+        assert_eq!(page.data, b"identity");
+
+        cache.remove(key).await;
+        assert_eq!(cache.stats()["size_bytes"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_stale_windows() {
+        let cache = CacheManager::new(&CacheConfig {
+            l2_enabled: false,
+            ..CacheConfig::default()
+        });
+        let key = "page:example.com:/windows";
+        cache
+            .set_with_lifetime(
+                key,
+                b"page".to_vec(),
+                "text/html",
+                vec![],
+                CacheLifetime::new(Duration::from_secs(120), Duration::from_secs(60))
+                    .with_stale_if_error(Duration::from_secs(300)),
+            )
+            .await;
+        let age = |secs: u64| {
+            cache.l1_cache.get_mut(key).unwrap().created_at_epoch_secs = now_epoch_secs() - secs;
+        };
+        let freshness = || async { cache.lookup_page(key, None).await.map(|(_, f)| f) };
+
+        assert_eq!(freshness().await, Some(Freshness::Fresh));
+        age(90);
+        assert_eq!(freshness().await, Some(Freshness::Stale));
+        assert!(cache.get(key).await.is_none());
+        age(200);
+        assert_eq!(freshness().await, Some(Freshness::StaleIfError));
+        age(400);
+        assert_eq!(freshness().await, None);
+        assert_eq!(cache.stats()["entries"], 0);
+
+        assert!(cache.try_begin_revalidation(key));
+        assert!(!cache.try_begin_revalidation(key));
+        cache.end_revalidation(key);
+        assert!(cache.try_begin_revalidation(key));
+    }
+
+    #[tokio::test]
+    async fn test_inflight_coalescing() {
+        let cache = Arc::new(CacheManager::new(&CacheConfig {
+            l2_enabled: false,
+            ..CacheConfig::default()
+        }));
+        let key = "page:example.com:/hot";
+
+        let Inflight::Leader(guard) = cache.join_inflight(key) else {
+            panic!("first request must lead");
+        };
+        let Inflight::Follower(receiver) = cache.join_inflight(key) else {
+            panic!("second request must follow");
+        };
+        let Inflight::Follower(late) = cache.join_inflight(key) else {
+            panic!("third request must follow");
+        };
+
+        // Without the leader finishing, a follower gives up after its timeout
+        assert!(!cache.wait_inflight(late, Duration::from_millis(20)).await);
+
+        let follower = {
+            let cache = cache.clone();
+            tokio::spawn(async move {
+                let finished = cache.wait_inflight(receiver, Duration::from_secs(5)).await;
+                let page = cache.get_page(key).await;
+                (finished, page.map(|page| page.data))
+            })
+        };
+        cache
+            .set(key, b"rendered".to_vec(), "text/html", vec![])
+            .await;
+        drop(guard);
+
+        let (finished, data) = follower.await.unwrap();
+        assert!(finished);
+        assert_eq!(data, Some(b"rendered".to_vec()));
+        assert!(matches!(cache.join_inflight(key), Inflight::Leader(_)));
+
+        let stats = cache.stats();
+        assert_eq!(stats["coalescing"]["leaders"], 2);
+        assert_eq!(stats["coalescing"]["followers"], 2);
+        assert_eq!(stats["coalescing"]["timeouts"], 1);
+    }
+
     #[tokio::test]
     async fn test_stale_entry_is_not_served() {
         let dir = tempdir().unwrap();
@@ -1333,6 +1914,7 @@ mod tests {
         assert!(cache.get("page:example.com:/remove").await.is_none());
 
         // New manager verifies L2 state on disk was also invalidated.
+        drop(cache);
         let fresh_cache = CacheManager::new(&config);
         assert!(fresh_cache.get("page:example.com:/remove").await.is_none());
     }
@@ -1414,4 +1996,70 @@ mod tests {
         assert!(cache.get("page:example.com:/shop").await.is_none());
         assert_eq!(cache.get("page:other.com:/").await, Some(b"other".to_vec()));
     }
+
+    #[tokio::test]
+    async fn test_l2_breaker_opens_and_replays_purges() {
+        let dir = tempdir().unwrap();
+        let l2_dir = dir.path().join("l2");
+        let moved_dir = dir.path().join("moved");
+        let config = CacheConfig {
+            disk_path: l2_dir.to_string_lossy().to_string(),
+            l1_enabled: false,
+            l2_enabled: true,
+            l2_breaker_failures: 2,
+            l2_breaker_retry_ms: 20,
+            l2_breaker_successes: 1,
+            ..CacheConfig::default()
+        };
+
+        let cache = CacheManager::new(&config);
+        cache
+            .set(
+                "page:example.com:/tagged",
+                b"tagged".to_vec(),
+                "text/html",
+                vec!["post-1".to_string()],
+            )
+            .await;
+
+        // Take the disk layer away: writes fail until the breaker opens
+        fs::rename(&l2_dir, &moved_dir).unwrap();
+        for _ in 0..2 {
+            cache
+                .set("page:example.com:/x", b"x".to_vec(), "text/html", vec![])
+                .await;
+        }
+        let breaker = &cache.stats()["l2"]["breaker"];
+        assert_eq!(breaker["state"], "open");
+
+        // Open breaker: L2 is skipped and purges are queued
+        assert!(cache.get("page:example.com:/tagged").await.is_none());
+        cache.purge_by_tag("post-1").await;
+        let breaker = &cache.stats()["l2"]["breaker"];
+        assert!(breaker["rejected"].as_u64().unwrap() >= 2);
+        assert_eq!(breaker["pending_purges"], 1);
+
+        fs::rename(&moved_dir, &l2_dir).unwrap();
+        let mut state = String::new();
+        for _ in 0..100 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            state = cache.stats()["l2"]["breaker"]["state"]
+                .as_str()
+                .unwrap()
+                .to_string();
+            if state != "open" {
+                break;
+            }
+        }
+        assert_eq!(state, "half_open");
+        assert_eq!(cache.stats()["l2"]["breaker"]["pending_purges"], 0);
+
+        // The purge reached the disk while it was away from traffic
+        assert!(cache.get("page:example.com:/tagged").await.is_none());
+        cache
+            .set("page:example.com:/x", b"x".to_vec(), "text/html", vec![])
+            .await;
+        assert_eq!(cache.stats()["l2"]["breaker"]["state"], "closed");
+        assert_eq!(cache.get("page:example.com:/x").await, Some(b"x".to_vec()));
+    }
 }
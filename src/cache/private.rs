@@ -0,0 +1,303 @@
+//! Private Cache
+//!
+//! Per-session pages for logged-in visitors. Entries live in memory only,
+//! under their own budget, keyed on a hash of the session cookies. Each
+//! entry keeps the full session fingerprint, which must match on every
+//! read, so a page is never served to another session.
+
+use super::{normalize_cache_key, CachedPage};
+
+use dashmap::DashMap;
+use lru::LruCache;
+use parking_lot::Mutex;
+use serde_json::json;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Session of a private-cache request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrivateSession {
+    /// Session cookies, `name=value` sorted
+    fingerprint: String,
+    hash: String,
+}
+
+impl PrivateSession {
+    pub fn new(fingerprint: String) -> Self {
+        let mut hasher = DefaultHasher::new();
+        fingerprint.hash(&mut hasher);
+        Self {
+            hash: format!("{:016x}", hasher.finish()),
+            fingerprint,
+        }
+    }
+
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+}
+
+struct PrivateEntry {
+    fingerprint: String,
+    /// Page key, without the session
+    key: String,
+    page: CachedPage,
+    tags: Vec<String>,
+    expires_at: Instant,
+}
+
+impl PrivateEntry {
+    fn size(&self) -> u64 {
+        let headers: usize = self
+            .page
+            .headers
+            .iter()
+            .map(|(name, value)| name.len() + value.len())
+            .sum();
+        (self.page.data.len() + headers + self.fingerprint.len()) as u64
+    }
+}
+
+#[derive(Default)]
+struct PrivateStats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    writes: AtomicU64,
+    evictions: AtomicU64,
+    purges: AtomicU64,
+}
+
+/// Memory-only store for private pages
+pub struct PrivateCache {
+    entries: DashMap<String, PrivateEntry>,
+    lru: Mutex<LruCache<String, ()>>,
+    size_bytes: AtomicU64,
+    max_memory: u64,
+    stats: PrivateStats,
+}
+
+impl PrivateCache {
+    pub fn new(max_memory: u64) -> Self {
+        Self {
+            entries: DashMap::new(),
+            lru: Mutex::new(LruCache::unbounded()),
+            size_bytes: AtomicU64::new(0),
+            max_memory,
+            stats: PrivateStats::default(),
+        }
+    }
+
+    /// Page stored for `session`, if it has not expired
+    pub fn get(&self, session: &PrivateSession, key: &str) -> Option<CachedPage> {
+        let store_key = store_key(session, key);
+        let page = match self.entries.get(&store_key) {
+            Some(entry) if entry.fingerprint != session.fingerprint => None,
+            Some(entry) if entry.expires_at > Instant::now() => Some(entry.page.clone()),
+            Some(entry) => {
+                drop(entry);
+                self.remove(&store_key);
+                None
+            }
+            None => None,
+        };
+
+        match page {
+            Some(page) => {
+                self.lru.lock().get(&store_key);
+                self.stats.hits.fetch_add(1, Ordering::Relaxed);
+                Some(page)
+            }
+            None => {
+                self.stats.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn set(
+        &self,
+        session: &PrivateSession,
+        key: &str,
+        page: CachedPage,
+        tags: Vec<String>,
+        ttl: Duration,
+    ) {
+        let store_key = store_key(session, key);
+        let entry = PrivateEntry {
+            fingerprint: session.fingerprint.clone(),
+            key: normalize_cache_key(key),
+            page: CachedPage {
+                variant: None,
+                ..page
+            },
+            tags,
+            expires_at: Instant::now() + ttl,
+        };
+        let size = entry.size();
+        if size > self.max_memory {
+            return;
+        }
+
+        self.remove(&store_key);
+        if self.size_bytes.load(Ordering::Relaxed) + size > self.max_memory {
+            self.evict_lru(size);
+        }
+        self.entries.insert(store_key.clone(), entry);
+        self.lru.lock().put(store_key.clone(), ());
+        self.size_bytes.fetch_add(size, Ordering::Relaxed);
+        self.stats.writes.fetch_add(1, Ordering::Relaxed);
+        debug!("Private cache set: {} ({} bytes)", store_key, size);
+    }
+
+    /// Drop every page of a session
+    pub fn purge_session(&self, session: &PrivateSession) -> usize {
+        let removed = self.purge_where(|entry| entry.fingerprint == session.fingerprint);
+        if removed > 0 {
+            self.stats.purges.fetch_add(1, Ordering::Relaxed);
+        }
+        removed
+    }
+
+    pub fn purge_tag(&self, tag: &str) -> usize {
+        self.purge_where(|entry| entry.tags.iter().any(|current| current == tag))
+    }
+
+    pub fn purge_prefix(&self, prefix: &str) -> usize {
+        let prefix = normalize_cache_key(prefix);
+        self.purge_where(|entry| entry.key.starts_with(&prefix))
+    }
+
+    pub fn clear(&self) {
+        self.entries.clear();
+        self.lru.lock().clear();
+        self.size_bytes.store(0, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> serde_json::Value {
+        json!({
+            "entries": self.entries.len(),
+            "size_bytes": self.size_bytes.load(Ordering::Relaxed),
+            "max_memory": self.max_memory,
+            "hits": self.stats.hits.load(Ordering::Relaxed),
+            "misses": self.stats.misses.load(Ordering::Relaxed),
+            "writes": self.stats.writes.load(Ordering::Relaxed),
+            "evictions": self.stats.evictions.load(Ordering::Relaxed),
+            "session_purges": self.stats.purges.load(Ordering::Relaxed),
+        })
+    }
+
+    fn purge_where(&self, matches: impl Fn(&PrivateEntry) -> bool) -> usize {
+        let keys: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|entry| matches(entry.value()))
+            .map(|entry| entry.key().clone())
+            .collect();
+        keys.iter().filter(|key| self.remove(key)).count()
+    }
+
+    fn remove(&self, store_key: &str) -> bool {
+        let Some((_, entry)) = self.entries.remove(store_key) else {
+            return false;
+        };
+        self.lru.lock().pop(store_key);
+        self.size_bytes.fetch_sub(entry.size(), Ordering::Relaxed);
+        true
+    }
+
+    /// Make room for `incoming` bytes, least recently used first
+    fn evict_lru(&self, incoming: u64) {
+        let target = (self.max_memory * 8 / 10).saturating_sub(incoming);
+        let mut evicted = 0;
+        while self.size_bytes.load(Ordering::Relaxed) > target {
+            let Some((key, _)) = self.lru.lock().pop_lru() else {
+                break;
+            };
+            if self.remove(&key) {
+                evicted += 1;
+            }
+        }
+        self.stats.evictions.fetch_add(evicted, Ordering::Relaxed);
+    }
+}
+
+fn store_key(session: &PrivateSession, key: &str) -> String {
+    format!("{}:{}", session.hash, normalize_cache_key(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    fn page(body: &str) -> CachedPage {
+        CachedPage {
+            data: body.as_bytes().to_vec(),
+            content_type: "text/html".to_string(),
+            status: 200,
+            headers: Vec::new(),
+            etag: String::new(),
+            created_at: SystemTime::now(),
+            variant: None,
+        }
+    }
+
+    fn set(cache: &PrivateCache, session: &PrivateSession, key: &str, body: &str) {
+        cache.set(
+            session,
+            key,
+            page(body),
+            vec!["domain:example.test".to_string()],
+            Duration::from_secs(60),
+        );
+    }
+
+    #[test]
+    fn test_sessions_are_isolated() {
+        let cache = PrivateCache::new(1024 * 1024);
+        let alice = PrivateSession::new("wordpress_logged_in_1=alice".to_string());
+        let bob = PrivateSession::new("wordpress_logged_in_1=bob".to_string());
+
+        set(&cache, &alice, "page:example.test:/account", "Hi Alice");
+        assert_eq!(
+            cache
+                .get(&alice, "page:example.test:/account")
+                .unwrap()
+                .data,
+            b"Hi Alice"
+        );
+        assert!(cache.get(&bob, "page:example.test:/account").is_none());
+
+        // Even a colliding hash never serves another session's page
+        let mallory = PrivateSession {
+            fingerprint: "wordpress_logged_in_1=mallory".to_string(),
+            hash: alice.hash.clone(),
+        };
+        assert!(cache.get(&mallory, "page:example.test:/account").is_none());
+
+        set(&cache, &bob, "page:example.test:/account", "Hi Bob");
+        assert_eq!(cache.purge_session(&alice), 1);
+        assert!(cache.get(&alice, "page:example.test:/account").is_none());
+        assert!(cache.get(&bob, "page:example.test:/account").is_some());
+        assert_eq!(cache.purge_tag("domain:example.test"), 1);
+        assert_eq!(cache.stats()["size_bytes"], 0);
+    }
+
+    #[test]
+    fn test_budget_and_expiry() {
+        let cache = PrivateCache::new(100);
+        let session = PrivateSession::new("sid=1".to_string());
+
+        set(&cache, &session, "page:a", &"a".repeat(60));
+        set(&cache, &session, "page:b", &"b".repeat(60));
+        assert!(cache.get(&session, "page:a").is_none());
+        assert!(cache.get(&session, "page:b").is_some());
+        assert!(cache.stats()["size_bytes"].as_u64().unwrap() <= 100);
+
+        cache.set(&session, "page:c", page("c"), Vec::new(), Duration::ZERO);
+        assert!(cache.get(&session, "page:c").is_none());
+    }
+}
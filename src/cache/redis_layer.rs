@@ -0,0 +1,679 @@
+//! Redis L2 Layer
+//!
+//! Async Redis storage for cached entries. Connections are opened on first
+//! use: a single node goes through a reconnecting connection manager, a
+//! Sentinel URL resolves the current master (again after connection errors)
+//! and a Cluster URL uses the cluster client. Index updates are pipelined.
+//!
+//! Keys live under `<key_prefix>:v1:`, and server groups sharing a Redis
+//! keep separate caches:
+//! - `entry:<key>`, the serialized entry, expiring with it
+//! - `tag:<tag>`, the keys carrying a tag
+//! - `keys`, every stored key, for prefix and full purges
+//!
+//! On a Cluster each key belongs to one of `CLUSTER_SHARDS` shards, picked
+//! by its hash slot, and the shard is a hash tag
+//! (`{<key_prefix>:v1:<shard>}:`). An entry and its index entries share a
+//! slot, so its pipelines stay on one node, while the cache spreads over
+//! the cluster. Tag, prefix and full purges visit every shard concurrently.
+
+use super::{content_etag, normalize_cache_key, to_io_error, CacheEntry, PersistentCacheLayer};
+
+use async_trait::async_trait;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::future::try_join_all;
+use redis::aio::{ConnectionLike, ConnectionManager};
+use redis::cluster::ClusterClient;
+use redis::cluster_async::ClusterConnection;
+use redis::cluster_routing::get_slot;
+use redis::sentinel::{Sentinel, SentinelNodeConnectionInfo};
+use redis::{AsyncCommands, Client, Cmd, Pipeline, RedisConnectionInfo, RedisFuture, Value};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::io::{Read, Write};
+use std::time::Duration;
+use tracing::{info, warn};
+
+const REDIS_ENTRY_VERSION: u8 = 3;
+const REDIS_COMPRESSION_THRESHOLD_BYTES: usize = 1024;
+const REDIS_TAG_INDEX_TTL_GRACE_SECS: u64 = 300;
+/// Slots a Cluster cache is spread over, each with its own indexes
+const CLUSTER_SHARDS: usize = 64;
+
+#[derive(Serialize, Deserialize)]
+struct RedisPersistedEntry {
+    version: u8,
+    content_type: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    tags: Vec<String>,
+    created_at_epoch_secs: u64,
+    ttl_seconds: u64,
+    stale_after_seconds: u64,
+    stale_if_error_seconds: u64,
+    compressed: bool,
+    data: Vec<u8>,
+}
+
+/// Deployment named by `cache.redis_url`
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RedisTarget {
+    /// `redis://`, `rediss://` or `unix://`
+    Single(String),
+    /// `redis+sentinel://[user:pass@]host:26379,host2:26379/service[/db]`
+    Sentinel {
+        nodes: Vec<String>,
+        service: String,
+        /// Master credentials
+        username: Option<String>,
+        password: Option<String>,
+        db: i64,
+    },
+    /// `redis+cluster://[user:pass@]host:6379,host2:6379`
+    Cluster(Vec<String>),
+}
+
+impl RedisTarget {
+    fn parse(url: &str) -> std::io::Result<Self> {
+        if let Some(rest) = url.strip_prefix("redis+sentinel://") {
+            let (credentials, rest) = split_credentials(rest);
+            let (hosts, path) = rest.split_once('/').unwrap_or((rest, ""));
+            let mut path = path.split('/').filter(|part| !part.is_empty());
+            let service = path
+                .next()
+                .ok_or_else(|| to_io_error("sentinel URL needs a service name"))?;
+            let db = match path.next() {
+                Some(db) => db
+                    .parse()
+                    .map_err(|_| to_io_error(format!("invalid database {:?}", db)))?,
+                None => 0,
+            };
+            let (username, password) = match credentials {
+                Some((username, password)) => (
+                    Some(username.to_string()).filter(|name| !name.is_empty()),
+                    Some(password.to_string()),
+                ),
+                None => (None, None),
+            };
+            Ok(Self::Sentinel {
+                nodes: node_urls(hosts, None)?,
+                service: service.to_string(),
+                username,
+                password,
+                db,
+            })
+        } else if let Some(rest) = url.strip_prefix("redis+cluster://") {
+            let (credentials, hosts) = split_credentials(rest);
+            let hosts = hosts.trim_end_matches('/');
+            Ok(Self::Cluster(node_urls(hosts, credentials)?))
+        } else {
+            Ok(Self::Single(url.to_string()))
+        }
+    }
+}
+
+/// `user:pass` before the last `@`, if any
+fn split_credentials(rest: &str) -> (Option<(&str, &str)>, &str) {
+    match rest.rsplit_once('@') {
+        Some((credentials, hosts)) => (
+            Some(credentials.split_once(':').unwrap_or(("", credentials))),
+            hosts,
+        ),
+        None => (None, rest),
+    }
+}
+
+fn node_urls(hosts: &str, credentials: Option<(&str, &str)>) -> std::io::Result<Vec<String>> {
+    let auth = match credentials {
+        Some((username, password)) => format!("{}:{}@", username, password),
+        None => String::new(),
+    };
+    let nodes: Vec<String> = hosts
+        .split(',')
+        .map(str::trim)
+        .filter(|host| !host.is_empty())
+        .map(|host| format!("redis://{}{}", auth, host))
+        .collect();
+    if nodes.is_empty() {
+        return Err(to_io_error("Redis URL names no hosts"));
+    }
+    Ok(nodes)
+}
+
+/// Open connection to any of the supported deployments
+#[derive(Clone)]
+enum RedisConnection {
+    Single(ConnectionManager),
+    Cluster(ClusterConnection),
+}
+
+impl ConnectionLike for RedisConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        match self {
+            Self::Single(conn) => conn.req_packed_command(cmd),
+            Self::Cluster(conn) => conn.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        match self {
+            Self::Single(conn) => conn.req_packed_commands(cmd, offset, count),
+            Self::Cluster(conn) => conn.req_packed_commands(cmd, offset, count),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            Self::Single(conn) => conn.get_db(),
+            Self::Cluster(conn) => conn.get_db(),
+        }
+    }
+}
+
+pub(super) struct RedisCacheLayer {
+    target: RedisTarget,
+    /// Opened on first use; dropped after Sentinel connection errors
+    connection: tokio::sync::Mutex<Option<RedisConnection>>,
+    /// One namespace, or a hash-tagged one per shard on a Cluster
+    shards: Vec<String>,
+    timeout: Duration,
+}
+
+impl RedisCacheLayer {
+    pub(super) fn new(
+        redis_url: &str,
+        key_prefix: &str,
+        timeout: Duration,
+    ) -> std::io::Result<Self> {
+        let target = RedisTarget::parse(redis_url)?;
+        // Catch malformed URLs at startup rather than on the first request
+        match &target {
+            RedisTarget::Single(url) => drop(Client::open(url.as_str()).map_err(to_io_error)?),
+            RedisTarget::Sentinel { nodes, .. } => {
+                drop(Sentinel::build(nodes.clone()).map_err(to_io_error)?)
+            }
+            RedisTarget::Cluster(nodes) => {
+                drop(ClusterClient::new(nodes.clone()).map_err(to_io_error)?)
+            }
+        }
+
+        let prefix = key_prefix.trim_end_matches(':');
+        let shards = match &target {
+            RedisTarget::Cluster(_) => (0..CLUSTER_SHARDS)
+                .map(|shard| format!("{{{}:v1:{}}}", prefix, shard))
+                .collect(),
+            _ => vec![format!("{}:v1", prefix)],
+        };
+
+        Ok(Self {
+            target,
+            connection: tokio::sync::Mutex::new(None),
+            shards,
+            timeout,
+        })
+    }
+
+    /// Namespace holding `key` and its index entries
+    fn shard(&self, key: &str) -> &str {
+        let index = match self.shards.len() {
+            1 => 0,
+            count => get_slot(key.as_bytes()) as usize % count,
+        };
+        &self.shards[index]
+    }
+
+    fn entry_key(&self, key: &str) -> String {
+        format!("{}:entry:{}", self.shard(key), key)
+    }
+
+    fn tag_key(shard: &str, tag: &str) -> String {
+        format!("{}:tag:{}", shard, normalize_cache_key(tag))
+    }
+
+    fn key_index_key(shard: &str) -> String {
+        format!("{}:keys", shard)
+    }
+
+    /// Run `op` on every shard concurrently and add up the counts
+    async fn sum_over_shards<'a, F, Fut>(
+        &'a self,
+        conn: &RedisConnection,
+        op: F,
+    ) -> redis::RedisResult<usize>
+    where
+        F: Fn(RedisConnection, &'a str) -> Fut,
+        Fut: Future<Output = redis::RedisResult<usize>>,
+    {
+        let counts = try_join_all(
+            self.shards
+                .iter()
+                .map(|shard| op(conn.clone(), shard.as_str())),
+        )
+        .await?;
+        Ok(counts.into_iter().sum())
+    }
+
+    async fn connection(&self) -> redis::RedisResult<RedisConnection> {
+        let mut slot = self.connection.lock().await;
+        if let Some(conn) = slot.as_ref() {
+            return Ok(conn.clone());
+        }
+
+        let conn = match &self.target {
+            RedisTarget::Single(url) => {
+                RedisConnection::Single(ConnectionManager::new(Client::open(url.as_str())?).await?)
+            }
+            RedisTarget::Sentinel {
+                nodes,
+                service,
+                username,
+                password,
+                db,
+            } => {
+                let node_info = SentinelNodeConnectionInfo {
+                    tls_mode: None,
+                    redis_connection_info: Some(RedisConnectionInfo {
+                        db: *db,
+                        username: username.clone(),
+                        password: password.clone(),
+                    }),
+                };
+                let master = Sentinel::build(nodes.clone())?
+                    .async_master_for(service, Some(&node_info))
+                    .await?;
+                info!(
+                    "Redis sentinel service {} resolved to {:?}",
+                    service,
+                    master.get_connection_info().addr
+                );
+                RedisConnection::Single(ConnectionManager::new(master).await?)
+            }
+            RedisTarget::Cluster(nodes) => RedisConnection::Cluster(
+                ClusterClient::new(nodes.clone())?
+                    .get_async_connection()
+                    .await?,
+            ),
+        };
+        *slot = Some(conn.clone());
+        Ok(conn)
+    }
+
+    /// Run `op` on a connection, bounded by the configured timeout
+    async fn run<T, F, Fut>(&self, op: F) -> std::io::Result<T>
+    where
+        F: FnOnce(RedisConnection) -> Fut,
+        Fut: Future<Output = redis::RedisResult<T>>,
+    {
+        let result = tokio::time::timeout(self.timeout, async {
+            let conn = self.connection().await?;
+            op(conn).await
+        })
+        .await;
+
+        match result {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(err)) => {
+                if err.is_io_error() || err.is_connection_dropped() || err.is_connection_refusal() {
+                    self.reset_sentinel().await;
+                }
+                Err(to_io_error(err))
+            }
+            Err(_) => {
+                self.reset_sentinel().await;
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("redis operation timed out after {:?}", self.timeout),
+                ))
+            }
+        }
+    }
+
+    /// Ask the sentinels for the master again on the next operation
+    async fn reset_sentinel(&self) {
+        if matches!(self.target, RedisTarget::Sentinel { .. })
+            && self.connection.lock().await.take().is_some()
+        {
+            warn!("Redis sentinel connection reset, re-resolving the master");
+        }
+    }
+
+    pub(super) fn serialize_entry(entry: &CacheEntry) -> std::io::Result<Vec<u8>> {
+        let (compressed, data) = if entry.data.len() >= REDIS_COMPRESSION_THRESHOLD_BYTES {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(&entry.data)?;
+            let compressed = encoder.finish()?;
+            if compressed.len() < entry.data.len() {
+                (true, compressed)
+            } else {
+                (false, entry.data.clone())
+            }
+        } else {
+            (false, entry.data.clone())
+        };
+
+        let persisted = RedisPersistedEntry {
+            version: REDIS_ENTRY_VERSION,
+            content_type: entry.content_type.clone(),
+            status: entry.status,
+            headers: entry.headers.clone(),
+            tags: entry.tags.clone(),
+            created_at_epoch_secs: entry.created_at_epoch_secs,
+            ttl_seconds: entry.ttl.as_secs(),
+            stale_after_seconds: entry.stale_after.as_secs(),
+            stale_if_error_seconds: entry.stale_if_error.as_secs(),
+            compressed,
+            data,
+        };
+
+        bincode::serialize(&persisted)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()))
+    }
+
+    pub(super) fn deserialize_entry(raw: &[u8]) -> Option<CacheEntry> {
+        let persisted: RedisPersistedEntry = bincode::deserialize(raw).ok()?;
+        if persisted.version != REDIS_ENTRY_VERSION {
+            return None;
+        }
+
+        let data = if persisted.compressed {
+            let mut decoder = GzDecoder::new(persisted.data.as_slice());
+            let mut out = Vec::new();
+            decoder.read_to_end(&mut out).ok()?;
+            out
+        } else {
+            persisted.data
+        };
+
+        Some(CacheEntry {
+            etag: content_etag(&data, &persisted.content_type),
+            variants: Vec::new(),
+            data,
+            content_type: persisted.content_type,
+            status: persisted.status,
+            headers: persisted.headers,
+            tags: persisted.tags,
+            created_at_epoch_secs: persisted.created_at_epoch_secs,
+            ttl: Duration::from_secs(persisted.ttl_seconds),
+            stale_after: Duration::from_secs(persisted.stale_after_seconds),
+            stale_if_error: Duration::from_secs(persisted.stale_if_error_seconds),
+        })
+    }
+
+    /// Delete `keys` of one shard and their index entries; returns how many
+    /// existed
+    ///
+    /// Two round trips: one pipeline reads the entries (for their tags),
+    /// the next removes everything. The shard's keys share a hash slot, so
+    /// on a Cluster each pipeline goes to a single node.
+    async fn remove_keys(
+        &self,
+        conn: &mut RedisConnection,
+        shard: &str,
+        keys: &[String],
+    ) -> redis::RedisResult<usize> {
+        if keys.is_empty() {
+            return Ok(0);
+        }
+
+        let mut reads = redis::pipe();
+        for key in keys {
+            reads.get(self.entry_key(key));
+        }
+        let raw: Vec<Option<Vec<u8>>> = reads.query_async(conn).await?;
+
+        let mut deletes = redis::pipe();
+        for (key, raw) in keys.iter().zip(raw) {
+            let tags = raw
+                .and_then(|raw| Self::deserialize_entry(&raw))
+                .map(|entry| entry.tags)
+                .unwrap_or_default();
+            for tag in tags {
+                deletes.srem(Self::tag_key(shard, &tag), key).ignore();
+            }
+            deletes.del(self.entry_key(key));
+            deletes.srem(Self::key_index_key(shard), key).ignore();
+        }
+        let deleted: Vec<usize> = deletes.query_async(conn).await?;
+        Ok(deleted.into_iter().filter(|count| *count > 0).count())
+    }
+}
+
+#[async_trait]
+impl PersistentCacheLayer for RedisCacheLayer {
+    async fn get(&self, key: &str) -> std::io::Result<Option<CacheEntry>> {
+        let entry_key = self.entry_key(key);
+        let raw: Option<Vec<u8>> = self
+            .run(|mut conn| async move { conn.get(entry_key).await })
+            .await?;
+        Ok(raw.and_then(|bytes| Self::deserialize_entry(&bytes)))
+    }
+
+    async fn set(&self, key: &str, entry: &CacheEntry) -> std::io::Result<()> {
+        let payload = Self::serialize_entry(entry)?;
+        let ttl_secs = entry.retention().as_secs().max(1);
+        let tag_ttl = ttl_secs.saturating_add(REDIS_TAG_INDEX_TTL_GRACE_SECS);
+
+        let shard = self.shard(key);
+        let mut pipe = redis::pipe();
+        pipe.set_ex(self.entry_key(key), payload, ttl_secs).ignore();
+        pipe.sadd(Self::key_index_key(shard), key).ignore();
+        for tag in &entry.tags {
+            let tag_key = Self::tag_key(shard, tag);
+            pipe.sadd(&tag_key, key).ignore();
+            pipe.expire(&tag_key, tag_ttl as i64).ignore();
+        }
+        self.run(|mut conn| async move { pipe.query_async(&mut conn).await })
+            .await
+    }
+
+    async fn remove(&self, key: &str) -> std::io::Result<bool> {
+        let keys = [key.to_string()];
+        let shard = self.shard(key);
+        self.run(|mut conn| async move { self.remove_keys(&mut conn, shard, &keys).await })
+            .await
+            .map(|removed| removed > 0)
+    }
+
+    async fn purge_by_tag(&self, tag: &str) -> std::io::Result<usize> {
+        self.run(|conn| async move {
+            self.sum_over_shards(&conn, |mut conn, shard| async move {
+                let tag_key = Self::tag_key(shard, tag);
+                let keys: Vec<String> = conn.smembers(&tag_key).await?;
+                let removed = self.remove_keys(&mut conn, shard, &keys).await?;
+                let _: usize = conn.del(&tag_key).await?;
+                Ok(removed)
+            })
+            .await
+        })
+        .await
+    }
+
+    async fn purge_by_prefix(&self, prefix: &str) -> std::io::Result<usize> {
+        self.run(|conn| async move {
+            self.sum_over_shards(&conn, |mut conn, shard| async move {
+                let keys: Vec<String> = conn.smembers(Self::key_index_key(shard)).await?;
+                let keys: Vec<String> = keys
+                    .into_iter()
+                    .filter(|key| key.starts_with(prefix))
+                    .collect();
+                self.remove_keys(&mut conn, shard, &keys).await
+            })
+            .await
+        })
+        .await
+    }
+
+    async fn purge_all(&self) -> std::io::Result<usize> {
+        self.run(|conn| async move {
+            self.sum_over_shards(&conn, |mut conn, shard| async move {
+                let key_index_key = Self::key_index_key(shard);
+                let keys: Vec<String> = conn.smembers(&key_index_key).await?;
+                let removed = self.remove_keys(&mut conn, shard, &keys).await?;
+                let _: usize = conn.del(&key_index_key).await?;
+                Ok(removed)
+            })
+            .await
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{CacheLifetime, ResponseMeta};
+
+    #[test]
+    fn test_redis_payload_roundtrip_with_compression() {
+        let entry = CacheEntry::new(
+            vec![b'x'; 4096],
+            "text/html".to_string(),
+            ResponseMeta {
+                status: 301,
+                headers: vec![("location".to_string(), "/moved".to_string())],
+            },
+            vec!["domain:example.test".to_string()],
+            CacheLifetime::new(Duration::from_secs(300), Duration::from_secs(120))
+                .with_stale_if_error(Duration::from_secs(600)),
+        );
+
+        let encoded = RedisCacheLayer::serialize_entry(&entry).unwrap();
+        let decoded = RedisCacheLayer::deserialize_entry(&encoded).unwrap();
+
+        assert_eq!(decoded.data, entry.data);
+        assert_eq!(decoded.content_type, entry.content_type);
+        assert_eq!(decoded.status, 301);
+        assert_eq!(decoded.headers, entry.headers);
+        assert_eq!(decoded.tags, entry.tags);
+        assert_eq!(decoded.ttl, entry.ttl);
+        assert_eq!(decoded.stale_after, entry.stale_after);
+        assert_eq!(decoded.stale_if_error, entry.stale_if_error);
+    }
+
+    #[test]
+    fn test_parse_targets() {
+        assert_eq!(
+            RedisTarget::parse("redis://localhost:6379/2").unwrap(),
+            RedisTarget::Single("redis://localhost:6379/2".to_string())
+        );
+        assert_eq!(
+            RedisTarget::parse("redis+cluster://:secret@10.0.0.1:6379,10.0.0.2:6379/").unwrap(),
+            RedisTarget::Cluster(vec![
+                "redis://:secret@10.0.0.1:6379".to_string(),
+                "redis://:secret@10.0.0.2:6379".to_string(),
+            ])
+        );
+        assert_eq!(
+            RedisTarget::parse("redis+sentinel://cache:pw@s1:26379,s2:26379/mymaster/3").unwrap(),
+            RedisTarget::Sentinel {
+                nodes: vec![
+                    "redis://s1:26379".to_string(),
+                    "redis://s2:26379".to_string()
+                ],
+                service: "mymaster".to_string(),
+                username: Some("cache".to_string()),
+                password: Some("pw".to_string()),
+                db: 3,
+            }
+        );
+        assert!(RedisTarget::parse("redis+sentinel://s1:26379").is_err());
+        assert!(RedisTarget::parse("redis+cluster://").is_err());
+    }
+
+    #[test]
+    fn test_cluster_entries_share_a_slot_with_their_indexes() {
+        let layer = RedisCacheLayer::new(
+            "redis+cluster://10.0.0.1:6379,10.0.0.2:6379",
+            "veloserve:",
+            Duration::from_secs(1),
+        )
+        .unwrap();
+        let mut slots = std::collections::HashSet::new();
+        for n in 0..200 {
+            let key = format!("page:a.com:/{}:q:p_3D2", n);
+            let shard = layer.shard(&key);
+            assert!(shard.starts_with("{veloserve:v1:"), "{}", shard);
+            let slot = get_slot(layer.entry_key(&key).as_bytes());
+            for index_key in [
+                RedisCacheLayer::key_index_key(shard),
+                RedisCacheLayer::tag_key(shard, "post-1"),
+                RedisCacheLayer::tag_key(shard, "domain:a.com"),
+            ] {
+                assert_eq!(get_slot(index_key.as_bytes()), slot, "{}", index_key);
+            }
+            slots.insert(slot);
+        }
+        // Spread over the cluster rather than pinned to one slot
+        assert!(slots.len() > CLUSTER_SHARDS / 2, "{} slots", slots.len());
+
+        // Single nodes keep the plain namespace
+        let single = RedisCacheLayer::new(
+            "redis://localhost:6379",
+            "veloserve",
+            Duration::from_secs(1),
+        )
+        .unwrap();
+        assert_eq!(single.shards, ["veloserve:v1"]);
+        assert_eq!(single.entry_key("page:a"), "veloserve:v1:entry:page:a");
+    }
+
+    /// Runs against the server in `VELOSERVE_TEST_REDIS_URL`, e.g.
+    /// `redis://127.0.0.1:6379/15`; skipped when it is not set
+    #[tokio::test]
+    async fn test_redis_layer_roundtrip() {
+        let Ok(url) = std::env::var("VELOSERVE_TEST_REDIS_URL") else {
+            return;
+        };
+        exercise_layer(&url).await;
+    }
+
+    /// Same against the cluster in `VELOSERVE_TEST_REDIS_CLUSTER_URL`, e.g.
+    /// `redis+cluster://127.0.0.1:7000,127.0.0.1:7001`; skipped when unset
+    #[tokio::test]
+    async fn test_redis_cluster_layer_roundtrip() {
+        let Ok(url) = std::env::var("VELOSERVE_TEST_REDIS_CLUSTER_URL") else {
+            return;
+        };
+        exercise_layer(&url).await;
+    }
+
+    async fn exercise_layer(url: &str) {
+        let prefix = format!("veloserve-test-{}", std::process::id());
+        let layer = RedisCacheLayer::new(url, &prefix, Duration::from_secs(2)).unwrap();
+        let other =
+            RedisCacheLayer::new(url, "veloserve-test-other", Duration::from_secs(2)).unwrap();
+        let entry = |tag: &str| {
+            CacheEntry::new(
+                b"<h1>cached</h1>".to_vec(),
+                "text/html".to_string(),
+                ResponseMeta::default(),
+                vec![tag.to_string()],
+                CacheLifetime::from_ttl(Duration::from_secs(60)),
+            )
+        };
+
+        layer.set("page:a", &entry("post-1")).await.unwrap();
+        layer.set("page:b", &entry("post-2")).await.unwrap();
+        layer.set("other:c", &entry("post-2")).await.unwrap();
+        let stored = layer.get("page:a").await.unwrap().unwrap();
+        assert_eq!(stored.data, b"<h1>cached</h1>");
+        assert_eq!(stored.tags, ["post-1"]);
+        // Prefixes isolate server groups
+        assert!(other.get("page:a").await.unwrap().is_none());
+
+        assert_eq!(layer.purge_by_tag("post-2").await.unwrap(), 2);
+        assert!(layer.get("page:b").await.unwrap().is_none());
+        assert_eq!(layer.purge_by_prefix("page:").await.unwrap(), 1);
+        assert!(!layer.remove("page:a").await.unwrap());
+        layer.set("page:d", &entry("post-3")).await.unwrap();
+        assert!(layer.remove("page:d").await.unwrap());
+        assert_eq!(layer.purge_all().await.unwrap(), 0);
+    }
+}
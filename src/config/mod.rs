@@ -35,6 +35,14 @@ pub struct Config {
     #[serde(default)]
     pub ssl: Option<SslConfig>,
 
+    /// Cron scheduler settings
+    #[serde(default)]
+    pub cron: CronConfig,
+
+    /// Response compression defaults
+    #[serde(default)]
+    pub compression: CompressionConfig,
+
     /// Virtual hosts
     #[serde(default)]
     pub virtualhost: Vec<VirtualHostConfig>,
@@ -47,6 +55,8 @@ impl Default for Config {
             php: PhpConfig::default(),
             cache: CacheConfig::default(),
             ssl: None,
+            cron: CronConfig::default(),
+            compression: CompressionConfig::default(),
             virtualhost: vec![],
         }
     }
@@ -93,6 +103,17 @@ impl Config {
             }
         }
 
+        // Validate try_files lists, which would otherwise be ignored at runtime
+        for vhost in &self.virtualhost {
+            let lists = std::iter::once(&vhost.try_files)
+                .chain(vhost.location.iter().map(|location| &location.try_files));
+            for try_files in lists.filter(|list| !list.is_empty()) {
+                crate::server::TryFiles::parse(try_files).map_err(|err| {
+                    ConfigError::ValidationError(format!("virtualhost {}: {}", vhost.domain, err))
+                })?;
+            }
+        }
+
         Ok(())
     }
 
@@ -135,6 +156,11 @@ pub struct ServerConfig {
     /// Maximum request body size
     #[serde(default = "default_max_body_size")]
     pub max_body_size: String,
+
+    /// Server-wide error pages (status → file path), used when the
+    /// virtual host has no page for the status
+    #[serde(default, deserialize_with = "deserialize_status_map")]
+    pub error_pages: std::collections::HashMap<u16, String>,
 }
 
 impl Default for ServerConfig {
@@ -147,6 +173,7 @@ impl Default for ServerConfig {
             keepalive_timeout: default_keepalive_timeout(),
             request_timeout: default_request_timeout(),
             max_body_size: default_max_body_size(),
+            error_pages: Default::default(),
         }
     }
 }
@@ -317,14 +344,109 @@ pub struct CacheConfig {
     #[serde(default = "default_cache_ttl")]
     pub default_ttl: u64,
 
-    /// Redis URL (if using Redis backend)
+    /// Seconds past the TTL a page is served stale while one background
+    /// request refreshes it
+    #[serde(default = "default_stale_while_revalidate")]
+    pub stale_while_revalidate: u64,
+
+    /// Seconds past the TTL a page stands in for PHP errors and timeouts
+    #[serde(default = "default_stale_if_error")]
+    pub stale_if_error: u64,
+
+    /// Let one request render a missed page while concurrent requests for
+    /// the same key wait for the stored result
+    #[serde(default = "default_true")]
+    pub coalesce: bool,
+
+    /// How long a waiting request gives the rendering one before running
+    /// on its own
+    #[serde(default = "default_coalesce_timeout_ms")]
+    pub coalesce_timeout_ms: u64,
+
+    /// Cache 301/302/307/308 responses with the page TTL
+    #[serde(default)]
+    pub cache_redirects: bool,
+
+    /// TTL in seconds for 404/410 responses (0 disables negative caching)
+    #[serde(default = "default_negative_ttl")]
+    pub negative_ttl: u64,
+
+    /// Report why a request bypassed the cache in `X-Cache-Bypass`
+    #[serde(default)]
+    pub debug_headers: bool,
+
+    /// Origin response headers carrying cache tags
+    #[serde(default = "default_tag_headers")]
+    pub tag_headers: Vec<String>,
+
+    /// Origin response headers carrying the cache TTL (`max-age`,
+    /// `no-store`)
+    #[serde(default = "default_surrogate_control_headers")]
+    pub surrogate_control_headers: Vec<String>,
+
+    /// Memory budget of the private (per-session) cache, separate from
+    /// `memory_limit`
+    #[serde(default = "default_private_memory_limit")]
+    pub private_memory_limit: String,
+
+    /// TTL in seconds of private pages without a `max-age` from the origin
+    #[serde(default = "default_private_ttl")]
+    pub private_ttl: u64,
+
+    /// Redis URL (if using Redis backend): `redis://`, `rediss://`,
+    /// `redis+sentinel://` or `redis+cluster://`
     #[serde(default)]
     pub redis_url: Option<String>,
 
+    /// Prefix of every Redis key, so server groups can share a Redis
+    #[serde(default = "default_redis_key_prefix")]
+    pub redis_key_prefix: String,
+
+    /// Upper bound in milliseconds for a Redis operation, connecting included
+    #[serde(default = "default_redis_timeout_ms")]
+    pub redis_timeout_ms: u64,
+
+    /// Memcached servers (if using Memcached backend), `host[:port]`; keys
+    /// are spread over them with consistent hashing
+    #[serde(default)]
+    pub memcached_servers: Vec<String>,
+
+    /// Prefix of every Memcached key, so server groups can share servers
+    #[serde(default = "default_memcached_key_prefix")]
+    pub memcached_key_prefix: String,
+
+    /// Upper bound in milliseconds for a Memcached operation, connecting
+    /// included
+    #[serde(default = "default_memcached_timeout_ms")]
+    pub memcached_timeout_ms: u64,
+
+    /// Consecutive L2 failures that open its circuit breaker, skipping L2
+    /// until it recovers (0 disables the breaker)
+    #[serde(default = "default_l2_breaker_failures")]
+    pub l2_breaker_failures: u32,
+
+    /// Milliseconds between background probes of an L2 layer with an open
+    /// breaker
+    #[serde(default = "default_l2_breaker_retry_ms")]
+    pub l2_breaker_retry_ms: u64,
+
+    /// Consecutive successes that close a half-open breaker
+    #[serde(default = "default_l2_breaker_successes")]
+    pub l2_breaker_successes: u32,
+
     /// Disk cache path
     #[serde(default = "default_cache_path")]
     pub disk_path: String,
 
+    /// Size limit of the disk cache, e.g. "10G"; least recently used
+    /// entries are evicted beyond it ("0" for no limit)
+    #[serde(default = "default_disk_max_size")]
+    pub disk_max_size: String,
+
+    /// Compress disk cache entries with zstd
+    #[serde(default = "default_true")]
+    pub disk_compression: bool,
+
     /// Enable cache warmer queue/worker.
     #[serde(default = "default_true")]
     pub warm_enabled: bool,
@@ -371,8 +493,29 @@ impl Default for CacheConfig {
             storage: CacheStorage::Memory,
             memory_limit: default_cache_memory_limit(),
             default_ttl: default_cache_ttl(),
+            stale_while_revalidate: default_stale_while_revalidate(),
+            stale_if_error: default_stale_if_error(),
+            coalesce: true,
+            coalesce_timeout_ms: default_coalesce_timeout_ms(),
+            cache_redirects: false,
+            negative_ttl: default_negative_ttl(),
+            debug_headers: false,
+            tag_headers: default_tag_headers(),
+            surrogate_control_headers: default_surrogate_control_headers(),
+            private_memory_limit: default_private_memory_limit(),
+            private_ttl: default_private_ttl(),
             redis_url: None,
+            redis_key_prefix: default_redis_key_prefix(),
+            redis_timeout_ms: default_redis_timeout_ms(),
+            memcached_servers: Vec::new(),
+            memcached_key_prefix: default_memcached_key_prefix(),
+            memcached_timeout_ms: default_memcached_timeout_ms(),
+            l2_breaker_failures: default_l2_breaker_failures(),
+            l2_breaker_retry_ms: default_l2_breaker_retry_ms(),
+            l2_breaker_successes: default_l2_breaker_successes(),
             disk_path: default_cache_path(),
+            disk_max_size: default_disk_max_size(),
+            disk_compression: true,
             warm_enabled: true,
             warm_schedule_secs: 0,
             warm_max_queue_size: default_warm_max_queue_size(),
@@ -398,10 +541,85 @@ fn default_cache_ttl() -> u64 {
     3600
 }
 
+fn default_stale_while_revalidate() -> u64 {
+    60
+}
+
+fn default_stale_if_error() -> u64 {
+    600
+}
+
+fn default_coalesce_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_negative_ttl() -> u64 {
+    60
+}
+
+fn default_tag_headers() -> Vec<String> {
+    [
+        "X-Cache-Tags",
+        "Surrogate-Key",
+        "X-Magento-Tags",
+        "X-LiteSpeed-Tag",
+    ]
+    .iter()
+    .map(|name| name.to_string())
+    .collect()
+}
+
+fn default_surrogate_control_headers() -> Vec<String> {
+    vec![
+        "Surrogate-Control".to_string(),
+        "X-LiteSpeed-Cache-Control".to_string(),
+    ]
+}
+
+fn default_private_memory_limit() -> String {
+    "64M".to_string()
+}
+
+fn default_private_ttl() -> u64 {
+    300
+}
+
+fn default_redis_key_prefix() -> String {
+    "veloserve".to_string()
+}
+
+fn default_redis_timeout_ms() -> u64 {
+    500
+}
+
+fn default_memcached_key_prefix() -> String {
+    "veloserve".to_string()
+}
+
+fn default_memcached_timeout_ms() -> u64 {
+    500
+}
+
+fn default_l2_breaker_failures() -> u32 {
+    5
+}
+
+fn default_l2_breaker_retry_ms() -> u64 {
+    5000
+}
+
+fn default_l2_breaker_successes() -> u32 {
+    3
+}
+
 fn default_cache_path() -> String {
     "/var/cache/veloserve".to_string()
 }
 
+fn default_disk_max_size() -> String {
+    "1G".to_string()
+}
+
 fn default_warm_max_queue_size() -> usize {
     2048
 }
@@ -437,6 +655,215 @@ pub enum CacheStorage {
     Memory,
     Disk,
     Redis,
+    Memcached,
+}
+
+/// Built-in cache bypass rules for common applications
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheBypassPreset {
+    /// Session cookies of the common PHP applications
+    Generic,
+    Wordpress,
+    Woocommerce,
+    #[serde(alias = "magento2")]
+    Magento,
+    Laravel,
+    /// Only the configured rules (and `Authorization`)
+    #[serde(rename = "none")]
+    Empty,
+}
+
+/// Response compression (`[compression]`, or per vhost)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompressionConfig {
+    /// Compress responses when the client accepts it
+    #[serde(default = "default_true")]
+    pub enable: bool,
+
+    /// Encodings offered, in preference order when the client's q-values tie
+    #[serde(default = "default_compression_algorithms")]
+    pub algorithms: Vec<ContentEncoding>,
+
+    /// Smaller bodies are sent uncompressed
+    #[serde(default = "default_compression_min_size")]
+    pub min_size: usize,
+
+    /// Compressible MIME types (`text/*` matches a whole family)
+    #[serde(default = "default_compression_types")]
+    pub types: Vec<String>,
+
+    /// gzip level (1-9)
+    #[serde(default = "default_gzip_level")]
+    pub gzip_level: u32,
+
+    /// Brotli quality (0-11)
+    #[serde(default = "default_brotli_level")]
+    pub brotli_level: u32,
+
+    /// zstd level (1-22)
+    #[serde(default = "default_zstd_level")]
+    pub zstd_level: i32,
+
+    /// Serve `file.br`/`file.gz`/`file.zst` siblings of static files when
+    /// they are at least as new as the original (independent of `enable`)
+    #[serde(default)]
+    pub precompressed: bool,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            algorithms: default_compression_algorithms(),
+            min_size: default_compression_min_size(),
+            types: default_compression_types(),
+            gzip_level: default_gzip_level(),
+            brotli_level: default_brotli_level(),
+            zstd_level: default_zstd_level(),
+            precompressed: false,
+        }
+    }
+}
+
+/// HTTP content coding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentEncoding {
+    #[serde(alias = "brotli")]
+    Br,
+    Zstd,
+    Gzip,
+}
+
+impl ContentEncoding {
+    /// Token used in Accept-Encoding / Content-Encoding
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Br => "br",
+            Self::Zstd => "zstd",
+            Self::Gzip => "gzip",
+        }
+    }
+}
+
+fn default_compression_algorithms() -> Vec<ContentEncoding> {
+    vec![
+        ContentEncoding::Br,
+        ContentEncoding::Zstd,
+        ContentEncoding::Gzip,
+    ]
+}
+
+fn default_compression_min_size() -> usize {
+    1024
+}
+
+fn default_compression_types() -> Vec<String> {
+    [
+        "text/*",
+        "application/javascript",
+        "application/json",
+        "application/xml",
+        "application/xhtml+xml",
+        "application/rss+xml",
+        "application/atom+xml",
+        "application/manifest+json",
+        "application/wasm",
+        "application/vnd.ms-fontobject",
+        "image/svg+xml",
+        "image/x-icon",
+        "font/ttf",
+        "font/otf",
+    ]
+    .iter()
+    .map(|t| t.to_string())
+    .collect()
+}
+
+fn default_gzip_level() -> u32 {
+    6
+}
+
+fn default_brotli_level() -> u32 {
+    4
+}
+
+fn default_zstd_level() -> i32 {
+    3
+}
+
+/// Cron scheduler configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CronConfig {
+    /// Enable the built-in scheduler
+    #[serde(default = "default_true")]
+    pub enable: bool,
+
+    /// Run `wp-cron.php` automatically for `platform = "wordpress"` vhosts
+    #[serde(default = "default_true")]
+    pub wp_cron: bool,
+
+    /// WP-Cron interval in seconds
+    #[serde(default = "default_wp_cron_interval_secs")]
+    pub wp_cron_interval_secs: u64,
+
+    /// Default per-job timeout in seconds
+    #[serde(default = "default_cron_timeout_secs")]
+    pub default_timeout_secs: u64,
+
+    /// Number of past runs kept per job
+    #[serde(default = "default_cron_history_size")]
+    pub history_size: usize,
+}
+
+impl Default for CronConfig {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            wp_cron: true,
+            wp_cron_interval_secs: default_wp_cron_interval_secs(),
+            default_timeout_secs: default_cron_timeout_secs(),
+            history_size: default_cron_history_size(),
+        }
+    }
+}
+
+fn default_wp_cron_interval_secs() -> u64 {
+    60
+}
+
+fn default_cron_timeout_secs() -> u64 {
+    300
+}
+
+fn default_cron_history_size() -> usize {
+    20
+}
+
+/// Scheduled job for a virtual host
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CronJobConfig {
+    /// Job name (unique per vhost)
+    pub name: String,
+
+    /// PHP script URI relative to the document root (e.g. "/wp-cron.php")
+    pub script: String,
+
+    /// Query string passed to the script
+    #[serde(default)]
+    pub query: Option<String>,
+
+    /// Schedule: "@every 5m", "@hourly" or a 5-field cron expression (UTC)
+    pub schedule: String,
+
+    /// Timeout in seconds (defaults to cron.default_timeout_secs)
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+
+    /// Enable this job
+    #[serde(default = "default_true")]
+    pub enable: bool,
 }
 
 /// SSL/TLS configuration
@@ -464,9 +891,19 @@ fn default_protocols() -> Vec<String> {
 /// Virtual host configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VirtualHostConfig {
-    /// Domain name
+    /// Domain name: exact, wildcard (`*.example.com`, `www.example.*`),
+    /// regex (`~^shop\d+\.example\.com$`) or `"*"` for any host
     pub domain: String,
 
+    /// Additional server names, same syntax as `domain`
+    #[serde(default)]
+    pub aliases: Vec<String>,
+
+    /// Answer requests for unknown hosts on all listeners (`true`) or the
+    /// listed ones (`["https"]`)
+    #[serde(default)]
+    pub default_server: DefaultServer,
+
     /// Document root
     pub root: String,
 
@@ -486,19 +923,213 @@ pub struct VirtualHostConfig {
     #[serde(default)]
     pub cache: Option<VHostCacheConfig>,
 
+    /// Compression settings replacing the global `[compression]` section
+    #[serde(default)]
+    pub compression: Option<CompressionConfig>,
+
     /// Index files
     #[serde(default = "default_index_files")]
     pub index: Vec<String>,
 
-    /// Error pages
+    /// Nginx-style try_files list (`$uri`, `$uri/`, fallback URI or `=404`).
+    /// Empty uses the platform preset, if any.
     #[serde(default)]
+    pub try_files: Vec<String>,
+
+    /// Error pages (status → URI under the document root, static or PHP),
+    /// served with the original status code
+    #[serde(default, deserialize_with = "deserialize_status_map")]
     pub error_pages: std::collections::HashMap<u16, String>,
+
+    /// Scheduled jobs
+    #[serde(default)]
+    pub cron: Vec<CronJobConfig>,
+
+    /// Per-path overrides (`[[virtualhost.location]]`)
+    #[serde(default)]
+    pub location: Vec<LocationConfig>,
+
+    /// Rewrite and redirect rules, evaluated before locations
+    #[serde(default)]
+    pub rewrites: Vec<RewriteRuleConfig>,
+
+    /// Honour `.htaccess` files in the document root and its subdirectories
+    #[serde(default)]
+    pub htaccess: bool,
+}
+
+/// Listener a request arrived on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListenerKind {
+    Http,
+    Https,
+}
+
+/// `default_server` setting of a virtual host
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DefaultServer {
+    All(bool),
+    Listeners(Vec<ListenerKind>),
+}
+
+impl Default for DefaultServer {
+    fn default() -> Self {
+        Self::All(false)
+    }
+}
+
+impl DefaultServer {
+    pub fn applies_to(&self, listener: ListenerKind) -> bool {
+        match self {
+            Self::All(enabled) => *enabled,
+            Self::Listeners(listeners) => listeners.contains(&listener),
+        }
+    }
+}
+
+/// Map keyed by HTTP status; TOML keys are always strings (`404 = "..."`)
+fn deserialize_status_map<'de, D>(
+    deserializer: D,
+) -> std::result::Result<std::collections::HashMap<u16, String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let raw = std::collections::HashMap::<String, String>::deserialize(deserializer)?;
+    raw.into_iter()
+        .map(|(status, page)| match status.trim().parse::<u16>() {
+            Ok(code) if (400..=599).contains(&code) => Ok((code, page)),
+            _ => Err(serde::de::Error::custom(format!(
+                "invalid error page status {:?} (expected 400-599)",
+                status
+            ))),
+        })
+        .collect()
 }
 
 fn default_index_files() -> Vec<String> {
     vec!["index.php".to_string(), "index.html".to_string()]
 }
 
+/// How a location path is matched (nginx modifiers accepted as aliases)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LocationMatch {
+    /// `location = /path`
+    #[serde(alias = "=")]
+    Exact,
+    /// `location /path`
+    #[default]
+    Prefix,
+    /// `location ^~ /path` (longest prefix wins over regexes)
+    #[serde(alias = "^~")]
+    PriorityPrefix,
+    /// `location ~ regex`
+    #[serde(alias = "~")]
+    Regex,
+    /// `location ~* regex` (case-insensitive)
+    #[serde(alias = "~*")]
+    Iregex,
+}
+
+/// Location block configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocationConfig {
+    /// Path prefix, exact path or regex (depending on `match`)
+    pub path: String,
+
+    /// Match type: exact, prefix, priority_prefix, regex, iregex
+    #[serde(rename = "match", default)]
+    pub match_type: LocationMatch,
+
+    /// Document root override (request path is appended)
+    #[serde(default)]
+    pub root: Option<String>,
+
+    /// Directory replacing the matched prefix
+    #[serde(default)]
+    pub alias: Option<String>,
+
+    /// try_files override
+    #[serde(default)]
+    pub try_files: Vec<String>,
+
+    /// Cache policy override
+    #[serde(default)]
+    pub cache: Option<VHostCacheConfig>,
+
+    /// Headers added to every response
+    #[serde(default)]
+    pub headers: std::collections::HashMap<String, String>,
+
+    /// Ordered access rules: "allow 10.0.0.0/8", "deny all"
+    #[serde(default)]
+    pub access: Vec<String>,
+
+    /// Allow PHP execution (false answers 403 for .php requests)
+    #[serde(default)]
+    pub php: Option<bool>,
+
+    /// Answer immediately with this status
+    #[serde(default)]
+    pub return_status: Option<u16>,
+
+    /// Body for `return_status`, or the target URL for redirects
+    #[serde(default)]
+    pub return_body: Option<String>,
+
+    /// Rewrite and redirect rules for this location
+    #[serde(default)]
+    pub rewrites: Vec<RewriteRuleConfig>,
+}
+
+/// Rewrite or redirect rule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RewriteRuleConfig {
+    /// Regex matched against the URI path
+    pub pattern: String,
+
+    /// Target (`$1` rule captures, `%1` condition captures, `$host`, `$args`, ...)
+    pub replacement: String,
+
+    /// Flags: last, break, redirect, permanent, 301/302/303/307/308, qsd, nocase
+    #[serde(default)]
+    pub flags: String,
+
+    /// Conditions that must all match (see `or`)
+    #[serde(default)]
+    pub conditions: Vec<RewriteConditionConfig>,
+}
+
+/// Rewrite rule condition
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RewriteConditionConfig {
+    /// host, method, header, cookie, query, file or dir
+    #[serde(rename = "type")]
+    pub kind: String,
+
+    /// Header or cookie name
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// Regex the value must match (not used by file/dir)
+    #[serde(default)]
+    pub pattern: Option<String>,
+
+    /// Invert the result
+    #[serde(default)]
+    pub negate: bool,
+
+    /// Combine with the next condition using OR instead of AND
+    #[serde(default)]
+    pub or: bool,
+
+    /// Case-insensitive pattern
+    #[serde(default)]
+    pub nocase: bool,
+}
+
 /// Virtual host cache configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VHostCacheConfig {
@@ -510,6 +1141,22 @@ pub struct VHostCacheConfig {
     #[serde(default = "default_cache_ttl")]
     pub ttl: u64,
 
+    /// Overrides `cache.stale_while_revalidate`
+    #[serde(default)]
+    pub stale_while_revalidate: Option<u64>,
+
+    /// Overrides `cache.stale_if_error`
+    #[serde(default)]
+    pub stale_if_error: Option<u64>,
+
+    /// Overrides `cache.cache_redirects`
+    #[serde(default)]
+    pub cache_redirects: Option<bool>,
+
+    /// Overrides `cache.negative_ttl`
+    #[serde(default)]
+    pub negative_ttl: Option<u64>,
+
     /// Vary headers
     #[serde(default)]
     pub vary: Vec<String>,
@@ -517,6 +1164,97 @@ pub struct VHostCacheConfig {
     /// Excluded paths from caching
     #[serde(default)]
     pub exclude: Vec<String>,
+
+    /// Query parameters left out of the cache key (`utm_*` matches a prefix)
+    #[serde(default = "default_query_ignore")]
+    pub query_ignore: Vec<String>,
+
+    /// Only these parameters are keyed; any other (not ignored) parameter
+    /// bypasses the cache. Empty keys on every parameter.
+    #[serde(default)]
+    pub query_include: Vec<String>,
+
+    /// Sort keyed parameters so their order does not split the cache
+    #[serde(default = "default_true")]
+    pub query_sort: bool,
+
+    /// Forward ignored parameters to PHP; when off they are stripped from
+    /// the request
+    #[serde(default = "default_true")]
+    pub query_passthrough: bool,
+
+    /// Bypass rules to start from; defaults to the vhost platform's preset,
+    /// else `generic`
+    #[serde(default)]
+    pub bypass_preset: Option<CacheBypassPreset>,
+
+    /// Cookie names that bypass the cache (`wp-postpass_*` matches a prefix)
+    #[serde(default)]
+    pub bypass_cookies: Vec<String>,
+
+    /// Request headers whose presence bypasses the cache
+    #[serde(default)]
+    pub bypass_headers: Vec<String>,
+
+    /// Methods that bypass the cache (only GET and HEAD are ever cached)
+    #[serde(default)]
+    pub bypass_methods: Vec<String>,
+
+    /// Resolve Edge Side Includes in HTML responses
+    #[serde(default)]
+    pub esi: bool,
+
+    /// TTL in seconds of `scope="private"` fragments without their own
+    #[serde(default = "default_esi_private_ttl")]
+    pub esi_private_ttl: u64,
+
+    /// Cache pages the origin marks private per session, for requests that
+    /// only bypass the cache because of their cookies
+    #[serde(default)]
+    pub private: bool,
+
+    /// Cookies identifying a session (`name_*` matches a prefix); empty
+    /// uses the bypass cookie rules
+    #[serde(default)]
+    pub private_cookies: Vec<String>,
+}
+
+impl Default for VHostCacheConfig {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            ttl: default_cache_ttl(),
+            stale_while_revalidate: None,
+            stale_if_error: None,
+            cache_redirects: None,
+            negative_ttl: None,
+            vary: Vec::new(),
+            exclude: Vec::new(),
+            query_ignore: default_query_ignore(),
+            query_include: Vec::new(),
+            query_sort: true,
+            query_passthrough: true,
+            bypass_preset: None,
+            bypass_cookies: Vec::new(),
+            bypass_headers: Vec::new(),
+            bypass_methods: Vec::new(),
+            esi: false,
+            esi_private_ttl: default_esi_private_ttl(),
+            private: false,
+            private_cookies: Vec::new(),
+        }
+    }
+}
+
+fn default_esi_private_ttl() -> u64 {
+    60
+}
+
+fn default_query_ignore() -> Vec<String> {
+    ["utm_*", "gclid", "fbclid"]
+        .iter()
+        .map(|param| param.to_string())
+        .collect()
 }
 
 #[cfg(test)]
@@ -558,6 +1296,84 @@ mod tests {
         assert_eq!(config.cache.default_ttl, 7200);
     }
 
+    #[test]
+    fn test_parse_cron_jobs() {
+        let toml = r#"
+            [cron]
+            wp_cron_interval_secs = 120
+
+            [[virtualhost]]
+            domain = "example.com"
+            root = "/var/www/example"
+
+            [[virtualhost.cron]]
+            name = "reports"
+            script = "/cron/reports.php"
+            schedule = "*/15 * * * *"
+            timeout_secs = 60
+        "#;
+
+        let config = Config::from_str(toml).unwrap();
+        assert!(config.cron.enable);
+        assert_eq!(config.cron.wp_cron_interval_secs, 120);
+        let job = &config.virtualhost[0].cron[0];
+        assert_eq!(job.name, "reports");
+        assert_eq!(job.schedule, "*/15 * * * *");
+        assert_eq!(job.timeout_secs, Some(60));
+        assert!(job.enable);
+    }
+
+    #[test]
+    fn test_parse_error_pages() {
+        let toml = r#"
+            [server]
+            error_pages = { 503 = "/srv/errors/maintenance.html" }
+
+            [[virtualhost]]
+            domain = "example.com"
+            root = "/var/www/example"
+            error_pages = { 404 = "/404.html", 500 = "/error.php" }
+        "#;
+
+        let config = Config::from_str(toml).unwrap();
+        assert_eq!(
+            config.server.error_pages.get(&503).map(String::as_str),
+            Some("/srv/errors/maintenance.html")
+        );
+        assert_eq!(
+            config.virtualhost[0]
+                .error_pages
+                .get(&404)
+                .map(String::as_str),
+            Some("/404.html")
+        );
+
+        let invalid = toml.replace("404 =", "200 =");
+        assert!(Config::from_str(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_rejects_invalid_try_files() {
+        let toml = r#"
+            [[virtualhost]]
+            domain = "example.com"
+            root = "/var/www/example"
+            try_files = ["$uri", "/index.php?$args"]
+        "#;
+        assert!(Config::from_str(toml).is_ok());
+
+        let bad_status = toml.replace("\"/index.php?$args\"", "\"=999\"");
+        assert!(Config::from_str(&bad_status).is_err());
+        let bad_fallback = toml.replace("\"/index.php?$args\"", "\"index.php\"");
+        assert!(Config::from_str(&bad_fallback).is_err());
+
+        let bad_location = format!(
+            "{}\n[[virtualhost.location]]\npath = \"/app/\"\ntry_files = [\"=404\", \"$uri\"]\n",
+            toml
+        );
+        assert!(Config::from_str(&bad_location).is_err());
+    }
+
     #[test]
     fn test_worker_threads() {
         let mut config = Config::default();
@@ -63,6 +63,34 @@ use tokio::process::Command;
 use tokio::sync::Semaphore;
 use tracing::{debug, info, warn};
 
+/// Request extension carrying the client's URI when the request was
+/// internally redirected (try_files fallback, rewrites). PHP sees it as
+/// `REQUEST_URI` while `QUERY_STRING` follows the rewritten URI.
+#[derive(Debug, Clone)]
+pub struct OriginalUri(pub hyper::Uri);
+
+/// Request extension marking an internal error page request. PHP sees the
+/// original status as `REDIRECT_STATUS` (like Apache's ErrorDocument).
+#[derive(Debug, Clone, Copy)]
+pub struct ErrorPageStatus(pub u16);
+
+/// Request extension overriding `max_execution_time` (seconds) for a single
+/// run, used by cron jobs whose timeout differs from the pool's.
+#[derive(Debug, Clone, Copy)]
+pub struct ExecutionTimeLimit(pub u64);
+
+/// PHP failures that are not the script's fault, surfaced to clients as
+/// 503 (unavailable) or 504 (timeout) instead of a generic 502
+#[derive(Debug, thiserror::Error)]
+pub enum PhpFailure {
+    #[error("PHP support is not available")]
+    Unavailable,
+    #[error("Failed to acquire PHP worker permit")]
+    NoWorker,
+    #[error("PHP script execution timed out after {0}s")]
+    Timeout(u64),
+}
+
 /// PHP worker pool for executing PHP scripts
 pub struct PhpPool {
     /// Pool configuration
@@ -266,7 +294,7 @@ impl PhpPool {
         body: &[u8],
     ) -> Result<String> {
         if !self.is_available() {
-            return Err(anyhow!("PHP support is not available"));
+            return Err(PhpFailure::Unavailable.into());
         }
 
         // Acquire semaphore permit (limits concurrent PHP processes)
@@ -274,7 +302,7 @@ impl PhpPool {
             .semaphore
             .acquire()
             .await
-            .map_err(|_| anyhow!("Failed to acquire PHP worker permit"))?;
+            .map_err(|_| PhpFailure::NoWorker)?;
 
         self.active_workers.fetch_add(1, Ordering::SeqCst);
         let result = self
@@ -304,7 +332,7 @@ impl PhpPool {
         body: &[u8],
     ) -> Result<String> {
         if !self.is_available() {
-            return Err(anyhow!("PHP support is not available"));
+            return Err(PhpFailure::Unavailable.into());
         }
 
         if self.mode != PhpMode::Cgi && self.mode != PhpMode::Socket {
@@ -316,7 +344,7 @@ impl PhpPool {
             .semaphore
             .acquire()
             .await
-            .map_err(|_| anyhow!("Failed to acquire PHP worker permit"))?;
+            .map_err(|_| PhpFailure::NoWorker)?;
 
         self.active_workers.fetch_add(1, Ordering::SeqCst);
         let result = self
@@ -349,7 +377,7 @@ impl PhpPool {
     /// Execute a PHP script with minimal parameters
     pub async fn execute_simple(&self, script_path: &Path) -> Result<String> {
         if !self.is_available() {
-            return Err(anyhow!("PHP support is not available"));
+            return Err(PhpFailure::Unavailable.into());
         }
         if self.mode != PhpMode::Cgi && self.mode != PhpMode::Socket {
             return Err(anyhow!("PHP pool not in CGI/Socket mode"));
@@ -359,7 +387,7 @@ impl PhpPool {
             .semaphore
             .acquire()
             .await
-            .map_err(|_| anyhow!("Failed to acquire PHP worker permit"))?;
+            .map_err(|_| PhpFailure::NoWorker)?;
 
         self.active_workers.fetch_add(1, Ordering::SeqCst);
         let result = self.do_execute_simple(script_path).await;
@@ -388,6 +416,7 @@ impl PhpPool {
 
         // Build CGI environment variables (like Nginx + PHP-FPM)
         let mut env = build_cgi_env(req, script_path, doc_root, script_name, path_info);
+        let max_execution_time = self.execution_time_limit(req.extensions());
 
         // Update CONTENT_LENGTH with actual body size (important for POST)
         if !body.is_empty() {
@@ -396,7 +425,7 @@ impl PhpPool {
 
         // Build command
         let mut cmd = Command::new(&self.php_binary);
-        self.configure_php_command(&mut cmd);
+        self.configure_php_command(&mut cmd, max_execution_time);
 
         // Execute the PHP script directly
         cmd.arg(script_path);
@@ -433,16 +462,11 @@ impl PhpPool {
 
         // Wait for completion with timeout
         let output = tokio::time::timeout(
-            std::time::Duration::from_secs(self.config.max_execution_time),
+            std::time::Duration::from_secs(max_execution_time),
             child.wait_with_output(),
         )
         .await
-        .map_err(|_| {
-            anyhow!(
-                "PHP script execution timed out after {}s",
-                self.config.max_execution_time
-            )
-        })?
+        .map_err(|_| PhpFailure::Timeout(max_execution_time))?
         .map_err(|e| anyhow!("Failed to execute PHP script: {}", e))?;
 
         // Log any errors
@@ -483,6 +507,7 @@ impl PhpPool {
         // Build CGI environment variables
         let mut env =
             build_cgi_env_from_parts(req_parts, script_path, doc_root, script_name, path_info);
+        let max_execution_time = self.execution_time_limit(&req_parts.extensions);
 
         // Update CONTENT_LENGTH with actual body size (important for POST)
         if !body.is_empty() {
@@ -491,7 +516,7 @@ impl PhpPool {
 
         // Build command
         let mut cmd = Command::new(&self.php_binary);
-        self.configure_php_command(&mut cmd);
+        self.configure_php_command(&mut cmd, max_execution_time);
 
         // Execute the PHP script directly
         cmd.arg(script_path);
@@ -528,16 +553,11 @@ impl PhpPool {
 
         // Wait for completion with timeout
         let output = tokio::time::timeout(
-            std::time::Duration::from_secs(self.config.max_execution_time),
+            std::time::Duration::from_secs(max_execution_time),
             child.wait_with_output(),
         )
         .await
-        .map_err(|_| {
-            anyhow!(
-                "PHP script execution timed out after {}s",
-                self.config.max_execution_time
-            )
-        })?
+        .map_err(|_| PhpFailure::Timeout(max_execution_time))?
         .map_err(|e| anyhow!("Failed to execute PHP script: {}", e))?;
 
         // Log any errors
@@ -560,7 +580,7 @@ impl PhpPool {
     /// Internal: Execute PHP with minimal environment
     async fn do_execute_simple(&self, script_path: &Path) -> Result<String> {
         let mut cmd = Command::new(&self.php_binary);
-        self.configure_php_command(&mut cmd);
+        self.configure_php_command(&mut cmd, self.config.max_execution_time);
         cmd.arg(script_path);
 
         if let Some(parent) = script_path.parent() {
@@ -580,17 +600,27 @@ impl PhpPool {
         Ok(String::from_utf8_lossy(&output.stdout).to_string())
     }
 
+    /// `max_execution_time` for one run: the request's `ExecutionTimeLimit`
+    /// if set, the pool's setting otherwise
+    fn execution_time_limit(&self, extensions: &hyper::http::Extensions) -> u64 {
+        extensions
+            .get::<ExecutionTimeLimit>()
+            .map_or(self.config.max_execution_time, |limit| limit.0)
+    }
+
     /// Configure PHP command with standard settings
-    fn configure_php_command(&self, cmd: &mut Command) {
+    fn configure_php_command(&self, cmd: &mut Command, max_execution_time: u64) {
+        // Kill the process if the caller gives up on it (timeouts, dropped
+        // cron runs) instead of leaving it running in the background
+        cmd.kill_on_drop(true);
+
         // Memory limit
         cmd.arg("-d")
             .arg(format!("memory_limit={}", self.config.memory_limit));
 
         // Execution time
-        cmd.arg("-d").arg(format!(
-            "max_execution_time={}",
-            self.config.max_execution_time
-        ));
+        cmd.arg("-d")
+            .arg(format!("max_execution_time={}", max_execution_time));
 
         // Security settings
         cmd.arg("-d").arg("expose_php=Off");
@@ -666,7 +696,7 @@ impl PhpPool {
         }
 
         if !self.is_available() {
-            return Err(anyhow!("PHP support is not available"));
+            return Err(PhpFailure::Unavailable.into());
         }
 
         #[cfg(not(feature = "php-embed"))]
@@ -680,7 +710,7 @@ impl PhpPool {
                 .semaphore
                 .acquire()
                 .await
-                .map_err(|_| anyhow!("Failed to acquire PHP worker permit"))?;
+                .map_err(|_| PhpFailure::NoWorker)?;
 
             // Build CGI-like environment for $_SERVER
             let mut server_vars =
@@ -820,7 +850,12 @@ fn build_cgi_env_from_parts(
     env.insert("REQUEST_METHOD".to_string(), parts.method.to_string());
 
     // Request URI (original, includes query string)
-    env.insert("REQUEST_URI".to_string(), parts.uri.to_string());
+    let request_uri = parts
+        .extensions
+        .get::<OriginalUri>()
+        .map(|original| &original.0)
+        .unwrap_or(&parts.uri);
+    env.insert("REQUEST_URI".to_string(), request_uri.to_string());
 
     // Script name (URI path to the PHP script)
     env.insert("SCRIPT_NAME".to_string(), script_name.to_string());
@@ -898,7 +933,12 @@ fn build_cgi_env_from_parts(
     }
 
     // === PHP-specific variables ===
-    env.insert("REDIRECT_STATUS".to_string(), "200".to_string());
+    let redirect_status = parts
+        .extensions
+        .get::<ErrorPageStatus>()
+        .map(|status| status.0)
+        .unwrap_or(200);
+    env.insert("REDIRECT_STATUS".to_string(), redirect_status.to_string());
     env.insert("PHP_SELF".to_string(), script_name.to_string());
     env.insert("HTTPS".to_string(), "off".to_string());
     env.insert("REMOTE_ADDR".to_string(), "127.0.0.1".to_string());
@@ -0,0 +1,438 @@
+//! Cache Bypass Rules
+//!
+//! Requests skip the page cache when they match a rule of the vhost (or
+//! location) cache policy, on top of a preset for the application:
+//! - methods other than GET and HEAD, and `bypass_methods`
+//! - an `Authorization` header, and `bypass_headers`
+//! - paths in `exclude`
+//! - cookies named in `bypass_cookies` (`prefix_*` matches a prefix)
+//!
+//! The preset defaults to the vhost `platform`, else `generic`. Cookies are
+//! checked last, so a cookie reason means every other rule passed (what the
+//! private cache relies on).
+
+use crate::config::{CacheBypassPreset, VHostCacheConfig};
+
+use hyper::header::{AUTHORIZATION, COOKIE};
+use hyper::http::HeaderMap;
+use hyper::Method;
+use std::fmt;
+
+/// Rules a preset contributes
+struct Preset {
+    cookies: &'static [&'static str],
+    headers: &'static [&'static str],
+    paths: &'static [&'static str],
+}
+
+const WORDPRESS_COOKIES: &[&str] = &[
+    "wordpress_logged_in_*",
+    "wp-postpass_*",
+    "comment_author_*",
+    // Carts of WooCommerce stores that only set platform = "wordpress"
+    "wp_woocommerce_session_*",
+    "woocommerce_items_in_cart",
+];
+
+const WORDPRESS_PATHS: &[&str] = &["/wp-admin", "/wp-login.php", "/wp-cron.php", "/xmlrpc.php"];
+
+const GENERIC: Preset = Preset {
+    cookies: &[
+        "PHPSESSID",
+        "wordpress_logged_in_*",
+        "wp-postpass_*",
+        "wp_woocommerce_session_*",
+        "woocommerce_items_in_cart",
+        "laravel_session",
+        "remember_web_*",
+        "X-Magento-Vary",
+    ],
+    headers: &[],
+    paths: &[],
+};
+
+const WORDPRESS: Preset = Preset {
+    cookies: WORDPRESS_COOKIES,
+    headers: &[],
+    paths: WORDPRESS_PATHS,
+};
+
+const WOOCOMMERCE: Preset = Preset {
+    cookies: &["woocommerce_cart_hash"],
+    headers: &[],
+    paths: &["/cart", "/checkout", "/my-account", "/wc-api"],
+};
+
+const MAGENTO: Preset = Preset {
+    cookies: &["X-Magento-Vary", "admin"],
+    headers: &[],
+    paths: &[
+        "/admin",
+        "/checkout",
+        "/customer",
+        "/wishlist",
+        "/sales",
+        "/paypal",
+    ],
+};
+
+const LARAVEL: Preset = Preset {
+    cookies: &["remember_web_*"],
+    headers: &["X-Inertia", "X-Livewire"],
+    paths: &[
+        "/login",
+        "/logout",
+        "/register",
+        "/password",
+        "/admin",
+        "/nova",
+        "/horizon",
+        "/telescope",
+        "/livewire",
+    ],
+};
+
+const EMPTY: Preset = Preset {
+    cookies: &[],
+    headers: &[],
+    paths: &[],
+};
+
+/// Why a request skipped the page cache (`X-Cache-Bypass`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BypassReason {
+    /// Caching is off globally or for the vhost
+    Disabled,
+    Method(String),
+    Header(String),
+    Cookie(String),
+    Path(String),
+    /// A query parameter outside `query_include`
+    Query,
+}
+
+impl fmt::Display for BypassReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Disabled => write!(f, "disabled"),
+            Self::Method(method) => write!(f, "method={}", method),
+            Self::Header(name) => write!(f, "header={}", name),
+            Self::Cookie(name) => write!(f, "cookie={}", name),
+            Self::Path(rule) => write!(f, "path={}", rule),
+            Self::Query => write!(f, "query"),
+        }
+    }
+}
+
+/// Preset for a vhost `platform` value
+pub fn platform_preset(platform: Option<&str>) -> CacheBypassPreset {
+    match platform.map(str::to_ascii_lowercase).as_deref() {
+        Some("wordpress") => CacheBypassPreset::Wordpress,
+        Some("woocommerce") => CacheBypassPreset::Woocommerce,
+        Some("magento") | Some("magento2") => CacheBypassPreset::Magento,
+        Some("laravel") => CacheBypassPreset::Laravel,
+        _ => CacheBypassPreset::Generic,
+    }
+}
+
+fn presets(preset: CacheBypassPreset) -> &'static [Preset] {
+    match preset {
+        CacheBypassPreset::Generic => &[GENERIC],
+        CacheBypassPreset::Wordpress => &[WORDPRESS],
+        CacheBypassPreset::Woocommerce => &[WORDPRESS, WOOCOMMERCE],
+        CacheBypassPreset::Magento => &[MAGENTO],
+        CacheBypassPreset::Laravel => &[LARAVEL],
+        CacheBypassPreset::Empty => &[EMPTY],
+    }
+}
+
+/// First bypass rule a request matches
+pub fn check(
+    policy: &VHostCacheConfig,
+    platform: Option<&str>,
+    method: &Method,
+    headers: &HeaderMap,
+    path: &str,
+) -> Option<BypassReason> {
+    if !policy.enable {
+        return Some(BypassReason::Disabled);
+    }
+
+    if (method != Method::GET && method != Method::HEAD)
+        || policy
+            .bypass_methods
+            .iter()
+            .any(|bypass| bypass.eq_ignore_ascii_case(method.as_str()))
+    {
+        return Some(BypassReason::Method(method.to_string()));
+    }
+
+    let presets = presets(
+        policy
+            .bypass_preset
+            .unwrap_or_else(|| platform_preset(platform)),
+    );
+
+    if headers.contains_key(AUTHORIZATION) {
+        return Some(BypassReason::Header("authorization".to_string()));
+    }
+    let header_rules = presets
+        .iter()
+        .flat_map(|preset| preset.headers.iter().copied())
+        .chain(policy.bypass_headers.iter().map(String::as_str));
+    for name in header_rules {
+        if headers.contains_key(name) {
+            return Some(BypassReason::Header(name.to_ascii_lowercase()));
+        }
+    }
+
+    let path_rule = presets
+        .iter()
+        .flat_map(|preset| preset.paths.iter().copied())
+        .chain(policy.exclude.iter().map(String::as_str))
+        .find(|rule| matches_path(path, rule));
+    if let Some(rule) = path_rule {
+        return Some(BypassReason::Path(rule.to_string()));
+    }
+
+    let cookie_rules = cookie_rules(policy, presets);
+    cookies(headers)
+        .find(|(name, _)| cookie_rules.iter().any(|rule| matches_name(name, rule)))
+        .map(|(name, _)| BypassReason::Cookie(name.to_string()))
+}
+
+/// Session cookies of a request for the private cache (`private_cookies`,
+/// else the bypass cookie rules), as sorted `name=value` pairs
+pub fn session_fingerprint(
+    policy: &VHostCacheConfig,
+    platform: Option<&str>,
+    headers: &HeaderMap,
+) -> Option<String> {
+    let rules: Vec<&str> = if policy.private_cookies.is_empty() {
+        let presets = presets(
+            policy
+                .bypass_preset
+                .unwrap_or_else(|| platform_preset(platform)),
+        );
+        cookie_rules(policy, presets)
+    } else {
+        policy.private_cookies.iter().map(String::as_str).collect()
+    };
+
+    let mut pairs: Vec<String> = cookies(headers)
+        .filter(|(name, value)| {
+            !value.is_empty() && rules.iter().any(|rule| matches_name(name, rule))
+        })
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    if pairs.is_empty() {
+        return None;
+    }
+    pairs.sort();
+    pairs.dedup();
+    Some(pairs.join("; "))
+}
+
+fn cookie_rules<'a>(policy: &'a VHostCacheConfig, presets: &'static [Preset]) -> Vec<&'a str> {
+    presets
+        .iter()
+        .flat_map(|preset| preset.cookies.iter().copied())
+        .chain(policy.bypass_cookies.iter().map(String::as_str))
+        .collect()
+}
+
+/// `(name, value)` of every request cookie
+fn cookies(headers: &HeaderMap) -> impl Iterator<Item = (&str, &str)> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            Some((name.trim(), value.trim()))
+        })
+}
+
+/// Exact cookie name, or a prefix for rules ending in `*`
+fn matches_name(name: &str, rule: &str) -> bool {
+    match rule.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == rule,
+    }
+}
+
+/// `prefix*`, else the path itself and everything below it
+fn matches_path(path: &str, rule: &str) -> bool {
+    if let Some(prefix) = rule.strip_suffix('*') {
+        path.starts_with(prefix)
+    } else {
+        path == rule || path.starts_with(&format!("{}/", rule.trim_end_matches('/')))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::http::HeaderValue;
+
+    fn check_get(
+        policy: &VHostCacheConfig,
+        platform: Option<&str>,
+        headers: &[(&'static str, &'static str)],
+        path: &str,
+    ) -> Option<BypassReason> {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(*name, HeaderValue::from_static(value));
+        }
+        check(policy, platform, &Method::GET, &map, path)
+    }
+
+    #[test]
+    fn test_cookie_names_match_exactly_or_by_prefix() {
+        let policy = VHostCacheConfig::default();
+
+        assert_eq!(
+            check_get(
+                &policy,
+                None,
+                &[("cookie", "_auth_pref=1; session_theme=dark")],
+                "/"
+            ),
+            None
+        );
+        assert_eq!(
+            check_get(&policy, None, &[("cookie", "a=1; PHPSESSID=abc")], "/"),
+            Some(BypassReason::Cookie("PHPSESSID".into()))
+        );
+        assert_eq!(
+            check_get(
+                &policy,
+                Some("wordpress"),
+                &[("cookie", "wordpress_logged_in_0a1b=admin")],
+                "/"
+            ),
+            Some(BypassReason::Cookie("wordpress_logged_in_0a1b".into()))
+        );
+        // Magento guests always carry PHPSESSID
+        assert_eq!(
+            check_get(
+                &policy,
+                Some("magento2"),
+                &[("cookie", "PHPSESSID=abc")],
+                "/"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_presets_and_configured_rules() {
+        let woocommerce = VHostCacheConfig {
+            bypass_preset: Some(CacheBypassPreset::Woocommerce),
+            bypass_headers: vec!["X-Preview".into()],
+            bypass_cookies: vec!["currency_switch".into()],
+            exclude: vec!["/account/*".into()],
+            ..VHostCacheConfig::default()
+        };
+
+        assert_eq!(
+            check_get(&woocommerce, Some("wordpress"), &[], "/checkout/pay"),
+            Some(BypassReason::Path("/checkout".into()))
+        );
+        assert_eq!(
+            check_get(&woocommerce, None, &[], "/wp-admin/edit.php"),
+            Some(BypassReason::Path("/wp-admin".into()))
+        );
+        assert_eq!(
+            check_get(&woocommerce, None, &[], "/account/orders"),
+            Some(BypassReason::Path("/account/*".into()))
+        );
+        assert_eq!(check_get(&woocommerce, None, &[], "/checkouts"), None);
+        assert_eq!(
+            check_get(&woocommerce, None, &[("x-preview", "1")], "/"),
+            Some(BypassReason::Header("x-preview".into()))
+        );
+        assert_eq!(
+            check_get(
+                &woocommerce,
+                None,
+                &[("cookie", "currency_switch=eur")],
+                "/"
+            ),
+            Some(BypassReason::Cookie("currency_switch".into()))
+        );
+        assert_eq!(
+            check_get(&woocommerce, None, &[("authorization", "Basic eA==")], "/"),
+            Some(BypassReason::Header("authorization".into()))
+        );
+
+        let laravel = VHostCacheConfig::default();
+        assert_eq!(
+            check_get(&laravel, Some("laravel"), &[("x-inertia", "true")], "/"),
+            Some(BypassReason::Header("x-inertia".into()))
+        );
+        assert_eq!(
+            check_get(
+                &laravel,
+                Some("laravel"),
+                &[("cookie", "laravel_session=x")],
+                "/"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_session_fingerprint() {
+        let headers = |cookie: &'static str| {
+            let mut map = HeaderMap::new();
+            map.insert(COOKIE, HeaderValue::from_static(cookie));
+            map
+        };
+        let policy = VHostCacheConfig::default();
+
+        assert_eq!(
+            session_fingerprint(
+                &policy,
+                Some("woocommerce"),
+                &headers("_ga=1; wp_woocommerce_session_ab=cart; wordpress_logged_in_ab=alice")
+            )
+            .as_deref(),
+            Some("wordpress_logged_in_ab=alice; wp_woocommerce_session_ab=cart")
+        );
+        assert_eq!(
+            session_fingerprint(&policy, Some("wordpress"), &headers("_ga=1")),
+            None
+        );
+
+        let explicit = VHostCacheConfig {
+            private_cookies: vec!["sid".into()],
+            ..VHostCacheConfig::default()
+        };
+        assert_eq!(
+            session_fingerprint(&explicit, None, &headers("PHPSESSID=x; sid=42")).as_deref(),
+            Some("sid=42")
+        );
+    }
+
+    #[test]
+    fn test_methods() {
+        let policy = VHostCacheConfig {
+            bypass_methods: vec!["head".into()],
+            ..VHostCacheConfig::default()
+        };
+        let headers = HeaderMap::new();
+
+        assert_eq!(check(&policy, None, &Method::GET, &headers, "/"), None);
+        assert_eq!(
+            check(&policy, None, &Method::HEAD, &headers, "/"),
+            Some(BypassReason::Method("HEAD".into()))
+        );
+        assert_eq!(
+            check(&policy, None, &Method::POST, &headers, "/").map(|r| r.to_string()),
+            Some("method=POST".to_string())
+        );
+    }
+}
@@ -3,6 +3,7 @@ use crate::config::{CacheConfig, Config, VirtualHostConfig};
 use bytes::Bytes;
 use dashmap::DashMap;
 use http_body_util::{BodyExt, Empty};
+use hyper::http::{HeaderMap, HeaderValue};
 use hyper::{Method, Request};
 use hyper_util::client::legacy::connect::HttpConnector;
 use hyper_util::client::legacy::Client;
@@ -19,6 +20,23 @@ use tokio::sync::{mpsc, Semaphore};
 use tokio::time::timeout;
 use tracing::{info, warn};
 
+/// Marks the loopback request refreshing a stale page (bypasses the lookup);
+/// the value is a per-process token so clients cannot force cache misses
+const REVALIDATE_HEADER: &str = "x-veloserve-revalidate";
+
+/// Client headers not forwarded to a revalidation request
+const SKIPPED_REVALIDATION_HEADERS: &[&str] = &[
+    "connection",
+    "content-length",
+    "transfer-encoding",
+    "if-match",
+    "if-none-match",
+    "if-modified-since",
+    "if-unmodified-since",
+    "if-range",
+    "range",
+];
+
 #[derive(Debug, Clone)]
 pub struct WarmTarget {
     pub domain: String,
@@ -64,6 +82,7 @@ pub struct CacheWarmer {
     pending: DashMap<String, u64>,
     stats: WarmStats,
     started: AtomicBool,
+    revalidate_token: String,
 }
 
 impl CacheWarmer {
@@ -78,6 +97,7 @@ impl CacheWarmer {
             pending: DashMap::new(),
             stats: WarmStats::default(),
             started: AtomicBool::new(false),
+            revalidate_token: random_token(),
         });
 
         warmer.clone().spawn_dispatcher(receiver);
@@ -218,6 +238,63 @@ impl CacheWarmer {
         }
     }
 
+    /// Fetch a stale page through the server so its cache entry is replaced
+    ///
+    /// `headers` are the client's request headers, so the refresh lands on
+    /// the same cache key; validators are dropped to get a full response.
+    pub async fn revalidate(
+        &self,
+        path_and_query: &str,
+        headers: &HeaderMap,
+    ) -> anyhow::Result<()> {
+        let origin = local_origin(&self.config.server.listen)?;
+        let uri = format!("{}{}", origin, path_and_query);
+
+        let connector = HttpConnector::new();
+        let client: Client<_, Empty<Bytes>> =
+            Client::builder(TokioExecutor::new()).build(connector);
+        let mut request = Request::builder()
+            .method(Method::GET)
+            .uri(uri)
+            .body(Empty::new())?;
+        for (name, value) in headers {
+            if !SKIPPED_REVALIDATION_HEADERS.contains(&name.as_str()) {
+                request.headers_mut().append(name, value.clone());
+            }
+        }
+        request.headers_mut().insert(
+            REVALIDATE_HEADER,
+            HeaderValue::from_str(&self.revalidate_token)?,
+        );
+
+        let response = timeout(
+            Duration::from_millis(self.cache_config.warm_request_timeout_ms),
+            client.request(request),
+        )
+        .await
+        .map_err(|_| anyhow::anyhow!("revalidation request timeout"))??;
+
+        let status = response.status();
+        let _ = response.into_body().collect().await;
+
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "unexpected revalidation response status: {}",
+                status
+            ))
+        }
+    }
+
+    /// Whether a request is a refresh sent by `revalidate`
+    pub fn is_revalidation(&self, headers: &HeaderMap) -> bool {
+        headers
+            .get(REVALIDATE_HEADER)
+            .map(|value| value.as_bytes() == self.revalidate_token.as_bytes())
+            .unwrap_or(false)
+    }
+
     pub async fn enqueue_deterministic(&self, trigger: &str) -> anyhow::Result<serde_json::Value> {
         let mut targets = Vec::new();
         for vhost in &self.config.virtualhost {
@@ -432,6 +509,20 @@ impl CacheWarmer {
     }
 }
 
+/// Unguessable per-process value (std's hasher keys are randomly seeded)
+fn random_token() -> String {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    let mut token = String::new();
+    for _ in 0..2 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(now_epoch_secs());
+        token.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    token
+}
+
 fn local_origin(listen: &str) -> anyhow::Result<String> {
     let addr: SocketAddr = listen.parse()?;
     let host = if addr.ip().is_unspecified() {
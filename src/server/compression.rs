@@ -0,0 +1,359 @@
+//! Response Compression
+//!
+//! On-the-fly gzip, brotli and zstd for static, PHP and cached responses:
+//! - Accept-Encoding negotiation with q-values (ties follow the configured order)
+//! - Only compressible MIME types above `min_size`; formats that are already
+//!   compressed are never touched
+//! - Content-Length is replaced, `Vary: Accept-Encoding` is merged and the
+//!   ETag gets an encoding suffix (`"abc-br"`) so representations differ
+
+use super::vary::add_vary;
+use crate::config::{CompressionConfig, ContentEncoding};
+
+use anyhow::Result;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::header::{CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG};
+use hyper::http::{HeaderMap, HeaderValue};
+use hyper::{Response, StatusCode};
+use std::io::Write;
+
+/// Bodies above this size are compressed on the blocking pool
+const BLOCKING_THRESHOLD: usize = 128 * 1024;
+
+/// Media types that are compressed already, even if a wildcard in
+/// `types` would match them
+const ALREADY_COMPRESSED: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "font/woff",
+    "font/woff2",
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/x-bzip2",
+    "application/x-7z-compressed",
+    "application/vnd.rar",
+    "application/pdf",
+    "application/zstd",
+];
+
+/// Pick the encoding for an Accept-Encoding header
+///
+/// Explicit entries win over `*`; `q=0` refuses an encoding. Among equal
+/// q-values the first entry of `offered` wins.
+pub fn negotiate(accept_encoding: &str, offered: &[ContentEncoding]) -> Option<ContentEncoding> {
+    let mut wildcard = None;
+    let mut explicit: Vec<(&str, f32)> = Vec::new();
+
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let coding = params.next().unwrap_or("").trim();
+        if coding.is_empty() {
+            continue;
+        }
+        let q = params
+            .filter_map(|param| {
+                let (name, value) = param.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case("q")
+                    .then(|| value.trim().parse::<f32>().ok())?
+            })
+            .next()
+            .unwrap_or(1.0);
+
+        if coding == "*" {
+            wildcard = Some(q);
+        } else {
+            explicit.push((coding, q));
+        }
+    }
+
+    let quality = |encoding: &ContentEncoding| {
+        explicit
+            .iter()
+            .find(|(coding, _)| {
+                coding.eq_ignore_ascii_case(encoding.as_str())
+                    || (*encoding == ContentEncoding::Gzip && coding.eq_ignore_ascii_case("x-gzip"))
+            })
+            .map(|(_, q)| *q)
+            .or(wildcard)
+            .unwrap_or(0.0)
+    };
+
+    let mut best: Option<(ContentEncoding, f32)> = None;
+    for encoding in offered {
+        let q = quality(encoding);
+        if q > 0.0 && best.map(|(_, best_q)| q > best_q).unwrap_or(true) {
+            best = Some((*encoding, q));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Whether a Content-Type is worth compressing under `types`
+pub fn is_compressible(content_type: &str, types: &[String]) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    if essence.is_empty() || ALREADY_COMPRESSED.contains(&essence.as_str()) {
+        return false;
+    }
+
+    types.iter().any(|pattern| {
+        let pattern = pattern.trim().to_ascii_lowercase();
+        match pattern.strip_suffix("/*") {
+            Some(family) => essence
+                .split_once('/')
+                .map(|(kind, _)| kind == family)
+                .unwrap_or(false),
+            None => essence == pattern,
+        }
+    })
+}
+
+/// Compress a body with the configured level for `encoding`
+pub fn compress(
+    encoding: ContentEncoding,
+    config: &CompressionConfig,
+    data: &[u8],
+) -> std::io::Result<Vec<u8>> {
+    match encoding {
+        ContentEncoding::Gzip => {
+            let level = flate2::Compression::new(config.gzip_level.min(9));
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), level);
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        ContentEncoding::Br => {
+            let mut out = Vec::new();
+            {
+                let mut writer =
+                    brotli::CompressorWriter::new(&mut out, 4096, config.brotli_level.min(11), 22);
+                writer.write_all(data)?;
+            }
+            Ok(out)
+        }
+        ContentEncoding::Zstd => zstd::bulk::compress(data, config.zstd_level.clamp(1, 22)),
+    }
+}
+
+/// `compress` on the blocking pool for large bodies
+pub async fn compress_body(
+    encoding: ContentEncoding,
+    config: &CompressionConfig,
+    data: &[u8],
+) -> Result<Vec<u8>> {
+    if data.len() > BLOCKING_THRESHOLD {
+        let config = config.clone();
+        let data = data.to_vec();
+        Ok(tokio::task::spawn_blocking(move || compress(encoding, &config, &data)).await??)
+    } else {
+        Ok(compress(encoding, config, data)?)
+    }
+}
+
+/// Entity tag without an encoding suffix added by `compress_response`
+pub fn strip_encoding_suffix(tag: &str) -> &str {
+    [
+        ContentEncoding::Br,
+        ContentEncoding::Zstd,
+        ContentEncoding::Gzip,
+    ]
+    .iter()
+    .find_map(|encoding| tag.strip_suffix(&format!("-{}", encoding.as_str())))
+    .unwrap_or(tag)
+}
+
+/// Compress a response if the client and the response allow it
+///
+/// `accept_encoding` is the request header; `None` (or HEAD requests, see
+/// the caller) leaves the body alone but still sets Vary for compressible
+/// responses so shared caches keep representations apart.
+pub async fn compress_response(
+    response: Response<Full<Bytes>>,
+    accept_encoding: Option<&str>,
+    config: &CompressionConfig,
+) -> Result<Response<Full<Bytes>>> {
+    if !config.enable || !eligible(response.status(), response.headers(), config) {
+        return Ok(response);
+    }
+
+    let (mut parts, body) = response.into_parts();
+    add_vary(&mut parts.headers, "Accept-Encoding");
+
+    let encoding = accept_encoding.and_then(|accept| negotiate(accept, &config.algorithms));
+    let body = body.collect().await?.to_bytes();
+    let Some(encoding) = encoding.filter(|_| body.len() >= config.min_size) else {
+        return Ok(Response::from_parts(parts, Full::new(body)));
+    };
+
+    let compressed = compress_body(encoding, config, &body).await?;
+    if compressed.len() >= body.len() {
+        return Ok(Response::from_parts(parts, Full::new(body)));
+    }
+
+    parts.headers.insert(
+        CONTENT_ENCODING,
+        HeaderValue::from_static(encoding.as_str()),
+    );
+    parts
+        .headers
+        .insert(CONTENT_LENGTH, HeaderValue::from(compressed.len()));
+    if let Some(etag) = parts.headers.get(ETAG).and_then(|v| v.to_str().ok()) {
+        if let Some(tagged) = etag
+            .strip_suffix('"')
+            .map(|tag| format!("{}-{}\"", tag, encoding.as_str()))
+            .and_then(|tag| HeaderValue::from_str(&tag).ok())
+        {
+            parts.headers.insert(ETAG, tagged);
+        }
+    }
+
+    Ok(Response::from_parts(
+        parts,
+        Full::new(Bytes::from(compressed)),
+    ))
+}
+
+/// Status, headers and type allow a compressed representation
+fn eligible(status: StatusCode, headers: &HeaderMap, config: &CompressionConfig) -> bool {
+    if status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::PARTIAL_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+    {
+        return false;
+    }
+    if headers.contains_key(CONTENT_ENCODING) {
+        return false;
+    }
+    let no_transform = headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.to_ascii_lowercase().contains("no-transform"));
+    if no_transform {
+        return false;
+    }
+
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|content_type| is_compressible(content_type, &config.types))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::VARY;
+    use std::io::Read;
+
+    const ALL: &[ContentEncoding] = &[
+        ContentEncoding::Br,
+        ContentEncoding::Zstd,
+        ContentEncoding::Gzip,
+    ];
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(
+            negotiate("gzip, deflate, br", ALL),
+            Some(ContentEncoding::Br)
+        );
+        assert_eq!(
+            negotiate("gzip;q=1.0, br;q=0.5", ALL),
+            Some(ContentEncoding::Gzip)
+        );
+        assert_eq!(negotiate("br;q=0, *", ALL), Some(ContentEncoding::Zstd));
+        assert_eq!(negotiate("x-gzip", ALL), Some(ContentEncoding::Gzip));
+        assert_eq!(negotiate("identity", ALL), None);
+        assert_eq!(negotiate("*;q=0", ALL), None);
+        assert_eq!(
+            negotiate("br, gzip", &[ContentEncoding::Gzip, ContentEncoding::Br]),
+            Some(ContentEncoding::Gzip)
+        );
+    }
+
+    #[test]
+    fn test_is_compressible() {
+        let types = CompressionConfig::default().types;
+
+        assert!(is_compressible("text/html; charset=utf-8", &types));
+        assert!(is_compressible("application/json", &types));
+        assert!(is_compressible("image/svg+xml", &types));
+        assert!(!is_compressible("image/png", &types));
+        assert!(!is_compressible("application/octet-stream", &types));
+        // Already compressed even when a wildcard matches
+        assert!(!is_compressible(
+            "application/zip",
+            &["application/*".to_string()]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_compress_response() {
+        let config = CompressionConfig::default();
+        let html = "<p>hello compression</p>".repeat(200);
+        let response = || {
+            Response::builder()
+                .header(CONTENT_TYPE, "text/html; charset=utf-8")
+                .header(CONTENT_LENGTH, html.len())
+                .header(ETAG, "\"abc\"")
+                .header(VARY, "Cookie")
+                .body(Full::new(Bytes::from(html.clone())))
+                .unwrap()
+        };
+
+        let gzip = compress_response(response(), Some("gzip"), &config)
+            .await
+            .unwrap();
+        assert_eq!(gzip.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(gzip.headers()[ETAG], "\"abc-gzip\"");
+        let vary: Vec<_> = gzip.headers().get_all(VARY).iter().collect();
+        assert_eq!(vary, ["Cookie", "Accept-Encoding"]);
+        let length: usize = gzip.headers()[CONTENT_LENGTH]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let body = gzip.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(length, body.len());
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, html);
+
+        let br = compress_response(response(), Some("br"), &config)
+            .await
+            .unwrap();
+        let body = br.into_body().collect().await.unwrap().to_bytes();
+        let mut decoded = Vec::new();
+        brotli::Decompressor::new(&body[..], 4096)
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, html.as_bytes());
+
+        let zstd = compress_response(response(), Some("zstd"), &config)
+            .await
+            .unwrap();
+        let body = zstd.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(zstd::decode_all(&body[..]).unwrap(), html.as_bytes());
+
+        // No Accept-Encoding: identity body, but Vary is still set
+        let identity = compress_response(response(), None, &config).await.unwrap();
+        assert!(!identity.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(identity.headers().get_all(VARY).iter().count(), 2);
+
+        assert_eq!(strip_encoding_suffix("abc-br"), "abc");
+        assert_eq!(strip_encoding_suffix("abc"), "abc");
+    }
+}
@@ -0,0 +1,179 @@
+//! Conditional Requests
+//!
+//! Precondition evaluation (RFC 9110 §13.2.2) shared by static files and
+//! cached pages:
+//! 1. `If-Match`, else `If-Unmodified-Since` → 412 when it fails
+//! 2. `If-None-Match`, else `If-Modified-Since` (GET/HEAD) → 304
+//!
+//! `If-Range` is evaluated by the range code once the preconditions passed.
+
+use super::compression::strip_encoding_suffix;
+
+use hyper::header::{IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_UNMODIFIED_SINCE};
+use hyper::http::HeaderMap;
+use hyper::Method;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Current validators of a representation
+#[derive(Debug, Clone, Copy)]
+pub struct Validators<'a> {
+    /// Strong entity tag without quotes
+    pub etag: &'a str,
+    pub last_modified: Option<SystemTime>,
+}
+
+/// Outcome of evaluating request preconditions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precondition {
+    /// Serve the representation normally
+    Proceed,
+    /// 304 Not Modified
+    NotModified,
+    /// 412 Precondition Failed
+    Failed,
+}
+
+/// Evaluate the conditional headers of a request
+pub fn evaluate(method: &Method, headers: &HeaderMap, validators: &Validators) -> Precondition {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+    if let Some(if_match) = header(IF_MATCH) {
+        if !etag_list_matches(if_match, validators.etag, true) {
+            return Precondition::Failed;
+        }
+    } else if let Some(since) = header(IF_UNMODIFIED_SINCE).and_then(parse_date_secs) {
+        if let Some(modified) = validators.last_modified.map(epoch_secs) {
+            if modified > since {
+                return Precondition::Failed;
+            }
+        }
+    }
+
+    let safe = method == Method::GET || method == Method::HEAD;
+    if let Some(if_none_match) = header(IF_NONE_MATCH) {
+        if etag_list_matches(if_none_match, validators.etag, false) {
+            return if safe {
+                Precondition::NotModified
+            } else {
+                Precondition::Failed
+            };
+        }
+    } else if safe {
+        if let Some(since) = header(IF_MODIFIED_SINCE).and_then(parse_date_secs) {
+            if let Some(modified) = validators.last_modified.map(epoch_secs) {
+                if modified <= since {
+                    return Precondition::NotModified;
+                }
+            }
+        }
+    }
+
+    Precondition::Proceed
+}
+
+/// Whether a `*` or comma-separated entity-tag list matches `etag`
+///
+/// Strong comparison (If-Match) never matches weak tags; weak comparison
+/// (If-None-Match) ignores the `W/` prefix and the encoding suffix added by
+/// response compression.
+fn etag_list_matches(list: &str, etag: &str, strong: bool) -> bool {
+    if list.trim() == "*" {
+        return true;
+    }
+
+    list.split(',').map(str::trim).any(|candidate| {
+        let (weak, tag) = match candidate.strip_prefix("W/") {
+            Some(tag) => (true, tag),
+            None => (false, candidate),
+        };
+        if strong && weak {
+            return false;
+        }
+        tag.strip_prefix('"')
+            .and_then(|tag| tag.strip_suffix('"'))
+            .map(|tag| tag == etag || (!strong && strip_encoding_suffix(tag) == etag))
+            .unwrap_or(false)
+    })
+}
+
+/// HTTP dates have second precision
+fn epoch_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn parse_date_secs(value: &str) -> Option<u64> {
+    super::static_files::parse_http_date(value)
+        .ok()
+        .map(epoch_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.insert(*name, value.parse().unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn test_preconditions() {
+        // Tue, 15 Nov 1994 08:12:31 GMT
+        let modified = UNIX_EPOCH + Duration::from_secs(784_887_151);
+        let validators = Validators {
+            etag: "abc",
+            last_modified: Some(modified),
+        };
+        let get =
+            |pairs: &[(&'static str, &str)]| evaluate(&Method::GET, &headers(pairs), &validators);
+
+        assert_eq!(get(&[]), Precondition::Proceed);
+        assert_eq!(
+            get(&[("if-none-match", "\"x\", W/\"abc\"")]),
+            Precondition::NotModified
+        );
+        assert_eq!(get(&[("if-none-match", "*")]), Precondition::NotModified);
+        assert_eq!(get(&[("if-none-match", "\"x\"")]), Precondition::Proceed);
+        assert_eq!(
+            get(&[("if-none-match", "\"abc-gzip\"")]),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            get(&[("if-modified-since", "Tue, 15 Nov 1994 08:12:31 GMT")]),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            get(&[("if-modified-since", "Mon, 14 Nov 1994 08:12:31 GMT")]),
+            Precondition::Proceed
+        );
+        // If-None-Match takes precedence over If-Modified-Since
+        assert_eq!(
+            get(&[
+                ("if-none-match", "\"x\""),
+                ("if-modified-since", "Tue, 15 Nov 1994 08:12:31 GMT"),
+            ]),
+            Precondition::Proceed
+        );
+
+        assert_eq!(get(&[("if-match", "\"abc\"")]), Precondition::Proceed);
+        assert_eq!(get(&[("if-match", "W/\"abc\"")]), Precondition::Failed);
+        assert_eq!(get(&[("if-match", "\"abc-br\"")]), Precondition::Failed);
+        assert_eq!(
+            get(&[("if-unmodified-since", "Mon, 14 Nov 1994 08:12:31 GMT")]),
+            Precondition::Failed
+        );
+
+        let put = evaluate(
+            &Method::PUT,
+            &headers(&[("if-none-match", "*")]),
+            &validators,
+        );
+        assert_eq!(put, Precondition::Failed);
+    }
+}
@@ -0,0 +1,318 @@
+//! Edge Side Includes
+//!
+//! With `esi = true` in `[virtualhost.cache]`, HTML responses are scanned for:
+//! - `<esi:include src="/fragment" />`, replaced by the fragment. Optional
+//!   attributes: `alt` (second source), `onerror="continue"` (drop the
+//!   include instead of failing the page), `ttl` (`90`, `5m`, `1h`) and
+//!   `scope` (`public`, or `private` for a per-session copy)
+//! - `<esi:remove>…</esi:remove>`, dropped
+//! - `<esi:comment text="…" />`, dropped
+//! - `<!--esi … -->`, unwrapped
+//!
+//! The page shell is cached with its markup and includes are resolved for
+//! every response. Fragments are not processed for nested includes.
+
+use std::time::Duration;
+
+/// Request header telling the origin ESI markup will be processed
+pub const SURROGATE_CAPABILITY: &str = "veloserve=\"ESI/1.0\"";
+
+/// Includes resolved per page; the rest are dropped
+pub const MAX_INCLUDES: usize = 32;
+
+/// Piece of a page with ESI markup
+#[derive(Debug, PartialEq, Eq)]
+pub enum Segment<'a> {
+    Text(&'a str),
+    Include(Include),
+}
+
+/// Cache scope of a fragment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FragmentScope {
+    /// Shared by every visitor
+    Public,
+    /// Keyed on the visitor's cookies
+    Private,
+}
+
+/// An `<esi:include>` tag
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Include {
+    pub src: String,
+    pub alt: Option<String>,
+    pub continue_on_error: bool,
+    pub ttl: Option<Duration>,
+    pub scope: FragmentScope,
+}
+
+/// Whether a body contains ESI markup worth parsing
+pub fn has_markup(body: &[u8]) -> bool {
+    contains(body, b"<esi:") || contains(body, b"<!--esi")
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+/// Split a page into text and includes
+pub fn parse(body: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    parse_into(body, &mut segments);
+    segments
+}
+
+/// Body with includes removed and the other markup processed
+pub fn strip(body: &str) -> String {
+    parse(body)
+        .into_iter()
+        .filter_map(|segment| match segment {
+            Segment::Text(text) => Some(text),
+            Segment::Include(_) => None,
+        })
+        .collect()
+}
+
+fn parse_into<'a>(mut rest: &'a str, segments: &mut Vec<Segment<'a>>) {
+    while let Some(start) = next_markup(rest) {
+        let (text, markup) = rest.split_at(start);
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+
+        let consumed = if let Some(inner) = markup.strip_prefix("<!--esi") {
+            inner.find("-->").map(|end| {
+                parse_into(&inner[..end], segments);
+                "<!--esi".len() + end + "-->".len()
+            })
+        } else if markup.starts_with("<esi:remove") {
+            markup
+                .find("</esi:remove>")
+                .map(|end| end + "</esi:remove>".len())
+        } else if markup.starts_with("<esi:comment") {
+            tag_end(markup).map(|(end, _)| end)
+        } else if markup.starts_with("<esi:include") {
+            tag_end(markup).map(|(end, self_closing)| {
+                if let Some(include) = parse_include(&markup[..end]) {
+                    segments.push(Segment::Include(include));
+                }
+                let after = &markup[end..];
+                let closing = after.trim_start();
+                match closing.strip_prefix("</esi:include>") {
+                    Some(_) if !self_closing => {
+                        end + (after.len() - closing.len()) + "</esi:include>".len()
+                    }
+                    _ => end,
+                }
+            })
+        } else {
+            None
+        };
+
+        match consumed {
+            Some(consumed) => rest = &markup[consumed..],
+            None => {
+                // Unsupported or unterminated markup stays as it is
+                segments.push(Segment::Text(&markup[..1]));
+                rest = &markup[1..];
+            }
+        }
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest));
+    }
+}
+
+fn next_markup(body: &str) -> Option<usize> {
+    match (body.find("<esi:"), body.find("<!--esi")) {
+        (Some(tag), Some(comment)) => Some(tag.min(comment)),
+        (tag, comment) => tag.or(comment),
+    }
+}
+
+/// End of the opening tag (past `>`) and whether it was `/>`
+fn tag_end(markup: &str) -> Option<(usize, bool)> {
+    let mut quote = None;
+    for (index, ch) in markup.char_indices() {
+        match (quote, ch) {
+            (Some(open), _) if ch == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(ch),
+            (None, '>') => return Some((index + 1, markup[..index].ends_with('/'))),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_include(tag: &str) -> Option<Include> {
+    let attributes = attributes(tag);
+    let attribute = |name: &str| {
+        attributes
+            .iter()
+            .find(|(current, _)| current.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone())
+    };
+
+    Some(Include {
+        src: attribute("src").filter(|src| !src.is_empty())?,
+        alt: attribute("alt").filter(|alt| !alt.is_empty()),
+        continue_on_error: attribute("onerror")
+            .is_some_and(|value| value.eq_ignore_ascii_case("continue")),
+        ttl: attribute("ttl").and_then(|ttl| parse_ttl(&ttl)),
+        scope: match attribute("scope") {
+            Some(scope) if scope.eq_ignore_ascii_case("private") => FragmentScope::Private,
+            _ => FragmentScope::Public,
+        },
+    })
+}
+
+/// `name="value"` pairs of a tag, entities in values decoded
+fn attributes(tag: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = tag
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .trim_end_matches('/');
+    // Skip the tag name
+    rest = rest.trim_start();
+    rest = &rest[rest.find(char::is_whitespace).unwrap_or(rest.len())..];
+
+    while let Some((name, after)) = rest.split_once('=') {
+        let name = name.trim();
+        let after = after.trim_start();
+        let Some(quote) = after.chars().next().filter(|ch| matches!(ch, '"' | '\'')) else {
+            break;
+        };
+        let Some(end) = after[1..].find(quote) else {
+            break;
+        };
+        attributes.push((name.to_string(), decode_entities(&after[1..=end])));
+        rest = &after[end + 2..];
+    }
+    attributes
+}
+
+fn decode_entities(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// `90`, `90s`, `5m`, `1h` or `1d`
+fn parse_ttl(raw: &str) -> Option<Duration> {
+    let raw = raw.trim();
+    let (number, unit) = match raw.char_indices().last()? {
+        (index, 's') => (&raw[..index], 1),
+        (index, 'm') => (&raw[..index], 60),
+        (index, 'h') => (&raw[..index], 3600),
+        (index, 'd') => (&raw[..index], 86400),
+        _ => (raw, 1),
+    };
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(|value| Duration::from_secs(value * unit))
+}
+
+/// Path and query of an include `src`; absolute URLs must name `host`
+pub fn resolve_src(src: &str, host: &str) -> Option<String> {
+    if src.starts_with('/') && !src.starts_with("//") {
+        return Some(src.to_string());
+    }
+    let rest = src
+        .strip_prefix("http://")
+        .or_else(|| src.strip_prefix("https://"))
+        .or_else(|| src.strip_prefix("//"))?;
+    let (authority, path) = match rest.find(['/', '?']) {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+    let authority_host = authority.split(':').next().unwrap_or(authority);
+    let host = host.split(':').next().unwrap_or(host);
+    if !authority_host.eq_ignore_ascii_case(host) {
+        return None;
+    }
+    Some(match path.strip_prefix('?') {
+        Some(_) => format!("/{}", path),
+        None => path.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn include(src: &str) -> Include {
+        Include {
+            src: src.to_string(),
+            alt: None,
+            continue_on_error: false,
+            ttl: None,
+            scope: FragmentScope::Public,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let body = "<p>Hi</p><esi:include src=\"/cart?a=1&amp;b=2\"/>\
+                    <esi:remove><a href=\"/cart\">Cart</a></esi:remove>\
+                    <esi:comment text=\"greeting\" />\
+                    <!--esi <esi:include src='/user' alt='/guest' onerror='continue' ttl='5m' scope='private'></esi:include> -->\
+                    <p>End</p>";
+        assert_eq!(
+            parse(body),
+            vec![
+                Segment::Text("<p>Hi</p>"),
+                Segment::Include(include("/cart?a=1&b=2")),
+                Segment::Text(" "),
+                Segment::Include(Include {
+                    alt: Some("/guest".into()),
+                    continue_on_error: true,
+                    ttl: Some(Duration::from_secs(300)),
+                    scope: FragmentScope::Private,
+                    ..include("/user")
+                }),
+                Segment::Text(" "),
+                Segment::Text("<p>End</p>"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_malformed_markup_is_kept() {
+        assert_eq!(strip("a <esi:remove>b"), "a <esi:remove>b");
+        assert_eq!(
+            strip("a <esi:choose>b</esi:choose>"),
+            "a <esi:choose>b</esi:choose>"
+        );
+        assert_eq!(strip("<esi:include src=\"\"/>x"), "x");
+        assert!(!has_markup(b"<p>plain</p>"));
+        assert!(has_markup(b"<p><!--esi x --></p>"));
+    }
+
+    #[test]
+    fn test_resolve_src() {
+        assert_eq!(
+            resolve_src("/frag?x=1", "shop.test").as_deref(),
+            Some("/frag?x=1")
+        );
+        assert_eq!(
+            resolve_src("https://Shop.test:8443/frag", "shop.test:8080").as_deref(),
+            Some("/frag")
+        );
+        assert_eq!(
+            resolve_src("http://shop.test", "shop.test").as_deref(),
+            Some("/")
+        );
+        assert_eq!(resolve_src("http://other.test/frag", "shop.test"), None);
+        assert_eq!(resolve_src("//other.test/frag", "shop.test"), None);
+        assert_eq!(resolve_src("frag.html", "shop.test"), None);
+    }
+}
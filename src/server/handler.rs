@@ -3,18 +3,44 @@
 //! Handles incoming HTTP requests similar to Nginx/Apache/LiteSpeed.
 //! Supports static files, PHP processing, and URL rewriting.
 
-use crate::cache::{build_page_cache_key, build_page_cache_key_scoped, CacheManager};
-use crate::config::Config;
+use crate::cache::{
+    build_page_cache_key, build_page_cache_key_scoped, content_etag, normalize_query,
+    strip_ignored_query, CacheLifetime, CacheManager, CachedPage, Freshness, Inflight,
+    PrivateSession, ResponseMeta,
+};
+use crate::config::{
+    CompressionConfig, Config, ContentEncoding, ListenerKind, VHostCacheConfig, VirtualHostConfig,
+};
 use crate::php::sapi::PhpResponse;
-use crate::php::PhpPool;
+use crate::php::{ErrorPageStatus, OriginalUri, PhpFailure, PhpPool};
+use crate::server::bypass::{self, BypassReason};
 use crate::server::cache_warmer::{CacheWarmer, WarmRequestPayload};
-use crate::server::static_files::StaticFileHandler;
+use crate::server::compression;
+use crate::server::conditional::{self, Precondition, Validators};
+use crate::server::esi::{self, FragmentScope, Segment};
+use crate::server::htaccess::{ErrorDocument, HtaccessCache, HtaccessChain};
+use crate::server::location::{normalize_path, Location, LocationTable};
+use crate::server::rewrite::{
+    RewriteInput, RewriteResult, RewriteSet, RewriteState, RewriteTraceEntry,
+};
+use crate::server::scheduler::CronScheduler;
+use crate::server::static_files::{format_http_date, StaticFileHandler};
+use crate::server::surrogate::{self, normalize_tag};
+use crate::server::try_files::{
+    expand_variables, platform_doc_root, split_uri, TryFiles, TryFilesFallback,
+};
+use crate::server::vary::{self, VaryRule};
+use crate::server::vhost::VhostTable;
 
 use anyhow::{anyhow, Result};
 use bytes::Bytes;
 use dashmap::DashMap;
 use http_body_util::{BodyExt, Full};
-use hyper::header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, SET_COOKIE};
+use hyper::header::{
+    ACCEPT, ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG,
+    IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, IF_UNMODIFIED_SINCE, LAST_MODIFIED,
+    RANGE, SET_COOKIE,
+};
 use hyper::http::{HeaderMap, HeaderValue};
 use hyper::{Method, Request, Response, StatusCode};
 use once_cell::sync::Lazy;
@@ -23,6 +49,7 @@ use serde::Deserialize;
 use std::collections::hash_map::DefaultHasher;
 use std::collections::{HashSet, VecDeque};
 use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
@@ -39,8 +66,155 @@ pub struct RequestHandler {
     config: Arc<Config>,
     cache: Arc<CacheManager>,
     warmer: Arc<CacheWarmer>,
+    scheduler: Arc<CronScheduler>,
     php_pool: Arc<PhpPool>,
     static_handler: StaticFileHandler,
+    /// Compiled location blocks, one table per `config.virtualhost` entry
+    locations: Vec<LocationTable>,
+    /// Compiled vhost rewrite rules, one set per `config.virtualhost` entry
+    rewrites: Vec<RewriteSet>,
+    /// Parsed `.htaccess` files for vhosts with `htaccess = true`
+    htaccess: HtaccessCache,
+    /// Server name lookup table
+    vhosts: VhostTable,
+}
+
+/// Maximum location re-searches caused by rewrites (nginx uses 10)
+const MAX_REWRITE_CYCLES: usize = 10;
+
+/// Result of running rewrite rules for a request
+enum RewriteOutcome<'a> {
+    /// Serve from this location (URI possibly rewritten)
+    Resolved(Option<&'a Location>),
+    /// Redirect the client
+    Redirect { status: u16, location: String },
+    /// Answer with a bare status
+    Status(u16),
+    /// Rewrites kept changing the location
+    Cycle,
+}
+
+#[derive(Debug, Deserialize)]
+struct RewriteTestRequest {
+    url: String,
+    #[serde(default)]
+    method: Option<String>,
+    #[serde(default)]
+    headers: std::collections::HashMap<String, String>,
+}
+
+/// Client socket address, attached to each request as an extension
+#[derive(Debug, Clone, Copy)]
+pub struct ClientAddr(pub SocketAddr);
+
+/// Response extension marking an error page generated by the server itself,
+/// as opposed to one produced by a PHP application
+#[derive(Debug, Clone)]
+struct ServerError(String);
+
+/// Request extension with the encodings whose precompressed siblings
+/// static files may be served from (vhost `compression.precompressed`)
+#[derive(Debug, Clone)]
+struct Precompressed(Vec<ContentEncoding>);
+
+/// Request extension set by a location with `php = false`: scripts resolved
+/// from the request path are refused, internal fallbacks may still run PHP
+#[derive(Debug, Clone, Copy)]
+struct PhpDenied;
+
+/// What an error page needs from the original request
+struct ErrorPageRequest {
+    /// Client prefers a JSON error body
+    wants_json: bool,
+    /// Request parts for a PHP error page (only kept when pages are configured)
+    parts: Option<hyper::http::request::Parts>,
+}
+
+impl ErrorPageRequest {
+    fn capture(req: &Request<hyper::body::Incoming>, keep_parts: bool) -> Self {
+        let accept = req
+            .headers()
+            .get(ACCEPT)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        let wants_json = accept.contains("application/json") && !accept.contains("text/html");
+
+        let parts = keep_parts.then(|| {
+            let (mut parts, _) = Request::builder()
+                .method(Method::GET)
+                .uri(req.uri().clone())
+                .body(())
+                .unwrap_or_default()
+                .into_parts();
+            parts.headers = req.headers().clone();
+            parts.headers.remove(CONTENT_LENGTH);
+            parts.headers.remove(CONTENT_TYPE);
+            parts
+                .extensions
+                .insert(OriginalUri(client_uri(req).clone()));
+            if let Some(addr) = req.extensions().get::<ClientAddr>() {
+                parts.extensions.insert(*addr);
+            }
+            parts
+        });
+
+        Self { wants_json, parts }
+    }
+}
+
+/// What ESI fragments need from the page request
+struct EsiRequest<'a> {
+    policy: &'a VHostCacheConfig,
+    /// Index into `config.virtualhost`, for the fragments' own routing
+    vhost_index: Option<usize>,
+    host: String,
+    /// Page request headers without validators, ranges and body headers
+    headers: HeaderMap,
+    client: Option<ClientAddr>,
+    /// The page passed the bypass rules, so public fragments rendered with
+    /// its cookies may be shared with other visitors
+    shared: bool,
+}
+
+impl<'a> EsiRequest<'a> {
+    fn capture(
+        req: &Request<hyper::body::Incoming>,
+        policy: &'a VHostCacheConfig,
+        platform: Option<&str>,
+        vhost_index: Option<usize>,
+    ) -> Self {
+        let shared = bypass::check(
+            policy,
+            platform,
+            req.method(),
+            req.headers(),
+            client_uri(req).path(),
+        )
+        .is_none();
+        let mut headers = req.headers().clone();
+        for name in [
+            ACCEPT_ENCODING,
+            CONTENT_LENGTH,
+            CONTENT_TYPE,
+            IF_MATCH,
+            IF_MODIFIED_SINCE,
+            IF_NONE_MATCH,
+            IF_RANGE,
+            IF_UNMODIFIED_SINCE,
+            RANGE,
+        ] {
+            headers.remove(name);
+        }
+        Self {
+            policy,
+            vhost_index,
+            host: request_host(req.headers()),
+            headers,
+            client: req.extensions().get::<ClientAddr>().copied(),
+            shared,
+        }
+    }
 }
 
 /// Result of resolving a PHP script path
@@ -60,8 +234,61 @@ struct CacheContext {
     domain: String,
     path: String,
     ttl: Duration,
+    /// Stale windows past `ttl`; origin Cache-Control directives override them
+    stale_while_revalidate: Duration,
+    stale_if_error: Duration,
+    cache_redirects: bool,
+    /// TTL of 404/410 entries, zero when negative caching is off
+    negative_ttl: Duration,
+    /// Request headers and cookies the key varies on
+    vary: Vec<VaryRule>,
+    /// Session of a request served from the private cache
+    private: Option<PrivateSession>,
+    /// Expired copy still inside its stale-if-error window
+    fallback: Option<CachedPage>,
 }
 
+/// Response header asking to drop the private pages of the request's session
+const PURGE_PRIVATE_HEADER: &str = "x-veloserve-purge-private";
+
+/// Response headers that are regenerated on every hit or must never be
+/// shared between clients
+const UNCACHED_RESPONSE_HEADERS: &[&str] = &[
+    "accept-ranges",
+    "age",
+    "connection",
+    "content-encoding",
+    "content-length",
+    "content-range",
+    "content-type",
+    "date",
+    "etag",
+    "keep-alive",
+    "last-modified",
+    "proxy-connection",
+    "server",
+    "set-cookie",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "x-cache",
+    "x-powered-by",
+];
+
+/// Connection-level headers a CGI response must not forward
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
 const INVALIDATION_DEDUPE_WINDOW_SECS: u64 = 15;
 const INVALIDATION_RATE_WINDOW_SECS: u64 = 60;
 const INVALIDATION_RATE_LIMIT: usize = 120;
@@ -71,6 +298,9 @@ const INVALIDATION_MAX_TAGS_PER_GROUP: usize = 64;
 
 static INVALIDATION_GUARD: Lazy<InvalidationGuard> = Lazy::new(InvalidationGuard::default);
 
+/// Query key rules for hosts and locations without a cache section
+static DEFAULT_CACHE_POLICY: Lazy<VHostCacheConfig> = Lazy::new(VHostCacheConfig::default);
+
 #[derive(Default)]
 struct InvalidationGuard {
     dedupe: DashMap<String, u64>,
@@ -162,16 +392,33 @@ impl RequestHandler {
         config: Arc<Config>,
         cache: Arc<CacheManager>,
         warmer: Arc<CacheWarmer>,
+        scheduler: Arc<CronScheduler>,
         php_pool: Arc<PhpPool>,
     ) -> Self {
         let static_handler = StaticFileHandler::new();
+        let locations = config
+            .virtualhost
+            .iter()
+            .map(|vhost| LocationTable::compile(&vhost.domain, &vhost.location))
+            .collect();
+        let rewrites = config
+            .virtualhost
+            .iter()
+            .map(|vhost| RewriteSet::compile(&vhost.domain, &vhost.rewrites))
+            .collect();
+        let vhosts = VhostTable::new(&config);
 
         Self {
             config,
             cache,
             warmer,
+            scheduler,
             php_pool,
             static_handler,
+            locations,
+            rewrites,
+            htaccess: HtaccessCache::new(),
+            vhosts,
         }
     }
 
@@ -179,16 +426,17 @@ impl RequestHandler {
     ///
     /// Request processing order (similar to Nginx/Apache):
     /// 1. Internal endpoints (health, API)
-    /// 2. Check if exact file exists
-    /// 3. If directory, try index files
-    /// 4. If PHP file, execute with PATH_INFO
-    /// 5. Try files pattern for clean URLs
-    /// 6. Return 404
+    /// 2. Resolve virtual host and location (access rules, fixed returns)
+    /// 3. Check if exact file exists
+    /// 4. If directory, try index files
+    /// 5. If PHP file, execute with PATH_INFO
+    /// 6. Try files pattern for clean URLs
+    /// 7. Return 404
+    /// 8. Replace server-generated errors with configured error pages
     pub async fn handle(
         &self,
-        req: Request<hyper::body::Incoming>,
+        mut req: Request<hyper::body::Incoming>,
     ) -> Result<Response<Full<Bytes>>> {
-        let method = req.method().clone();
         let path = req.uri().path().to_string();
 
         // Health check endpoint (internal)
@@ -201,29 +449,577 @@ impl RequestHandler {
             return self.handle_api(req).await;
         }
 
-        // Find the virtual host and document root
-        let (doc_root, vhost) = self.find_vhost(&req);
-        debug!("Document root: {:?}, path: {}", doc_root, path);
+        let vhost_index = self.find_vhost(&req);
+        let compression =
+            self.compression_for(vhost_index.map(|index| &self.config.virtualhost[index]));
+        if compression.precompressed {
+            req.extensions_mut()
+                .insert(Precompressed(compression.algorithms.clone()));
+        }
+        let error_request = ErrorPageRequest::capture(&req, self.has_error_pages(vhost_index));
+        // HEAD carries no body to compress, only the Vary header
+        let accept_encoding = req
+            .headers()
+            .get(ACCEPT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .filter(|_| req.method() != Method::HEAD)
+            .map(str::to_string);
+        let response = self.route(req, path, vhost_index).await?;
+        let response = self
+            .render_error(response, vhost_index, &error_request)
+            .await?;
+        compression::compress_response(response, accept_encoding.as_deref(), compression).await
+    }
+
+    /// Resolve the virtual host, run rewrites, then pick the location once per request
+    async fn route(
+        &self,
+        mut req: Request<hyper::body::Incoming>,
+        mut path: String,
+        vhost_index: Option<usize>,
+    ) -> Result<Response<Full<Bytes>>> {
+        let vhost = vhost_index.map(|index| &self.config.virtualhost[index]);
+        let mut location = None;
+
+        // Rewrites, locations and file lookups all see the canonical path,
+        // so encoded or dot-segment variants can't slip past access rules
+        let normalized = normalize_path(&path);
+        if normalized != path {
+            let uri = match req.uri().query() {
+                Some(query) => format!("{}?{}", normalized, query),
+                None => normalized.clone(),
+            };
+            let uri = uri
+                .parse()
+                .map_err(|e| anyhow!("Invalid normalized URI {}: {}", uri, e))?;
+            let original = std::mem::replace(req.uri_mut(), uri);
+            req.extensions_mut().insert(OriginalUri(original));
+            path = normalized;
+        }
+
+        if let (Some(index), Some(vhost)) = (vhost_index, vhost) {
+            let doc_root = platform_doc_root(Path::new(&vhost.root), vhost.platform.as_deref());
+            let mut state = RewriteState::new(&path, req.uri().query());
+            let host = request_host(req.headers());
+            let input = RewriteInput {
+                host: &host,
+                method: req.method(),
+                headers: req.headers(),
+                doc_root: &doc_root,
+            };
+
+            match self.run_rewrites(index, &input, &mut state, &mut Vec::new()) {
+                RewriteOutcome::Resolved(resolved) => location = resolved,
+                RewriteOutcome::Redirect { status, location } => {
+                    return self.return_response(status, Some(&location));
+                }
+                RewriteOutcome::Status(status) => return self.status_response(status),
+                RewriteOutcome::Cycle => {
+                    warn!(path = %path, "rewrite cycle detected");
+                    return self.internal_error("Rewrite cycle detected");
+                }
+            }
 
-        let cache_context = self.cache_context(&req, &path, vhost);
-        if let Some(context) = &cache_context {
-            if let Some((data, content_type)) = self.cache.get_with_metadata(&context.key).await {
-                return self.cached_response(&method, &data, &content_type);
+            if state.path != path || state.query.as_deref() != req.uri().query() {
+                debug!("Rewrote {} -> {}", req.uri(), state.uri());
+                let rewritten = state
+                    .uri()
+                    .parse()
+                    .map_err(|e| anyhow!("Invalid rewritten URI {}: {}", state.uri(), e))?;
+                let original = std::mem::replace(req.uri_mut(), rewritten);
+                if req.extensions().get::<OriginalUri>().is_none() {
+                    req.extensions_mut().insert(OriginalUri(original));
+                }
+                path = state.path;
             }
         }
 
-        // Get index files from vhost config or use defaults
-        let index_files = vhost.map(|v| v.index.clone()).unwrap_or_else(|| {
-            vec![
-                "index.php".to_string(),
-                "index.html".to_string(),
-                "index.htm".to_string(),
-            ]
-        });
+        let mut response = self.serve_site(req, path, vhost_index, location).await?;
+
+        if let Some(location) = location {
+            for (name, value) in &location.config.headers {
+                if let (Ok(name), Ok(value)) = (
+                    hyper::header::HeaderName::from_bytes(name.as_bytes()),
+                    HeaderValue::from_str(value),
+                ) {
+                    response.headers_mut().insert(name, value);
+                }
+            }
+        }
+
+        Ok(response)
+    }
+
+    /// Run vhost rules, then location rules until the location is stable
+    ///
+    /// Like nginx, `last` (or a rewrite without a flag) inside a location
+    /// searches the locations again for the new URI; `break` stays put.
+    fn run_rewrites(
+        &self,
+        index: usize,
+        input: &RewriteInput,
+        state: &mut RewriteState,
+        trace: &mut Vec<RewriteTraceEntry>,
+    ) -> RewriteOutcome<'_> {
+        match self.rewrites[index].apply(input, state, trace) {
+            RewriteResult::Redirect { status, location } => {
+                return RewriteOutcome::Redirect { status, location };
+            }
+            RewriteResult::Status(status) => return RewriteOutcome::Status(status),
+            _ => {}
+        }
+
+        for _ in 0..MAX_REWRITE_CYCLES {
+            let location = self.locations[index].resolve(&state.path);
+            let Some(current) = location else {
+                return RewriteOutcome::Resolved(None);
+            };
+
+            let before = state.clone();
+            match current.rewrites.apply(input, state, trace) {
+                RewriteResult::Redirect { status, location } => {
+                    return RewriteOutcome::Redirect { status, location };
+                }
+                RewriteResult::Status(status) => return RewriteOutcome::Status(status),
+                RewriteResult::Last => continue,
+                RewriteResult::Continue if *state != before => continue,
+                _ => return RewriteOutcome::Resolved(location),
+            }
+        }
+
+        RewriteOutcome::Cycle
+    }
+
+    /// Serve a request for a resolved virtual host and location
+    async fn serve_site(
+        &self,
+        mut req: Request<hyper::body::Incoming>,
+        path: String,
+        vhost_index: Option<usize>,
+        location: Option<&Location>,
+    ) -> Result<Response<Full<Bytes>>> {
+        let vhost = vhost_index.map(|index| &self.config.virtualhost[index]);
+        if let Some(location) = location {
+            let client_ip = req.extensions().get::<ClientAddr>().map(|addr| addr.0.ip());
+            if !location.allows(client_ip) {
+                return self.forbidden("Access denied");
+            }
+            if let Some(status) = location.config.return_status {
+                return self.return_response(status, location.config.return_body.as_deref());
+            }
+            if location.denies_php() {
+                req.extensions_mut().insert(PhpDenied);
+            }
+        }
+
+        let (doc_root, mut lookup_path) = self.document_root(vhost, location, &path);
+        debug!("Document root: {:?}, path: {}", doc_root, lookup_path);
+
+        // Per-directory .htaccess overrides
+        let htaccess = match vhost.filter(|v| v.htaccess) {
+            Some(_) => {
+                let chain = self.htaccess.chain(&doc_root, &lookup_path);
+                let client_ip = req.extensions().get::<ClientAddr>().map(|addr| addr.0.ip());
+                if file_name(&lookup_path).starts_with(".ht")
+                    || !chain.allows(client_ip, &file_name(&lookup_path))
+                {
+                    return self.forbidden("Access denied");
+                }
+
+                let host = request_host(req.headers());
+                let mut state = RewriteState::new(&lookup_path, req.uri().query());
+                let input = RewriteInput {
+                    host: &host,
+                    method: req.method(),
+                    headers: req.headers(),
+                    doc_root: &doc_root,
+                };
+                match chain.apply(&input, &mut state, &mut Vec::new()) {
+                    RewriteResult::Redirect { status, location } => {
+                        return self.return_response(status, Some(&location));
+                    }
+                    RewriteResult::Status(status) => return self.status_response(status),
+                    _ => {}
+                }
+
+                if state.path != lookup_path || state.query.as_deref() != req.uri().query() {
+                    debug!(".htaccess rewrote {} -> {}", req.uri(), state.uri());
+                    if !chain.allows(client_ip, &file_name(&state.path)) {
+                        return self.forbidden("Access denied");
+                    }
+                    let rewritten = state
+                        .uri()
+                        .parse()
+                        .map_err(|e| anyhow!("Invalid rewritten URI {}: {}", state.uri(), e))?;
+                    let original = std::mem::replace(req.uri_mut(), rewritten);
+                    if req.extensions().get::<OriginalUri>().is_none() {
+                        req.extensions_mut().insert(OriginalUri(original));
+                    }
+                    lookup_path = state.path;
+                }
+                Some(chain)
+            }
+            None => None,
+        };
+
+        // Fragments are rendered with the headers of the page request
+        let esi = match cache_policy(vhost, location).filter(|policy| policy.esi) {
+            Some(policy) => {
+                req.headers_mut().insert(
+                    "surrogate-capability",
+                    HeaderValue::from_static(esi::SURROGATE_CAPABILITY),
+                );
+                let platform = vhost.and_then(|v| v.platform.as_deref());
+                Some(EsiRequest::capture(&req, policy, platform, vhost_index))
+            }
+            None => None,
+        };
+
+        let served_name = file_name(&lookup_path);
+        let response = self
+            .serve_path(
+                req,
+                lookup_path,
+                &doc_root,
+                vhost,
+                location,
+                htaccess.as_ref(),
+            )
+            .await?;
+        let response = match &esi {
+            Some(page) => self.process_esi(response, page).await?,
+            None => response,
+        };
+
+        match htaccess {
+            Some(chain) => {
+                self.apply_htaccess(&chain, response, &doc_root, &served_name)
+                    .await
+            }
+            None => Ok(response),
+        }
+    }
+
+    /// Document root and lookup path: location root/alias, else the vhost root
+    fn document_root(
+        &self,
+        vhost: Option<&VirtualHostConfig>,
+        location: Option<&Location>,
+        path: &str,
+    ) -> (PathBuf, String) {
+        match location.and_then(|l| l.map_path(path)) {
+            Some((root, lookup_path)) => (PathBuf::from(root), lookup_path),
+            None => {
+                let root = vhost
+                    .map(|v| PathBuf::from(&v.root))
+                    .unwrap_or_else(|| PathBuf::from("/var/www/html"));
+                let platform = vhost.and_then(|v| v.platform.as_deref());
+                (platform_doc_root(&root, platform), path.to_string())
+            }
+        }
+    }
+
+    /// Apply `.htaccess` ErrorDocument and Header directives to a response
+    async fn apply_htaccess(
+        &self,
+        chain: &HtaccessChain,
+        response: Response<Full<Bytes>>,
+        doc_root: &Path,
+        file_name: &str,
+    ) -> Result<Response<Full<Bytes>>> {
+        let status = response.status();
+        let document = chain
+            .error_document(status.as_u16())
+            .filter(|_| status.is_client_error() || status.is_server_error());
+
+        let mut response = match document {
+            Some(document) => match self.error_document(status, document, doc_root).await? {
+                Some(replacement) => replacement,
+                None => response,
+            },
+            None => response,
+        };
+        chain.apply_headers(file_name, response.headers_mut());
+        Ok(response)
+    }
+
+    /// Body for an `ErrorDocument`, keeping the original status
+    /// (external URLs redirect instead)
+    async fn error_document(
+        &self,
+        status: StatusCode,
+        document: &ErrorDocument,
+        doc_root: &Path,
+    ) -> Result<Option<Response<Full<Bytes>>>> {
+        match document {
+            ErrorDocument::Url(url) => self.return_response(302, Some(url)).map(Some),
+            ErrorDocument::Text(text) => Response::builder()
+                .status(status)
+                .header(CONTENT_TYPE, "text/html; charset=utf-8")
+                .header("Server", crate::SERVER_NAME)
+                .body(Full::new(Bytes::from(text.clone())))
+                .map(Some)
+                .map_err(|e| anyhow!("Failed to build response: {}", e)),
+            ErrorDocument::Path(uri) => {
+                let file = self.resolve_path(doc_root, split_uri(uri).0);
+                if !file.is_file() || self.is_php_file(&file) {
+                    debug!("ErrorDocument {} is not a static file, ignoring", uri);
+                    return Ok(None);
+                }
+                let mut response = self.static_handler.serve(&file).await?;
+                *response.status_mut() = status;
+                for header in [ETAG, LAST_MODIFIED, CACHE_CONTROL] {
+                    response.headers_mut().remove(header);
+                }
+                Ok(Some(response))
+            }
+            ErrorDocument::Default => Ok(None),
+        }
+    }
+
+    /// Whether a vhost (or the server) has any error pages configured
+    fn has_error_pages(&self, vhost_index: Option<usize>) -> bool {
+        !self.config.server.error_pages.is_empty()
+            || vhost_index
+                .map(|index| !self.config.virtualhost[index].error_pages.is_empty())
+                .unwrap_or(false)
+    }
+
+    /// Replace a server-generated error with a JSON body or a configured page
+    ///
+    /// Vhost `error_pages` (URIs under the document root) take precedence over
+    /// the server-wide set (file paths). Pages are rendered once: if the page
+    /// is missing or fails itself, the built-in page is sent instead, so an
+    /// error page can never loop.
+    async fn render_error(
+        &self,
+        response: Response<Full<Bytes>>,
+        vhost_index: Option<usize>,
+        request: &ErrorPageRequest,
+    ) -> Result<Response<Full<Bytes>>> {
+        let Some(error) = response.extensions().get::<ServerError>() else {
+            return Ok(response);
+        };
+        let status = response.status();
+
+        if request.wants_json {
+            let mut json = self.json_response_with_status(
+                status,
+                serde_json::json!({
+                    "error": {
+                        "status": status.as_u16(),
+                        "message": error.0,
+                    }
+                }),
+            )?;
+            for (name, value) in response.headers() {
+                if !json.headers().contains_key(name) && name != CONTENT_LENGTH {
+                    json.headers_mut().append(name, value.clone());
+                }
+            }
+            return Ok(json);
+        }
+
+        let Some(parts) = &request.parts else {
+            return Ok(response);
+        };
+        let vhost = vhost_index.map(|index| &self.config.virtualhost[index]);
+        let page = match vhost.and_then(|v| v.error_pages.get(&status.as_u16())) {
+            Some(uri) => {
+                let doc_root = vhost
+                    .map(|v| platform_doc_root(Path::new(&v.root), v.platform.as_deref()))
+                    .unwrap_or_default();
+                let uri = split_uri(uri).0.to_string();
+                Some((self.resolve_path(&doc_root, &uri), doc_root, uri))
+            }
+            None => self
+                .config
+                .server
+                .error_pages
+                .get(&status.as_u16())
+                .map(|file| {
+                    let file = PathBuf::from(file);
+                    let doc_root = file.parent().map(Path::to_path_buf).unwrap_or_default();
+                    let uri = format!("/{}", file_name(&file.to_string_lossy()));
+                    (file, doc_root, uri)
+                }),
+        };
+        let Some((file, doc_root, uri)) = page else {
+            return Ok(response);
+        };
+
+        if !file.is_file() {
+            warn!(
+                "error page {} for status {} does not exist, using built-in page",
+                file.display(),
+                status.as_u16()
+            );
+            return Ok(response);
+        }
+
+        let mut page = if self.is_php_file(&file) {
+            let mut parts = parts.clone();
+            parts.extensions.insert(ErrorPageStatus(status.as_u16()));
+            let page = self
+                .execute_php(&parts, &doc_root, &file, &uri, "", Vec::new())
+                .await?;
+            if page.extensions().get::<ServerError>().is_some() {
+                warn!(
+                    "error page {} failed with status {}, using built-in page",
+                    file.display(),
+                    page.status().as_u16()
+                );
+                return Ok(response);
+            }
+            page
+        } else {
+            let mut page = self.static_handler.serve(&file).await?;
+            for header in [ETAG, LAST_MODIFIED, CACHE_CONTROL] {
+                page.headers_mut().remove(header);
+            }
+            page
+        };
+
+        *page.status_mut() = status;
+        for (name, value) in response.headers() {
+            if !page.headers().contains_key(name) && name != CONTENT_LENGTH && name != CONTENT_TYPE
+            {
+                page.headers_mut().append(name, value.clone());
+            }
+        }
+        Ok(page)
+    }
+
+    /// Serve a mapped path: cache lookup, then try_files or the built-in lookup order
+    async fn serve_path(
+        &self,
+        mut req: Request<hyper::body::Incoming>,
+        lookup_path: String,
+        doc_root: &Path,
+        vhost: Option<&VirtualHostConfig>,
+        location: Option<&Location>,
+        htaccess: Option<&HtaccessChain>,
+    ) -> Result<Response<Full<Bytes>>> {
+        let method = req.method().clone();
+        let platform = vhost.and_then(|v| v.platform.as_deref());
+        let doc_root = doc_root.to_path_buf();
+
+        let cache_policy = cache_policy(vhost, location);
+        // ESI shells are assembled per response: no 304s, compressed later
+        let esi = cache_policy.is_some_and(|policy| policy.esi);
+        let session = cache_policy
+            .filter(|policy| policy.private && self.config.cache.enable)
+            .and_then(|policy| bypass::session_fingerprint(policy, platform, req.headers()))
+            .map(PrivateSession::new);
+        let mut cache_context = self.cache_context(
+            &req,
+            client_uri(&req).path(),
+            cache_policy,
+            platform,
+            session.as_ref(),
+        );
+        if let Some(policy) = cache_policy.filter(|policy| !policy.query_passthrough) {
+            strip_query_params(&mut req, &policy.query_ignore)?;
+        }
+        let path = lookup_path;
+        let revalidation = self.warmer.is_revalidation(req.headers());
+        // Held until the response is stored, so concurrent misses wait for it
+        let mut _inflight = None;
+        let no_headers = HeaderMap::new();
+        if let Some(context) = cache_context.as_mut().ok().filter(|_| !revalidation) {
+            let compression = self.compression_for(vhost);
+            let encoding =
+                cache_variant_encoding(&method, req.headers(), compression).filter(|_| !esi);
+            let conditional_headers = if esi { &no_headers } else { req.headers() };
+
+            if let Some(session) = &context.private {
+                // Private pages: no stale copies, coalescing or stored variants
+                if let Some(page) = self.cache.private().get(session, &context.key) {
+                    let mut response = self
+                        .cached_response(
+                            &method,
+                            conditional_headers,
+                            &context.key,
+                            page,
+                            None,
+                            compression,
+                        )
+                        .await?;
+                    response
+                        .headers_mut()
+                        .insert("X-Cache-Scope", HeaderValue::from_static("private"));
+                    return Ok(response);
+                }
+            } else {
+                match self.cache.lookup_page(&context.key, encoding).await {
+                    Some((page, Freshness::StaleIfError)) => context.fallback = Some(page),
+                    Some((page, freshness)) => {
+                        if freshness == Freshness::Stale {
+                            self.revalidate_in_background(&req, &context.key);
+                        }
+                        let mut response = self
+                            .cached_response(
+                                &method,
+                                conditional_headers,
+                                &context.key,
+                                page,
+                                encoding,
+                                compression,
+                            )
+                            .await?;
+                        if freshness == Freshness::Stale {
+                            response
+                                .headers_mut()
+                                .insert("X-Cache", HeaderValue::from_static("STALE"));
+                        }
+                        return Ok(response);
+                    }
+                    None => {}
+                }
+            }
+
+            if self.config.cache.coalesce && method == Method::GET && context.private.is_none() {
+                match self.cache.join_inflight(&context.key) {
+                    Inflight::Leader(guard) => _inflight = Some(guard),
+                    Inflight::Follower(receiver) => {
+                        let timeout = Duration::from_millis(self.config.cache.coalesce_timeout_ms);
+                        if self.cache.wait_inflight(receiver, timeout).await {
+                            // Uncacheable leader result: render independently
+                            let page = self.cache.get_page_variant(&context.key, encoding).await;
+                            self.cache.record_coalesced(page.is_some());
+                            if let Some(page) = page {
+                                return self
+                                    .cached_response(
+                                        &method,
+                                        conditional_headers,
+                                        &context.key,
+                                        page,
+                                        encoding,
+                                        compression,
+                                    )
+                                    .await;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // Get index files from .htaccess, the vhost config or use defaults
+        let index_files = match htaccess.and_then(|chain| chain.directory_index()) {
+            Some(index_files) => index_files.to_vec(),
+            None => vhost.map(|v| v.index.clone()).unwrap_or_else(|| {
+                vec![
+                    "index.php".to_string(),
+                    "index.html".to_string(),
+                    "index.htm".to_string(),
+                ]
+            }),
+        };
 
         // Read the request body for POST/PUT requests
         // We need to consume the body before we can use the request further
-        let (parts, incoming_body) = req.into_parts();
+        let (mut parts, incoming_body) = req.into_parts();
 
         let body = if method == Method::POST || method == Method::PUT {
             match incoming_body.collect().await {
@@ -237,6 +1033,19 @@ impl RequestHandler {
             Vec::new()
         };
 
+        // Configured try_files (or platform preset) replaces the built-in lookup order
+        let try_files = location
+            .and_then(|l| l.try_files.clone())
+            .or_else(|| vhost.and_then(|v| TryFiles::for_vhost(&v.try_files, platform)));
+        if let Some(try_files) = try_files {
+            let response = self
+                .serve_try_files(&try_files, &mut parts, &doc_root, &path, &index_files, body)
+                .await?;
+            return self
+                .finalize_response(response, cache_context.as_ref(), session.as_ref(), &method)
+                .await;
+        }
+
         // Create a reference-like wrapper with the request parts for PHP execution
         let req_parts = &parts;
 
@@ -253,13 +1062,13 @@ impl RequestHandler {
                     .execute_php(req_parts, &doc_root, &file_path, &path, "", body)
                     .await?;
                 return self
-                    .finalize_response(response, cache_context.as_ref(), &method)
+                    .finalize_response(response, cache_context.as_ref(), session.as_ref(), &method)
                     .await;
             } else {
                 // Static file - serve it
                 let response = self.serve_static_parts(req_parts, &file_path).await?;
                 return self
-                    .finalize_response(response, cache_context.as_ref(), &method)
+                    .finalize_response(response, cache_context.as_ref(), session.as_ref(), &method)
                     .await;
             }
         }
@@ -276,12 +1085,22 @@ impl RequestHandler {
                             .execute_php(req_parts, &doc_root, &index_path, &index_uri, "", body)
                             .await?;
                         return self
-                            .finalize_response(response, cache_context.as_ref(), &method)
+                            .finalize_response(
+                                response,
+                                cache_context.as_ref(),
+                                session.as_ref(),
+                                &method,
+                            )
                             .await;
                     } else {
                         let response = self.serve_static_parts(req_parts, &index_path).await?;
                         return self
-                            .finalize_response(response, cache_context.as_ref(), &method)
+                            .finalize_response(
+                                response,
+                                cache_context.as_ref(),
+                                session.as_ref(),
+                                &method,
+                            )
                             .await;
                     }
                 }
@@ -289,7 +1108,7 @@ impl RequestHandler {
             // No index file found - return 403 (no directory listing)
             let response = self.forbidden("Directory listing denied")?;
             return self
-                .finalize_response(response, cache_context.as_ref(), &method)
+                .finalize_response(response, cache_context.as_ref(), session.as_ref(), &method)
                 .await;
         }
 
@@ -307,7 +1126,7 @@ impl RequestHandler {
                 )
                 .await?;
             return self
-                .finalize_response(response, cache_context.as_ref(), &method)
+                .finalize_response(response, cache_context.as_ref(), session.as_ref(), &method)
                 .await;
         }
 
@@ -321,9 +1140,11 @@ impl RequestHandler {
                     "Using front controller pattern: index.php with PATH_INFO={}",
                     path
                 );
+                // Internal redirect, like a try_files fallback
+                parts.extensions.remove::<PhpDenied>();
                 let response = self
                     .execute_php(
-                        req_parts,
+                        &parts,
                         &doc_root,
                         &front_controller,
                         "/index.php",
@@ -332,65 +1153,183 @@ impl RequestHandler {
                     )
                     .await?;
                 return self
-                    .finalize_response(response, cache_context.as_ref(), &method)
+                    .finalize_response(response, cache_context.as_ref(), session.as_ref(), &method)
                     .await;
             }
         }
 
         // Step 5: Nothing found - return 404
         let response = self.not_found()?;
-        self.finalize_response(response, cache_context.as_ref(), &method)
+        self.finalize_response(response, cache_context.as_ref(), session.as_ref(), &method)
             .await
     }
 
-    /// Check if a file is a PHP file
-    fn is_php_file(&self, path: &Path) -> bool {
-        path.extension()
-            .map(|ext| ext.to_str().unwrap_or("").to_lowercase() == "php")
-            .unwrap_or(false)
-    }
-
-    /// Resolve PHP path with PATH_INFO support
+    /// Resolve a request through a try_files list
     ///
-    /// For a URL like /blog/index.php/post/123:
-    /// - script_filename: /var/www/blog/index.php
-    /// - script_name: /blog/index.php
-    /// - path_info: /post/123
-    fn resolve_php_path_info(&self, doc_root: &Path, uri_path: &str) -> Option<PhpPathInfo> {
-        // Split the path and look for a PHP file
-        let parts: Vec<&str> = uri_path.split('/').collect();
-        let mut accumulated_path = String::new();
-
-        for (i, part) in parts.iter().enumerate() {
-            if !part.is_empty() {
-                accumulated_path.push('/');
-                accumulated_path.push_str(part);
-            }
+    /// Candidates are checked in order; `$uri/` entries match directories and
+    /// serve their index file. Scripts addressed with PATH_INFO
+    /// (`/index.php/foo`) still work. The fallback is an internal redirect
+    /// (REQUEST_URI keeps the original URI) or a bare status.
+    async fn serve_try_files(
+        &self,
+        try_files: &TryFiles,
+        parts: &mut hyper::http::request::Parts,
+        doc_root: &Path,
+        path: &str,
+        index_files: &[String],
+        body: Vec<u8>,
+    ) -> Result<Response<Full<Bytes>>> {
+        let query = parts.uri.query().map(str::to_string);
 
-            // Check if this accumulated path is a PHP file
-            if part.ends_with(".php") || part.contains(".php") {
-                let script_path = self.resolve_path(doc_root, &accumulated_path);
-                if script_path.is_file() && self.is_php_file(&script_path) {
-                    // Found a PHP file - rest is PATH_INFO
-                    let path_info = if i + 1 < parts.len() {
-                        format!("/{}", parts[i + 1..].join("/"))
-                    } else {
-                        String::new()
-                    };
+        for candidate in &try_files.candidates {
+            let expanded = expand_variables(candidate, path, query.as_deref());
+            let (candidate_path, _) = split_uri(&expanded);
+            let file_path = self.resolve_path(doc_root, candidate_path);
 
-                    return Some(PhpPathInfo {
-                        script_filename: script_path,
-                        script_name: accumulated_path,
-                        path_info,
-                    });
+            if candidate_path.ends_with('/') {
+                if !file_path.is_dir() {
+                    continue;
                 }
+                return match self.find_index_file(&file_path, index_files) {
+                    Some((index_path, index)) => {
+                        let index_uri =
+                            format!("{}/{}", candidate_path.trim_end_matches('/'), index);
+                        self.serve_file(parts, doc_root, &index_path, &index_uri, body)
+                            .await
+                    }
+                    None => self.forbidden("Directory listing denied"),
+                };
             }
-        }
 
-        None
-    }
+            if file_path.is_file() {
+                return self
+                    .serve_file(parts, doc_root, &file_path, candidate_path, body)
+                    .await;
+            }
+        }
 
-    /// Execute a PHP script
+        if let Some(php_info) = self.resolve_php_path_info(doc_root, path) {
+            return self
+                .execute_php(
+                    parts,
+                    doc_root,
+                    &php_info.script_filename,
+                    &php_info.script_name,
+                    &php_info.path_info,
+                    body,
+                )
+                .await;
+        }
+
+        let template = match &try_files.fallback {
+            TryFilesFallback::Status(code) => return self.status_response(*code),
+            TryFilesFallback::Uri(template) => template,
+        };
+
+        let expanded = expand_variables(template, path, query.as_deref());
+        let (target_path, target_query) = split_uri(&expanded);
+        let target_file = self.resolve_path(doc_root, target_path);
+        if !target_file.is_file() {
+            return self.not_found();
+        }
+
+        debug!("try_files fallback: {} -> {}", path, expanded);
+        // Internal redirect to a configured target, not a script the client named
+        parts.extensions.remove::<PhpDenied>();
+        if self.is_php_file(&target_file) {
+            let target_uri = match target_query {
+                Some(q) => format!("{}?{}", target_path, q),
+                None => target_path.to_string(),
+            };
+            if let Ok(uri) = target_uri.parse() {
+                let original = std::mem::replace(&mut parts.uri, uri);
+                if parts.extensions.get::<OriginalUri>().is_none() {
+                    parts.extensions.insert(OriginalUri(original));
+                }
+            }
+        }
+
+        self.serve_file(parts, doc_root, &target_file, target_path, body)
+            .await
+    }
+
+    /// Serve a resolved file: execute PHP scripts, otherwise send it as static content
+    async fn serve_file(
+        &self,
+        req_parts: &hyper::http::request::Parts,
+        doc_root: &Path,
+        file_path: &Path,
+        script_name: &str,
+        body: Vec<u8>,
+    ) -> Result<Response<Full<Bytes>>> {
+        if self.is_php_file(file_path) {
+            self.execute_php(req_parts, doc_root, file_path, script_name, "", body)
+                .await
+        } else {
+            self.serve_static_parts(req_parts, file_path).await
+        }
+    }
+
+    /// First existing index file in a directory
+    fn find_index_file<'a>(
+        &self,
+        dir: &Path,
+        index_files: &'a [String],
+    ) -> Option<(PathBuf, &'a str)> {
+        index_files
+            .iter()
+            .map(|index| (dir.join(index), index.as_str()))
+            .find(|(index_path, _)| index_path.is_file())
+    }
+
+    /// Check if a file is a PHP file
+    fn is_php_file(&self, path: &Path) -> bool {
+        path.extension()
+            .map(|ext| ext.to_str().unwrap_or("").to_lowercase() == "php")
+            .unwrap_or(false)
+    }
+
+    /// Resolve PHP path with PATH_INFO support
+    ///
+    /// For a URL like /blog/index.php/post/123:
+    /// - script_filename: /var/www/blog/index.php
+    /// - script_name: /blog/index.php
+    /// - path_info: /post/123
+    fn resolve_php_path_info(&self, doc_root: &Path, uri_path: &str) -> Option<PhpPathInfo> {
+        // Split the path and look for a PHP file
+        let parts: Vec<&str> = uri_path.split('/').collect();
+        let mut accumulated_path = String::new();
+
+        for (i, part) in parts.iter().enumerate() {
+            if !part.is_empty() {
+                accumulated_path.push('/');
+                accumulated_path.push_str(part);
+            }
+
+            // Check if this accumulated path is a PHP file
+            if part.ends_with(".php") || part.contains(".php") {
+                let script_path = self.resolve_path(doc_root, &accumulated_path);
+                if script_path.is_file() && self.is_php_file(&script_path) {
+                    // Found a PHP file - rest is PATH_INFO
+                    let path_info = if i + 1 < parts.len() {
+                        format!("/{}", parts[i + 1..].join("/"))
+                    } else {
+                        String::new()
+                    };
+
+                    return Some(PhpPathInfo {
+                        script_filename: script_path,
+                        script_name: accumulated_path,
+                        path_info,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Execute a PHP script
     async fn execute_php(
         &self,
         req_parts: &hyper::http::request::Parts,
@@ -400,10 +1339,17 @@ impl RequestHandler {
         path_info: &str,
         body: Vec<u8>,
     ) -> Result<Response<Full<Bytes>>> {
+        if req_parts.extensions.get::<PhpDenied>().is_some() {
+            return self.forbidden("PHP execution is not allowed here");
+        }
+
         // Check if PHP is available
         if !self.php_pool.is_available() {
             warn!("PHP requested but not available: {}", script_name);
-            return self.internal_error("PHP is not available on this server");
+            return self.error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "PHP is not available on this server",
+            );
         }
 
         debug!(
@@ -431,7 +1377,7 @@ impl RequestHandler {
                 Ok(resp) => self.build_embed_response(resp),
                 Err(e) => {
                     warn!("PHP embed execution error: {}", e);
-                    self.internal_error(&format!("PHP Error: {}", e))
+                    self.php_failure(&e)
                 }
             }
         } else {
@@ -454,7 +1400,7 @@ impl RequestHandler {
                 }
                 Err(e) => {
                     warn!("PHP execution error: {}", e);
-                    self.internal_error(&format!("PHP Error: {}", e))
+                    self.php_failure(&e)
                 }
             }
         }
@@ -559,7 +1505,8 @@ impl RequestHandler {
                                 continue;
                             }
 
-                            match name.to_lowercase().as_str() {
+                            let lower = name.to_lowercase();
+                            match lower.as_str() {
                                 "status" => {
                                     if let Some(code) = value.split_whitespace().next() {
                                         if let Ok(code) = code.parse::<u16>() {
@@ -577,17 +1524,20 @@ impl RequestHandler {
                                     }
                                     builder = builder.header("Location", value);
                                 }
-                                "set-cookie"
-                                | "cache-control"
-                                | "expires"
-                                | "pragma"
-                                | "x-powered-by"
-                                | "x-frame-options"
-                                | "x-content-type-options" => {
+                                "set-cookie" => {
                                     builder = builder.header(name, value);
                                 }
+                                _ if HOP_BY_HOP_HEADERS.contains(&lower.as_str())
+                                    || UNCACHED_RESPONSE_HEADERS.contains(&lower.as_str()) =>
+                                {
+                                    // Connection-level or regenerated by us
+                                }
                                 _ => {
-                                    // Skip unknown headers from PHP to avoid issues
+                                    // Cache headers among these are read and
+                                    // removed by finalize_response
+                                    if HeaderValue::from_str(value).is_ok() {
+                                        builder = builder.header(name, value);
+                                    }
                                 }
                             }
                         }
@@ -605,20 +1555,6 @@ impl RequestHandler {
             .map_err(|e| anyhow!("Failed to build response: {}", e))
     }
 
-    /// Serve a static file
-    async fn serve_static(
-        &self,
-        req: &Request<hyper::body::Incoming>,
-        path: &Path,
-    ) -> Result<Response<Full<Bytes>>> {
-        // Only GET and HEAD for static files
-        if req.method() != Method::GET && req.method() != Method::HEAD {
-            return self.method_not_allowed();
-        }
-
-        self.static_handler.serve(path).await
-    }
-
     /// Serve a static file (using request parts)
     async fn serve_static_parts(
         &self,
@@ -630,7 +1566,14 @@ impl RequestHandler {
             return self.method_not_allowed();
         }
 
-        self.static_handler.serve(path).await
+        let precompressed = req_parts
+            .extensions
+            .get::<Precompressed>()
+            .map(|p| p.0.as_slice())
+            .unwrap_or_default();
+        self.static_handler
+            .serve_conditional(path, &req_parts.method, &req_parts.headers, precompressed)
+            .await
     }
 
     /// Handle API requests
@@ -668,6 +1611,15 @@ impl RequestHandler {
         if method == Method::GET && path == "/api/v1/workers" {
             return self.api_workers();
         }
+        if method == Method::GET && path == "/api/v1/cron" {
+            return self.api_cron();
+        }
+        if method == Method::POST && path == "/api/v1/cron/run" {
+            return self.api_cron_run(&req);
+        }
+        if method == Method::POST && path == "/api/v1/rewrite/test" {
+            return self.api_rewrite_test(req).await;
+        }
 
         self.not_found()
     }
@@ -729,6 +1681,7 @@ impl RequestHandler {
                 "default_ttl": self.config.cache.default_ttl,
                 "disk_path": self.config.cache.disk_path,
                 "redis_url": self.config.cache.redis_url,
+                "memcached_servers": self.config.cache.memcached_servers,
             },
             "vhosts": vhosts
         }))
@@ -749,7 +1702,7 @@ impl RequestHandler {
             self.cache.remove(&key).await;
             format!("Purged cache key: {}", key)
         } else if let (Some(domain), Some(path)) = (domain.clone(), path) {
-            let base_key = build_page_cache_key(&domain, &path);
+            let base_key = self.purge_page_key(&domain, &path);
             let key_prefix = format!("{}:", base_key);
             let mut purged = self.cache.purge_by_prefix_count(&key_prefix).await;
             purged += self.cache.remove_with_count(&base_key).await;
@@ -866,7 +1819,7 @@ impl RequestHandler {
                         let prefix_key = build_page_cache_key(domain, prefix_path);
                         affected += self.cache.purge_by_prefix_count(&prefix_key).await;
                     } else {
-                        let base_key = build_page_cache_key(domain, &path);
+                        let base_key = self.purge_page_key(domain, &path);
                         let key_prefix = format!("{}:", base_key);
                         affected += self.cache.purge_by_prefix_count(&key_prefix).await;
                         affected += self.cache.remove_with_count(&base_key).await;
@@ -1022,26 +1975,186 @@ impl RequestHandler {
         self.json_response(workers)
     }
 
-    /// Find virtual host for request
-    fn find_vhost(
+    /// API: Cron jobs and their recent runs
+    fn api_cron(&self) -> Result<Response<Full<Bytes>>> {
+        self.json_response(self.scheduler.stats_json())
+    }
+
+    /// API: Run a cron job immediately
+    fn api_cron_run(&self, req: &Request<hyper::body::Incoming>) -> Result<Response<Full<Bytes>>> {
+        let query = req.uri().query().unwrap_or("");
+        let Some(job) = self.query_param(query, "job") else {
+            return self.json_error_response(
+                StatusCode::BAD_REQUEST,
+                "missing 'job' query parameter",
+                None,
+            );
+        };
+
+        match self.scheduler.trigger(&job) {
+            Some(started) => self.json_response_with_status(
+                StatusCode::ACCEPTED,
+                serde_json::json!({
+                    "success": started,
+                    "job": job,
+                    "message": if started {
+                        "cron job started"
+                    } else {
+                        "cron job is already running"
+                    }
+                }),
+            ),
+            None => self.json_error_response(
+                StatusCode::NOT_FOUND,
+                &format!("unknown cron job: {}", job),
+                None,
+            ),
+        }
+    }
+
+    /// API: Show which rewrite rules fire for a URL
+    async fn api_rewrite_test(
         &self,
-        req: &Request<hyper::body::Incoming>,
-    ) -> (PathBuf, Option<&crate::config::VirtualHostConfig>) {
-        let host = req
-            .headers()
-            .get("host")
-            .and_then(|h| h.to_str().ok())
-            .unwrap_or("localhost");
+        req: Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>> {
+        let body = req.into_body().collect().await?.to_bytes();
+        let payload: RewriteTestRequest = match serde_json::from_slice(&body) {
+            Ok(payload) => payload,
+            Err(err) => {
+                return self.json_error_response(
+                    StatusCode::BAD_REQUEST,
+                    &format!(
+                        "invalid rewrite test payload: {}. expected {{\"url\": \"...\"}}",
+                        err
+                    ),
+                    None,
+                )
+            }
+        };
 
-        let host = host.split(':').next().unwrap_or(host);
+        let uri: hyper::Uri = match payload.url.parse() {
+            Ok(uri) => uri,
+            Err(err) => {
+                return self.json_error_response(
+                    StatusCode::BAD_REQUEST,
+                    &format!("invalid url: {}", err),
+                    None,
+                )
+            }
+        };
+        let Ok(method) = payload.method.as_deref().unwrap_or("GET").parse::<Method>() else {
+            return self.json_error_response(StatusCode::BAD_REQUEST, "invalid method", None);
+        };
 
-        for vhost in &self.config.virtualhost {
-            if vhost.domain == host || vhost.domain == "*" {
-                return (PathBuf::from(&vhost.root), Some(vhost));
+        let mut headers = HeaderMap::new();
+        for (name, value) in &payload.headers {
+            if let (Ok(name), Ok(value)) = (
+                hyper::header::HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                headers.insert(name, value);
             }
         }
+        let host = uri
+            .host()
+            .map(|host| host.to_ascii_lowercase())
+            .unwrap_or_else(|| request_host(&headers));
+
+        let listener = match uri.scheme_str() {
+            Some("https") => ListenerKind::Https,
+            _ => ListenerKind::Http,
+        };
+        let Some(index) = self.vhosts.lookup(&host, listener) else {
+            return self.json_response(serde_json::json!({
+                "url": payload.url,
+                "vhost": null,
+                "fired": [],
+                "result": { "action": "none", "uri": uri.path_and_query().map(|pq| pq.as_str()) }
+            }));
+        };
+
+        let vhost = &self.config.virtualhost[index];
+        let doc_root = platform_doc_root(Path::new(&vhost.root), vhost.platform.as_deref());
+        let mut state = RewriteState::new(&normalize_path(uri.path()), uri.query());
+        let original = state.clone();
+        let input = RewriteInput {
+            host: &host,
+            method: &method,
+            headers: &headers,
+            doc_root: &doc_root,
+        };
+        let mut trace = Vec::new();
+
+        let result = match self.run_rewrites(index, &input, &mut state, &mut trace) {
+            RewriteOutcome::Resolved(location) => serde_json::json!({
+                "action": if state == original { "none" } else { "rewrite" },
+                "uri": state.uri(),
+                "location": location.map(|l| l.config.path.clone()),
+            }),
+            RewriteOutcome::Redirect { status, location } => serde_json::json!({
+                "action": "redirect",
+                "status": status,
+                "location": location,
+            }),
+            RewriteOutcome::Status(status) => serde_json::json!({
+                "action": "status",
+                "status": status,
+            }),
+            RewriteOutcome::Cycle => serde_json::json!({
+                "action": "error",
+                "error": "rewrite cycle detected",
+            }),
+        };
+
+        self.json_response(serde_json::json!({
+            "url": payload.url,
+            "vhost": vhost.domain,
+            "fired": trace,
+            "result": result,
+        }))
+    }
+
+    /// Find virtual host for request (index into `config.virtualhost`)
+    fn find_vhost(&self, req: &Request<hyper::body::Incoming>) -> Option<usize> {
+        let listener = req
+            .extensions()
+            .get::<ListenerKind>()
+            .copied()
+            .unwrap_or(ListenerKind::Http);
+        self.vhosts.lookup(&request_host(req.headers()), listener)
+    }
+
+    /// Refresh a stale page with one loopback request per key
+    fn revalidate_in_background(&self, req: &Request<hyper::body::Incoming>, key: &str) {
+        if !self.cache.try_begin_revalidation(key) {
+            return;
+        }
+
+        let cache = self.cache.clone();
+        let warmer = self.warmer.clone();
+        let key = key.to_string();
+        let path_and_query = client_uri(req)
+            .path_and_query()
+            .map(|pq| pq.as_str().to_string())
+            .unwrap_or_else(|| "/".to_string());
+        let headers = req.headers().clone();
+        tokio::spawn(async move {
+            debug!("Revalidating stale page {}", key);
+            if let Err(err) = warmer.revalidate(&path_and_query, &headers).await {
+                warn!(key = %key, "stale page revalidation failed: {}", err);
+            }
+            cache.end_revalidation(&key);
+        });
+    }
 
-        (PathBuf::from("/var/www/html"), None)
+    /// Compression settings of a vhost, or the global `[compression]` section
+    fn compression_for<'a>(
+        &'a self,
+        vhost: Option<&'a VirtualHostConfig>,
+    ) -> &'a CompressionConfig {
+        vhost
+            .and_then(|v| v.compression.as_ref())
+            .unwrap_or(&self.config.compression)
     }
 
     /// Resolve path to file system path (with security checks)
@@ -1062,55 +2175,78 @@ impl RequestHandler {
     }
 
     /// Generate cache key for request
-    fn cache_key(&self, req: &Request<hyper::body::Incoming>) -> String {
-        let host = req
-            .headers()
+    fn cache_key(
+        &self,
+        req: &Request<hyper::body::Incoming>,
+        path_and_query: &str,
+        vary: &[VaryRule],
+    ) -> String {
+        let headers = req.headers();
+        let host = headers
             .get("host")
             .and_then(|h| h.to_str().ok())
             .unwrap_or("localhost");
-        let path = req
-            .uri()
-            .path_and_query()
-            .map(|pq| pq.as_str())
-            .unwrap_or(req.uri().path());
 
         build_page_cache_key_scoped(
             host,
-            self.cache_site(req).as_deref(),
-            self.cache_store(req).as_deref(),
-            self.cache_variant(req).as_deref(),
-            path,
+            self.cache_site(headers).as_deref(),
+            self.cache_store(headers).as_deref(),
+            self.cache_variant(headers, vary).as_deref(),
+            path_and_query,
         )
     }
 
-    fn cache_site(&self, req: &Request<hyper::body::Incoming>) -> Option<String> {
-        req.headers()
+    /// Page key for a purge target, its query in the vhost's cache-key form
+    fn purge_page_key(&self, domain: &str, path: &str) -> String {
+        let Some((path_only, query)) = path.split_once('?') else {
+            return build_page_cache_key(domain, path);
+        };
+        let policy = self
+            .vhosts
+            .lookup(domain, ListenerKind::Http)
+            .and_then(|index| self.config.virtualhost[index].cache.as_ref())
+            .unwrap_or(&DEFAULT_CACHE_POLICY);
+        let query = normalize_query(query, &policy.query_ignore, &[], policy.query_sort)
+            .unwrap_or_default();
+        if query.is_empty() {
+            build_page_cache_key(domain, path_only)
+        } else {
+            build_page_cache_key(domain, &format!("{}?{}", path_only, query))
+        }
+    }
+
+    fn cache_site(&self, headers: &HeaderMap) -> Option<String> {
+        headers
             .get("x-veloserve-site")
-            .or_else(|| req.headers().get("x-site-id"))
+            .or_else(|| headers.get("x-site-id"))
             .and_then(|value| value.to_str().ok())
             .map(str::trim)
             .filter(|value| !value.is_empty())
             .map(|value| value.to_ascii_lowercase())
     }
 
-    fn cache_store(&self, req: &Request<hyper::body::Incoming>) -> Option<String> {
-        req.headers()
+    fn cache_store(&self, headers: &HeaderMap) -> Option<String> {
+        headers
             .get("x-magento-store")
-            .or_else(|| req.headers().get("x-store-id"))
+            .or_else(|| headers.get("x-store-id"))
             .and_then(|value| value.to_str().ok())
             .map(str::trim)
             .filter(|value| !value.is_empty())
             .map(|value| value.to_ascii_lowercase())
     }
 
-    fn cache_variant(&self, req: &Request<hyper::body::Incoming>) -> Option<String> {
-        req.headers()
+    /// Explicit `X-VeloServe-Cache-Variant`, then the configured vary rules
+    fn cache_variant(&self, headers: &HeaderMap, vary: &[VaryRule]) -> Option<String> {
+        let explicit = headers
             .get("x-veloserve-cache-variant")
-            .or_else(|| req.headers().get("accept-language"))
             .and_then(|value| value.to_str().ok())
             .map(str::trim)
             .filter(|value| !value.is_empty())
-            .map(|value| value.to_ascii_lowercase())
+            .map(|value| value.to_ascii_lowercase());
+        match (explicit, vary::request_variant(vary, headers)) {
+            (Some(explicit), Some(vary)) => Some(format!("{}+{}", explicit, vary)),
+            (explicit, vary) => explicit.or(vary),
+        }
     }
 
     fn query_param(&self, query: &str, key: &str) -> Option<String> {
@@ -1310,11 +2446,37 @@ impl RequestHandler {
         &self,
         req: &Request<hyper::body::Incoming>,
         path: &str,
-        vhost: Option<&crate::config::VirtualHostConfig>,
-    ) -> Option<CacheContext> {
-        if !self.config.cache.enable || !self.is_cacheable_request(req, path, vhost) {
-            return None;
+        cache_policy: Option<&VHostCacheConfig>,
+        platform: Option<&str>,
+        session: Option<&PrivateSession>,
+    ) -> Result<CacheContext, BypassReason> {
+        if !self.config.cache.enable {
+            return Err(BypassReason::Disabled);
         }
+        let policy = cache_policy.unwrap_or(&DEFAULT_CACHE_POLICY);
+        // Cookies are checked last: a session passed every other rule
+        let private = match bypass::check(policy, platform, req.method(), req.headers(), path) {
+            None => None,
+            Some(BypassReason::Cookie(_)) if session.is_some() => session.cloned(),
+            Some(reason) => return Err(reason),
+        };
+
+        // Ignored parameters are dropped; unlisted ones bypass the cache
+        let uri = client_uri(req);
+        let path_and_query = match uri.query() {
+            Some(query) => match normalize_query(
+                query,
+                &policy.query_ignore,
+                &policy.query_include,
+                policy.query_sort,
+            )
+            .ok_or(BypassReason::Query)?
+            {
+                query if query.is_empty() => uri.path().to_string(),
+                query => format!("{}?{}", uri.path(), query),
+            },
+            None => uri.path().to_string(),
+        };
 
         let host = req
             .headers()
@@ -1323,119 +2485,235 @@ impl RequestHandler {
             .unwrap_or("localhost");
         let host = host.split(':').next().unwrap_or(host).to_string();
 
-        let ttl = vhost
-            .and_then(|v| v.cache.as_ref().map(|c| c.ttl))
+        let ttl = cache_policy
+            .map(|c| c.ttl)
             .unwrap_or(self.config.cache.default_ttl);
-
-        Some(CacheContext {
-            key: self.cache_key(req),
+        let stale_while_revalidate = cache_policy
+            .and_then(|c| c.stale_while_revalidate)
+            .unwrap_or(self.config.cache.stale_while_revalidate);
+        let stale_if_error = cache_policy
+            .and_then(|c| c.stale_if_error)
+            .unwrap_or(self.config.cache.stale_if_error);
+        let cache_redirects = cache_policy
+            .and_then(|c| c.cache_redirects)
+            .unwrap_or(self.config.cache.cache_redirects);
+        let negative_ttl = cache_policy
+            .and_then(|c| c.negative_ttl)
+            .unwrap_or(self.config.cache.negative_ttl);
+        let vary = cache_policy
+            .map(|c| vary::parse_rules(&c.vary))
+            .unwrap_or_default();
+
+        Ok(CacheContext {
+            key: self.cache_key(req, &path_and_query, &vary),
             domain: host,
             path: path.to_string(),
             ttl: Duration::from_secs(ttl),
+            stale_while_revalidate: Duration::from_secs(stale_while_revalidate),
+            stale_if_error: Duration::from_secs(stale_if_error),
+            cache_redirects,
+            negative_ttl: Duration::from_secs(negative_ttl),
+            vary,
+            private,
+            fallback: None,
         })
     }
 
-    fn is_cacheable_request(
+    /// Response for a page cache hit; revalidations get a 304
+    ///
+    /// With `encoding` the stored variant is sent, compressing and storing
+    /// it on the first hit that asks for it.
+    async fn cached_response(
         &self,
-        req: &Request<hyper::body::Incoming>,
-        path: &str,
-        vhost: Option<&crate::config::VirtualHostConfig>,
-    ) -> bool {
-        if req.method() != Method::GET && req.method() != Method::HEAD {
-            return false;
-        }
-        if req.uri().query().is_some() {
-            return false;
-        }
-        if self.is_authenticated_request(req) {
-            return false;
+        method: &Method,
+        headers: &HeaderMap,
+        key: &str,
+        mut page: CachedPage,
+        encoding: Option<ContentEncoding>,
+        compression: &CompressionConfig,
+    ) -> Result<Response<Full<Bytes>>> {
+        let stored_status = StatusCode::from_u16(page.status).unwrap_or(StatusCode::OK);
+        if stored_status != StatusCode::OK {
+            // Redirects and negative entries are replayed as stored
+            let builder = cached_response_builder(stored_status, &page.headers)
+                .header(CONTENT_TYPE, &page.content_type)
+                .header(CONTENT_LENGTH, page.data.len());
+            let body = if method == Method::HEAD {
+                Vec::new()
+            } else {
+                page.data
+            };
+            return builder
+                .body(Full::new(Bytes::from(body)))
+                .map_err(|e| anyhow!("Failed to build cached response: {}", e));
         }
 
-        if let Some(vhost) = vhost {
-            if let Some(vhost_cache) = &vhost.cache {
-                if !vhost_cache.enable {
-                    return false;
-                }
-                if self.is_excluded_path(path, &vhost_cache.exclude) {
-                    return false;
-                }
+        let validators = Validators {
+            etag: &page.etag,
+            last_modified: Some(page.created_at),
+        };
+        let status = match conditional::evaluate(method, headers, &validators) {
+            Precondition::Proceed => StatusCode::OK,
+            Precondition::NotModified => StatusCode::NOT_MODIFIED,
+            Precondition::Failed => StatusCode::PRECONDITION_FAILED,
+        };
+
+        let compressible = page.data.len() >= compression.min_size
+            && compression::is_compressible(&page.content_type, &compression.types);
+        let mut encoding = encoding.filter(|_| compressible);
+        if let (StatusCode::OK, Some(wanted), None) = (status, encoding, &page.variant) {
+            let compressed = compression::compress_body(wanted, compression, &page.data).await?;
+            if compressed.len() < page.data.len() {
+                self.cache
+                    .store_variant(key, &page.etag, wanted, compressed.clone())
+                    .await;
+                page.variant = Some((wanted, compressed));
+            } else {
+                encoding = None;
             }
         }
+        let etag = match encoding {
+            Some(encoding) => format!("\"{}-{}\"", page.etag, encoding.as_str()),
+            None => format!("\"{}\"", page.etag),
+        };
 
-        true
-    }
-
-    fn is_authenticated_request(&self, req: &Request<hyper::body::Incoming>) -> bool {
-        if req.headers().contains_key("authorization") {
-            return true;
+        let mut builder = cached_response_builder(status, &page.headers)
+            .header(ETAG, etag)
+            .header(LAST_MODIFIED, format_http_date(page.created_at));
+        if let Some(headers) = builder
+            .headers_mut()
+            .filter(|_| compressible && compression.enable)
+        {
+            vary::add_vary(headers, "Accept-Encoding");
         }
 
-        let Some(cookie_header) = req.headers().get("cookie").and_then(|h| h.to_str().ok()) else {
-            return false;
-        };
-        let cookie = cookie_header.to_ascii_lowercase();
-        cookie.contains("wordpress_logged_in")
-            || cookie.contains("phpsessid")
-            || cookie.contains("session")
-            || cookie.contains("auth")
-            || cookie.contains("token")
-            || cookie.contains("woocommerce")
-    }
-
-    fn is_excluded_path(&self, path: &str, rules: &[String]) -> bool {
-        rules.iter().any(|rule| {
-            if let Some(prefix) = rule.strip_suffix('*') {
-                path.starts_with(prefix)
-            } else {
-                path == rule || path.starts_with(&format!("{}/", rule.trim_end_matches('/')))
-            }
-        })
-    }
-
-    fn cached_response(
-        &self,
-        method: &Method,
-        body: &[u8],
-        content_type: &str,
-    ) -> Result<Response<Full<Bytes>>> {
-        let mut builder = Response::builder()
-            .status(StatusCode::OK)
-            .header(CONTENT_TYPE, content_type)
-            .header("Server", crate::SERVER_NAME)
-            .header("X-Powered-By", format!("VeloServe/{}", crate::VERSION))
-            .header("X-Cache", "HIT");
+        if status != StatusCode::OK {
+            return builder
+                .body(Full::new(Bytes::new()))
+                .map_err(|e| anyhow!("Failed to build cached response: {}", e));
+        }
 
+        builder = builder.header(CONTENT_TYPE, &page.content_type);
         if method == Method::HEAD {
-            builder = builder.header(CONTENT_LENGTH, body.len().to_string());
+            builder = builder.header(CONTENT_LENGTH, page.data.len().to_string());
             return builder
                 .body(Full::new(Bytes::new()))
                 .map_err(|e| anyhow!("Failed to build cached HEAD response: {}", e));
         }
 
+        let body = match (encoding, page.variant) {
+            (Some(_), Some((stored, data))) => {
+                builder = builder
+                    .header(CONTENT_ENCODING, stored.as_str())
+                    .header(CONTENT_LENGTH, data.len());
+                data
+            }
+            _ => page.data,
+        };
         builder
-            .body(Full::new(Bytes::from(body.to_vec())))
+            .body(Full::new(Bytes::from(body)))
             .map_err(|e| anyhow!("Failed to build cached response: {}", e))
     }
 
     async fn finalize_response(
         &self,
-        response: Response<Full<Bytes>>,
-        cache_context: Option<&CacheContext>,
+        mut response: Response<Full<Bytes>>,
+        cache_context: Result<&CacheContext, &BypassReason>,
+        session: Option<&PrivateSession>,
         method: &Method,
     ) -> Result<Response<Full<Bytes>>> {
-        let Some(context) = cache_context else {
-            return Ok(response);
+        // Meant for the page cache only, never passed on to clients
+        let surrogate = surrogate::take(
+            response.headers_mut(),
+            &self.config.cache.tag_headers,
+            &self.config.cache.surrogate_control_headers,
+        );
+        // Sent by the origin on logout, profile or cart changes
+        if response
+            .headers_mut()
+            .remove(PURGE_PRIVATE_HEADER)
+            .is_some()
+        {
+            if let Some(session) = session {
+                let removed = self.cache.private().purge_session(session);
+                debug!(session = session.hash(), removed, "private cache purged");
+            }
+        }
+
+        let context = match cache_context {
+            Ok(context) => context,
+            Err(reason) => {
+                if self.config.cache.debug_headers {
+                    if let Ok(value) = HeaderValue::from_str(&reason.to_string()) {
+                        response.headers_mut().insert("X-Cache-Bypass", value);
+                    }
+                }
+                return Ok(response);
+            }
         };
 
         if method != Method::GET {
             return Ok(response);
         }
 
-        if response.status() != StatusCode::OK {
+        if response.status().is_server_error() {
+            if let Some(page) = context.fallback.clone() {
+                warn!(
+                    key = %context.key,
+                    status = response.status().as_u16(),
+                    "origin failed, serving stale copy"
+                );
+                self.cache.record_served_on_error();
+                let mut response = self
+                    .cached_response(
+                        method,
+                        &HeaderMap::new(),
+                        &context.key,
+                        page,
+                        None,
+                        &self.config.compression,
+                    )
+                    .await?;
+                response
+                    .headers_mut()
+                    .insert("X-Cache", HeaderValue::from_static("STALE"));
+                return Ok(response);
+            }
+        }
+
+        // Built-in error pages are rendered per request (error_page, Accept)
+        if response.extensions().get::<ServerError>().is_some() {
+            return Ok(response);
+        }
+
+        let status = response.status();
+        let ttl = match status.as_u16() {
+            200 => context.ttl,
+            301 | 302 | 307 | 308 if context.cache_redirects => context.ttl,
+            404 | 410 if !context.negative_ttl.is_zero() => context.negative_ttl,
+            _ => return Ok(response),
+        };
+        let ttl = match &context.private {
+            // Sessions only get the pages the origin marks private
+            Some(_) if status != StatusCode::OK || !surrogate.private => return Ok(response),
+            Some(_) => surrogate
+                .max_age
+                .unwrap_or(Duration::from_secs(self.config.cache.private_ttl)),
+            None if surrogate.private => return Ok(response),
+            None => surrogate.max_age.unwrap_or(ttl),
+        };
+        if surrogate.no_store || ttl.is_zero() {
+            return Ok(response);
+        }
+
+        if response.headers().contains_key(SET_COOKIE)
+            || response.headers().contains_key(CONTENT_ENCODING)
+        {
             return Ok(response);
         }
 
-        if response.headers().contains_key(SET_COOKIE) {
+        if !vary::is_cacheable(response.headers(), &context.vary) {
+            debug!(key = %context.key, "origin Vary not covered by the cache key, not caching");
             return Ok(response);
         }
 
@@ -1445,7 +2723,9 @@ impl RequestHandler {
             .and_then(|h| h.to_str().ok())
             .unwrap_or("")
             .to_ascii_lowercase();
-        if cache_control.contains("no-store") || cache_control.contains("private") {
+        if cache_control.contains("no-store")
+            || (context.private.is_none() && cache_control.contains("private"))
+        {
             return Ok(response);
         }
 
@@ -1455,25 +2735,67 @@ impl RequestHandler {
             .and_then(|h| h.to_str().ok())
             .unwrap_or("text/html; charset=utf-8")
             .to_string();
-        if !content_type.to_ascii_lowercase().starts_with("text/html") {
+        if status == StatusCode::OK && !content_type.to_ascii_lowercase().starts_with("text/html") {
             return Ok(response);
         }
 
-        let (parts, body) = response.into_parts();
+        let (mut parts, body) = response.into_parts();
         let body = body.collect().await?.to_bytes();
         let body_vec = body.to_vec();
+        vary::add_rules(&mut parts.headers, &context.vary);
+        let meta = ResponseMeta {
+            status: status.as_u16(),
+            headers: cacheable_headers(&parts.headers),
+        };
+
+        // Same validators as later hits, so clients can revalidate right away
+        let etag = content_etag(&body_vec, &content_type);
+        let created_at = SystemTime::now();
+        if status == StatusCode::OK {
+            if let Ok(value) = HeaderValue::from_str(&format!("\"{}\"", etag)) {
+                parts.headers.insert(ETAG, value);
+            }
+            if let Ok(value) = HeaderValue::from_str(&format_http_date(created_at)) {
+                parts.headers.insert(LAST_MODIFIED, value);
+            }
+        }
+
+        let mut tags = vec![
+            format!("domain:{}", context.domain),
+            format!("path:{}{}", context.domain, context.path),
+        ];
+        tags.extend(surrogate.tags);
+
+        if let Some(session) = &context.private {
+            let page = CachedPage {
+                data: body_vec,
+                content_type,
+                status: meta.status,
+                headers: meta.headers,
+                etag,
+                created_at,
+                variant: None,
+            };
+            self.cache
+                .private()
+                .set(session, &context.key, page, tags, ttl);
+
+            let mut response = Response::from_parts(parts, Full::new(body));
+            let headers = response.headers_mut();
+            headers.insert("X-Cache", HeaderValue::from_static("MISS"));
+            headers.insert("X-Cache-Scope", HeaderValue::from_static("private"));
+            return Ok(response);
+        }
+
+        let lifetime = if status.is_client_error() {
+            // Negative entries are short-lived and never stand in for errors
+            CacheLifetime::from_ttl(ttl)
+        } else {
+            self.page_lifetime(context, ttl, &cache_control)
+        };
 
         self.cache
-            .set_with_ttl(
-                &context.key,
-                body_vec,
-                &content_type,
-                vec![
-                    format!("domain:{}", context.domain),
-                    format!("path:{}{}", context.domain, context.path),
-                ],
-                context.ttl,
-            )
+            .set_response(&context.key, body_vec, &content_type, meta, tags, lifetime)
             .await;
 
         let mut response = Response::from_parts(parts, Full::new(body));
@@ -1483,6 +2805,390 @@ impl RequestHandler {
         Ok(response)
     }
 
+    /// Lifetime of a page: `ttl` plus the stale windows, which the origin's
+    /// Cache-Control directives override
+    fn page_lifetime(
+        &self,
+        context: &CacheContext,
+        ttl: Duration,
+        cache_control: &str,
+    ) -> CacheLifetime {
+        let stale_while_revalidate = cache_control_secs(cache_control, "stale-while-revalidate")
+            .map(Duration::from_secs)
+            .unwrap_or(context.stale_while_revalidate);
+        let stale_if_error = cache_control_secs(cache_control, "stale-if-error")
+            .map(Duration::from_secs)
+            .unwrap_or(context.stale_if_error);
+        CacheLifetime::new(ttl + stale_while_revalidate, ttl).with_stale_if_error(stale_if_error)
+    }
+
+    // === Edge Side Includes ===
+
+    /// Replace the ESI markup of an HTML response with its fragments
+    async fn process_esi(
+        &self,
+        response: Response<Full<Bytes>>,
+        page: &EsiRequest<'_>,
+    ) -> Result<Response<Full<Bytes>>> {
+        let is_html = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|value| value.to_ascii_lowercase().starts_with("text/html"));
+        if response.status() != StatusCode::OK || !is_html {
+            return Ok(response);
+        }
+
+        let (mut parts, body) = response.into_parts();
+        let body = body.collect().await?.to_bytes();
+        let shell = match std::str::from_utf8(&body) {
+            Ok(shell) if esi::has_markup(&body) => shell,
+            _ => return Ok(Response::from_parts(parts, Full::new(body))),
+        };
+
+        let segments = esi::parse(shell);
+        let includes: Vec<&esi::Include> = segments
+            .iter()
+            .filter_map(|segment| match segment {
+                Segment::Include(include) => Some(include),
+                Segment::Text(_) => None,
+            })
+            .take(esi::MAX_INCLUDES)
+            .collect();
+        if includes.len() == esi::MAX_INCLUDES {
+            debug!("ESI include limit reached, later includes are dropped");
+        }
+        let fragments = futures::future::join_all(
+            includes
+                .iter()
+                .map(|include| self.esi_fragment(include, page)),
+        )
+        .await;
+
+        let mut fragments = fragments.into_iter();
+        let mut assembled = Vec::with_capacity(body.len());
+        for segment in &segments {
+            match segment {
+                Segment::Text(text) => assembled.extend_from_slice(text.as_bytes()),
+                Segment::Include(_) => match fragments.next() {
+                    Some(Ok(fragment)) => assembled.extend_from_slice(&fragment),
+                    Some(Err(_)) => {
+                        return self.error_response(StatusCode::BAD_GATEWAY, "ESI include failed");
+                    }
+                    None => {}
+                },
+            }
+        }
+
+        // Validators and length describe the shell, not the assembled page
+        parts.headers.remove(ETAG);
+        parts.headers.remove(LAST_MODIFIED);
+        parts.headers.remove(CONTENT_LENGTH);
+        if includes
+            .iter()
+            .any(|include| include.scope == FragmentScope::Private)
+        {
+            parts
+                .headers
+                .insert(CACHE_CONTROL, HeaderValue::from_static("private"));
+        }
+        Ok(Response::from_parts(
+            parts,
+            Full::new(Bytes::from(assembled)),
+        ))
+    }
+
+    /// Body of an include: its `src`, else `alt`, else nothing with
+    /// `onerror="continue"`
+    async fn esi_fragment(&self, include: &esi::Include, page: &EsiRequest<'_>) -> Result<Bytes> {
+        let err = match self.esi_fetch(&include.src, include, page).await {
+            Ok(fragment) => return Ok(fragment),
+            Err(err) => err,
+        };
+        warn!(src = %include.src, "ESI fragment failed: {}", err);
+
+        if let Some(alt) = &include.alt {
+            match self.esi_fetch(alt, include, page).await {
+                Ok(fragment) => return Ok(fragment),
+                Err(err) => warn!(src = %alt, "ESI alt fragment failed: {}", err),
+            }
+        }
+        if include.continue_on_error {
+            Ok(Bytes::new())
+        } else {
+            Err(err)
+        }
+    }
+
+    /// Fragment from the cache, else rendered and stored; an expired copy
+    /// stands in when rendering fails
+    async fn esi_fetch(
+        &self,
+        src: &str,
+        include: &esi::Include,
+        page: &EsiRequest<'_>,
+    ) -> Result<Bytes> {
+        let uri =
+            esi::resolve_src(src, &page.host).ok_or_else(|| anyhow!("unsupported src {}", src))?;
+        let key = self.fragment_key(page, &uri, include.scope);
+
+        let mut fallback = None;
+        if let Some(key) = &key {
+            match self.cache.lookup_page(key, None).await {
+                Some((fragment, Freshness::Fresh)) => return Ok(Bytes::from(fragment.data)),
+                Some((fragment, _)) => fallback = Some(fragment),
+                None => {}
+            }
+        }
+
+        match self
+            .render_fragment(&uri, include, page, key.as_deref())
+            .await
+        {
+            Ok(fragment) => Ok(fragment),
+            Err(err) => match fallback {
+                Some(fragment) => {
+                    warn!(src = %src, "ESI fragment failed, serving stale copy: {}", err);
+                    self.cache.record_served_on_error();
+                    Ok(Bytes::from(fragment.data))
+                }
+                None => Err(err),
+            },
+        }
+    }
+
+    /// Render a fragment through the static/PHP pipeline and cache it
+    async fn render_fragment(
+        &self,
+        uri: &str,
+        include: &esi::Include,
+        page: &EsiRequest<'_>,
+        key: Option<&str>,
+    ) -> Result<Bytes> {
+        let (mut parts, _) = Request::builder()
+            .method(Method::GET)
+            .uri(uri)
+            .body(())
+            .map_err(|e| anyhow!("invalid fragment URI {}: {}", uri, e))?
+            .into_parts();
+        parts.headers = page.headers.clone();
+        if let Some(addr) = page.client {
+            parts.extensions.insert(addr);
+        }
+
+        let path = normalize_path(parts.uri.path());
+        let (doc_root, lookup_path) = self.fragment_route(&mut parts, page, &path)?;
+        let response = self
+            .fragment_response(&parts, &doc_root, &lookup_path)
+            .await?;
+        let (mut response_parts, body) = response.into_parts();
+        let surrogate = surrogate::take(
+            &mut response_parts.headers,
+            &self.config.cache.tag_headers,
+            &self.config.cache.surrogate_control_headers,
+        );
+        if response_parts.status != StatusCode::OK {
+            return Err(anyhow!("{} returned {}", uri, response_parts.status));
+        }
+
+        let body = body.collect().await?.to_bytes();
+        // Nested includes are not resolved
+        let body = match std::str::from_utf8(&body) {
+            Ok(text) if esi::has_markup(&body) => Bytes::from(esi::strip(text)),
+            _ => body,
+        };
+
+        let Some(key) = key else {
+            return Ok(body);
+        };
+        let headers = &response_parts.headers;
+        let cache_control = headers
+            .get(CACHE_CONTROL)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        let cacheable = !surrogate.no_store
+            && !headers.contains_key(SET_COOKIE)
+            && !cache_control.contains("no-store")
+            && (include.scope == FragmentScope::Private
+                || !(surrogate.private || cache_control.contains("private")));
+        let ttl = include.ttl.or(surrogate.max_age).unwrap_or_else(|| {
+            Duration::from_secs(match include.scope {
+                FragmentScope::Public => page.policy.ttl,
+                FragmentScope::Private => page.policy.esi_private_ttl,
+            })
+        });
+        if cacheable && !ttl.is_zero() {
+            let content_type = headers
+                .get(CONTENT_TYPE)
+                .and_then(|h| h.to_str().ok())
+                .unwrap_or("text/html; charset=utf-8");
+            let stale_if_error = page
+                .policy
+                .stale_if_error
+                .unwrap_or(self.config.cache.stale_if_error);
+            let mut tags = vec![
+                format!("domain:{}", page.host),
+                format!("path:{}{}", page.host, path),
+            ];
+            tags.extend(surrogate.tags);
+            self.cache
+                .set_response(
+                    key,
+                    body.to_vec(),
+                    content_type,
+                    ResponseMeta::default(),
+                    tags,
+                    CacheLifetime::from_ttl(ttl)
+                        .with_stale_if_error(Duration::from_secs(stale_if_error)),
+                )
+                .await;
+        }
+        Ok(body)
+    }
+
+    /// Rewrites, location rules and `.htaccess` access checks for a fragment,
+    /// as `route` and `serve_site` apply them to pages. Returns the document
+    /// root and lookup path; refusals and redirects fail the include.
+    fn fragment_route(
+        &self,
+        parts: &mut hyper::http::request::Parts,
+        page: &EsiRequest<'_>,
+        path: &str,
+    ) -> Result<(PathBuf, String)> {
+        let vhost = page
+            .vhost_index
+            .map(|index| &self.config.virtualhost[index]);
+        let mut path = path.to_string();
+        let mut location = None;
+
+        if let (Some(index), Some(vhost)) = (page.vhost_index, vhost) {
+            let doc_root = platform_doc_root(Path::new(&vhost.root), vhost.platform.as_deref());
+            let mut state = RewriteState::new(&path, parts.uri.query());
+            let input = RewriteInput {
+                host: &page.host,
+                method: &parts.method,
+                headers: &parts.headers,
+                doc_root: &doc_root,
+            };
+            match self.run_rewrites(index, &input, &mut state, &mut Vec::new()) {
+                RewriteOutcome::Resolved(resolved) => location = resolved,
+                RewriteOutcome::Redirect { status, .. } | RewriteOutcome::Status(status) => {
+                    return Err(anyhow!("{} is answered with {}", path, status));
+                }
+                RewriteOutcome::Cycle => return Err(anyhow!("rewrite cycle for {}", path)),
+            }
+            if state.path != path || state.query.as_deref() != parts.uri.query() {
+                parts.uri = state
+                    .uri()
+                    .parse()
+                    .map_err(|e| anyhow!("Invalid rewritten URI {}: {}", state.uri(), e))?;
+                path = state.path;
+            }
+        }
+
+        let client_ip = page.client.map(|addr| addr.0.ip());
+        if let Some(location) = location {
+            if !location.allows(client_ip) || location.config.return_status.is_some() {
+                return Err(anyhow!("{} is not served by its location", path));
+            }
+            if location.denies_php() {
+                parts.extensions.insert(PhpDenied);
+            }
+        }
+
+        let (doc_root, lookup_path) = self.document_root(vhost, location, &path);
+        if vhost.is_some_and(|v| v.htaccess) {
+            let name = file_name(&lookup_path);
+            let chain = self.htaccess.chain(&doc_root, &lookup_path);
+            if name.starts_with(".ht") || !chain.allows(client_ip, &name) {
+                return Err(anyhow!("{} is denied by .htaccess", path));
+            }
+        }
+        Ok((doc_root, lookup_path))
+    }
+
+    /// Static file or PHP script for a fragment path, like `serve_path`
+    /// without index files and try_files
+    async fn fragment_response(
+        &self,
+        parts: &hyper::http::request::Parts,
+        doc_root: &Path,
+        path: &str,
+    ) -> Result<Response<Full<Bytes>>> {
+        let file_path = self.resolve_path(doc_root, path);
+        if file_path.is_file() {
+            if self.is_php_file(&file_path) {
+                return self
+                    .execute_php(parts, doc_root, &file_path, path, "", Vec::new())
+                    .await;
+            }
+            return self.serve_static_parts(parts, &file_path).await;
+        }
+
+        if let Some(php_info) = self.resolve_php_path_info(doc_root, path) {
+            return self
+                .execute_php(
+                    parts,
+                    doc_root,
+                    &php_info.script_filename,
+                    &php_info.script_name,
+                    &php_info.path_info,
+                    Vec::new(),
+                )
+                .await;
+        }
+
+        let front_controller = doc_root.join("index.php");
+        if self.php_pool.is_available() && front_controller.is_file() {
+            // Internal redirect, like a try_files fallback
+            let mut parts = parts.clone();
+            parts.extensions.remove::<PhpDenied>();
+            return self
+                .execute_php(
+                    &parts,
+                    doc_root,
+                    &front_controller,
+                    "/index.php",
+                    path,
+                    Vec::new(),
+                )
+                .await;
+        }
+
+        self.not_found()
+    }
+
+    /// Cache key of a fragment, `None` when caching is off
+    fn fragment_key(
+        &self,
+        page: &EsiRequest<'_>,
+        uri: &str,
+        scope: FragmentScope,
+    ) -> Option<String> {
+        if !self.config.cache.enable || !page.policy.enable {
+            return None;
+        }
+        // A bypassed page (logged-in visitor, POST) renders public fragments
+        // with its own cookies, which must not reach the shared cache
+        if scope == FragmentScope::Public && !page.shared {
+            return None;
+        }
+
+        let mut variant = vec!["esi".to_string()];
+        if scope == FragmentScope::Private {
+            variant.push(session_variant(&page.headers));
+        }
+        variant.extend(self.cache_variant(&page.headers, &vary::parse_rules(&page.policy.vary)));
+        Some(build_page_cache_key_scoped(
+            &page.host,
+            self.cache_site(&page.headers).as_deref(),
+            self.cache_store(&page.headers).as_deref(),
+            Some(&variant.join("+")),
+            uri,
+        ))
+    }
+
     // === Response Helpers ===
 
     fn health_check(&self) -> Result<Response<Full<Bytes>>> {
@@ -1495,48 +3201,104 @@ impl RequestHandler {
     }
 
     fn not_found(&self) -> Result<Response<Full<Bytes>>> {
-        let body = r#"<!DOCTYPE html>
-<html>
-<head><title>404 Not Found</title></head>
-<body>
-<h1>404 Not Found</h1>
-<p>The requested resource was not found on this server.</p>
-<hr>
-<p><em>VeloServe</em></p>
-</body>
-</html>"#;
-
-        Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .header("Content-Type", "text/html; charset=utf-8")
-            .header("Server", crate::SERVER_NAME)
-            .body(Full::new(Bytes::from(body)))
-            .map_err(|e| anyhow!("Failed to build response: {}", e))
+        self.error_response(
+            StatusCode::NOT_FOUND,
+            "The requested resource was not found on this server.",
+        )
     }
 
     fn forbidden(&self, message: &str) -> Result<Response<Full<Bytes>>> {
+        self.error_response(StatusCode::FORBIDDEN, message)
+    }
+
+    /// Gateway error for a failed PHP execution: 504 on timeout, 503 when
+    /// no worker is available, 502 otherwise
+    fn php_failure(&self, err: &anyhow::Error) -> Result<Response<Full<Bytes>>> {
+        let status = match err.downcast_ref::<PhpFailure>() {
+            Some(PhpFailure::Timeout(_)) => StatusCode::GATEWAY_TIMEOUT,
+            Some(_) => StatusCode::SERVICE_UNAVAILABLE,
+            None => StatusCode::BAD_GATEWAY,
+        };
+        self.error_response(status, &format!("PHP Error: {}", err))
+    }
+
+    /// Built-in error page, marked so configured error pages can replace it
+    fn error_response(&self, status: StatusCode, message: &str) -> Result<Response<Full<Bytes>>> {
+        let title = format!(
+            "{} {}",
+            status.as_u16(),
+            status.canonical_reason().unwrap_or("Error")
+        );
         let body = format!(
             r#"<!DOCTYPE html>
 <html>
-<head><title>403 Forbidden</title></head>
+<head><title>{title}</title></head>
 <body>
-<h1>403 Forbidden</h1>
+<h1>{title}</h1>
 <p>{}</p>
 <hr>
 <p><em>VeloServe</em></p>
 </body>
 </html>"#,
-            message
+            html_escape(message)
         );
 
         Response::builder()
-            .status(StatusCode::FORBIDDEN)
+            .status(status)
             .header("Content-Type", "text/html; charset=utf-8")
             .header("Server", crate::SERVER_NAME)
+            .extension(ServerError(message.to_string()))
             .body(Full::new(Bytes::from(body)))
             .map_err(|e| anyhow!("Failed to build response: {}", e))
     }
 
+    /// Bare status response (try_files `=CODE`)
+    fn status_response(&self, code: u16) -> Result<Response<Full<Bytes>>> {
+        match code {
+            404 => return self.not_found(),
+            403 => return self.forbidden("Access denied"),
+            _ => {}
+        }
+
+        let status = StatusCode::from_u16(code)?;
+        let reason = status.canonical_reason().unwrap_or("");
+        let mut builder = Response::builder()
+            .status(status)
+            .header("Content-Type", "text/plain")
+            .header("Server", crate::SERVER_NAME);
+        if status.is_client_error() || status.is_server_error() {
+            builder = builder.extension(ServerError(reason.to_string()));
+        }
+        builder
+            .body(Full::new(Bytes::from(reason.to_string())))
+            .map_err(|e| anyhow!("Failed to build response: {}", e))
+    }
+
+    /// Fixed response configured on a location (`return_status`)
+    fn return_response(&self, code: u16, body: Option<&str>) -> Result<Response<Full<Bytes>>> {
+        let status = StatusCode::from_u16(code)?;
+        if status.is_redirection() {
+            if let Some(target) = body {
+                return Response::builder()
+                    .status(status)
+                    .header("Location", target)
+                    .header("Server", crate::SERVER_NAME)
+                    .body(Full::new(Bytes::new()))
+                    .map_err(|e| anyhow!("Failed to build response: {}", e));
+            }
+        }
+
+        match body {
+            Some(body) => Response::builder()
+                .status(status)
+                .header("Content-Type", "text/plain; charset=utf-8")
+                .header("Server", crate::SERVER_NAME)
+                .body(Full::new(Bytes::from(body.to_string())))
+                .map_err(|e| anyhow!("Failed to build response: {}", e)),
+            None => self.status_response(code),
+        }
+    }
+
     fn method_not_allowed(&self) -> Result<Response<Full<Bytes>>> {
         Response::builder()
             .status(StatusCode::METHOD_NOT_ALLOWED)
@@ -1548,26 +3310,7 @@ impl RequestHandler {
     }
 
     fn internal_error(&self, message: &str) -> Result<Response<Full<Bytes>>> {
-        let body = format!(
-            r#"<!DOCTYPE html>
-<html>
-<head><title>500 Internal Server Error</title></head>
-<body>
-<h1>500 Internal Server Error</h1>
-<p>{}</p>
-<hr>
-<p><em>VeloServe</em></p>
-</body>
-</html>"#,
-            message
-        );
-
-        Response::builder()
-            .status(StatusCode::INTERNAL_SERVER_ERROR)
-            .header("Content-Type", "text/html; charset=utf-8")
-            .header("Server", crate::SERVER_NAME)
-            .body(Full::new(Bytes::from(body)))
-            .map_err(|e| anyhow!("Failed to build response: {}", e))
+        self.error_response(StatusCode::INTERNAL_SERVER_ERROR, message)
     }
 
     fn json_response(&self, data: serde_json::Value) -> Result<Response<Full<Bytes>>> {
@@ -1606,6 +3349,136 @@ impl RequestHandler {
     }
 }
 
+/// URI the client sent, before any internal rewrite
+fn client_uri<B>(req: &Request<B>) -> &hyper::Uri {
+    req.extensions()
+        .get::<OriginalUri>()
+        .map(|original| &original.0)
+        .unwrap_or(req.uri())
+}
+
+/// Drop ignored query parameters before the request reaches PHP
+fn strip_query_params<B>(req: &mut Request<B>, ignore: &[String]) -> Result<()> {
+    let Some(query) = req.uri().query() else {
+        return Ok(());
+    };
+    let stripped = strip_ignored_query(query, ignore);
+    if stripped == query {
+        return Ok(());
+    }
+
+    let path = req.uri().path();
+    let path_and_query = if stripped.is_empty() {
+        path.to_string()
+    } else {
+        format!("{}?{}", path, stripped)
+    };
+    *req.uri_mut() = path_and_query
+        .parse()
+        .map_err(|e| anyhow!("Invalid stripped URI {}: {}", path_and_query, e))?;
+    Ok(())
+}
+
+/// Last path segment, percent-decoded (`<Files>` sections match against it)
+fn file_name(path: &str) -> String {
+    let last = path.rsplit('/').next().unwrap_or("");
+    percent_encoding::percent_decode_str(last)
+        .decode_utf8_lossy()
+        .to_string()
+}
+
+/// Cache policy of a location, else of its vhost
+fn cache_policy<'a>(
+    vhost: Option<&'a VirtualHostConfig>,
+    location: Option<&'a Location>,
+) -> Option<&'a VHostCacheConfig> {
+    location
+        .and_then(|l| l.config.cache.as_ref())
+        .or_else(|| vhost.and_then(|v| v.cache.as_ref()))
+}
+
+/// Key scope of a private ESI fragment: a hash of the visitor's cookies
+fn session_variant(headers: &HeaderMap) -> String {
+    match vary::request_variant(&[VaryRule::Header("cookie".to_string())], headers) {
+        Some(cookies) => {
+            let mut hasher = DefaultHasher::new();
+            cookies.hash(&mut hasher);
+            format!("s-{:016x}", hasher.finish())
+        }
+        None => "s-anon".to_string(),
+    }
+}
+
+/// Seconds of a `directive=N` entry in a (lowercased) Cache-Control value
+fn cache_control_secs(cache_control: &str, directive: &str) -> Option<u64> {
+    cache_control.split(',').find_map(|item| {
+        let (name, value) = item.split_once('=')?;
+        (name.trim() == directive)
+            .then(|| value.trim().trim_matches('"').parse().ok())
+            .flatten()
+    })
+}
+
+/// Response headers worth storing with a cached page
+fn cacheable_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .filter(|(name, _)| !UNCACHED_RESPONSE_HEADERS.contains(&name.as_str()))
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect()
+}
+
+/// Builder for a cache hit: stored headers first, then our own
+fn cached_response_builder(
+    status: StatusCode,
+    headers: &[(String, String)],
+) -> hyper::http::response::Builder {
+    let mut builder = Response::builder().status(status);
+    for (name, value) in headers {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    builder
+        .header("Server", crate::SERVER_NAME)
+        .header("X-Powered-By", format!("VeloServe/{}", crate::VERSION))
+        .header("X-Cache", "HIT")
+}
+
+/// Page cache variant for a request (the page cache keeps brotli and gzip)
+fn cache_variant_encoding(
+    method: &Method,
+    headers: &HeaderMap,
+    config: &CompressionConfig,
+) -> Option<ContentEncoding> {
+    if !config.enable || method != Method::GET {
+        return None;
+    }
+    let accept_encoding = headers.get(ACCEPT_ENCODING)?.to_str().ok()?;
+    let offered: Vec<_> = config
+        .algorithms
+        .iter()
+        .copied()
+        .filter(|encoding| matches!(encoding, ContentEncoding::Br | ContentEncoding::Gzip))
+        .collect();
+    compression::negotiate(accept_encoding, &offered)
+}
+
+/// Escape text for an HTML body
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Host header without port (defaults to "localhost")
+fn request_host(headers: &HeaderMap) -> String {
+    let host = headers
+        .get("host")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("localhost");
+    host.split(':').next().unwrap_or(host).to_string()
+}
+
 fn normalize_domain(raw: &str) -> Result<String> {
     let trimmed = raw.trim().trim_end_matches('.').to_ascii_lowercase();
     if trimmed.is_empty() {
@@ -1660,24 +3533,6 @@ fn normalize_invalidation_path(raw: &str) -> Result<String> {
     Ok(normalized)
 }
 
-fn normalize_tag(raw: &str) -> Result<String> {
-    let trimmed = raw.trim().to_ascii_lowercase();
-    if trimmed.is_empty() {
-        return Err(anyhow!("tag cannot be empty"));
-    }
-    let mut normalized = String::with_capacity(trimmed.len());
-    for ch in trimmed.chars() {
-        if ch.is_ascii_alphanumeric() || matches!(ch, ':' | '_' | '-' | '/' | '.') {
-            normalized.push(ch);
-        } else if ch.is_whitespace() {
-            normalized.push('_');
-        } else {
-            return Err(anyhow!("tag contains unsupported characters"));
-        }
-    }
-    Ok(normalized)
-}
-
 fn now_epoch_secs() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
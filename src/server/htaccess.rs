@@ -0,0 +1,1308 @@
+//! .htaccess Support
+//!
+//! Per-directory Apache overrides read at request time. Files are looked up
+//! from the document root down to the requested directory, parsed with the
+//! Apache config parser and cached until their mtime or size changes.
+//!
+//! Supported: RewriteEngine, RewriteBase, RewriteCond, RewriteRule,
+//! Redirect/RedirectMatch/RedirectPermanent/RedirectTemp, ErrorDocument,
+//! Header, Require, Order/Allow/Deny, DirectoryIndex and Options, plus
+//! `<IfModule>`, `<Files>`, `<FilesMatch>` and `<RequireAny>` sections.
+//! Anything else is logged once and ignored.
+
+use crate::apache_compat::parser::unquote;
+use crate::apache_compat::{ApacheConfigParser, ApacheDirective};
+use crate::config::{RewriteConditionConfig, RewriteRuleConfig};
+use crate::server::location::ip_in_network;
+use crate::server::rewrite::{
+    RewriteInput, RewriteResult, RewriteSet, RewriteState, RewriteTraceEntry,
+};
+
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::http::HeaderMap;
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use regex::{Regex, RegexBuilder};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tracing::{debug, warn};
+
+/// Per-directory configuration file name
+pub const HTACCESS_FILE: &str = ".htaccess";
+
+/// Directives already reported as unsupported (logged once per process)
+static WARNED: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+fn warn_unsupported(what: &str, file: &Path) {
+    if WARNED.lock().insert(what.to_string()) {
+        warn!(
+            file = %file.display(),
+            "unsupported .htaccess directive ignored: {}",
+            what
+        );
+    }
+}
+
+/// `ErrorDocument` target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorDocument {
+    /// Local URI served with the original status
+    Path(String),
+    /// External URL the client is redirected to
+    Url(String),
+    /// Literal message
+    Text(String),
+    /// `default`: undo an inherited ErrorDocument
+    Default,
+}
+
+/// `Header` action
+#[derive(Debug, Clone)]
+enum HeaderAction {
+    Set(HeaderName, HeaderValue),
+    Append(HeaderName, HeaderValue),
+    Add(HeaderName, HeaderValue),
+    Merge(HeaderName, HeaderValue),
+    Unset(HeaderName),
+}
+
+impl HeaderAction {
+    fn apply(&self, headers: &mut HeaderMap) {
+        match self {
+            Self::Set(name, value) => {
+                headers.insert(name.clone(), value.clone());
+            }
+            Self::Add(name, value) => {
+                headers.append(name.clone(), value.clone());
+            }
+            Self::Append(name, value) | Self::Merge(name, value) => {
+                let current = headers.get(name).and_then(|v| v.to_str().ok());
+                let merged = match (current, value.to_str()) {
+                    (Some(current), Ok(new)) => {
+                        let present = current.split(',').any(|token| token.trim() == new);
+                        if matches!(self, Self::Merge(..)) && present {
+                            return;
+                        }
+                        HeaderValue::from_str(&format!("{}, {}", current, new)).ok()
+                    }
+                    _ => Some(value.clone()),
+                };
+                if let Some(merged) = merged {
+                    headers.insert(name.clone(), merged);
+                }
+            }
+            Self::Unset(name) => {
+                headers.remove(name);
+            }
+        }
+    }
+}
+
+/// A network in `Require ip` / `Allow from`; `None` is "all"
+type Network = Option<(IpAddr, u8)>;
+
+/// `Require` lines of one section, evaluated as RequireAny
+#[derive(Debug, Clone, Default)]
+struct RequireRules {
+    grant_all: bool,
+    grant: Vec<(IpAddr, u8)>,
+    refuse: Vec<(IpAddr, u8)>,
+}
+
+/// Apache 2.2 `Order`/`Allow`/`Deny`
+#[derive(Debug, Clone)]
+struct LegacyRules {
+    /// `Order Deny,Allow` (the default): allowed unless denied and not allowed
+    deny_first: bool,
+    allow: Vec<Network>,
+    deny: Vec<Network>,
+}
+
+impl Default for LegacyRules {
+    fn default() -> Self {
+        Self {
+            deny_first: true,
+            allow: Vec::new(),
+            deny: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct AccessPolicy {
+    require: Option<RequireRules>,
+    legacy: Option<LegacyRules>,
+}
+
+impl AccessPolicy {
+    fn allows(&self, addr: Option<IpAddr>) -> bool {
+        let matches = |network: &(IpAddr, u8)| {
+            addr.map(|addr| ip_in_network(addr, network.0, network.1))
+                .unwrap_or(false)
+        };
+
+        if let Some(require) = &self.require {
+            if require.refuse.iter().any(matches) {
+                return false;
+            }
+            return require.grant_all || require.grant.iter().any(matches);
+        }
+
+        if let Some(legacy) = &self.legacy {
+            let hit = |networks: &[Network]| {
+                networks
+                    .iter()
+                    .any(|network| network.as_ref().map(matches).unwrap_or(true))
+            };
+            let allowed = hit(&legacy.allow);
+            let denied = hit(&legacy.deny);
+            return if legacy.deny_first {
+                !denied || allowed
+            } else {
+                allowed && !denied
+            };
+        }
+
+        true
+    }
+}
+
+/// `<Files>` / `<FilesMatch>` section
+#[derive(Debug)]
+struct FilesSection {
+    pattern: Regex,
+    access: Option<AccessPolicy>,
+    headers: Vec<HeaderAction>,
+}
+
+/// One compiled `.htaccess` file
+#[derive(Debug, Default)]
+pub struct Htaccess {
+    rewrite_engine: Option<bool>,
+    rewrites: RewriteSet,
+    redirects: RewriteSet,
+    access: Option<AccessPolicy>,
+    files: Vec<FilesSection>,
+    headers: Vec<HeaderAction>,
+    error_documents: HashMap<u16, ErrorDocument>,
+    directory_index: Option<Vec<String>>,
+}
+
+impl Htaccess {
+    /// Compile the contents of `file`, which lives in the directory served at `dir_uri`
+    pub fn parse(content: &str, file: &Path, dir_uri: &str) -> Self {
+        let directives = match ApacheConfigParser::new().parse_directives(content) {
+            Ok(directives) => directives,
+            Err(err) => {
+                warn!(file = %file.display(), "failed to parse .htaccess: {}", err);
+                return Self::default();
+            }
+        };
+
+        let mut builder = Builder::new(file);
+        builder.directives(&directives);
+
+        let scope = file.display().to_string();
+        let base = builder
+            .rewrite_base
+            .as_deref()
+            .unwrap_or(dir_uri)
+            .to_string();
+        Self {
+            rewrite_engine: builder.rewrite_engine,
+            rewrites: RewriteSet::compile(&scope, &builder.rewrite_rules)
+                .in_directory(dir_uri, &base),
+            redirects: RewriteSet::compile(&scope, &builder.redirect_rules),
+            access: builder.access,
+            files: builder.files,
+            headers: builder.headers,
+            error_documents: builder.error_documents,
+            directory_index: builder.directory_index,
+        }
+    }
+}
+
+/// Collects the directives of one file (or `<Files>` section)
+struct Builder<'a> {
+    file: &'a Path,
+    rewrite_engine: Option<bool>,
+    rewrite_base: Option<String>,
+    pending_conditions: Vec<RewriteConditionConfig>,
+    /// A condition of the next rule could not be translated; drop the rule
+    skip_next_rule: bool,
+    rewrite_rules: Vec<RewriteRuleConfig>,
+    redirect_rules: Vec<RewriteRuleConfig>,
+    access: Option<AccessPolicy>,
+    files: Vec<FilesSection>,
+    headers: Vec<HeaderAction>,
+    error_documents: HashMap<u16, ErrorDocument>,
+    directory_index: Option<Vec<String>>,
+}
+
+impl<'a> Builder<'a> {
+    fn new(file: &'a Path) -> Self {
+        Self {
+            file,
+            rewrite_engine: None,
+            rewrite_base: None,
+            pending_conditions: Vec::new(),
+            skip_next_rule: false,
+            rewrite_rules: Vec::new(),
+            redirect_rules: Vec::new(),
+            access: None,
+            files: Vec::new(),
+            headers: Vec::new(),
+            error_documents: HashMap::new(),
+            directory_index: None,
+        }
+    }
+
+    fn unsupported(&self, what: &str) {
+        warn_unsupported(what, self.file);
+    }
+
+    fn directives(&mut self, directives: &[ApacheDirective]) {
+        for directive in directives {
+            match directive {
+                ApacheDirective::Comment(_) => {}
+                ApacheDirective::Simple { name, value } => self.directive(name, value),
+                ApacheDirective::IfModule { module, content } => {
+                    if module_enabled(module) {
+                        self.directives(content);
+                    }
+                }
+                ApacheDirective::Files { pattern, content } => {
+                    let regex = match pattern.strip_prefix('~') {
+                        Some(regex) => unquote(regex.trim()).to_string(),
+                        None => wildcard_regex(pattern),
+                    };
+                    self.files_section(&regex, content);
+                }
+                ApacheDirective::Block {
+                    name,
+                    args,
+                    content,
+                } => match name.to_ascii_lowercase().as_str() {
+                    "filesmatch" => {
+                        let regex = args.first().cloned().unwrap_or_default();
+                        self.files_section(&regex, content);
+                    }
+                    "requireany" => self.directives(content),
+                    _ => self.unsupported(&format!("<{}>", name)),
+                },
+                ApacheDirective::VirtualHost { .. } => self.unsupported("<VirtualHost>"),
+                ApacheDirective::Directory { .. } => self.unsupported("<Directory>"),
+            }
+        }
+    }
+
+    fn files_section(&mut self, pattern: &str, content: &[ApacheDirective]) {
+        let pattern = match RegexBuilder::new(pattern).build() {
+            Ok(pattern) => pattern,
+            Err(err) => {
+                warn!(file = %self.file.display(), "invalid <Files> pattern {}: {}", pattern, err);
+                return;
+            }
+        };
+
+        let mut section = Builder::new(self.file);
+        section.directives(content);
+        if !section.rewrite_rules.is_empty()
+            || !section.redirect_rules.is_empty()
+            || !section.error_documents.is_empty()
+            || section.directory_index.is_some()
+        {
+            self.unsupported("<Files> sections only support access and Header directives");
+        }
+
+        self.files.push(FilesSection {
+            pattern,
+            access: section.access,
+            headers: section.headers,
+        });
+    }
+
+    fn directive(&mut self, name: &str, value: &str) {
+        match name.to_ascii_lowercase().as_str() {
+            "rewriteengine" => self.rewrite_engine = Some(value.eq_ignore_ascii_case("on")),
+            "rewritebase" => self.rewrite_base = Some(unquote(value).to_string()),
+            "rewritecond" => self.rewrite_cond(value),
+            "rewriterule" => self.rewrite_rule(value),
+            "redirect" => self.redirect(value, None),
+            "redirectpermanent" => self.redirect(value, Some("301")),
+            "redirecttemp" => self.redirect(value, Some("302")),
+            "redirectmatch" => self.redirect_match(value),
+            "errordocument" => self.error_document(value),
+            "header" => self.header(value),
+            "require" => self.require(value),
+            "order" => {
+                let order = value.replace(' ', "").to_ascii_lowercase();
+                self.legacy().deny_first = order != "allow,deny";
+            }
+            "allow" => self.allow_deny(value, true),
+            "deny" => self.allow_deny(value, false),
+            "directoryindex" => {
+                let files: Vec<String> = split_args(value)
+                    .into_iter()
+                    .filter(|f| !f.eq_ignore_ascii_case("disabled"))
+                    .map(|f| f.trim_start_matches('/').to_string())
+                    .collect();
+                self.directory_index = Some(files);
+            }
+            "options" => self.options(value),
+            _ => self.unsupported(name),
+        }
+    }
+
+    fn rewrite_cond(&mut self, value: &str) {
+        let args = split_args(value);
+        let (Some(test), Some(pattern)) = (args.first(), args.get(1)) else {
+            self.unsupported("RewriteCond without a pattern");
+            self.skip_next_rule = true;
+            return;
+        };
+
+        let mut or = false;
+        let mut nocase = false;
+        for flag in parse_flags(args.get(2)) {
+            match flag.to_ascii_uppercase().as_str() {
+                "OR" | "ORNEXT" => or = true,
+                "NC" | "NOCASE" => nocase = true,
+                "NV" | "NOVARY" => {}
+                other => self.unsupported(&format!("RewriteCond flag {}", other)),
+            }
+        }
+
+        let (negate, pattern) = match pattern.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern.as_str()),
+        };
+
+        match translate_condition(test, pattern) {
+            Some((kind, name, pattern)) => self.pending_conditions.push(RewriteConditionConfig {
+                kind: kind.to_string(),
+                name,
+                pattern,
+                negate,
+                or,
+                nocase,
+            }),
+            None => {
+                self.unsupported(&format!("RewriteCond {} {}", test, pattern));
+                self.skip_next_rule = true;
+            }
+        }
+    }
+
+    fn rewrite_rule(&mut self, value: &str) {
+        let conditions = std::mem::take(&mut self.pending_conditions);
+        let skip = std::mem::take(&mut self.skip_next_rule);
+
+        let args = split_args(value);
+        let (Some(pattern), Some(substitution)) = (args.first(), args.get(1)) else {
+            self.unsupported("RewriteRule without a substitution");
+            return;
+        };
+        if skip {
+            return;
+        }
+        if pattern.starts_with('!') {
+            self.unsupported("RewriteRule with a negated pattern");
+            return;
+        }
+
+        let mut flags = Vec::new();
+        let mut append_query = false;
+        let mut discard_query = false;
+        for flag in parse_flags(args.get(2)) {
+            let (name, arg) = match flag.split_once('=') {
+                Some((name, arg)) => (name.to_ascii_uppercase(), Some(arg.to_ascii_lowercase())),
+                None => (flag.to_ascii_uppercase(), None),
+            };
+            match name.as_str() {
+                "L" | "LAST" => flags.push("last".to_string()),
+                "END" => flags.push("break".to_string()),
+                "R" | "REDIRECT" => flags.push(match arg.as_deref() {
+                    None | Some("temp") => "302".to_string(),
+                    Some("permanent") => "301".to_string(),
+                    Some("seeother") => "303".to_string(),
+                    Some(code) => code.to_string(),
+                }),
+                "NC" | "NOCASE" => flags.push("nocase".to_string()),
+                "F" | "FORBIDDEN" => flags.push("forbidden".to_string()),
+                "G" | "GONE" => flags.push("gone".to_string()),
+                "QSA" | "QSAPPEND" => append_query = true,
+                "QSD" | "QSDISCARD" => discard_query = true,
+                // No effect here: no escaping/passthrough stages, env vars unused
+                "NE" | "NOESCAPE" | "PT" | "PASSTHROUGH" | "DPI" | "DISCARDPATH" | "NS"
+                | "NOSUBREQ" | "E" | "ENV" => {}
+                other => self.unsupported(&format!("RewriteRule flag {}", other)),
+            }
+        }
+
+        // Apache replaces the query when the substitution has one, unless QSA.
+        if discard_query || (substitution.contains('?') && !append_query) {
+            flags.push("qsd".to_string());
+        }
+
+        let Some(replacement) = translate_variables(substitution) else {
+            self.unsupported(&format!("RewriteRule substitution {}", substitution));
+            return;
+        };
+
+        self.rewrite_rules.push(RewriteRuleConfig {
+            pattern: pattern.clone(),
+            replacement,
+            flags: flags.join(","),
+            conditions,
+        });
+    }
+
+    /// `Redirect [status] URL-path URL`
+    fn redirect(&mut self, value: &str, fixed_status: Option<&str>) {
+        let mut args = split_args(value);
+        let status = match fixed_status {
+            Some(status) => status.to_string(),
+            None if args.len() > 2 || args.first().map(|a| a == "gone").unwrap_or(false) => {
+                args.remove(0)
+            }
+            None => "302".to_string(),
+        };
+        let Some(flag) = redirect_flag(&status) else {
+            self.unsupported(&format!("Redirect status {}", status));
+            return;
+        };
+        let Some(path) = args.first() else {
+            self.unsupported("Redirect without a path");
+            return;
+        };
+
+        // Prefix match on whole path segments; the rest is appended
+        let pattern = if path.ends_with('/') {
+            format!("^{}(.*)$", regex::escape(path))
+        } else {
+            format!("^{}(/.*)?$", regex::escape(path))
+        };
+        let target = args.get(1).map(|t| format!("{}$1", t));
+        self.push_redirect(pattern, target, flag);
+    }
+
+    /// `RedirectMatch [status] regex URL`
+    fn redirect_match(&mut self, value: &str) {
+        let mut args = split_args(value);
+        let status = if args.len() > 2 || args.first().map(|a| a == "gone").unwrap_or(false) {
+            args.remove(0)
+        } else {
+            "302".to_string()
+        };
+        let Some(flag) = redirect_flag(&status) else {
+            self.unsupported(&format!("RedirectMatch status {}", status));
+            return;
+        };
+        let Some(pattern) = args.first().cloned() else {
+            self.unsupported("RedirectMatch without a pattern");
+            return;
+        };
+        self.push_redirect(pattern, args.get(1).cloned(), flag);
+    }
+
+    fn push_redirect(&mut self, pattern: String, target: Option<String>, flag: String) {
+        let replacement = match (target, flag.as_str()) {
+            (_, "gone") => "-".to_string(),
+            (Some(target), _) => target,
+            (None, _) => {
+                self.unsupported("Redirect without a target URL");
+                return;
+            }
+        };
+        self.redirect_rules.push(RewriteRuleConfig {
+            pattern,
+            replacement,
+            flags: flag,
+            conditions: Vec::new(),
+        });
+    }
+
+    fn error_document(&mut self, value: &str) {
+        let Some((code, target)) = value.trim().split_once(char::is_whitespace) else {
+            self.unsupported("ErrorDocument without a target");
+            return;
+        };
+        let Ok(code) = code.parse::<u16>() else {
+            self.unsupported(&format!("ErrorDocument {}", code));
+            return;
+        };
+
+        let target = target.trim();
+        let document = if target.eq_ignore_ascii_case("default") {
+            ErrorDocument::Default
+        } else if target.starts_with('/') {
+            ErrorDocument::Path(target.to_string())
+        } else if target.contains("://") && !target.contains(char::is_whitespace) {
+            ErrorDocument::Url(target.to_string())
+        } else {
+            ErrorDocument::Text(unquote(target).to_string())
+        };
+        self.error_documents.insert(code, document);
+    }
+
+    /// `Header [always|onsuccess] action name [value]`
+    fn header(&mut self, value: &str) {
+        let mut args = split_args(value);
+        if args
+            .first()
+            .map(|a| a.eq_ignore_ascii_case("always") || a.eq_ignore_ascii_case("onsuccess"))
+            .unwrap_or(false)
+        {
+            args.remove(0);
+        }
+
+        let action = args
+            .first()
+            .map(|a| a.to_ascii_lowercase())
+            .unwrap_or_default();
+        let expected_args = if action == "unset" { 2 } else { 3 };
+        if args.len() > expected_args {
+            self.unsupported("Header with a condition");
+            return;
+        }
+
+        let Some(Ok(name)) = args.get(1).map(|n| HeaderName::from_bytes(n.as_bytes())) else {
+            self.unsupported("Header without a valid name");
+            return;
+        };
+        if action == "unset" {
+            self.headers.push(HeaderAction::Unset(name));
+            return;
+        }
+        let Some(Ok(value)) = args.get(2).map(|v| HeaderValue::from_str(v)) else {
+            self.unsupported("Header without a valid value");
+            return;
+        };
+
+        self.headers.push(match action.as_str() {
+            "set" => HeaderAction::Set(name, value),
+            "append" => HeaderAction::Append(name, value),
+            "add" => HeaderAction::Add(name, value),
+            "merge" => HeaderAction::Merge(name, value),
+            other => {
+                self.unsupported(&format!("Header {}", other));
+                return;
+            }
+        });
+    }
+
+    fn require(&mut self, value: &str) {
+        let args = split_args(value);
+        let (negate, args) = match args.first() {
+            Some(first) if first.eq_ignore_ascii_case("not") => (true, &args[1..]),
+            _ => (false, &args[..]),
+        };
+        let kind = args
+            .first()
+            .map(|a| a.to_ascii_lowercase())
+            .unwrap_or_default();
+
+        let mut networks = Vec::new();
+        match kind.as_str() {
+            "all" => {
+                let granted = args
+                    .get(1)
+                    .map(|a| a.eq_ignore_ascii_case("granted"))
+                    .unwrap_or(false);
+                let rules = self.require_rules();
+                rules.grant_all |= granted != negate;
+                return;
+            }
+            "ip" => {
+                for raw in &args[1..] {
+                    match parse_network(raw) {
+                        Some(Some(network)) => networks.push(network),
+                        _ => self.unsupported(&format!("Require ip {}", raw)),
+                    }
+                }
+            }
+            "local" => {
+                networks.push(("127.0.0.0".parse().unwrap(), 8));
+                networks.push(("::1".parse().unwrap(), 128));
+            }
+            // Fail closed: grants nothing
+            other => self.unsupported(&format!("Require {}", other)),
+        }
+
+        let rules = self.require_rules();
+        if negate {
+            rules.refuse.extend(networks);
+        } else {
+            rules.grant.extend(networks);
+        }
+    }
+
+    fn allow_deny(&mut self, value: &str, allow: bool) {
+        let args = split_args(value);
+        let hosts = match args.first() {
+            Some(from) if from.eq_ignore_ascii_case("from") => &args[1..],
+            _ => &args[..],
+        };
+
+        let mut networks = Vec::new();
+        for host in hosts {
+            match parse_network(host) {
+                Some(network) => networks.push(network),
+                None => self.unsupported(&format!("Allow/Deny from {}", host)),
+            }
+        }
+
+        let legacy = self.legacy();
+        if allow {
+            legacy.allow.extend(networks);
+        } else {
+            legacy.deny.extend(networks);
+        }
+    }
+
+    fn require_rules(&mut self) -> &mut RequireRules {
+        self.access
+            .get_or_insert_with(AccessPolicy::default)
+            .require
+            .get_or_insert_with(RequireRules::default)
+    }
+
+    fn legacy(&mut self) -> &mut LegacyRules {
+        self.access
+            .get_or_insert_with(AccessPolicy::default)
+            .legacy
+            .get_or_insert_with(LegacyRules::default)
+    }
+
+    /// Directory listings are never generated, so only enabling them is unsupported
+    fn options(&mut self, value: &str) {
+        for option in value.split_whitespace() {
+            let disabled = option.starts_with('-');
+            let name = option.trim_start_matches(['+', '-']).to_ascii_lowercase();
+            match name.as_str() {
+                "followsymlinks" | "symlinksifownermatch" | "none" => {}
+                "indexes" | "multiviews" if disabled => {}
+                _ => self.unsupported(&format!("Options {}", option)),
+            }
+        }
+    }
+}
+
+/// Whether an `<IfModule>` section applies; only modules we emulate count as loaded
+fn module_enabled(module: &str) -> bool {
+    let (negate, module) = match module.strip_prefix('!') {
+        Some(module) => (true, module),
+        None => (false, module),
+    };
+    let name = module
+        .trim()
+        .trim_start_matches("mod_")
+        .trim_end_matches(".c")
+        .trim_end_matches("_module")
+        .to_ascii_lowercase();
+    let loaded = matches!(
+        name.as_str(),
+        "rewrite" | "alias" | "headers" | "dir" | "authz_core" | "authz_host" | "access_compat"
+    );
+    loaded != negate
+}
+
+/// Map `%{VAR}` test strings onto rewrite condition types
+fn translate_condition(
+    test: &str,
+    pattern: &str,
+) -> Option<(&'static str, Option<String>, Option<String>)> {
+    let file_test = matches!(
+        test,
+        "%{REQUEST_FILENAME}" | "%{SCRIPT_FILENAME}" | "%{DOCUMENT_ROOT}%{REQUEST_URI}"
+    );
+    if file_test && pattern.starts_with('-') {
+        return match pattern {
+            "-f" | "-F" | "-s" => Some(("file", None, None)),
+            "-d" => Some(("dir", None, None)),
+            _ => None,
+        };
+    }
+
+    // `=value` is a literal comparison; other lexicographic tests are not supported
+    let pattern = match pattern.strip_prefix('=') {
+        Some(literal) => format!("^{}$", regex::escape(unquote(literal))),
+        None if pattern.starts_with(['<', '>', '-']) => return None,
+        None => pattern.to_string(),
+    };
+
+    let variable = test.strip_prefix("%{")?.strip_suffix('}')?;
+    let (kind, name) = match variable {
+        "HTTP_HOST" | "SERVER_NAME" => ("host", None),
+        "REQUEST_METHOD" => ("method", None),
+        "QUERY_STRING" => ("query", None),
+        "REQUEST_URI" => ("uri", None),
+        other => {
+            let header = match other.strip_prefix("HTTP:") {
+                Some(header) => header.to_string(),
+                None => other.strip_prefix("HTTP_")?.replace('_', "-"),
+            };
+            ("header", Some(header.to_ascii_lowercase()))
+        }
+    };
+    Some((kind, name, Some(pattern)))
+}
+
+/// Translate `%{VAR}` references in a substitution into rewrite variables
+fn translate_variables(substitution: &str) -> Option<String> {
+    let translated = substitution
+        .replace("%{HTTP_HOST}", "$host")
+        .replace("%{SERVER_NAME}", "$host")
+        .replace("%{REQUEST_URI}", "$uri")
+        .replace("%{QUERY_STRING}", "$query_string");
+    if translated.contains("%{") {
+        None
+    } else {
+        Some(translated)
+    }
+}
+
+/// Rewrite flag for a Redirect status keyword or code
+fn redirect_flag(status: &str) -> Option<String> {
+    match status.to_ascii_lowercase().as_str() {
+        "permanent" | "301" => Some("301".to_string()),
+        "temp" | "302" => Some("302".to_string()),
+        "seeother" | "303" => Some("303".to_string()),
+        "gone" | "410" => Some("gone".to_string()),
+        code @ ("307" | "308") => Some(code.to_string()),
+        _ => None,
+    }
+}
+
+/// Flags in `[A,B=c]` form
+fn parse_flags(raw: Option<&String>) -> Vec<String> {
+    raw.map(|raw| {
+        raw.trim_start_matches('[')
+            .trim_end_matches(']')
+            .split(',')
+            .map(|flag| flag.trim().to_string())
+            .filter(|flag| !flag.is_empty())
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// Split directive arguments on whitespace, keeping double-quoted strings together
+fn split_args(value: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut chars = value.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quoted && chars.peek() == Some(&'"') => {
+                current.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    args.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        args.push(current);
+    }
+
+    args
+}
+
+/// `Allow from`/`Require ip` address: `all`, an address, CIDR, netmask or
+/// partial IPv4 (`10.1`). `None` when it cannot be parsed (host names).
+fn parse_network(raw: &str) -> Option<Network> {
+    if raw.eq_ignore_ascii_case("all") {
+        return Some(None);
+    }
+
+    if let Some((addr, mask)) = raw.split_once('/') {
+        let addr: IpAddr = addr.parse().ok()?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match mask.parse::<u8>() {
+            Ok(prefix) if prefix <= max => prefix,
+            Ok(_) => return None,
+            Err(_) => match mask.parse::<IpAddr>().ok()? {
+                IpAddr::V4(mask) => u32::from(mask).count_ones() as u8,
+                IpAddr::V6(_) => return None,
+            },
+        };
+        return Some(Some((addr, prefix)));
+    }
+
+    if let Ok(addr) = raw.parse::<IpAddr>() {
+        let prefix = if addr.is_ipv4() { 32 } else { 128 };
+        return Some(Some((addr, prefix)));
+    }
+
+    // Partial IPv4 such as "10.1" or "192.168.1."
+    let octets: Vec<&str> = raw.trim_end_matches('.').split('.').collect();
+    if octets.len() < 4 && octets.iter().all(|o| o.parse::<u8>().is_ok()) {
+        let mut full = octets.clone();
+        full.resize(4, "0");
+        let addr: IpAddr = full.join(".").parse().ok()?;
+        return Some(Some((addr, (octets.len() * 8) as u8)));
+    }
+
+    None
+}
+
+/// `<Files>` wildcard (`*`, `?`) as an anchored regex
+fn wildcard_regex(pattern: &str) -> String {
+    let mut regex = String::from("^");
+    for c in unquote(pattern).chars() {
+        match c {
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// `.htaccess` files applying to a request, outermost directory first
+#[derive(Debug, Default)]
+pub struct HtaccessChain {
+    files: Vec<Arc<Htaccess>>,
+}
+
+impl HtaccessChain {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Access check; the innermost directory policy applies, then any
+    /// matching `<Files>` sections override it
+    pub fn allows(&self, addr: Option<IpAddr>, file_name: &str) -> bool {
+        let mut policy = None;
+        for htaccess in &self.files {
+            if let Some(access) = &htaccess.access {
+                policy = Some(access);
+            }
+        }
+        for section in self.sections(file_name) {
+            if let Some(access) = &section.access {
+                policy = Some(access);
+            }
+        }
+        policy.map(|policy| policy.allows(addr)).unwrap_or(true)
+    }
+
+    /// Redirect directives, then the rewrite rules of the innermost file
+    /// that configures `RewriteEngine` (like Apache without `RewriteOptions inherit`)
+    pub fn apply(
+        &self,
+        input: &RewriteInput,
+        state: &mut RewriteState,
+        trace: &mut Vec<RewriteTraceEntry>,
+    ) -> RewriteResult {
+        for htaccess in &self.files {
+            let result = htaccess.redirects.apply(input, state, trace);
+            if matches!(
+                result,
+                RewriteResult::Redirect { .. } | RewriteResult::Status(_)
+            ) {
+                return result;
+            }
+        }
+
+        let rewriting = self
+            .files
+            .iter()
+            .rev()
+            .find(|htaccess| htaccess.rewrite_engine.is_some());
+        match rewriting {
+            Some(htaccess) if htaccess.rewrite_engine == Some(true) => {
+                htaccess.rewrites.apply(input, state, trace)
+            }
+            _ => RewriteResult::Continue,
+        }
+    }
+
+    /// Innermost `DirectoryIndex`
+    pub fn directory_index(&self) -> Option<&[String]> {
+        self.files
+            .iter()
+            .rev()
+            .find_map(|htaccess| htaccess.directory_index.as_deref())
+    }
+
+    /// Innermost `ErrorDocument` for a status
+    pub fn error_document(&self, status: u16) -> Option<&ErrorDocument> {
+        self.files
+            .iter()
+            .rev()
+            .find_map(|htaccess| htaccess.error_documents.get(&status))
+            .filter(|document| **document != ErrorDocument::Default)
+    }
+
+    /// Apply `Header` directives, directories first, then `<Files>` sections
+    pub fn apply_headers(&self, file_name: &str, headers: &mut HeaderMap) {
+        for htaccess in &self.files {
+            for action in &htaccess.headers {
+                action.apply(headers);
+            }
+        }
+        for section in self.sections(file_name) {
+            for action in &section.headers {
+                action.apply(headers);
+            }
+        }
+    }
+
+    fn sections<'a>(&'a self, file_name: &'a str) -> impl Iterator<Item = &'a FilesSection> {
+        self.files
+            .iter()
+            .flat_map(|htaccess| htaccess.files.iter())
+            .filter(move |section| section.pattern.is_match(file_name))
+    }
+}
+
+/// Size and mtime of a file; `None` when it doesn't exist
+type Stamp = Option<(Option<SystemTime>, u64)>;
+
+#[derive(Debug, Clone)]
+struct CachedHtaccess {
+    stamp: Stamp,
+    parsed: Option<Arc<Htaccess>>,
+}
+
+/// Most directories whose `.htaccess` state (including "no file") is kept
+const MAX_CACHED_DIRECTORIES: usize = 4096;
+
+/// Parsed `.htaccess` files, re-read when they change on disk. Bounded, as
+/// every directory a request walks through gets an entry.
+#[derive(Debug)]
+pub struct HtaccessCache {
+    entries: Mutex<LruCache<(PathBuf, String), CachedHtaccess>>,
+}
+
+impl Default for HtaccessCache {
+    fn default() -> Self {
+        Self::with_capacity(MAX_CACHED_DIRECTORIES)
+    }
+}
+
+impl HtaccessCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn with_capacity(capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).expect("non-zero LRU size");
+        Self {
+            entries: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Files from `doc_root` down to the directory `uri_path` points into
+    pub fn chain(&self, doc_root: &Path, uri_path: &str) -> HtaccessChain {
+        let mut files = Vec::new();
+        let mut dir = doc_root.to_path_buf();
+        let mut dir_uri = String::from("/");
+
+        files.extend(self.load(&dir, &dir_uri));
+        for segment in uri_path.split('/').filter(|s| !s.is_empty()) {
+            let decoded = percent_encoding::percent_decode_str(segment).decode_utf8_lossy();
+            if decoded == ".." || decoded.contains('/') {
+                return HtaccessChain::default();
+            }
+            dir.push(decoded.as_ref());
+            if !dir.is_dir() {
+                break;
+            }
+            dir_uri.push_str(segment);
+            dir_uri.push('/');
+            files.extend(self.load(&dir, &dir_uri));
+        }
+
+        HtaccessChain { files }
+    }
+
+    fn load(&self, dir: &Path, dir_uri: &str) -> Option<Arc<Htaccess>> {
+        let path = dir.join(HTACCESS_FILE);
+        let stamp: Stamp = std::fs::metadata(&path)
+            .ok()
+            .filter(|metadata| metadata.is_file())
+            .map(|metadata| (metadata.modified().ok(), metadata.len()));
+
+        let key = (path, dir_uri.to_string());
+        if let Some(cached) = self.entries.lock().get(&key) {
+            if cached.stamp == stamp {
+                return cached.parsed.clone();
+            }
+        }
+
+        let parsed = match stamp {
+            Some(_) => match std::fs::read_to_string(&key.0) {
+                Ok(content) => {
+                    debug!("Loading {}", key.0.display());
+                    Some(Arc::new(Htaccess::parse(&content, &key.0, dir_uri)))
+                }
+                Err(err) => {
+                    warn!(file = %key.0.display(), "failed to read .htaccess: {}", err);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        self.entries.lock().put(
+            key,
+            CachedHtaccess {
+                stamp,
+                parsed: parsed.clone(),
+            },
+        );
+        parsed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::Method;
+
+    const WORDPRESS: &str = r#"
+# BEGIN WordPress
+<IfModule mod_rewrite.c>
+RewriteEngine On
+RewriteRule .* - [E=HTTP_AUTHORIZATION:%{HTTP:Authorization}]
+RewriteBase /
+RewriteRule ^index\.php$ - [L]
+RewriteCond %{REQUEST_FILENAME} !-f
+RewriteCond %{REQUEST_FILENAME} !-d
+RewriteRule . /index.php [L]
+</IfModule>
+# END WordPress
+"#;
+
+    fn apply(chain: &HtaccessChain, doc_root: &Path, uri: &str) -> (RewriteResult, String) {
+        let headers = HeaderMap::new();
+        let input = RewriteInput {
+            host: "example.com",
+            method: &Method::GET,
+            headers: &headers,
+            doc_root,
+        };
+        let (path, query) = match uri.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (uri, None),
+        };
+        let mut state = RewriteState::new(path, query);
+        let result = chain.apply(&input, &mut state, &mut Vec::new());
+        (result, state.uri())
+    }
+
+    #[test]
+    fn test_wordpress_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(".htaccess"), WORDPRESS).unwrap();
+        std::fs::write(dir.path().join("style.css"), "body{}").unwrap();
+
+        let cache = HtaccessCache::new();
+        let chain = cache.chain(dir.path(), "/hello-world/");
+
+        assert_eq!(
+            apply(&chain, dir.path(), "/hello-world/?p=1"),
+            (RewriteResult::Last, "/index.php?p=1".to_string())
+        );
+        assert_eq!(
+            apply(&chain, dir.path(), "/style.css").1,
+            "/style.css".to_string()
+        );
+        assert_eq!(
+            apply(&chain, dir.path(), "/index.php"),
+            (RewriteResult::Last, "/index.php".to_string())
+        );
+    }
+
+    #[test]
+    fn test_nested_directories_and_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("blog")).unwrap();
+        std::fs::write(
+            dir.path().join(".htaccess"),
+            "RewriteEngine On\nRewriteRule ^(.*)$ /root.php [L]\nDirectoryIndex home.html\n",
+        )
+        .unwrap();
+        let nested = dir.path().join("blog/.htaccess");
+        std::fs::write(
+            &nested,
+            "RewriteEngine On\nRewriteRule ^post/(\\d+)$ show.php?id=$1 [L,QSA]\n",
+        )
+        .unwrap();
+
+        let cache = HtaccessCache::new();
+        let chain = cache.chain(dir.path(), "/blog/post/7");
+        // Innermost RewriteEngine wins; relative substitutions use the directory
+        assert_eq!(
+            apply(&chain, dir.path(), "/blog/post/7?x=1").1,
+            "/blog/show.php?id=7&x=1"
+        );
+        assert_eq!(
+            chain.directory_index(),
+            Some(&["home.html".to_string()][..])
+        );
+
+        // Edited files are picked up; size changes even within one mtime tick
+        std::fs::write(&nested, "RewriteEngine Off\n").unwrap();
+        let chain = cache.chain(dir.path(), "/blog/post/7");
+        assert_eq!(
+            apply(&chain, dir.path(), "/blog/post/7"),
+            (RewriteResult::Continue, "/blog/post/7".to_string())
+        );
+    }
+
+    #[test]
+    fn test_cache_is_bounded() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a", "b", "c"] {
+            std::fs::create_dir(dir.path().join(name)).unwrap();
+        }
+        std::fs::write(dir.path().join(".htaccess"), "DirectoryIndex home.html\n").unwrap();
+
+        let cache = HtaccessCache::with_capacity(2);
+        for uri in ["/a/", "/b/", "/c/", "/a/x", "/b/y"] {
+            let chain = cache.chain(dir.path(), uri);
+            assert_eq!(
+                chain.directory_index(),
+                Some(&["home.html".to_string()][..])
+            );
+            assert!(cache.entries.lock().len() <= 2);
+        }
+    }
+
+    #[test]
+    fn test_access_control() {
+        let file = Path::new("/tmp/.htaccess");
+        let ip = |s: &str| Some(s.parse::<IpAddr>().unwrap());
+
+        let modern = HtaccessChain {
+            files: vec![Arc::new(Htaccess::parse(
+                "Require ip 10.0.0.0/8 192.168.1.5\n<Files \"wp-config.php\">\nRequire all denied\n</Files>\n<FilesMatch \"\\.(log|ini)$\">\nRequire all denied\n</FilesMatch>\n",
+                file,
+                "/",
+            ))],
+        };
+        assert!(modern.allows(ip("10.2.3.4"), "index.php"));
+        assert!(modern.allows(ip("192.168.1.5"), "index.php"));
+        assert!(!modern.allows(ip("8.8.8.8"), "index.php"));
+        assert!(!modern.allows(ip("10.2.3.4"), "wp-config.php"));
+        assert!(!modern.allows(ip("10.2.3.4"), "debug.log"));
+
+        let legacy = HtaccessChain {
+            files: vec![Arc::new(Htaccess::parse(
+                "Order Deny,Allow\nDeny from all\nAllow from 127.0.0.1 10.1\n",
+                file,
+                "/",
+            ))],
+        };
+        assert!(legacy.allows(ip("127.0.0.1"), "x"));
+        assert!(legacy.allows(ip("10.1.200.3"), "x"));
+        assert!(!legacy.allows(ip("10.2.0.1"), "x"));
+
+        let allow_first = HtaccessChain {
+            files: vec![Arc::new(Htaccess::parse("Order Allow,Deny\n", file, "/"))],
+        };
+        assert!(!allow_first.allows(ip("127.0.0.1"), "x"));
+    }
+
+    #[test]
+    fn test_redirects_headers_and_error_documents() {
+        let chain = HtaccessChain {
+            files: vec![Arc::new(Htaccess::parse(
+                r#"
+Redirect 301 /old /new
+RedirectMatch gone ^/removed/
+Header set X-Frame-Options "SAMEORIGIN"
+Header always unset X-Powered-By
+Header append Vary Cookie
+ErrorDocument 404 /errors/404.html
+ErrorDocument 500 "Something broke"
+ErrorDocument 503 https://status.example.com/
+php_value upload_max_filesize 64M
+"#,
+                Path::new("/tmp/.htaccess"),
+                "/",
+            ))],
+        };
+        let root = std::env::temp_dir();
+
+        assert_eq!(
+            apply(&chain, &root, "/old/page?a=1").0,
+            RewriteResult::Redirect {
+                status: 301,
+                location: "/new/page?a=1".to_string()
+            }
+        );
+        assert_eq!(apply(&chain, &root, "/older").0, RewriteResult::Continue);
+        assert_eq!(
+            apply(&chain, &root, "/removed/x").0,
+            RewriteResult::Status(410)
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-powered-by", HeaderValue::from_static("PHP"));
+        headers.insert("vary", HeaderValue::from_static("Accept-Encoding"));
+        chain.apply_headers("index.php", &mut headers);
+        assert_eq!(headers["x-frame-options"], "SAMEORIGIN");
+        assert_eq!(headers["vary"], "Accept-Encoding, Cookie");
+        assert!(headers.get("x-powered-by").is_none());
+
+        assert_eq!(
+            chain.error_document(404),
+            Some(&ErrorDocument::Path("/errors/404.html".to_string()))
+        );
+        assert_eq!(
+            chain.error_document(500),
+            Some(&ErrorDocument::Text("Something broke".to_string()))
+        );
+        assert!(matches!(
+            chain.error_document(503),
+            Some(ErrorDocument::Url(_))
+        ));
+        assert!(chain.error_document(403).is_none());
+    }
+
+    #[test]
+    fn test_unsupported_conditions_drop_the_rule() {
+        let chain = HtaccessChain {
+            files: vec![Arc::new(Htaccess::parse(
+                "RewriteEngine On\nRewriteCond %{HTTPS} off\nRewriteRule ^(.*)$ https://%{HTTP_HOST}/$1 [R=301,L]\nRewriteCond %{HTTP_HOST} ^www\\.(.+)$ [NC]\nRewriteRule ^(.*)$ https://%1/$1 [R=301,L]\n",
+                Path::new("/tmp/.htaccess"),
+                "/",
+            ))],
+        };
+        let headers = HeaderMap::new();
+        let root = std::env::temp_dir();
+        let input = RewriteInput {
+            host: "www.example.com",
+            method: &Method::GET,
+            headers: &headers,
+            doc_root: &root,
+        };
+        let mut state = RewriteState::new("/a", None);
+        assert_eq!(
+            chain.apply(&input, &mut state, &mut Vec::new()),
+            RewriteResult::Redirect {
+                status: 301,
+                location: "https://example.com/a".to_string()
+            }
+        );
+    }
+}
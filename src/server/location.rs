@@ -0,0 +1,415 @@
+//! Location Blocks
+//!
+//! Per-path configuration inside a virtual host, matched with nginx
+//! precedence:
+//! 1. Exact match (`=`) wins immediately
+//! 2. Longest prefix; if it is a priority prefix (`^~`) it wins
+//! 3. First matching regex in configuration order
+//! 4. Otherwise the longest prefix
+
+use crate::config::{LocationConfig, LocationMatch};
+use crate::server::rewrite::RewriteSet;
+use crate::server::try_files::TryFiles;
+
+use anyhow::{anyhow, Result};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use regex::{Regex, RegexBuilder};
+use std::net::IpAddr;
+use tracing::warn;
+
+/// A compiled location block
+#[derive(Debug)]
+pub struct Location {
+    /// Original configuration
+    pub config: LocationConfig,
+    /// Parsed try_files override
+    pub try_files: Option<TryFiles>,
+    /// Rewrite rules evaluated after this location matched
+    pub rewrites: RewriteSet,
+    regex: Option<Regex>,
+    access: Vec<AccessRule>,
+}
+
+impl Location {
+    fn compile(config: &LocationConfig) -> Result<Self> {
+        let regex = match config.match_type {
+            LocationMatch::Regex | LocationMatch::Iregex => Some(
+                RegexBuilder::new(&config.path)
+                    .case_insensitive(config.match_type == LocationMatch::Iregex)
+                    .build()?,
+            ),
+            _ => None,
+        };
+
+        if config.alias.is_some() && regex.is_some() {
+            return Err(anyhow!(
+                "alias is only supported on exact and prefix locations"
+            ));
+        }
+
+        let try_files = if config.try_files.is_empty() {
+            None
+        } else {
+            Some(TryFiles::parse(&config.try_files).map_err(|e| anyhow!(e))?)
+        };
+
+        let access = config
+            .access
+            .iter()
+            .map(|rule| AccessRule::parse(rule))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            config: config.clone(),
+            try_files,
+            rewrites: RewriteSet::compile(&format!("location {}", config.path), &config.rewrites),
+            regex,
+            access,
+        })
+    }
+
+    /// Whether a client address may access this location (no rules: allowed)
+    pub fn allows(&self, addr: Option<IpAddr>) -> bool {
+        for rule in &self.access {
+            if rule.matches(addr) {
+                return rule.allow;
+            }
+        }
+        true
+    }
+
+    /// Whether PHP execution is disabled here
+    pub fn denies_php(&self) -> bool {
+        self.config.php == Some(false)
+    }
+
+    /// Map a request path onto the filesystem: `(document root, lookup path)`
+    pub fn map_path(&self, path: &str) -> Option<(String, String)> {
+        if let Some(alias) = &self.config.alias {
+            let rest = path.strip_prefix(self.config.path.as_str()).unwrap_or(path);
+            let rest = format!("/{}", rest.trim_start_matches('/'));
+            return Some((alias.clone(), rest));
+        }
+        self.config
+            .root
+            .as_ref()
+            .map(|root| (root.clone(), path.to_string()))
+    }
+}
+
+/// All locations of a virtual host
+#[derive(Debug, Default)]
+pub struct LocationTable {
+    locations: Vec<Location>,
+}
+
+impl LocationTable {
+    /// Compile location blocks, skipping (and logging) invalid ones
+    pub fn compile(domain: &str, configs: &[LocationConfig]) -> Self {
+        let mut locations = Vec::with_capacity(configs.len());
+        for config in configs {
+            match Location::compile(config) {
+                Ok(location) => locations.push(location),
+                Err(err) => warn!(
+                    domain = %domain,
+                    location = %config.path,
+                    "invalid location block, skipping: {}",
+                    err
+                ),
+            }
+        }
+        Self { locations }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    /// Find the location handling `path`
+    pub fn resolve(&self, path: &str) -> Option<&Location> {
+        let mut longest_prefix: Option<&Location> = None;
+
+        for location in &self.locations {
+            match location.config.match_type {
+                LocationMatch::Exact if location.config.path == path => return Some(location),
+                LocationMatch::Prefix | LocationMatch::PriorityPrefix
+                    if path.starts_with(location.config.path.as_str()) =>
+                {
+                    let longer = longest_prefix
+                        .map(|current| location.config.path.len() > current.config.path.len())
+                        .unwrap_or(true);
+                    if longer {
+                        longest_prefix = Some(location);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(prefix) = longest_prefix {
+            if prefix.config.match_type == LocationMatch::PriorityPrefix {
+                return Some(prefix);
+            }
+        }
+
+        self.locations
+            .iter()
+            .find(|location| {
+                location
+                    .regex
+                    .as_ref()
+                    .map(|regex| regex.is_match(path))
+                    .unwrap_or(false)
+            })
+            .or(longest_prefix)
+    }
+}
+
+/// One `allow`/`deny` rule
+#[derive(Debug, Clone)]
+struct AccessRule {
+    allow: bool,
+    /// `None` matches every client ("all")
+    network: Option<(IpAddr, u8)>,
+}
+
+impl AccessRule {
+    fn parse(raw: &str) -> Result<Self> {
+        let mut words = raw.split_whitespace();
+        let allow = match words.next().map(|w| w.to_ascii_lowercase()) {
+            Some(w) if w == "allow" => true,
+            Some(w) if w == "deny" => false,
+            _ => {
+                return Err(anyhow!(
+                    "access rule must start with allow or deny: {}",
+                    raw
+                ))
+            }
+        };
+        let target = words
+            .next()
+            .ok_or_else(|| anyhow!("access rule is missing an address: {}", raw))?;
+
+        if target.eq_ignore_ascii_case("all") {
+            return Ok(Self {
+                allow,
+                network: None,
+            });
+        }
+
+        let (addr, prefix) = match target.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (target, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| anyhow!("invalid address in access rule: {}", raw))?;
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max_prefix)
+                .ok_or_else(|| anyhow!("invalid prefix length in access rule: {}", raw))?,
+            None => max_prefix,
+        };
+
+        Ok(Self {
+            allow,
+            network: Some((addr, prefix)),
+        })
+    }
+
+    fn matches(&self, addr: Option<IpAddr>) -> bool {
+        let Some((network, prefix)) = self.network else {
+            return true;
+        };
+        let Some(addr) = addr else {
+            return false;
+        };
+        ip_in_network(addr, network, prefix)
+    }
+}
+
+/// CIDR membership test; IPv4-mapped IPv6 clients match IPv4 networks
+pub fn ip_in_network(addr: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    let addr = match addr {
+        IpAddr::V6(v6) => v6
+            .to_ipv4_mapped()
+            .map(IpAddr::V4)
+            .unwrap_or(IpAddr::V6(v6)),
+        v4 => v4,
+    };
+
+    match (addr, network) {
+        (IpAddr::V4(a), IpAddr::V4(n)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(a) & mask == u32::from(n) & mask
+        }
+        (IpAddr::V6(a), IpAddr::V6(n)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(a) & mask == u128::from(n) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Characters re-encoded in a normalized path segment
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'[')
+    .add(b'\\')
+    .add(b']')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}');
+
+/// Canonical request path used for rewrites and location matching, so
+/// `/%70rivate/`, `//private/` and `/a/../private/` all match `/private/`
+///
+/// Percent-escapes are decoded, repeated slashes merged and `.`/`..`
+/// segments resolved (never above the root). Characters that are unsafe in
+/// a URI are encoded again, so the result is still a valid request path and
+/// decoding it once more yields the same filesystem path.
+pub fn normalize_path(path: &str) -> String {
+    let decoded = percent_decode_str(path).decode_utf8_lossy();
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    let mut normalized = String::with_capacity(path.len());
+    for segment in &segments {
+        normalized.push('/');
+        normalized.extend(utf8_percent_encode(segment, PATH_SEGMENT));
+    }
+    let trailing_slash =
+        decoded.ends_with('/') || decoded.ends_with("/.") || decoded.ends_with("/..");
+    if segments.is_empty() || trailing_slash {
+        normalized.push('/');
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(path: &str, match_type: LocationMatch) -> LocationConfig {
+        let toml = format!("path = {:?}", path);
+        let mut config: LocationConfig = toml::from_str(&toml).unwrap();
+        config.match_type = match_type;
+        config
+    }
+
+    #[test]
+    fn test_nginx_precedence() {
+        let table = LocationTable::compile(
+            "example.com",
+            &[
+                location("/", LocationMatch::Prefix),
+                location("/images/", LocationMatch::PriorityPrefix),
+                location(r"\.(gif|jpg|png)$", LocationMatch::Iregex),
+                location("/docs/", LocationMatch::Prefix),
+                location("/exact", LocationMatch::Exact),
+            ],
+        );
+
+        let matched = |path: &str| table.resolve(path).map(|l| l.config.path.clone());
+
+        assert_eq!(matched("/exact").as_deref(), Some("/exact"));
+        assert_eq!(matched("/exact/more").as_deref(), Some("/"));
+        // ^~ prefix beats the regex
+        assert_eq!(matched("/images/a.png").as_deref(), Some("/images/"));
+        // regex beats the plain prefix
+        assert_eq!(matched("/docs/a.PNG").as_deref(), Some(r"\.(gif|jpg|png)$"));
+        assert_eq!(matched("/docs/readme").as_deref(), Some("/docs/"));
+        assert_eq!(matched("/other").as_deref(), Some("/"));
+    }
+
+    #[test]
+    fn test_invalid_locations_are_skipped() {
+        let mut bad_regex = location("(unclosed", LocationMatch::Regex);
+        bad_regex.alias = None;
+        let mut regex_alias = location(r"^/a", LocationMatch::Regex);
+        regex_alias.alias = Some("/srv".to_string());
+
+        let table = LocationTable::compile("example.com", &[bad_regex, regex_alias]);
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn test_access_rules() {
+        let mut config = location("/admin", LocationMatch::Prefix);
+        config.access = vec![
+            "allow 10.0.0.0/8".to_string(),
+            "allow ::1".to_string(),
+            "deny all".to_string(),
+        ];
+        let location = Location::compile(&config).unwrap();
+
+        assert!(location.allows(Some("10.1.2.3".parse().unwrap())));
+        assert!(location.allows(Some("::ffff:10.1.2.3".parse().unwrap())));
+        assert!(location.allows(Some("::1".parse().unwrap())));
+        assert!(!location.allows(Some("192.168.1.1".parse().unwrap())));
+        assert!(!location.allows(None));
+
+        config.access = vec!["permit all".to_string()];
+        assert!(Location::compile(&config).is_err());
+    }
+
+    #[test]
+    fn test_alias_mapping() {
+        let mut config = location("/static/", LocationMatch::Prefix);
+        config.alias = Some("/srv/assets".to_string());
+        let location = Location::compile(&config).unwrap();
+
+        assert_eq!(
+            location.map_path("/static/css/app.css"),
+            Some(("/srv/assets".to_string(), "/css/app.css".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("/"), "/");
+        assert_eq!(normalize_path(""), "/");
+        assert_eq!(normalize_path("/%70rivate/s.txt"), "/private/s.txt");
+        assert_eq!(normalize_path("//private//s.txt"), "/private/s.txt");
+        assert_eq!(normalize_path("/a/./b/../private/"), "/a/private/");
+        assert_eq!(normalize_path("/../../etc/passwd"), "/etc/passwd");
+        assert_eq!(normalize_path("/uploads/evil%2ephp"), "/uploads/evil.php");
+        assert_eq!(normalize_path("/a/.."), "/");
+        assert_eq!(normalize_path("/docs/.."), "/");
+        assert_eq!(normalize_path("/blog/."), "/blog/");
+
+        // Unsafe characters stay encoded, and normalizing is idempotent
+        assert_eq!(normalize_path("/a%20b/%25%3F"), "/a%20b/%25%3F");
+        assert_eq!(normalize_path("/caf%C3%A9"), "/caf%C3%A9");
+        assert_eq!(normalize_path("/%2e%2e/%2F/x"), "/x");
+        let once = normalize_path("/x/%2525/%E6%97%A5");
+        assert_eq!(normalize_path(&once), once);
+
+        let table = LocationTable::compile(
+            "example.com",
+            &[location("/private/", LocationMatch::Prefix)],
+        );
+        assert!(table.resolve(&normalize_path("/%70rivate/s.txt")).is_some());
+        assert!(table.resolve(&normalize_path("//private/s.txt")).is_some());
+    }
+}
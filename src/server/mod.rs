@@ -2,19 +2,37 @@
 //!
 //! Core HTTP/1.1 and HTTP/2 server implementation using Hyper and Tokio.
 
+mod bypass;
 mod cache_warmer;
+mod compression;
+mod conditional;
+mod esi;
 mod handler;
+mod htaccess;
+mod location;
+mod rewrite;
 mod router;
+mod scheduler;
 mod static_files;
+mod surrogate;
 pub mod tls;
+mod try_files;
+mod vary;
+mod vhost;
 
 pub use cache_warmer::{CacheWarmer, WarmRequestPayload};
-pub use handler::RequestHandler;
+pub use handler::{ClientAddr, RequestHandler};
+pub use htaccess::{HtaccessCache, HtaccessChain};
+pub use location::{Location, LocationTable};
+pub use rewrite::{RewriteResult, RewriteSet};
 pub use router::Router;
+pub use scheduler::CronScheduler;
 pub use static_files::StaticFileHandler;
+pub use try_files::{TryFiles, TryFilesFallback};
+pub use vhost::VhostTable;
 
 use crate::cache::CacheManager;
-use crate::config::Config;
+use crate::config::{Config, ListenerKind};
 use crate::php::PhpPool;
 
 use anyhow::Result;
@@ -34,9 +52,10 @@ use tracing::{debug, error, info};
 /// VeloServe HTTP Server
 pub struct Server {
     config: Arc<Config>,
-    cache: Arc<CacheManager>,
     warmer: Arc<CacheWarmer>,
+    scheduler: Arc<CronScheduler>,
     php_pool: Arc<PhpPool>,
+    handler: Arc<RequestHandler>,
 }
 
 impl Server {
@@ -46,12 +65,21 @@ impl Server {
         let cache = Arc::new(CacheManager::new(&config.cache));
         let warmer = CacheWarmer::new(config.clone());
         let php_pool = Arc::new(PhpPool::new(&config.php));
+        let scheduler = CronScheduler::new(config.clone(), php_pool.clone());
+        let handler = Arc::new(RequestHandler::new(
+            config.clone(),
+            cache,
+            warmer.clone(),
+            scheduler.clone(),
+            php_pool.clone(),
+        ));
 
         Self {
             config,
-            cache,
             warmer,
+            scheduler,
             php_pool,
+            handler,
         }
     }
 
@@ -69,6 +97,7 @@ impl Server {
             self.php_pool.start().await?;
         }
         self.warmer.start();
+        self.scheduler.start();
 
         let http_listener = TcpListener::bind(addr).await?;
         info!("Server listening on http://{}", addr);
@@ -89,21 +118,10 @@ impl Server {
                     let tls_listener = TcpListener::bind(ssl_addr).await?;
                     info!("Server listening on https://{}", ssl_addr);
 
-                    let config = self.config.clone();
-                    let cache = self.cache.clone();
-                    let warmer = self.warmer.clone();
-                    let php_pool = self.php_pool.clone();
+                    let handler = self.handler.clone();
 
                     Some(tokio::spawn(async move {
-                        Self::accept_tls_loop(
-                            tls_listener,
-                            tls_acceptor,
-                            config,
-                            cache,
-                            warmer,
-                            php_pool,
-                        )
-                        .await;
+                        Self::accept_tls_loop(tls_listener, tls_acceptor, handler).await;
                     }))
                 }
                 Err(e) => {
@@ -135,22 +153,13 @@ impl Server {
             };
             debug!("Accepted HTTP connection from {}", remote_addr);
 
-            let config = self.config.clone();
-            let cache = self.cache.clone();
-            let warmer = self.warmer.clone();
-            let php_pool = self.php_pool.clone();
+            let handler = self.handler.clone();
 
             tokio::spawn(async move {
                 let io = TokioIo::new(stream);
                 let service = service_fn(move |req: Request<hyper::body::Incoming>| {
-                    let config = config.clone();
-                    let cache = cache.clone();
-                    let warmer = warmer.clone();
-                    let php_pool = php_pool.clone();
-                    async move {
-                        handle_request(req, remote_addr, config, cache, warmer, php_pool, false)
-                            .await
-                    }
+                    let handler = handler.clone();
+                    async move { handle_request(req, remote_addr, handler, false).await }
                 });
 
                 let conn = http1::Builder::new()
@@ -169,10 +178,7 @@ impl Server {
     async fn accept_tls_loop(
         listener: TcpListener,
         acceptor: TlsAcceptor,
-        config: Arc<Config>,
-        cache: Arc<CacheManager>,
-        warmer: Arc<CacheWarmer>,
-        php_pool: Arc<PhpPool>,
+        handler: Arc<RequestHandler>,
     ) {
         loop {
             let (stream, remote_addr) = match listener.accept().await {
@@ -184,10 +190,7 @@ impl Server {
             };
 
             let acceptor = acceptor.clone();
-            let config = config.clone();
-            let cache = cache.clone();
-            let warmer = warmer.clone();
-            let php_pool = php_pool.clone();
+            let handler = handler.clone();
 
             tokio::spawn(async move {
                 let tls_stream = match acceptor.accept(stream).await {
@@ -200,14 +203,8 @@ impl Server {
 
                 let io = TokioIo::new(tls_stream);
                 let service = service_fn(move |req: Request<hyper::body::Incoming>| {
-                    let config = config.clone();
-                    let cache = cache.clone();
-                    let warmer = warmer.clone();
-                    let php_pool = php_pool.clone();
-                    async move {
-                        handle_request(req, remote_addr, config, cache, warmer, php_pool, true)
-                            .await
-                    }
+                    let handler = handler.clone();
+                    async move { handle_request(req, remote_addr, handler, true).await }
                 });
 
                 let conn = http1::Builder::new()
@@ -232,24 +229,14 @@ impl Server {
             let (stream, remote_addr) = listener.accept().await?;
             debug!("Accepted HTTP/2 connection from {}", remote_addr);
 
-            let config = self.config.clone();
-            let cache = self.cache.clone();
-            let warmer = self.warmer.clone();
-            let php_pool = self.php_pool.clone();
+            let handler = self.handler.clone();
 
             tokio::spawn(async move {
                 let io = TokioIo::new(stream);
 
                 let service = service_fn(move |req: Request<hyper::body::Incoming>| {
-                    let config = config.clone();
-                    let cache = cache.clone();
-                    let warmer = warmer.clone();
-                    let php_pool = php_pool.clone();
-
-                    async move {
-                        handle_request(req, remote_addr, config, cache, warmer, php_pool, true)
-                            .await
-                    }
+                    let handler = handler.clone();
+                    async move { handle_request(req, remote_addr, handler, true).await }
                 });
 
                 let conn = http2::Builder::new(TokioExecutor).serve_connection(io, service);
@@ -282,22 +269,22 @@ fn is_connection_closed_error(e: &hyper::Error) -> bool {
 
 /// Handle incoming HTTP request
 async fn handle_request(
-    req: Request<hyper::body::Incoming>,
+    mut req: Request<hyper::body::Incoming>,
     remote_addr: SocketAddr,
-    config: Arc<Config>,
-    cache: Arc<CacheManager>,
-    warmer: Arc<CacheWarmer>,
-    php_pool: Arc<PhpPool>,
-    _is_https: bool,
+    handler: Arc<RequestHandler>,
+    is_https: bool,
 ) -> Result<Response<Full<Bytes>>, hyper::Error> {
     let method = req.method().clone();
     let uri = req.uri().clone();
     let start = std::time::Instant::now();
 
     debug!("{} {} from {}", method, uri, remote_addr);
-
-    // Create request handler
-    let handler = RequestHandler::new(config, cache, warmer, php_pool);
+    req.extensions_mut().insert(ClientAddr(remote_addr));
+    req.extensions_mut().insert(if is_https {
+        ListenerKind::Https
+    } else {
+        ListenerKind::Http
+    });
 
     // Handle the request
     let response = match handler.handle(req).await {
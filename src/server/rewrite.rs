@@ -0,0 +1,745 @@
+//! Rewrite Engine
+//!
+//! Regex rewrite and redirect rules for virtual hosts and locations, with
+//! nginx flag semantics and Apache-style conditions:
+//! - `$1`..`$9` expand rule captures, `%1`..`%9` captures of the last
+//!   matching condition, plus `$host`, `$uri`, `$args`, `$query_string`
+//!   and `$request_uri`
+//! - no flag: continue with the next rule using the rewritten URI
+//! - `last`: stop and search locations again for the new URI
+//! - `break`: stop and stay in the current location
+//! - `redirect`/`permanent`/`301`/`302`/`303`/`307`/`308`: answer with a redirect
+//! - `forbidden`/`gone`: answer with 403/410
+//! - `qsd`: drop the original query string, `nocase`: case-insensitive match
+
+use crate::config::{RewriteConditionConfig, RewriteRuleConfig};
+
+use anyhow::{anyhow, Result};
+use hyper::http::HeaderMap;
+use hyper::Method;
+use regex::{Captures, Regex, RegexBuilder};
+use serde::Serialize;
+use std::path::Path;
+use tracing::warn;
+
+/// What to do after a rule matched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RuleFlag {
+    Continue,
+    Last,
+    Break,
+    Redirect(u16),
+    Status(u16),
+}
+
+/// Request data conditions are evaluated against
+pub struct RewriteInput<'a> {
+    pub host: &'a str,
+    pub method: &'a Method,
+    pub headers: &'a HeaderMap,
+    /// Document root used by `file`/`dir` conditions
+    pub doc_root: &'a Path,
+}
+
+/// URI being rewritten
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RewriteState {
+    pub path: String,
+    pub query: Option<String>,
+}
+
+impl RewriteState {
+    pub fn new(path: &str, query: Option<&str>) -> Self {
+        Self {
+            path: path.to_string(),
+            query: query.filter(|q| !q.is_empty()).map(str::to_string),
+        }
+    }
+
+    /// Path with query string
+    pub fn uri(&self) -> String {
+        match &self.query {
+            Some(query) => format!("{}?{}", self.path, query),
+            None => self.path.clone(),
+        }
+    }
+}
+
+/// How a rule set finished
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RewriteResult {
+    /// No terminal rule fired (the URI may still have changed)
+    Continue,
+    /// `last` fired: search locations again
+    Last,
+    /// `break` fired: stop rewriting
+    Break,
+    /// Redirect the client
+    Redirect { status: u16, location: String },
+    /// Answer with a bare status (`forbidden`, `gone`)
+    Status(u16),
+}
+
+/// A rule that fired, for the rewrite test API
+#[derive(Debug, Clone, Serialize)]
+pub struct RewriteTraceEntry {
+    pub scope: String,
+    pub rule: usize,
+    pub pattern: String,
+    pub replacement: String,
+    pub result: String,
+}
+
+#[derive(Debug)]
+enum ConditionKind {
+    Host,
+    Method,
+    Header(String),
+    Cookie(String),
+    Query,
+    Uri,
+    File,
+    Dir,
+}
+
+#[derive(Debug)]
+struct RewriteCondition {
+    kind: ConditionKind,
+    pattern: Option<Regex>,
+    negate: bool,
+    or_next: bool,
+}
+
+impl RewriteCondition {
+    fn compile(config: &RewriteConditionConfig) -> Result<Self> {
+        let name = || {
+            config
+                .name
+                .clone()
+                .ok_or_else(|| anyhow!("'{}' condition needs a name", config.kind))
+        };
+        let kind = match config.kind.to_ascii_lowercase().as_str() {
+            "host" => ConditionKind::Host,
+            "method" => ConditionKind::Method,
+            "header" => ConditionKind::Header(name()?),
+            "cookie" => ConditionKind::Cookie(name()?),
+            "query" => ConditionKind::Query,
+            "uri" => ConditionKind::Uri,
+            "file" => ConditionKind::File,
+            "dir" => ConditionKind::Dir,
+            other => return Err(anyhow!("unknown condition type: {}", other)),
+        };
+
+        let pattern = match (&kind, &config.pattern) {
+            (ConditionKind::File | ConditionKind::Dir, _) => None,
+            (_, Some(pattern)) => Some(
+                RegexBuilder::new(pattern)
+                    .case_insensitive(config.nocase || matches!(kind, ConditionKind::Host))
+                    .build()?,
+            ),
+            (_, None) => return Err(anyhow!("'{}' condition needs a pattern", config.kind)),
+        };
+
+        Ok(Self {
+            kind,
+            pattern,
+            negate: config.negate,
+            or_next: config.or,
+        })
+    }
+
+    /// Evaluate; returns the match result and, when matched, the captures for `%N`
+    fn evaluate(&self, input: &RewriteInput, state: &RewriteState) -> (bool, Option<Vec<String>>) {
+        let value = match &self.kind {
+            ConditionKind::Host => input.host.to_string(),
+            ConditionKind::Method => input.method.to_string(),
+            ConditionKind::Header(name) => input
+                .headers
+                .get(name.as_str())
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("")
+                .to_string(),
+            ConditionKind::Cookie(name) => cookie_value(input.headers, name).unwrap_or_default(),
+            ConditionKind::Query => state.query.clone().unwrap_or_default(),
+            ConditionKind::Uri => state.path.clone(),
+            ConditionKind::File | ConditionKind::Dir => {
+                let exists = filesystem_path(input.doc_root, &state.path)
+                    .map(|path| match self.kind {
+                        ConditionKind::File => path.is_file(),
+                        _ => path.is_dir(),
+                    })
+                    .unwrap_or(false);
+                return (exists != self.negate, None);
+            }
+        };
+
+        let Some(pattern) = &self.pattern else {
+            return (!self.negate, None);
+        };
+        match pattern.captures(&value) {
+            Some(captures) if !self.negate => (true, Some(capture_list(&captures))),
+            Some(_) => (false, None),
+            None => (self.negate, None),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RewriteRule {
+    source: String,
+    pattern: Regex,
+    replacement: String,
+    flag: RuleFlag,
+    discard_query: bool,
+    conditions: Vec<RewriteCondition>,
+}
+
+impl RewriteRule {
+    fn compile(config: &RewriteRuleConfig) -> Result<Self> {
+        // A response flag (redirect/status) wins over `last`/`break`
+        let mut terminal = None;
+        let mut response = None;
+        let mut discard_query = false;
+        let mut nocase = false;
+
+        for raw in config
+            .flags
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|f| !f.is_empty())
+        {
+            match raw.to_ascii_lowercase().as_str() {
+                "last" | "l" => terminal = Some(RuleFlag::Last),
+                "break" | "end" => terminal = Some(RuleFlag::Break),
+                "redirect" | "r" => response = Some(RuleFlag::Redirect(302)),
+                "permanent" => response = Some(RuleFlag::Redirect(301)),
+                "forbidden" | "f" => response = Some(RuleFlag::Status(403)),
+                "gone" | "g" => response = Some(RuleFlag::Status(410)),
+                "qsd" => discard_query = true,
+                "nocase" | "nc" => nocase = true,
+                code => match code.parse::<u16>() {
+                    Ok(code @ (301 | 302 | 303 | 307 | 308)) => {
+                        response = Some(RuleFlag::Redirect(code))
+                    }
+                    _ => return Err(anyhow!("unknown rewrite flag: {}", raw)),
+                },
+            }
+        }
+        let mut flag = response.or(terminal).unwrap_or(RuleFlag::Continue);
+
+        // Absolute targets are always redirects.
+        if matches!(flag, RuleFlag::Continue | RuleFlag::Last | RuleFlag::Break) {
+            let lower = config.replacement.to_ascii_lowercase();
+            if lower.starts_with("http://") || lower.starts_with("https://") {
+                flag = RuleFlag::Redirect(302);
+            }
+        }
+
+        let conditions = config
+            .conditions
+            .iter()
+            .map(RewriteCondition::compile)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            source: config.pattern.clone(),
+            pattern: RegexBuilder::new(&config.pattern)
+                .case_insensitive(nocase)
+                .build()?,
+            replacement: config.replacement.clone(),
+            flag,
+            discard_query,
+            conditions,
+        })
+    }
+
+    /// Conditions are ANDed; `or` joins a condition with the next one.
+    fn conditions_match(&self, input: &RewriteInput, state: &RewriteState) -> Option<Vec<String>> {
+        let mut last_captures = Vec::new();
+        let mut pending_or = false;
+        let mut group_matched = false;
+
+        for condition in &self.conditions {
+            let (matched, captures) = condition.evaluate(input, state);
+            if matched {
+                if let Some(captures) = captures {
+                    last_captures = captures;
+                }
+            }
+            group_matched = if pending_or {
+                group_matched || matched
+            } else {
+                matched
+            };
+            pending_or = condition.or_next;
+            if !pending_or && !group_matched {
+                return None;
+            }
+        }
+
+        if pending_or && !group_matched {
+            return None;
+        }
+        Some(last_captures)
+    }
+}
+
+/// Compiled rules of one scope (virtual host, location or .htaccess)
+#[derive(Debug, Default)]
+pub struct RewriteSet {
+    scope: String,
+    rules: Vec<RewriteRule>,
+    directory: Option<DirectoryScope>,
+}
+
+/// Per-directory matching, as in `.htaccess` files
+#[derive(Debug)]
+struct DirectoryScope {
+    /// URI prefix of the directory (ends with `/`); stripped before matching
+    prefix: String,
+    /// Prepended to relative substitutions (`RewriteBase`)
+    base: String,
+}
+
+impl RewriteSet {
+    /// Compile rules, skipping (and logging) invalid ones
+    pub fn compile(scope: &str, configs: &[RewriteRuleConfig]) -> Self {
+        let mut rules = Vec::with_capacity(configs.len());
+        for config in configs {
+            match RewriteRule::compile(config) {
+                Ok(rule) => rules.push(rule),
+                Err(err) => warn!(
+                    scope = %scope,
+                    pattern = %config.pattern,
+                    "invalid rewrite rule, skipping: {}",
+                    err
+                ),
+            }
+        }
+        Self {
+            scope: scope.to_string(),
+            rules,
+            directory: None,
+        }
+    }
+
+    /// Match patterns against the path relative to `prefix` and resolve
+    /// relative substitutions against `base`, like Apache per-directory rules
+    pub fn in_directory(mut self, prefix: &str, base: &str) -> Self {
+        let with_slash = |s: &str| format!("/{}/", s.trim_matches('/')).replace("//", "/");
+        self.directory = Some(DirectoryScope {
+            prefix: with_slash(prefix),
+            base: with_slash(base),
+        });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Run the rules against `state`, rewriting it in place
+    pub fn apply(
+        &self,
+        input: &RewriteInput,
+        state: &mut RewriteState,
+        trace: &mut Vec<RewriteTraceEntry>,
+    ) -> RewriteResult {
+        for (index, rule) in self.rules.iter().enumerate() {
+            let subject = match &self.directory {
+                Some(directory) => match state.path.strip_prefix(directory.prefix.as_str()) {
+                    Some(relative) => relative,
+                    None => continue,
+                },
+                None => state.path.as_str(),
+            };
+            let Some(captures) = rule.pattern.captures(subject) else {
+                continue;
+            };
+            let rule_captures = capture_list(&captures);
+            let Some(condition_captures) = rule.conditions_match(input, state) else {
+                continue;
+            };
+
+            let mut expanded = expand(
+                &rule.replacement,
+                &rule_captures,
+                &condition_captures,
+                input,
+                state,
+            );
+            if let Some(directory) = &self.directory {
+                let lower = expanded.to_ascii_lowercase();
+                let relative = expanded != "-"
+                    && !expanded.starts_with('/')
+                    && !lower.starts_with("http://")
+                    && !lower.starts_with("https://");
+                if relative {
+                    expanded = format!("{}{}", directory.base, expanded);
+                }
+            }
+
+            if let RuleFlag::Status(status) = rule.flag {
+                trace.push(self.trace_entry(index, rule, format!("status {}", status)));
+                return RewriteResult::Status(status);
+            }
+
+            if let RuleFlag::Redirect(status) = rule.flag {
+                let location = with_query(&expanded, state.query.as_deref(), rule.discard_query);
+                trace.push(self.trace_entry(
+                    index,
+                    rule,
+                    format!("redirect {} {}", status, location),
+                ));
+                return RewriteResult::Redirect { status, location };
+            }
+
+            // "-" leaves the URI untouched (Apache), useful with flags only.
+            if expanded != "-" {
+                let target = with_query(&expanded, state.query.as_deref(), rule.discard_query);
+                let (path, query) = match target.split_once('?') {
+                    Some((path, query)) => (path.to_string(), Some(query.to_string())),
+                    None => (target, None),
+                };
+                state.path = if path.starts_with('/') {
+                    path
+                } else {
+                    format!("/{}", path)
+                };
+                state.query = query.filter(|q| !q.is_empty());
+            }
+
+            trace.push(self.trace_entry(index, rule, format!("rewrite {}", state.uri())));
+
+            match rule.flag {
+                RuleFlag::Last => return RewriteResult::Last,
+                RuleFlag::Break => return RewriteResult::Break,
+                _ => {}
+            }
+        }
+
+        RewriteResult::Continue
+    }
+
+    fn trace_entry(&self, index: usize, rule: &RewriteRule, result: String) -> RewriteTraceEntry {
+        RewriteTraceEntry {
+            scope: self.scope.clone(),
+            rule: index,
+            pattern: rule.source.clone(),
+            replacement: rule.replacement.clone(),
+            result,
+        }
+    }
+}
+
+fn capture_list(captures: &Captures) -> Vec<String> {
+    captures
+        .iter()
+        .map(|m| m.map(|m| m.as_str().to_string()).unwrap_or_default())
+        .collect()
+}
+
+/// Combine the rewritten target with the original query string
+///
+/// A target without a query keeps the original one; a target with a query
+/// gets the original appended (nginx), unless `qsd` is set or the target
+/// ends with `?`.
+fn with_query(target: &str, original: Option<&str>, discard: bool) -> String {
+    if let Some(stripped) = target.strip_suffix('?') {
+        return stripped.to_string();
+    }
+    match original.filter(|_| !discard) {
+        Some(original) if target.contains('?') => format!("{}&{}", target, original),
+        Some(original) => format!("{}?{}", target, original),
+        None => target.to_string(),
+    }
+}
+
+fn expand(
+    template: &str,
+    rule_captures: &[String],
+    condition_captures: &[String],
+    input: &RewriteInput,
+    state: &RewriteState,
+) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '$' && c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        if let Some(digit) = chars.peek().and_then(|d| d.to_digit(10)) {
+            chars.next();
+            let captures = if c == '$' {
+                rule_captures
+            } else {
+                condition_captures
+            };
+            if let Some(value) = captures.get(digit as usize) {
+                out.push_str(value);
+            }
+            continue;
+        }
+
+        if c == '$' {
+            let mut name = String::new();
+            while let Some(&next) = chars.peek() {
+                if next.is_ascii_alphanumeric() || next == '_' {
+                    name.push(next);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let query = state.query.as_deref().unwrap_or("");
+            match name.as_str() {
+                "host" => out.push_str(input.host),
+                "uri" => out.push_str(&state.path),
+                "args" | "query_string" => out.push_str(query),
+                "request_uri" => out.push_str(&state.uri()),
+                _ => {
+                    out.push('$');
+                    out.push_str(&name);
+                }
+            }
+            continue;
+        }
+
+        out.push(c);
+    }
+
+    out
+}
+
+fn cookie_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all("cookie")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(cookie, _)| *cookie == name)
+        .map(|(_, value)| value.to_string())
+}
+
+fn filesystem_path(doc_root: &Path, uri_path: &str) -> Option<std::path::PathBuf> {
+    let decoded = percent_encoding::percent_decode_str(uri_path.trim_start_matches('/'))
+        .decode_utf8_lossy()
+        .to_string();
+    if decoded.split('/').any(|segment| segment == "..") {
+        return None;
+    }
+    Some(doc_root.join(decoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, replacement: &str, flags: &str) -> RewriteRuleConfig {
+        RewriteRuleConfig {
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            flags: flags.to_string(),
+            conditions: Vec::new(),
+        }
+    }
+
+    fn condition(kind: &str, name: Option<&str>, pattern: Option<&str>) -> RewriteConditionConfig {
+        RewriteConditionConfig {
+            kind: kind.to_string(),
+            name: name.map(str::to_string),
+            pattern: pattern.map(str::to_string),
+            negate: false,
+            or: false,
+            nocase: false,
+        }
+    }
+
+    fn run(
+        rules: &[RewriteRuleConfig],
+        headers: &HeaderMap,
+        uri: &str,
+    ) -> (RewriteResult, RewriteState, Vec<RewriteTraceEntry>) {
+        let set = RewriteSet::compile("test", rules);
+        let (path, query) = match uri.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (uri, None),
+        };
+        let mut state = RewriteState::new(path, query);
+        let mut trace = Vec::new();
+        let doc_root = std::env::temp_dir();
+        let input = RewriteInput {
+            host: "www.example.com",
+            method: &Method::GET,
+            headers,
+            doc_root: &doc_root,
+        };
+        let result = set.apply(&input, &mut state, &mut trace);
+        (result, state, trace)
+    }
+
+    #[test]
+    fn test_internal_rewrite_with_captures() {
+        let (result, state, trace) = run(
+            &[rule(r"^/product/(\d+)$", "/index.php?id=$1", "last")],
+            &HeaderMap::new(),
+            "/product/42?ref=home",
+        );
+        assert_eq!(result, RewriteResult::Last);
+        assert_eq!(state.uri(), "/index.php?id=42&ref=home");
+        assert_eq!(trace.len(), 1);
+        assert_eq!(trace[0].rule, 0);
+    }
+
+    #[test]
+    fn test_rules_chain_without_flags() {
+        let (result, state, trace) = run(
+            &[
+                rule("^/a/(.*)$", "/b/$1", ""),
+                rule("^/b/(.*)$", "/c/$1?", "break"),
+            ],
+            &HeaderMap::new(),
+            "/a/x?q=1",
+        );
+        assert_eq!(result, RewriteResult::Break);
+        assert_eq!(state.uri(), "/c/x");
+        assert_eq!(trace.len(), 2);
+    }
+
+    #[test]
+    fn test_redirects() {
+        let (result, _, _) = run(
+            &[rule("^/old/(.*)$", "/new/$1", "permanent")],
+            &HeaderMap::new(),
+            "/old/page?x=1",
+        );
+        assert_eq!(
+            result,
+            RewriteResult::Redirect {
+                status: 301,
+                location: "/new/page?x=1".to_string()
+            }
+        );
+
+        let mut host_rule = rule("^(.*)$", "https://%1$1", "308,qsd");
+        host_rule.conditions = vec![condition("host", None, Some(r"^www\.(.+)$"))];
+        let (result, _, _) = run(&[host_rule], &HeaderMap::new(), "/path?x=1");
+        assert_eq!(
+            result,
+            RewriteResult::Redirect {
+                status: 308,
+                location: "https://example.com/path".to_string()
+            }
+        );
+
+        let (result, _, _) = run(
+            &[rule("^/ext$", "https://other.example/", "")],
+            &HeaderMap::new(),
+            "/ext",
+        );
+        assert!(matches!(
+            result,
+            RewriteResult::Redirect { status: 302, .. }
+        ));
+    }
+
+    #[test]
+    fn test_conditions() {
+        let mut headers = HeaderMap::new();
+        headers.insert("cookie", "lang=de; theme=dark".parse().unwrap());
+        headers.insert("user-agent", "Googlebot/2.1".parse().unwrap());
+
+        let mut lang = rule("^/$", "/de/", "last");
+        lang.conditions = vec![condition("cookie", Some("lang"), Some("^de$"))];
+        let (_, state, _) = run(&[lang], &headers, "/");
+        assert_eq!(state.path, "/de/");
+
+        let mut not_bot = rule("^/$", "/app/", "last");
+        let mut ua = condition("header", Some("User-Agent"), Some("bot"));
+        ua.negate = true;
+        ua.nocase = true;
+        not_bot.conditions = vec![ua];
+        let (result, state, _) = run(&[not_bot], &headers, "/");
+        assert_eq!(result, RewriteResult::Continue);
+        assert_eq!(state.path, "/");
+
+        // OR group: method POST or query containing debug
+        let mut either = rule("^/$", "/debug", "last");
+        let mut method = condition("method", None, Some("^POST$"));
+        method.or = true;
+        either.conditions = vec![method, condition("query", None, Some("debug"))];
+        let (result, _, _) = run(&[either], &headers, "/?debug=1");
+        assert_eq!(result, RewriteResult::Last);
+
+        // Front controller for anything that isn't a file
+        let mut front = rule("^/(.*)$", "/index.php", "last");
+        let mut not_file = condition("file", None, None);
+        not_file.negate = true;
+        front.conditions = vec![not_file];
+        let (_, state, _) = run(&[front], &headers, "/definitely-missing-file");
+        assert_eq!(state.path, "/index.php");
+    }
+
+    #[test]
+    fn test_invalid_rules_are_skipped() {
+        let set = RewriteSet::compile(
+            "test",
+            &[
+                rule("(", "/x", ""),
+                rule("^/a$", "/b", "sideways"),
+                RewriteRuleConfig {
+                    conditions: vec![condition("header", None, Some("x"))],
+                    ..rule("^/a$", "/b", "")
+                },
+            ],
+        );
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn test_directory_scope_and_status_flags() {
+        let set = RewriteSet::compile(
+            "/blog/.htaccess",
+            &[
+                rule(r"^private/", "-", "f"),
+                rule(r"^old/(.*)$", "new/$1", "301"),
+                rule("^(.+)$", "index.php?q=$1", "last"),
+            ],
+        )
+        .in_directory("/blog", "/blog/");
+        let doc_root = std::env::temp_dir();
+        let headers = HeaderMap::new();
+        let input = RewriteInput {
+            host: "example.com",
+            method: &Method::GET,
+            headers: &headers,
+            doc_root: &doc_root,
+        };
+        let apply = |uri: &str| {
+            let mut state = RewriteState::new(uri, None);
+            let result = set.apply(&input, &mut state, &mut Vec::new());
+            (result, state.uri())
+        };
+
+        assert_eq!(apply("/blog/private/x").0, RewriteResult::Status(403));
+        assert_eq!(
+            apply("/blog/old/post").0,
+            RewriteResult::Redirect {
+                status: 301,
+                location: "/blog/new/post".to_string()
+            }
+        );
+        assert_eq!(
+            apply("/blog/hello"),
+            (RewriteResult::Last, "/blog/index.php?q=hello".to_string())
+        );
+        // Outside the directory nothing applies
+        assert_eq!(
+            apply("/shop/hello"),
+            (RewriteResult::Continue, "/shop/hello".to_string())
+        );
+    }
+}
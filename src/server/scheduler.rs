@@ -0,0 +1,816 @@
+//! Cron Scheduler
+//!
+//! Runs per-vhost PHP jobs (WP-Cron, framework schedulers, maintenance
+//! scripts) through the shared PHP pool, so low-traffic sites behind the page
+//! cache still run their scheduled work and visitors never pay for it.
+
+use crate::config::{Config, CronConfig, CronJobConfig, VirtualHostConfig};
+use crate::php::{ExecutionTimeLimit, PhpPool};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, NaiveDate, Timelike, Utc};
+use hyper::{Method, Request};
+use parking_lot::Mutex;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+
+/// Name of the job registered automatically for WordPress vhosts.
+const WP_CRON_JOB_NAME: &str = "wp-cron";
+
+/// When a job fires.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CronTrigger {
+    /// Fixed interval between runs
+    Interval(Duration),
+    /// 5-field cron expression evaluated in UTC
+    Expression(CronExpr),
+}
+
+impl CronTrigger {
+    /// Parse "@every 5m", "@hourly"-style macros or a 5-field cron expression.
+    pub fn parse(raw: &str) -> Result<Self> {
+        let raw = raw.trim();
+        if let Some(interval) = raw.strip_prefix("@every") {
+            let interval = parse_interval(interval.trim())?;
+            return Ok(Self::Interval(interval));
+        }
+
+        let expression = match raw.to_ascii_lowercase().as_str() {
+            "@yearly" | "@annually" => "0 0 1 1 *".to_string(),
+            "@monthly" => "0 0 1 * *".to_string(),
+            "@weekly" => "0 0 * * 0".to_string(),
+            "@daily" | "@midnight" => "0 0 * * *".to_string(),
+            "@hourly" => "0 * * * *".to_string(),
+            _ => raw.to_string(),
+        };
+        Ok(Self::Expression(CronExpr::parse(&expression)?))
+    }
+
+    /// Time to wait from `now` until the next run.
+    fn delay_from(&self, now: DateTime<Utc>) -> Option<Duration> {
+        match self {
+            Self::Interval(interval) => Some(*interval),
+            Self::Expression(expr) => {
+                let next = expr.next_after(now)?;
+                (next - now).to_std().ok()
+            }
+        }
+    }
+}
+
+/// Parsed 5-field cron expression (minute hour day-of-month month day-of-week).
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronExpr {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    dom_restricted: bool,
+    dow_restricted: bool,
+}
+
+impl CronExpr {
+    /// Parse a standard 5-field cron expression.
+    pub fn parse(raw: &str) -> Result<Self> {
+        let fields: Vec<&str> = raw.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(anyhow!(
+                "cron expression must have 5 fields (minute hour day month weekday): {}",
+                raw
+            ));
+        }
+
+        let mut days_of_week = parse_cron_field(fields[4], 0, 7, &DAY_NAMES)?;
+        // Both 0 and 7 mean Sunday.
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week = (days_of_week & !(1 << 7)) | 1;
+        }
+
+        Ok(Self {
+            minutes: parse_cron_field(fields[0], 0, 59, &[])?,
+            hours: parse_cron_field(fields[1], 0, 23, &[])?,
+            days_of_month: parse_cron_field(fields[2], 1, 31, &[])?,
+            months: parse_cron_field(fields[3], 1, 12, &MONTH_NAMES)?,
+            days_of_week,
+            dom_restricted: fields[2] != "*",
+            dow_restricted: fields[4] != "*",
+        })
+    }
+
+    /// First matching minute strictly after `after`, searched up to five years ahead.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut t = after
+            .with_second(0)?
+            .with_nanosecond(0)?
+            .checked_add_signed(ChronoDuration::minutes(1))?;
+        let limit = after.checked_add_signed(ChronoDuration::days(366 * 5))?;
+
+        while t <= limit {
+            if !has_bit(self.months, t.month()) {
+                let (year, month) = if t.month() == 12 {
+                    (t.year() + 1, 1)
+                } else {
+                    (t.year(), t.month() + 1)
+                };
+                t = NaiveDate::from_ymd_opt(year, month, 1)?
+                    .and_hms_opt(0, 0, 0)?
+                    .and_utc();
+                continue;
+            }
+            if !self.day_matches(&t) {
+                t = t.date_naive().succ_opt()?.and_hms_opt(0, 0, 0)?.and_utc();
+                continue;
+            }
+            if !has_bit(self.hours, t.hour()) {
+                t = t
+                    .with_minute(0)?
+                    .checked_add_signed(ChronoDuration::hours(1))?;
+                continue;
+            }
+            if !has_bit(self.minutes, t.minute()) {
+                t = t.checked_add_signed(ChronoDuration::minutes(1))?;
+                continue;
+            }
+            return Some(t);
+        }
+
+        None
+    }
+
+    fn day_matches(&self, t: &DateTime<Utc>) -> bool {
+        let dom = has_bit(self.days_of_month, t.day());
+        let dow = has_bit(self.days_of_week, t.weekday().num_days_from_sunday());
+        // Vixie cron semantics: when both fields are restricted either may match.
+        if self.dom_restricted && self.dow_restricted {
+            dom || dow
+        } else {
+            dom && dow
+        }
+    }
+}
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const DAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+fn has_bit(mask: u64, value: u32) -> bool {
+    mask & (1u64 << value) != 0
+}
+
+fn parse_cron_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64> {
+    let mut mask = 0u64;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step
+                    .parse()
+                    .map_err(|_| anyhow!("invalid cron step: {}", part))?;
+                if step == 0 {
+                    return Err(anyhow!("cron step cannot be zero: {}", part));
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (
+                parse_cron_value(start, min, names)?,
+                parse_cron_value(end, min, names)?,
+            )
+        } else {
+            let start = parse_cron_value(range, min, names)?;
+            // "5/15" means every 15 starting at 5.
+            (start, if part.contains('/') { max } else { start })
+        };
+
+        if start < min || end > max || start > end {
+            return Err(anyhow!(
+                "cron field value out of range {}-{}: {}",
+                min,
+                max,
+                part
+            ));
+        }
+
+        let mut value = start;
+        while value <= end {
+            mask |= 1u64 << value;
+            value += step;
+        }
+    }
+
+    Ok(mask)
+}
+
+fn parse_cron_value(raw: &str, min: u32, names: &[&str]) -> Result<u32> {
+    if let Ok(value) = raw.parse::<u32>() {
+        return Ok(value);
+    }
+    let lower = raw.to_ascii_lowercase();
+    names
+        .iter()
+        .position(|name| *name == lower)
+        .map(|index| index as u32 + min)
+        .ok_or_else(|| anyhow!("invalid cron value: {}", raw))
+}
+
+/// Parse an interval like "90s", "5m", "2h", "1d" or plain seconds.
+pub fn parse_interval(raw: &str) -> Result<Duration> {
+    let raw = raw.trim().to_ascii_lowercase();
+    let (number, unit) = match raw.find(|c: char| !c.is_ascii_digit()) {
+        Some(pos) => raw.split_at(pos),
+        None => (raw.as_str(), "s"),
+    };
+    let value: u64 = number
+        .parse()
+        .map_err(|_| anyhow!("invalid interval: {}", raw))?;
+    let unit_secs: u64 = match unit.trim() {
+        "s" | "sec" | "secs" => 1,
+        "m" | "min" | "mins" => 60,
+        "h" | "hour" | "hours" => 3600,
+        "d" | "day" | "days" => 86_400,
+        other => return Err(anyhow!("unknown interval unit '{}' in {}", other, raw)),
+    };
+    let secs = value
+        .checked_mul(unit_secs)
+        .ok_or_else(|| anyhow!("interval too large: {}", raw))?;
+    if secs == 0 {
+        return Err(anyhow!("interval must be greater than zero"));
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// A single recorded job run.
+#[derive(Debug, Clone, Serialize)]
+struct CronRun {
+    started_epoch: u64,
+    duration_ms: u64,
+    trigger: &'static str,
+    outcome: &'static str,
+    status: Option<u16>,
+    error: Option<String>,
+}
+
+#[derive(Default)]
+struct CronJobStats {
+    runs_total: AtomicU64,
+    success_total: AtomicU64,
+    failure_total: AtomicU64,
+    timeout_total: AtomicU64,
+    skipped_total: AtomicU64,
+    next_run_epoch: AtomicU64,
+}
+
+struct CronJob {
+    id: String,
+    domain: String,
+    name: String,
+    doc_root: PathBuf,
+    script: String,
+    query: Option<String>,
+    schedule: String,
+    trigger: CronTrigger,
+    timeout: Duration,
+    running: AtomicBool,
+    stats: CronJobStats,
+    history: Mutex<VecDeque<CronRun>>,
+}
+
+impl CronJob {
+    fn from_config(
+        vhost: &VirtualHostConfig,
+        job: &CronJobConfig,
+        cron: &CronConfig,
+    ) -> Result<Self> {
+        let trigger = CronTrigger::parse(&job.schedule)?;
+        let script = if job.script.starts_with('/') {
+            job.script.clone()
+        } else {
+            format!("/{}", job.script)
+        };
+        if script.contains("..") {
+            return Err(anyhow!("cron script must stay inside the document root"));
+        }
+
+        Ok(Self {
+            id: format!("{}:{}", vhost.domain, job.name),
+            domain: vhost.domain.clone(),
+            name: job.name.clone(),
+            doc_root: PathBuf::from(&vhost.root),
+            script,
+            query: job.query.clone().filter(|q| !q.is_empty()),
+            schedule: job.schedule.clone(),
+            trigger,
+            timeout: Duration::from_secs(
+                job.timeout_secs.unwrap_or(cron.default_timeout_secs).max(1),
+            ),
+            running: AtomicBool::new(false),
+            stats: CronJobStats::default(),
+            history: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    fn record(&self, run: CronRun, history_size: usize) {
+        match run.outcome {
+            "ok" => {
+                self.stats.success_total.fetch_add(1, Ordering::Relaxed);
+            }
+            "timeout" => {
+                self.stats.timeout_total.fetch_add(1, Ordering::Relaxed);
+                self.stats.failure_total.fetch_add(1, Ordering::Relaxed);
+            }
+            "skipped_overlap" => {
+                self.stats.skipped_total.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {
+                self.stats.failure_total.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        if run.outcome != "skipped_overlap" {
+            self.stats.runs_total.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let mut history = self.history.lock();
+        history.push_back(run);
+        while history.len() > history_size.max(1) {
+            history.pop_front();
+        }
+    }
+
+    fn host(&self) -> &str {
+        if self.domain == "*" {
+            "localhost"
+        } else {
+            &self.domain
+        }
+    }
+
+    fn uri(&self) -> String {
+        match &self.query {
+            Some(query) => format!("{}?{}", self.script, query),
+            None => self.script.clone(),
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let history: Vec<CronRun> = self.history.lock().iter().cloned().collect();
+        json!({
+            "id": self.id,
+            "domain": self.domain,
+            "name": self.name,
+            "script": self.script,
+            "query": self.query,
+            "schedule": self.schedule,
+            "timeout_secs": self.timeout.as_secs(),
+            "running": self.running.load(Ordering::Relaxed),
+            "next_run_epoch": self.stats.next_run_epoch.load(Ordering::Relaxed),
+            "runs_total": self.stats.runs_total.load(Ordering::Relaxed),
+            "success_total": self.stats.success_total.load(Ordering::Relaxed),
+            "failure_total": self.stats.failure_total.load(Ordering::Relaxed),
+            "timeout_total": self.stats.timeout_total.load(Ordering::Relaxed),
+            "skipped_total": self.stats.skipped_total.load(Ordering::Relaxed),
+            "last_run": history.last(),
+            "history": history,
+        })
+    }
+}
+
+/// Built-in job scheduler (WP-Cron and generic cron jobs)
+pub struct CronScheduler {
+    cron_config: CronConfig,
+    php_pool: Arc<PhpPool>,
+    jobs: Vec<Arc<CronJob>>,
+    started: AtomicBool,
+}
+
+impl CronScheduler {
+    pub fn new(config: Arc<Config>, php_pool: Arc<PhpPool>) -> Arc<Self> {
+        let cron_config = config.cron.clone();
+        let mut jobs = Vec::new();
+
+        for vhost in &config.virtualhost {
+            for job in vhost.cron.iter().filter(|job| job.enable) {
+                match CronJob::from_config(vhost, job, &cron_config) {
+                    Ok(job) => jobs.push(Arc::new(job)),
+                    Err(err) => warn!(
+                        domain = %vhost.domain,
+                        job = %job.name,
+                        "invalid cron job, skipping: {}",
+                        err
+                    ),
+                }
+            }
+
+            if let Some(job) = wp_cron_job(vhost, &cron_config) {
+                match CronJob::from_config(vhost, &job, &cron_config) {
+                    Ok(job) => jobs.push(Arc::new(job)),
+                    Err(err) => warn!(domain = %vhost.domain, "invalid wp-cron job: {}", err),
+                }
+            }
+        }
+
+        Arc::new(Self {
+            cron_config,
+            php_pool,
+            jobs,
+            started: AtomicBool::new(false),
+        })
+    }
+
+    pub fn start(self: &Arc<Self>) {
+        if self.started.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        if !self.cron_config.enable {
+            info!("cron scheduler disabled via config");
+            return;
+        }
+
+        for job in &self.jobs {
+            let scheduler = self.clone();
+            let job = job.clone();
+            tokio::spawn(async move {
+                loop {
+                    let Some(delay) = job.trigger.delay_from(Utc::now()) else {
+                        warn!(job = %job.id, "cron expression never fires, stopping job");
+                        break;
+                    };
+                    job.stats
+                        .next_run_epoch
+                        .store(now_epoch_secs() + delay.as_secs(), Ordering::Relaxed);
+                    tokio::time::sleep(delay).await;
+                    scheduler.dispatch(job.clone(), "scheduled");
+                }
+            });
+        }
+
+        if !self.jobs.is_empty() {
+            info!("cron scheduler started with {} job(s)", self.jobs.len());
+        }
+    }
+
+    /// Run a job immediately. `None` when the job is unknown, `Some(false)` when it is still running.
+    pub fn trigger(self: &Arc<Self>, id: &str) -> Option<bool> {
+        let job = self.jobs.iter().find(|job| job.id == id)?.clone();
+        Some(self.dispatch(job, "manual"))
+    }
+
+    /// Start a run unless the previous one is still in flight.
+    fn dispatch(self: &Arc<Self>, job: Arc<CronJob>, trigger: &'static str) -> bool {
+        if job.running.swap(true, Ordering::AcqRel) {
+            warn!(job = %job.id, "previous cron run still in progress, skipping");
+            job.record(
+                CronRun {
+                    started_epoch: now_epoch_secs(),
+                    duration_ms: 0,
+                    trigger,
+                    outcome: "skipped_overlap",
+                    status: None,
+                    error: None,
+                },
+                self.cron_config.history_size,
+            );
+            return false;
+        }
+
+        let scheduler = self.clone();
+        tokio::spawn(async move {
+            let started_epoch = now_epoch_secs();
+            let started = Instant::now();
+            let result = timeout(job.timeout, scheduler.execute(&job)).await;
+            let duration_ms = started.elapsed().as_millis() as u64;
+
+            let (outcome, status, error) = match result {
+                Ok(Ok(status)) if status < 500 => ("ok", Some(status), None),
+                Ok(Ok(status)) => ("error", Some(status), None),
+                Ok(Err(err)) => ("error", None, Some(err.to_string())),
+                Err(_) => (
+                    "timeout",
+                    None,
+                    Some(format!("timed out after {}s", job.timeout.as_secs())),
+                ),
+            };
+
+            if outcome == "ok" {
+                debug!(job = %job.id, duration_ms, "cron run finished");
+            } else {
+                warn!(
+                    job = %job.id,
+                    outcome,
+                    duration_ms,
+                    "cron run failed: {}",
+                    error.as_deref().unwrap_or("error status")
+                );
+            }
+
+            job.running.store(false, Ordering::Release);
+            job.record(
+                CronRun {
+                    started_epoch,
+                    duration_ms,
+                    trigger,
+                    outcome,
+                    status,
+                    error,
+                },
+                scheduler.cron_config.history_size,
+            );
+        });
+
+        true
+    }
+
+    /// Execute the job script through the PHP pool and return the HTTP status it produced.
+    async fn execute(&self, job: &CronJob) -> Result<u16> {
+        if !self.php_pool.is_available() {
+            return Err(anyhow!("PHP support is not available"));
+        }
+
+        let script_path = job.doc_root.join(job.script.trim_start_matches('/'));
+        if !script_path.is_file() {
+            return Err(anyhow!("script not found: {}", script_path.display()));
+        }
+
+        // PHP's own limit sits just above the job timeout so the scheduler's
+        // timeout fires first and the run is recorded as "timeout"
+        let (parts, _) = Request::builder()
+            .method(Method::GET)
+            .uri(job.uri())
+            .header("Host", job.host())
+            .header("User-Agent", format!("VeloServe-Cron/{}", crate::VERSION))
+            .extension(ExecutionTimeLimit(job.timeout.as_secs() + 1))
+            .body(())?
+            .into_parts();
+
+        if self.php_pool.is_embed_mode() {
+            let response = self
+                .php_pool
+                .execute_embed(&script_path, &parts, &job.doc_root, &job.script, "", &[])
+                .await?;
+            Ok(response.status_code)
+        } else {
+            let output = self
+                .php_pool
+                .execute_cgi(&script_path, &parts, &job.doc_root, &job.script, "", &[])
+                .await?;
+            Ok(cgi_status(&output))
+        }
+    }
+
+    pub fn stats_json(&self) -> serde_json::Value {
+        let jobs: Vec<serde_json::Value> = self.jobs.iter().map(|job| job.to_json()).collect();
+        json!({
+            "enabled": self.cron_config.enable,
+            "jobs": jobs,
+            "config": {
+                "wp_cron": self.cron_config.wp_cron,
+                "wp_cron_interval_secs": self.cron_config.wp_cron_interval_secs,
+                "default_timeout_secs": self.cron_config.default_timeout_secs,
+                "history_size": self.cron_config.history_size,
+            }
+        })
+    }
+}
+
+/// Implicit WP-Cron job for WordPress vhosts unless one is configured explicitly.
+fn wp_cron_job(vhost: &VirtualHostConfig, cron: &CronConfig) -> Option<CronJobConfig> {
+    let is_wordpress = vhost
+        .platform
+        .as_deref()
+        .map(|p| p.to_ascii_lowercase().contains("wordpress"))
+        .unwrap_or(false);
+    if !cron.wp_cron || !is_wordpress {
+        return None;
+    }
+    if vhost.cron.iter().any(|job| job.name == WP_CRON_JOB_NAME) {
+        return None;
+    }
+
+    Some(CronJobConfig {
+        name: WP_CRON_JOB_NAME.to_string(),
+        script: "/wp-cron.php".to_string(),
+        query: Some("doing_wp_cron".to_string()),
+        schedule: format!("@every {}s", cron.wp_cron_interval_secs.max(1)),
+        timeout_secs: None,
+        enable: true,
+    })
+}
+
+/// Extract the `Status:` header from php-cgi output (200 when absent).
+fn cgi_status(output: &str) -> u16 {
+    let headers = output
+        .split("\r\n\r\n")
+        .next()
+        .and_then(|head| head.split("\n\n").next())
+        .unwrap_or("");
+    headers
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("status"))
+        .and_then(|(_, value)| value.split_whitespace().next()?.parse().ok())
+        .unwrap_or(200)
+}
+
+fn now_epoch_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_interval("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_interval("2h").unwrap(), Duration::from_secs(7200));
+        assert!(parse_interval("0s").is_err());
+        assert!(parse_interval("5w").is_err());
+        assert!(parse_interval("18446744073709551615d").is_err());
+        assert!(parse_interval("307445734561825861m").is_err());
+    }
+
+    #[test]
+    fn test_cron_next_after() {
+        let every_15 = CronExpr::parse("*/15 * * * *").unwrap();
+        assert_eq!(
+            every_15.next_after(at(2026, 3, 1, 10, 7)),
+            Some(at(2026, 3, 1, 10, 15))
+        );
+
+        let nightly = CronExpr::parse("30 2 * * *").unwrap();
+        assert_eq!(
+            nightly.next_after(at(2026, 3, 1, 10, 7)),
+            Some(at(2026, 3, 2, 2, 30))
+        );
+
+        // 2026-03-01 is a Sunday; next Monday 09:00.
+        let weekdays = CronExpr::parse("0 9 * * mon-fri").unwrap();
+        assert_eq!(
+            weekdays.next_after(at(2026, 3, 1, 10, 0)),
+            Some(at(2026, 3, 2, 9, 0))
+        );
+
+        let new_year = CronExpr::parse("0 0 1 jan *").unwrap();
+        assert_eq!(
+            new_year.next_after(at(2026, 3, 1, 0, 0)),
+            Some(at(2027, 1, 1, 0, 0))
+        );
+    }
+
+    #[test]
+    fn test_cron_rejects_invalid_expressions() {
+        assert!(CronExpr::parse("* * * *").is_err());
+        assert!(CronExpr::parse("60 * * * *").is_err());
+        assert!(CronExpr::parse("*/0 * * * *").is_err());
+        assert!(CronTrigger::parse("@every 0s").is_err());
+        assert_eq!(
+            CronTrigger::parse("@every 30s").unwrap(),
+            CronTrigger::Interval(Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn test_cgi_status() {
+        assert_eq!(
+            cgi_status("Status: 503 Service Unavailable\r\n\r\nbody"),
+            503
+        );
+        assert_eq!(cgi_status("Content-Type: text/html\n\nok"), 200);
+        assert_eq!(cgi_status("plain output"), 200);
+    }
+
+    #[tokio::test]
+    async fn test_wordpress_vhost_gets_wp_cron_job() {
+        let mut config = Config::default();
+        config.php.enable = false;
+        config.virtualhost.push(VirtualHostConfig {
+            domain: "blog.example.com".to_string(),
+            aliases: vec![],
+            default_server: Default::default(),
+            root: "/var/www/blog".to_string(),
+            platform: Some("wordpress".to_string()),
+            ssl_certificate: None,
+            ssl_certificate_key: None,
+            cache: None,
+            compression: None,
+            index: vec!["index.php".to_string()],
+            error_pages: Default::default(),
+            try_files: vec![],
+            cron: vec![],
+            location: vec![],
+            rewrites: vec![],
+            htaccess: false,
+        });
+        let php_pool = Arc::new(PhpPool::new(&config.php));
+        let scheduler = CronScheduler::new(Arc::new(config), php_pool);
+
+        let stats = scheduler.stats_json();
+        let jobs = stats["jobs"].as_array().unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0]["id"], "blog.example.com:wp-cron");
+        assert_eq!(jobs[0]["script"], "/wp-cron.php");
+        assert_eq!(jobs[0]["schedule"], "@every 60s");
+    }
+
+    /// Answers `-v` like PHP and runs the script with sh
+    #[cfg(unix)]
+    const FAKE_PHP: &str = "#!/bin/sh\nif [ \"$1\" = \"-v\" ]; then echo 'PHP 8.3.0 (cli)'; exit 0; fi\nfor arg; do script=\"$arg\"; done\nexec sh \"$script\"\n";
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_timed_out_run_kills_php() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let php_path = dir.path().join("php");
+        std::fs::write(&php_path, FAKE_PHP).unwrap();
+        std::fs::set_permissions(&php_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let pid_path = dir.path().join("pid");
+        std::fs::write(
+            dir.path().join("slow.php"),
+            format!("echo $$ > {}\nexec sleep 30\n", pid_path.display()),
+        )
+        .unwrap();
+
+        let mut config = Config::default();
+        config.php.enable = true;
+        config.php.mode = crate::config::PhpMode::Cgi;
+        config.php.binary_path = Some(php_path.display().to_string());
+        config.virtualhost.push(VirtualHostConfig {
+            domain: "jobs.example.com".to_string(),
+            aliases: vec![],
+            default_server: Default::default(),
+            root: dir.path().display().to_string(),
+            platform: None,
+            ssl_certificate: None,
+            ssl_certificate_key: None,
+            cache: None,
+            compression: None,
+            index: vec!["index.php".to_string()],
+            error_pages: Default::default(),
+            try_files: vec![],
+            cron: vec![CronJobConfig {
+                name: "slow".to_string(),
+                script: "/slow.php".to_string(),
+                query: None,
+                schedule: "@hourly".to_string(),
+                timeout_secs: Some(1),
+                enable: true,
+            }],
+            location: vec![],
+            rewrites: vec![],
+            htaccess: false,
+        });
+        let php_pool = Arc::new(PhpPool::new(&config.php));
+        php_pool.start().await.unwrap();
+        assert!(php_pool.is_available());
+        let scheduler = CronScheduler::new(Arc::new(config), php_pool);
+
+        assert_eq!(scheduler.trigger("jobs.example.com:slow"), Some(true));
+        let mut last_run = serde_json::Value::Null;
+        for _ in 0..50 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            last_run = scheduler.stats_json()["jobs"][0]["last_run"].clone();
+            if !last_run.is_null() {
+                break;
+            }
+        }
+        assert_eq!(last_run["outcome"], "timeout");
+
+        // The PHP process is killed (at most a zombie awaiting reaping)
+        let pid = std::fs::read_to_string(&pid_path).unwrap();
+        let stat_path = format!("/proc/{}/stat", pid.trim());
+        let mut gone = false;
+        for _ in 0..20 {
+            gone = std::fs::read_to_string(&stat_path).map_or(true, |stat| stat.contains(") Z "));
+            if gone {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(gone, "php process {} still running", pid.trim());
+    }
+}
@@ -3,20 +3,57 @@
 //! Serves static files like Nginx/Apache/LiteSpeed with:
 //! - Proper MIME type detection
 //! - ETag and Last-Modified headers
-//! - Conditional requests (If-None-Match, If-Modified-Since)
+//! - Conditional requests (If-Match, If-None-Match, If-Modified-Since, ...)
+//! - Byte ranges (single and multipart/byteranges) with If-Range
+//! - Precompressed siblings (`app.js.br`, `app.js.gz`, `app.js.zst`)
 //! - Cache-Control headers based on file type
 //! - Content-Length header
 
+use crate::config::ContentEncoding;
+use crate::server::compression;
+use crate::server::conditional::{self, Precondition, Validators};
+
 use anyhow::{anyhow, Result};
 use bytes::Bytes;
 use http_body_util::Full;
-use hyper::{Response, StatusCode};
-use std::path::Path;
+use hyper::header::{ACCEPT_ENCODING, IF_RANGE, RANGE};
+use hyper::http::response::Builder;
+use hyper::http::{HeaderMap, HeaderValue};
+use hyper::{Method, Response, StatusCode};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 use tokio::fs::{self, File};
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tracing::debug;
 
+/// More ranges than this in one request are ignored (full response)
+const MAX_RANGES: usize = 64;
+
+/// A satisfiable byte range, both bounds inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+/// A `Range` header evaluated against a file size
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// No usable Range header: send the whole file
+    Full,
+    /// Sorted, coalesced ranges
+    Partial(Vec<ByteRange>),
+    /// No range overlaps the file (416)
+    Unsatisfiable,
+}
+
 /// Handler for serving static files
 ///
 /// Implements static file serving similar to Nginx/Apache:
@@ -39,6 +76,20 @@ impl StaticFileHandler {
 
     /// Serve a static file
     pub async fn serve(&self, path: &Path) -> Result<Response<Full<Bytes>>> {
+        self.serve_range(path, None, None).await
+    }
+
+    /// Serve a static file honouring `Range` and `If-Range`
+    ///
+    /// Only the requested bytes are read. A single range larger than the
+    /// maximum file size is shortened (clients request the rest later), which
+    /// also makes files above the limit available to range requests.
+    pub async fn serve_range(
+        &self,
+        path: &Path,
+        range: Option<&str>,
+        if_range: Option<&str>,
+    ) -> Result<Response<Full<Bytes>>> {
         // Check if file exists
         if !path.exists() {
             return Err(anyhow!("File not found: {:?}", path));
@@ -53,11 +104,6 @@ impl StaticFileHandler {
         let metadata = fs::metadata(path).await?;
         let file_size = metadata.len();
 
-        // Check file size
-        if file_size > self.max_file_size {
-            return Err(anyhow!("File too large: {} bytes", file_size));
-        }
-
         // Get modification time for Last-Modified and ETag
         let modified = metadata.modified().ok();
         let etag = self.generate_etag(path, file_size, modified);
@@ -66,28 +112,115 @@ impl StaticFileHandler {
         // Determine MIME type
         let mime_type = self.guess_mime_type(path);
 
+        let ranges = match range {
+            Some(range) if if_range_matches(if_range, &etag, last_modified.as_deref()) => {
+                parse_range(range, file_size)
+            }
+            _ => RangeRequest::Full,
+        };
+
         debug!(
-            "Serving {:?} ({}, {} bytes, etag={})",
-            path, mime_type, file_size, etag
+            "Serving {:?} ({}, {} bytes, etag={}, ranges={:?})",
+            path, mime_type, file_size, etag, ranges
         );
 
-        // Read file contents
-        let mut file = File::open(path).await?;
-        let mut contents = Vec::with_capacity(file_size as usize);
-        file.read_to_end(&mut contents).await?;
+        let builder =
+            |status| self.response_builder(status, mime_type, &etag, last_modified.as_deref());
+
+        match ranges {
+            RangeRequest::Full => {
+                // Check file size
+                if file_size > self.max_file_size {
+                    return Err(anyhow!("File too large: {} bytes", file_size));
+                }
+
+                // Read file contents
+                let mut file = File::open(path).await?;
+                let mut contents = Vec::with_capacity(file_size as usize);
+                file.read_to_end(&mut contents).await?;
+
+                builder(StatusCode::OK)
+                    .header("Content-Type", mime_type)
+                    .header("Content-Length", file_size)
+                    .body(Full::new(Bytes::from(contents)))
+                    .map_err(|e| anyhow!("Failed to build response: {}", e))
+            }
+            RangeRequest::Unsatisfiable => builder(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header("Content-Range", format!("bytes */{}", file_size))
+                .header("Content-Length", 0)
+                .body(Full::new(Bytes::new()))
+                .map_err(|e| anyhow!("Failed to build response: {}", e)),
+            RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+                let mut range = ranges[0];
+                if range.len() > self.max_file_size {
+                    range.end = range.start + self.max_file_size - 1;
+                }
+
+                let mut file = File::open(path).await?;
+                let contents = read_range(&mut file, range).await?;
+
+                builder(StatusCode::PARTIAL_CONTENT)
+                    .header("Content-Type", mime_type)
+                    .header(
+                        "Content-Range",
+                        format!("bytes {}-{}/{}", range.start, range.end, file_size),
+                    )
+                    .header("Content-Length", contents.len())
+                    .body(Full::new(Bytes::from(contents)))
+                    .map_err(|e| anyhow!("Failed to build response: {}", e))
+            }
+            RangeRequest::Partial(ranges) => {
+                let total: u64 = ranges.iter().map(ByteRange::len).sum();
+                if total > self.max_file_size {
+                    return Err(anyhow!("Requested ranges too large: {} bytes", total));
+                }
 
-        // Build response with headers like Nginx/Apache
+                let boundary = multipart_boundary(&etag);
+                let mut file = File::open(path).await?;
+                let mut body = Vec::with_capacity(total as usize + ranges.len() * 128);
+                for range in &ranges {
+                    body.extend_from_slice(
+                        format!(
+                            "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                            boundary, mime_type, range.start, range.end, file_size
+                        )
+                        .as_bytes(),
+                    );
+                    body.extend_from_slice(&read_range(&mut file, *range).await?);
+                }
+                body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+                builder(StatusCode::PARTIAL_CONTENT)
+                    .header(
+                        "Content-Type",
+                        format!("multipart/byteranges; boundary={}", boundary),
+                    )
+                    .header("Content-Length", body.len())
+                    .body(Full::new(Bytes::from(body)))
+                    .map_err(|e| anyhow!("Failed to build response: {}", e))
+            }
+        }
+    }
+
+    /// Response builder with the headers every static response carries
+    /// (like Nginx/Apache); Content-Type is left to the caller since
+    /// multipart responses carry the file type per part
+    fn response_builder(
+        &self,
+        status: StatusCode,
+        mime_type: &str,
+        etag: &str,
+        last_modified: Option<&str>,
+    ) -> Builder {
         let mut builder = Response::builder()
-            .status(StatusCode::OK)
-            .header("Content-Type", mime_type)
-            .header("Content-Length", file_size)
+            .status(status)
             .header("Server", crate::SERVER_NAME)
             .header("Accept-Ranges", "bytes")
             .header("ETag", format!("\"{}\"", etag))
             .header("X-Content-Type-Options", "nosniff");
 
         // Add Last-Modified header
-        if let Some(ref lm) = last_modified {
+        if let Some(lm) = last_modified {
             builder = builder.header("Last-Modified", lm);
         }
 
@@ -95,19 +228,19 @@ impl StaticFileHandler {
         builder = builder.header("Cache-Control", self.cache_control(mime_type));
 
         // Add Vary header for encoded content
-        builder = builder.header("Vary", "Accept-Encoding");
-
-        builder
-            .body(Full::new(Bytes::from(contents)))
-            .map_err(|e| anyhow!("Failed to build response: {}", e))
+        builder.header("Vary", "Accept-Encoding")
     }
 
-    /// Serve with conditional request support (304 Not Modified)
+    /// Serve with conditional request support (304/412), then ranges
+    ///
+    /// `precompressed` lists the sibling encodings to look for (like nginx
+    /// `gzip_static`/`brotli_static`); empty disables the lookup.
     pub async fn serve_conditional(
         &self,
         path: &Path,
-        if_none_match: Option<&str>,
-        if_modified_since: Option<&str>,
+        method: &Method,
+        headers: &HeaderMap,
+        precompressed: &[ContentEncoding],
     ) -> Result<Response<Full<Bytes>>> {
         // Get file metadata first
         let metadata = fs::metadata(path).await?;
@@ -115,35 +248,88 @@ impl StaticFileHandler {
         let modified = metadata.modified().ok();
         let etag = self.generate_etag(path, file_size, modified);
 
-        // Check If-None-Match (ETag)
-        if let Some(client_etag) = if_none_match {
-            let client_etag = client_etag.trim_matches('"');
-            if client_etag == etag || client_etag == "*" {
-                return Ok(Response::builder()
-                    .status(StatusCode::NOT_MODIFIED)
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|h: &HeaderValue| h.to_str().ok())
+        };
+
+        // Ranges are served from the original file
+        let sidecar = match header(ACCEPT_ENCODING) {
+            Some(accept) if header(RANGE).is_none() && !precompressed.is_empty() => {
+                find_sidecar(path, modified, accept, precompressed).await
+            }
+            _ => None,
+        };
+        let response_etag = match &sidecar {
+            Some((encoding, _, _)) => format!("{}-{}", etag, encoding.as_str()),
+            None => etag.clone(),
+        };
+
+        let validators = Validators {
+            etag: &etag,
+            last_modified: modified,
+        };
+        match conditional::evaluate(method, headers, &validators) {
+            Precondition::Proceed => {}
+            Precondition::NotModified => {
+                let mime_type = self.guess_mime_type(path);
+                let last_modified = modified.map(format_http_date);
+                return self
+                    .response_builder(
+                        StatusCode::NOT_MODIFIED,
+                        mime_type,
+                        &response_etag,
+                        last_modified.as_deref(),
+                    )
+                    .body(Full::new(Bytes::new()))
+                    .map_err(|e| anyhow!("Failed to build response: {}", e));
+            }
+            Precondition::Failed => {
+                return Response::builder()
+                    .status(StatusCode::PRECONDITION_FAILED)
                     .header("Server", crate::SERVER_NAME)
                     .header("ETag", format!("\"{}\"", etag))
                     .body(Full::new(Bytes::new()))
-                    .unwrap());
+                    .map_err(|e| anyhow!("Failed to build response: {}", e));
             }
         }
 
-        // Check If-Modified-Since
-        if let (Some(ims), Some(file_modified)) = (if_modified_since, modified) {
-            if let Ok(client_time) = parse_http_date(ims) {
-                if file_modified <= client_time {
-                    return Ok(Response::builder()
-                        .status(StatusCode::NOT_MODIFIED)
-                        .header("Server", crate::SERVER_NAME)
-                        .header("ETag", format!("\"{}\"", etag))
-                        .body(Full::new(Bytes::new()))
-                        .unwrap());
-                }
+        if let Some((encoding, sidecar_path, sidecar_size)) = sidecar {
+            if sidecar_size > self.max_file_size {
+                return Err(anyhow!("File too large: {} bytes", sidecar_size));
             }
+            let mime_type = self.guess_mime_type(path);
+            let last_modified = modified.map(format_http_date);
+            let contents = fs::read(&sidecar_path).await?;
+            debug!(
+                "Serving {:?} for {:?} ({})",
+                sidecar_path,
+                path,
+                encoding.as_str()
+            );
+
+            return self
+                .response_builder(
+                    StatusCode::OK,
+                    mime_type,
+                    &response_etag,
+                    last_modified.as_deref(),
+                )
+                .header("Content-Type", mime_type)
+                .header("Content-Encoding", encoding.as_str())
+                .header("Content-Length", contents.len())
+                .body(Full::new(Bytes::from(contents)))
+                .map_err(|e| anyhow!("Failed to build response: {}", e));
         }
 
-        // Serve the full file
-        self.serve(path).await
+        // Ranges only apply to GET
+        let (range, if_range) = if method == Method::GET {
+            (header(RANGE), header(IF_RANGE))
+        } else {
+            (None, None)
+        };
+        self.serve_range(path, range, if_range).await
     }
 
     /// Generate ETag from file metadata
@@ -301,8 +487,163 @@ impl Default for StaticFileHandler {
     }
 }
 
+/// File name suffix of a precompressed sibling
+fn sidecar_extension(encoding: ContentEncoding) -> &'static str {
+    match encoding {
+        ContentEncoding::Br => "br",
+        ContentEncoding::Gzip => "gz",
+        ContentEncoding::Zstd => "zst",
+    }
+}
+
+/// Best precompressed sibling the client accepts
+///
+/// Siblings older than the original are stale and ignored.
+async fn find_sidecar(
+    path: &Path,
+    modified: Option<SystemTime>,
+    accept_encoding: &str,
+    encodings: &[ContentEncoding],
+) -> Option<(ContentEncoding, PathBuf, u64)> {
+    let mut available = Vec::new();
+    for encoding in encodings {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".");
+        name.push(sidecar_extension(*encoding));
+        let sidecar = PathBuf::from(name);
+
+        let Ok(metadata) = fs::metadata(&sidecar).await else {
+            continue;
+        };
+        let fresh = match (metadata.modified().ok(), modified) {
+            (Some(sidecar_modified), Some(original)) => sidecar_modified >= original,
+            _ => false,
+        };
+        if metadata.is_file() && fresh {
+            available.push((*encoding, sidecar, metadata.len()));
+        }
+    }
+
+    let offered: Vec<_> = available.iter().map(|(encoding, _, _)| *encoding).collect();
+    let chosen = compression::negotiate(accept_encoding, &offered)?;
+    available
+        .into_iter()
+        .find(|(encoding, _, _)| *encoding == chosen)
+}
+
+/// Parse a `Range` header (RFC 9110 §14.1.2) against a file size
+///
+/// Syntactically invalid headers, other units and requests with more than
+/// `MAX_RANGES` ranges are ignored. Overlapping or adjacent ranges are merged.
+pub fn parse_range(header: &str, size: u64) -> RangeRequest {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+
+    let mut ranges = Vec::new();
+    for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let Some((first, last)) = part.split_once('-') else {
+            return RangeRequest::Full;
+        };
+        let (first, last) = (first.trim(), last.trim());
+
+        let range = if first.is_empty() {
+            // Suffix range: the last N bytes
+            let Ok(suffix) = last.parse::<u64>() else {
+                return RangeRequest::Full;
+            };
+            (suffix > 0 && size > 0).then(|| ByteRange {
+                start: size.saturating_sub(suffix),
+                end: size - 1,
+            })
+        } else {
+            let Ok(start) = first.parse::<u64>() else {
+                return RangeRequest::Full;
+            };
+            let end = match last {
+                "" => u64::MAX,
+                last => match last.parse::<u64>() {
+                    Ok(end) if end >= start => end,
+                    _ => return RangeRequest::Full,
+                },
+            };
+            (start < size).then(|| ByteRange {
+                start,
+                end: end.min(size - 1),
+            })
+        };
+        ranges.extend(range);
+
+        if ranges.len() > MAX_RANGES {
+            return RangeRequest::Full;
+        }
+    }
+
+    if ranges.is_empty() {
+        return if spec.trim().is_empty() {
+            RangeRequest::Full
+        } else {
+            RangeRequest::Unsatisfiable
+        };
+    }
+
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => {
+                last.end = last.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+    RangeRequest::Partial(merged)
+}
+
+/// Whether an `If-Range` validator still matches the file (no header: yes)
+///
+/// Entity tags compare strongly (weak tags never match); dates must equal
+/// Last-Modified exactly.
+fn if_range_matches(if_range: Option<&str>, etag: &str, last_modified: Option<&str>) -> bool {
+    let Some(validator) = if_range.map(str::trim) else {
+        return true;
+    };
+
+    if validator.starts_with("W/") {
+        return false;
+    }
+    if let Some(tag) = validator.strip_prefix('"') {
+        return tag.strip_suffix('"') == Some(etag);
+    }
+
+    match (
+        parse_http_date(validator),
+        last_modified.map(parse_http_date),
+    ) {
+        (Ok(since), Some(Ok(modified))) => since == modified,
+        _ => false,
+    }
+}
+
+/// Read one range from an open file
+async fn read_range(file: &mut File, range: ByteRange) -> Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(range.start)).await?;
+    let mut buf = vec![0; range.len() as usize];
+    file.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+/// Boundary for a multipart/byteranges body
+fn multipart_boundary(etag: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    format!("veloserve-{}-{:08x}", etag, nanos)
+}
+
 /// Format a SystemTime as an HTTP date (RFC 7231)
-fn format_http_date(time: SystemTime) -> String {
+pub(crate) fn format_http_date(time: SystemTime) -> String {
     use chrono::{DateTime, Utc};
 
     let datetime: DateTime<Utc> = time.into();
@@ -310,7 +651,7 @@ fn format_http_date(time: SystemTime) -> String {
 }
 
 /// Parse an HTTP date string
-fn parse_http_date(s: &str) -> Result<SystemTime> {
+pub(crate) fn parse_http_date(s: &str) -> Result<SystemTime> {
     use chrono::{DateTime, Utc};
 
     // Try RFC 7231 format first
@@ -329,6 +670,7 @@ fn parse_http_date(s: &str) -> Result<SystemTime> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use http_body_util::BodyExt;
 
     #[test]
     fn test_mime_types() {
@@ -385,4 +727,171 @@ mod tests {
         let etag3 = handler.generate_etag(Path::new("/test.html"), 2000, None);
         assert_ne!(etag1, etag3);
     }
+
+    #[test]
+    fn test_parse_range() {
+        let range = |start, end| ByteRange { start, end };
+
+        assert_eq!(
+            parse_range("bytes=0-499", 1000),
+            RangeRequest::Partial(vec![range(0, 499)])
+        );
+        assert_eq!(
+            parse_range("bytes=900-", 1000),
+            RangeRequest::Partial(vec![range(900, 999)])
+        );
+        assert_eq!(
+            parse_range("bytes=-100", 1000),
+            RangeRequest::Partial(vec![range(900, 999)])
+        );
+        assert_eq!(
+            parse_range("bytes=990-2000", 1000),
+            RangeRequest::Partial(vec![range(990, 999)])
+        );
+        // Sorted and merged
+        assert_eq!(
+            parse_range("bytes=500-599, 0-99, 50-150, 600-700", 1000),
+            RangeRequest::Partial(vec![range(0, 150), range(500, 700)])
+        );
+        // Unsatisfiable ranges are dropped
+        assert_eq!(
+            parse_range("bytes=0-9, 5000-6000", 1000),
+            RangeRequest::Partial(vec![range(0, 9)])
+        );
+        assert_eq!(
+            parse_range("bytes=1000-", 1000),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(parse_range("bytes=-0", 1000), RangeRequest::Unsatisfiable);
+        // Invalid headers are ignored
+        assert_eq!(parse_range("bytes=5-1", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("items=0-1", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=a-b", 1000), RangeRequest::Full);
+        let many = (0..=MAX_RANGES)
+            .map(|i| format!("{}-{}", i * 10, i * 10))
+            .collect::<Vec<_>>()
+            .join(",");
+        assert_eq!(
+            parse_range(&format!("bytes={}", many), 10_000),
+            RangeRequest::Full
+        );
+    }
+
+    #[test]
+    fn test_if_range() {
+        let date = "Tue, 15 Nov 1994 08:12:31 GMT";
+
+        assert!(if_range_matches(None, "abc", None));
+        assert!(if_range_matches(Some("\"abc\""), "abc", Some(date)));
+        assert!(!if_range_matches(Some("\"old\""), "abc", Some(date)));
+        assert!(!if_range_matches(Some("W/\"abc\""), "abc", Some(date)));
+        assert!(if_range_matches(Some(date), "abc", Some(date)));
+        assert!(!if_range_matches(
+            Some("Wed, 16 Nov 1994 08:12:31 GMT"),
+            "abc",
+            Some(date)
+        ));
+        assert!(!if_range_matches(Some(date), "abc", None));
+    }
+
+    #[tokio::test]
+    async fn test_serve_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.txt");
+        std::fs::write(&path, "0123456789abcdefghij").unwrap();
+        let handler = StaticFileHandler::new();
+
+        let response = handler
+            .serve_range(&path, Some("bytes=5-9"), None)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()["content-range"], "bytes 5-9/20");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"56789");
+
+        let response = handler
+            .serve_range(&path, Some("bytes=0-1,-2"), None)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        let content_type = response.headers()["content-type"].to_str().unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap()
+            .to_string();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let expected = format!(
+            "\r\n--{b}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 0-1/20\r\n\r\n01\
+             \r\n--{b}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 18-19/20\r\n\r\nij\
+             \r\n--{b}--\r\n",
+            b = boundary
+        );
+        assert_eq!(String::from_utf8_lossy(&body), expected);
+
+        let response = handler
+            .serve_range(&path, Some("bytes=50-"), None)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()["content-range"], "bytes */20");
+
+        // A stale If-Range validator gets the full file
+        let response = handler
+            .serve_range(&path, Some("bytes=5-9"), Some("\"stale\""))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-length"], "20");
+    }
+
+    #[tokio::test]
+    async fn test_precompressed_siblings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.js");
+        std::fs::write(&path, "console.log(1);").unwrap();
+        std::fs::write(dir.path().join("app.js.gz"), "gzip bytes").unwrap();
+        // Older than the original: stale, never served
+        let stale = std::fs::File::create(dir.path().join("app.js.br")).unwrap();
+        stale
+            .set_modified(SystemTime::now() - std::time::Duration::from_secs(3600))
+            .unwrap();
+
+        let handler = StaticFileHandler::new();
+        let encodings = [ContentEncoding::Br, ContentEncoding::Gzip];
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("br, gzip"));
+
+        let response = handler
+            .serve_conditional(&path, &Method::GET, &headers, &encodings)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/javascript; charset=utf-8"
+        );
+        assert_eq!(response.headers()["content-encoding"], "gzip");
+        assert!(response.headers()["etag"]
+            .to_str()
+            .unwrap()
+            .ends_with("-gzip\""));
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"gzip bytes");
+
+        // Disabled for the vhost, or a range request: the original file
+        let response = handler
+            .serve_conditional(&path, &Method::GET, &headers, &[])
+            .await
+            .unwrap();
+        assert!(!response.headers().contains_key("content-encoding"));
+
+        headers.insert(RANGE, HeaderValue::from_static("bytes=0-6"));
+        let response = handler
+            .serve_conditional(&path, &Method::GET, &headers, &encodings)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(!response.headers().contains_key("content-encoding"));
+    }
 }
@@ -0,0 +1,182 @@
+//! Surrogate Headers
+//!
+//! Origin instructions meant for the page cache rather than the client:
+//! - cache tags (`X-Cache-Tags`, `Surrogate-Key`, `X-Magento-Tags`,
+//!   `X-LiteSpeed-Tag`), comma or space separated
+//! - cache lifetime (`Surrogate-Control`, `X-LiteSpeed-Cache-Control`):
+//!   `max-age=N` sets the TTL, `no-store` / `no-cache` prevent caching and
+//!   `private` limits it to the private (per-session) cache
+//!
+//! Both are removed from the response before it is stored or sent.
+
+use anyhow::{anyhow, Result};
+use hyper::http::HeaderMap;
+use std::time::Duration;
+use tracing::debug;
+
+/// Cache instructions read from an origin response
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Surrogate {
+    /// Normalized tags, deduplicated
+    pub tags: Vec<String>,
+    /// TTL from the first control header carrying `max-age`
+    pub max_age: Option<Duration>,
+    pub no_store: bool,
+    /// Only cacheable per session
+    pub private: bool,
+}
+
+/// Remove the tag and control headers, returning what they said
+pub fn take(
+    headers: &mut HeaderMap,
+    tag_headers: &[String],
+    control_headers: &[String],
+) -> Surrogate {
+    let mut surrogate = Surrogate::default();
+
+    for name in tag_headers {
+        let values: Vec<String> = headers
+            .get_all(name.as_str())
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .map(str::to_string)
+            .collect();
+        headers.remove(name.as_str());
+
+        let raw_tags = values
+            .iter()
+            .flat_map(|value| value.split(|ch: char| ch == ',' || ch.is_whitespace()))
+            .filter(|tag| !tag.is_empty());
+        for raw in raw_tags {
+            match normalize_tag(raw) {
+                Ok(tag) if !surrogate.tags.contains(&tag) => surrogate.tags.push(tag),
+                Ok(_) => {}
+                Err(err) => debug!(tag = raw, "ignoring origin cache tag: {}", err),
+            }
+        }
+    }
+
+    for name in control_headers {
+        let values: Vec<String> = headers
+            .get_all(name.as_str())
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .map(str::to_ascii_lowercase)
+            .collect();
+        headers.remove(name.as_str());
+
+        for directive in values.iter().flat_map(|value| value.split(',')) {
+            let directive = directive.trim();
+            match directive.split_once('=') {
+                Some((name, value)) if name.trim() == "max-age" && surrogate.max_age.is_none() => {
+                    surrogate.max_age = value
+                        .trim()
+                        .trim_matches('"')
+                        .parse()
+                        .ok()
+                        .map(Duration::from_secs);
+                }
+                _ if matches!(directive, "no-store" | "no-cache") => surrogate.no_store = true,
+                _ if directive == "private" => surrogate.private = true,
+                _ => {}
+            }
+        }
+    }
+
+    surrogate
+}
+
+/// Canonical form of a cache tag: lowercase, whitespace as `_`
+pub fn normalize_tag(raw: &str) -> Result<String> {
+    let trimmed = raw.trim().to_ascii_lowercase();
+    if trimmed.is_empty() {
+        return Err(anyhow!("tag cannot be empty"));
+    }
+    let mut normalized = String::with_capacity(trimmed.len());
+    for ch in trimmed.chars() {
+        if ch.is_ascii_alphanumeric() || matches!(ch, ':' | '_' | '-' | '/' | '.') {
+            normalized.push(ch);
+        } else if ch.is_whitespace() {
+            normalized.push('_');
+        } else {
+            return Err(anyhow!("tag contains unsupported characters"));
+        }
+    }
+    Ok(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::http::HeaderValue;
+
+    fn names(raw: &[&str]) -> Vec<String> {
+        raw.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_take_tags() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-cache-tags",
+            HeaderValue::from_static("post-123, Category-4"),
+        );
+        headers.insert("surrogate-key", HeaderValue::from_static("post-123 home"));
+        headers.insert(
+            "x-magento-tags",
+            HeaderValue::from_static("cat_p_42,bad!tag"),
+        );
+        headers.insert("content-type", HeaderValue::from_static("text/html"));
+
+        let surrogate = take(
+            &mut headers,
+            &names(&["X-Cache-Tags", "Surrogate-Key", "X-Magento-Tags"]),
+            &[],
+        );
+        assert_eq!(
+            surrogate.tags,
+            ["post-123", "category-4", "home", "cat_p_42"]
+        );
+        assert_eq!(surrogate.max_age, None);
+        assert!(!surrogate.no_store);
+        assert_eq!(headers.len(), 1);
+    }
+
+    #[test]
+    fn test_take_control() {
+        let controls = names(&["Surrogate-Control", "X-LiteSpeed-Cache-Control"]);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-litespeed-cache-control",
+            HeaderValue::from_static("public, max-age=120"),
+        );
+        headers.insert("surrogate-control", HeaderValue::from_static("max-age=600"));
+        let surrogate = take(&mut headers, &[], &controls);
+        assert_eq!(surrogate.max_age, Some(Duration::from_secs(600)));
+        assert!(headers.is_empty());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-litespeed-cache-control",
+            HeaderValue::from_static("no-cache"),
+        );
+        assert!(take(&mut headers, &[], &controls).no_store);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-litespeed-cache-control",
+            HeaderValue::from_static("private, max-age=60"),
+        );
+        let surrogate = take(&mut headers, &[], &controls);
+        assert!(surrogate.private && !surrogate.no_store);
+        assert_eq!(surrogate.max_age, Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_normalize_tag() {
+        assert_eq!(normalize_tag(" Post 123 ").unwrap(), "post_123");
+        assert!(normalize_tag("").is_err());
+        assert!(normalize_tag("a,b").is_err());
+    }
+}
@@ -12,13 +12,19 @@ use rustls::sign::CertifiedKey;
 use rustls::ServerConfig;
 use tracing::{info, warn};
 
-use crate::config::Config;
+use crate::config::{Config, ListenerKind};
+use crate::server::vhost::VhostTable;
 
 /// SNI-aware certificate resolver that picks the right cert per domain.
+///
+/// SNI names are matched with the same table as Host headers, so aliases
+/// and wildcard names share their virtual host's certificate.
 #[derive(Debug)]
 pub struct VeloServeCertResolver {
     default: Option<Arc<CertifiedKey>>,
-    certs: std::collections::HashMap<String, Arc<CertifiedKey>>,
+    /// Certificates by `config.virtualhost` index
+    certs: std::collections::HashMap<usize, Arc<CertifiedKey>>,
+    vhosts: VhostTable,
 }
 
 impl VeloServeCertResolver {
@@ -26,6 +32,7 @@ impl VeloServeCertResolver {
         let mut resolver = Self {
             default: None,
             certs: std::collections::HashMap::new(),
+            vhosts: VhostTable::new(config),
         };
 
         if let Some(ref ssl) = config.ssl {
@@ -38,14 +45,14 @@ impl VeloServeCertResolver {
             }
         }
 
-        for vhost in &config.virtualhost {
+        for (index, vhost) in config.virtualhost.iter().enumerate() {
             if let (Some(ref cert_path), Some(ref key_path)) =
                 (&vhost.ssl_certificate, &vhost.ssl_certificate_key)
             {
                 match load_certified_key(cert_path, key_path) {
                     Ok(ck) => {
                         info!("Loaded SSL cert for {} from {}", vhost.domain, cert_path);
-                        resolver.certs.insert(vhost.domain.clone(), Arc::new(ck));
+                        resolver.certs.insert(index, Arc::new(ck));
                     }
                     Err(e) => warn!("Failed to load SSL cert for {}: {}", vhost.domain, e),
                 }
@@ -63,7 +70,8 @@ impl VeloServeCertResolver {
 impl ResolvesServerCert for VeloServeCertResolver {
     fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
         if let Some(sni) = client_hello.server_name() {
-            if let Some(ck) = self.certs.get(sni) {
+            let index = self.vhosts.lookup(sni, ListenerKind::Https);
+            if let Some(ck) = index.and_then(|index| self.certs.get(&index)) {
                 return Some(ck.clone());
             }
         }
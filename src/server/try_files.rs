@@ -0,0 +1,254 @@
+//! try_files Resolution
+//!
+//! Nginx-style `try_files` lists: every entry but the last is a candidate
+//! path checked on disk (`$uri` for a file, `$uri/` for a directory), the
+//! last one is either an internal redirect to a URI (usually a front
+//! controller) or `=CODE` to answer with a status.
+
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Final step of a try_files list
+#[derive(Debug, Clone, PartialEq)]
+pub enum TryFilesFallback {
+    /// Internal redirect to this URI template (may carry a query string)
+    Uri(String),
+    /// Answer with this status code
+    Status(u16),
+}
+
+/// Parsed try_files list
+#[derive(Debug, Clone, PartialEq)]
+pub struct TryFiles {
+    /// Candidate templates checked in order
+    pub candidates: Vec<String>,
+    /// What to do when no candidate exists
+    pub fallback: TryFilesFallback,
+}
+
+impl TryFiles {
+    /// Parse a configured list. The last entry is the fallback.
+    pub fn parse(entries: &[String]) -> Result<Self, String> {
+        let (last, candidates) = entries
+            .split_last()
+            .ok_or_else(|| "try_files needs at least one entry".to_string())?;
+
+        for candidate in candidates {
+            if candidate.starts_with('=') {
+                return Err(format!(
+                    "try_files status '{}' is only allowed as the last entry",
+                    candidate
+                ));
+            }
+            if !candidate.starts_with('/') && !candidate.starts_with('$') {
+                return Err(format!(
+                    "try_files entry '{}' must start with '/' or a variable",
+                    candidate
+                ));
+            }
+        }
+
+        let fallback = if let Some(code) = last.strip_prefix('=') {
+            let code: u16 = code
+                .parse()
+                .map_err(|_| format!("invalid try_files status: {}", last))?;
+            if !(100..=599).contains(&code) {
+                return Err(format!("invalid try_files status: {}", last));
+            }
+            TryFilesFallback::Status(code)
+        } else if last.starts_with('/') || last.starts_with('$') {
+            TryFilesFallback::Uri(last.clone())
+        } else {
+            return Err(format!(
+                "try_files fallback '{}' must be a URI or =CODE",
+                last
+            ));
+        };
+
+        Ok(Self {
+            candidates: candidates.to_vec(),
+            fallback,
+        })
+    }
+
+    /// Built-in list for a platform, used when the vhost has no try_files.
+    pub fn preset(platform: &str) -> Option<Self> {
+        let entries: &[&str] = match platform.to_ascii_lowercase().as_str() {
+            "wordpress" => &["$uri", "$uri/", "/index.php?$args"],
+            "laravel" => &["$uri", "$uri/", "/index.php?$query_string"],
+            "magento" | "magento2" => &["$uri", "$uri/", "/index.php$is_args$args"],
+            "spa" => &["$uri", "$uri/", "/index.html"],
+            _ => return None,
+        };
+
+        let entries: Vec<String> = entries.iter().map(|e| e.to_string()).collect();
+        Self::parse(&entries).ok()
+    }
+
+    /// Configured list, else the platform preset, else `None` (built-in lookup order).
+    ///
+    /// Configured lists are checked by `Config::validate`, so a parse error
+    /// here only happens for configs built in code; it is logged and the
+    /// built-in lookup order is used.
+    pub fn for_vhost(try_files: &[String], platform: Option<&str>) -> Option<Self> {
+        if !try_files.is_empty() {
+            return Self::parse(try_files)
+                .map_err(|err| warn!("invalid try_files, ignoring: {}", err))
+                .ok();
+        }
+        platform.and_then(Self::preset)
+    }
+}
+
+/// Expand `$uri`, `$args`, `$query_string`, `$is_args` and `$request_uri`.
+pub fn expand_variables(template: &str, uri_path: &str, query: Option<&str>) -> String {
+    let query = query.unwrap_or("");
+    let request_uri = if query.is_empty() {
+        uri_path.to_string()
+    } else {
+        format!("{}?{}", uri_path, query)
+    };
+    let is_args = if query.is_empty() { "" } else { "?" };
+
+    // Longest names first so `$uri` doesn't eat `$request_uri`'s suffix.
+    let variables = [
+        ("$request_uri", request_uri.as_str()),
+        ("$query_string", query),
+        ("$is_args", is_args),
+        ("$args", query),
+        ("$uri", uri_path),
+    ];
+
+    // One pass over the template: client text in the values is never expanded
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(pos) = rest.find('$') {
+        expanded.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        match variables.iter().find(|(name, _)| rest.starts_with(name)) {
+            Some((name, value)) => {
+                expanded.push_str(value);
+                rest = &rest[name.len()..];
+            }
+            None => {
+                expanded.push('$');
+                rest = &rest[1..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Split an expanded URI into path and optional (non-empty) query.
+pub fn split_uri(uri: &str) -> (&str, Option<&str>) {
+    match uri.split_once('?') {
+        Some((path, query)) if !query.is_empty() => (path, Some(query)),
+        Some((path, _)) => (path, None),
+        None => (uri, None),
+    }
+}
+
+/// Document root adjusted for platforms that serve from a subdirectory
+/// (Laravel `public/`, Magento 2 `pub/`) when the vhost root points at the
+/// project directory.
+pub fn platform_doc_root(root: &Path, platform: Option<&str>) -> PathBuf {
+    let subdir = match platform.map(|p| p.to_ascii_lowercase()) {
+        Some(p) if p == "laravel" => "public",
+        Some(p) if p == "magento" || p == "magento2" => "pub",
+        _ => return root.to_path_buf(),
+    };
+
+    let candidate = root.join(subdir);
+    if candidate.join("index.php").is_file() {
+        candidate
+    } else {
+        root.to_path_buf()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(entries: &[&str]) -> Vec<String> {
+        entries.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_try_files() {
+        let parsed = TryFiles::parse(&list(&["$uri", "$uri/", "=404"])).unwrap();
+        assert_eq!(parsed.candidates, list(&["$uri", "$uri/"]));
+        assert_eq!(parsed.fallback, TryFilesFallback::Status(404));
+
+        let parsed = TryFiles::parse(&list(&["$uri", "/index.php?$args"])).unwrap();
+        assert_eq!(
+            parsed.fallback,
+            TryFilesFallback::Uri("/index.php?$args".to_string())
+        );
+
+        assert!(TryFiles::parse(&[]).is_err());
+        assert!(TryFiles::parse(&list(&["=404", "$uri"])).is_err());
+        assert!(TryFiles::parse(&list(&["$uri", "=99"])).is_err());
+        assert!(TryFiles::parse(&list(&["$uri", "index.php"])).is_err());
+    }
+
+    #[test]
+    fn test_expand_variables() {
+        assert_eq!(
+            expand_variables("/index.php?$args", "/blog/post", Some("p=1")),
+            "/index.php?p=1"
+        );
+        assert_eq!(
+            expand_variables("/index.php$is_args$args", "/blog", None),
+            "/index.php"
+        );
+        assert_eq!(
+            expand_variables("/index.php?q=$uri&$args", "/a", Some("x=1")),
+            "/index.php?q=/a&x=1"
+        );
+        assert_eq!(
+            expand_variables("$request_uri", "/a", Some("x=1")),
+            "/a?x=1"
+        );
+        // Values are inserted verbatim, never expanded again
+        assert_eq!(
+            expand_variables("/index.php?q=$uri&$args", "/a", Some("a=$uri")),
+            "/index.php?q=/a&a=$uri"
+        );
+        assert_eq!(
+            expand_variables("$uri$is_args$args", "/$query_string", Some("x=1")),
+            "/$query_string?x=1"
+        );
+        assert_eq!(split_uri("/index.php?"), ("/index.php", None));
+        assert_eq!(split_uri("/index.php?a=1"), ("/index.php", Some("a=1")));
+    }
+
+    #[test]
+    fn test_platform_presets() {
+        assert_eq!(
+            TryFiles::preset("spa").unwrap().fallback,
+            TryFilesFallback::Uri("/index.html".to_string())
+        );
+        assert!(TryFiles::preset("Laravel").is_some());
+        assert!(TryFiles::preset("generic").is_none());
+
+        // Explicit list wins over the preset.
+        let explicit = TryFiles::for_vhost(&list(&["$uri", "=404"]), Some("wordpress")).unwrap();
+        assert_eq!(explicit.fallback, TryFilesFallback::Status(404));
+    }
+
+    #[test]
+    fn test_platform_doc_root() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("public")).unwrap();
+        std::fs::write(dir.path().join("public/index.php"), "<?php").unwrap();
+
+        assert_eq!(
+            platform_doc_root(dir.path(), Some("laravel")),
+            dir.path().join("public")
+        );
+        assert_eq!(platform_doc_root(dir.path(), Some("magento2")), dir.path());
+        assert_eq!(platform_doc_root(dir.path(), None), dir.path());
+    }
+}
@@ -0,0 +1,327 @@
+//! Cache Key Variants
+//!
+//! `vary` in `[virtualhost.cache]` lists the request headers and cookies a
+//! cached page depends on:
+//! - `"Accept-Language"` keys on the primary tag of the preferred language
+//! - `"User-Agent"` keys on the device class (`mobile` / `desktop`)
+//! - `"Cookie"` keys on every cookie, `"cookie:currency"` on a single one
+//! - any other header keys on its trimmed, lowercased value
+//!
+//! Origin `Vary` headers must be covered by these rules for a response to
+//! be cached; `Vary: *` is never cached.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use hyper::header::{HeaderName, VARY};
+use hyper::http::{HeaderMap, HeaderValue};
+
+/// Variants longer than this are replaced by a hash
+const MAX_VARIANT_LEN: usize = 64;
+
+/// Origin Vary fields the page cache handles itself
+const IMPLICIT_VARY: &[&str] = &["accept-encoding", "host"];
+
+/// One entry of the `vary` list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VaryRule {
+    /// Lowercased request header name
+    Header(String),
+    /// Single cookie by name
+    Cookie(String),
+}
+
+impl VaryRule {
+    pub fn parse(raw: &str) -> Option<Self> {
+        let raw = raw.trim();
+        if let Some((prefix, name)) = raw.split_once(':') {
+            let name = name.trim();
+            return (prefix.trim().eq_ignore_ascii_case("cookie") && !name.is_empty())
+                .then(|| Self::Cookie(name.to_string()));
+        }
+        HeaderName::from_bytes(raw.as_bytes())
+            .ok()
+            .map(|name| Self::Header(name.as_str().to_string()))
+    }
+
+    /// Request header this rule reads, as listed in a response Vary
+    pub fn header_name(&self) -> &str {
+        match self {
+            Self::Header(name) => name,
+            Self::Cookie(_) => "cookie",
+        }
+    }
+
+    fn label(&self) -> String {
+        match self {
+            Self::Header(name) => name.clone(),
+            Self::Cookie(name) => format!("cookie.{}", name),
+        }
+    }
+
+    /// Normalized request value, `None` when absent
+    fn value(&self, headers: &HeaderMap) -> Option<String> {
+        let joined = headers
+            .get_all(self.header_name())
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(match self.header_name() {
+                "cookie" => "; ",
+                _ => ", ",
+            });
+        if joined.trim().is_empty() {
+            return None;
+        }
+
+        let value = match self {
+            Self::Cookie(name) => cookie_value(&joined, name)?.to_string(),
+            Self::Header(name) => match name.as_str() {
+                "accept-language" => primary_language(&joined)?,
+                "user-agent" => device_class(&joined).to_string(),
+                "cookie" => sorted_cookies(&joined),
+                _ => joined
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+                    .to_ascii_lowercase(),
+            },
+        };
+        (!value.is_empty()).then_some(value)
+    }
+}
+
+/// Parse a configured `vary` list, skipping invalid entries
+pub fn parse_rules(raw: &[String]) -> Vec<VaryRule> {
+    let mut rules: Vec<VaryRule> = Vec::new();
+    for rule in raw.iter().filter_map(|raw| VaryRule::parse(raw)) {
+        // Accept-Encoding is served from compressed variants of one entry
+        if rule.header_name() != "accept-encoding" && !rules.contains(&rule) {
+            rules.push(rule);
+        }
+    }
+    rules
+}
+
+/// Cache key variant for a request, `None` when no rule has a value
+pub fn request_variant(rules: &[VaryRule], headers: &HeaderMap) -> Option<String> {
+    let parts: Vec<String> = rules
+        .iter()
+        .filter_map(|rule| Some(format!("{}={}", rule.label(), rule.value(headers)?)))
+        .collect();
+    if parts.is_empty() {
+        return None;
+    }
+
+    let variant = parts.join("+");
+    if variant.len() <= MAX_VARIANT_LEN {
+        return Some(variant);
+    }
+    // Stable across restarts, the key is persisted in L2
+    let mut hasher = DefaultHasher::new();
+    variant.hash(&mut hasher);
+    Some(format!("h-{:016x}", hasher.finish()))
+}
+
+/// Whether a response may be cached under a key built from `rules`
+pub fn is_cacheable(headers: &HeaderMap, rules: &[VaryRule]) -> bool {
+    vary_fields(headers).all(|field| {
+        field != "*"
+            && (IMPLICIT_VARY.contains(&field.as_str())
+                || rules.iter().any(|rule| rule.header_name() == field))
+    })
+}
+
+/// Add `name` to the Vary header unless it is listed already
+pub fn add_vary(headers: &mut HeaderMap, name: &'static str) {
+    let present =
+        vary_fields(headers).any(|field| field == "*" || field.eq_ignore_ascii_case(name));
+    if !present {
+        headers.append(VARY, HeaderValue::from_static(name));
+    }
+}
+
+/// Advertise the configured rules in the response Vary header
+pub fn add_rules(headers: &mut HeaderMap, rules: &[VaryRule]) {
+    for rule in rules {
+        let present = vary_fields(headers).any(|field| field == "*" || field == rule.header_name());
+        if !present {
+            if let Ok(value) = HeaderValue::from_str(rule.header_name()) {
+                headers.append(VARY, value);
+            }
+        }
+    }
+}
+
+/// Lowercased fields of every Vary header
+fn vary_fields(headers: &HeaderMap) -> impl Iterator<Item = String> + '_ {
+    headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|field| field.trim().to_ascii_lowercase())
+        .filter(|field| !field.is_empty())
+}
+
+/// Primary subtag of the most preferred language (`de-AT;q=1` → `de`)
+fn primary_language(value: &str) -> Option<String> {
+    let mut best: Option<(&str, f32)> = None;
+    for item in value.split(',') {
+        let mut params = item.split(';');
+        let tag = params.next().unwrap_or("").trim();
+        let quality = params
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .and_then(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if tag.is_empty() || tag == "*" || quality <= 0.0 {
+            continue;
+        }
+        if best.is_none_or(|(_, current)| quality > current) {
+            best = Some((tag, quality));
+        }
+    }
+    let (tag, _) = best?;
+    let primary = tag.split(['-', '_']).next().unwrap_or(tag);
+    Some(primary.to_ascii_lowercase())
+}
+
+fn device_class(user_agent: &str) -> &'static str {
+    let user_agent = user_agent.to_ascii_lowercase();
+    if ["mobi", "android", "iphone", "ipod", "windows phone"]
+        .iter()
+        .any(|marker| user_agent.contains(marker))
+    {
+        "mobile"
+    } else {
+        "desktop"
+    }
+}
+
+fn cookie_pairs(header: &str) -> impl Iterator<Item = (&str, &str)> {
+    header.split(';').filter_map(|pair| {
+        let (name, value) = pair.split_once('=')?;
+        Some((name.trim(), value.trim()))
+    })
+}
+
+fn cookie_value<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    cookie_pairs(header)
+        .find(|(current, _)| *current == name)
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_empty())
+}
+
+/// Cookie header with pairs in a stable order
+fn sorted_cookies(header: &str) -> String {
+    let mut pairs: Vec<String> = cookie_pairs(header)
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    pairs.sort();
+    pairs.join(";")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(*name, HeaderValue::from_static(value));
+        }
+        map
+    }
+
+    fn rules(raw: &[&str]) -> Vec<VaryRule> {
+        parse_rules(&raw.iter().map(|s| s.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        assert_eq!(
+            rules(&[
+                "Accept-Language",
+                "cookie:currency",
+                "Accept-Encoding",
+                "bad header"
+            ]),
+            vec![
+                VaryRule::Header("accept-language".into()),
+                VaryRule::Cookie("currency".into()),
+            ]
+        );
+        assert!(rules(&["cookie:"]).is_empty());
+    }
+
+    #[test]
+    fn test_request_variant() {
+        let rules = rules(&["Accept-Language", "cookie:currency", "User-Agent"]);
+
+        let en = headers(&[("accept-language", "en-US,en;q=0.9,de;q=0.8")]);
+        let en_gb = headers(&[("accept-language", "fr;q=0.5, en-GB")]);
+        assert_eq!(
+            request_variant(&rules, &en).as_deref(),
+            Some("accept-language=en")
+        );
+        assert_eq!(
+            request_variant(&rules, &en),
+            request_variant(&rules, &en_gb)
+        );
+
+        let full = headers(&[
+            ("accept-language", "de-AT"),
+            ("cookie", "session=1; currency=EUR"),
+            (
+                "user-agent",
+                "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0) Mobile/15E148",
+            ),
+        ]);
+        assert_eq!(
+            request_variant(&rules, &full).as_deref(),
+            Some("accept-language=de+cookie.currency=EUR+user-agent=mobile")
+        );
+
+        assert_eq!(
+            request_variant(&rules, &headers(&[("accept-language", "*")])),
+            None
+        );
+        assert_eq!(request_variant(&rules, &HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_long_variants_are_hashed() {
+        let rules = rules(&["X-Tenant"]);
+        let long = headers(&[(
+            "x-tenant",
+            "a-very-long-tenant-identifier-that-would-otherwise-be-truncated-in-the-key",
+        )]);
+        let variant = request_variant(&rules, &long).unwrap();
+        assert!(variant.starts_with("h-"));
+        assert_eq!(request_variant(&rules, &long), Some(variant));
+    }
+
+    #[test]
+    fn test_is_cacheable() {
+        let rules = rules(&["Accept-Language", "cookie:currency"]);
+        assert!(is_cacheable(&HeaderMap::new(), &rules));
+        assert!(is_cacheable(
+            &headers(&[
+                ("vary", "Accept-Encoding, Accept-Language"),
+                ("vary", "Cookie")
+            ]),
+            &rules
+        ));
+        assert!(!is_cacheable(&headers(&[("vary", "*")]), &rules));
+        assert!(!is_cacheable(&headers(&[("vary", "User-Agent")]), &rules));
+    }
+
+    #[test]
+    fn test_add_rules() {
+        let mut map = headers(&[("vary", "Accept-Encoding")]);
+        add_rules(&mut map, &rules(&["Accept-Language", "cookie:currency"]));
+        add_vary(&mut map, "Accept-Encoding");
+        let vary: Vec<_> = map.get_all(VARY).iter().collect();
+        assert_eq!(vary, ["Accept-Encoding", "accept-language", "cookie"]);
+    }
+}
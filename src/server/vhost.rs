@@ -0,0 +1,243 @@
+//! Virtual Host Lookup
+//!
+//! Precomputed server-name table, matched like nginx:
+//! 1. Exact name (`domain` or an entry of `aliases`)
+//! 2. Longest leading wildcard (`*.example.com`; `.example.com` also
+//!    matches `example.com`)
+//! 3. Longest trailing wildcard (`www.example.*`)
+//! 4. First matching regex (`~^(www\.)?shop\d+\.example\.com$`)
+//! 5. The listener's default server, then a `"*"` catch-all
+//!
+//! Host names are compared case-insensitively without a trailing dot.
+
+use crate::config::{Config, ListenerKind};
+
+use regex::{Regex, RegexBuilder};
+use std::collections::HashMap;
+use tracing::warn;
+
+/// Server name lookup table (values index `config.virtualhost`)
+#[derive(Debug, Default)]
+pub struct VhostTable {
+    exact: HashMap<String, usize>,
+    /// `*.example.com` stored as `.example.com`
+    leading: HashMap<String, usize>,
+    /// `www.example.*` stored as `www.example.`
+    trailing: HashMap<String, usize>,
+    regex: Vec<(Regex, usize)>,
+    catch_all: Option<usize>,
+    default_http: Option<usize>,
+    default_https: Option<usize>,
+}
+
+impl VhostTable {
+    /// Build the table; earlier vhosts win on duplicate names
+    pub fn new(config: &Config) -> Self {
+        let mut table = Self::default();
+
+        for (index, vhost) in config.virtualhost.iter().enumerate() {
+            for name in std::iter::once(&vhost.domain).chain(vhost.aliases.iter()) {
+                table.insert(name, index, &vhost.domain);
+            }
+
+            for (listener, slot) in [
+                (ListenerKind::Http, &mut table.default_http),
+                (ListenerKind::Https, &mut table.default_https),
+            ] {
+                if !vhost.default_server.applies_to(listener) {
+                    continue;
+                }
+                match slot {
+                    Some(existing) => warn!(
+                        domain = %vhost.domain,
+                        "{:?} listener already has default server {}, ignoring",
+                        listener,
+                        config.virtualhost[*existing].domain
+                    ),
+                    None => *slot = Some(index),
+                }
+            }
+        }
+
+        table
+    }
+
+    fn insert(&mut self, name: &str, index: usize, domain: &str) {
+        if let Some(pattern) = name.strip_prefix('~') {
+            match RegexBuilder::new(pattern).case_insensitive(true).build() {
+                Ok(regex) => self.regex.push((regex, index)),
+                Err(err) => warn!(
+                    domain = %domain,
+                    "invalid server name regex {}, ignoring: {}",
+                    name,
+                    err
+                ),
+            }
+            return;
+        }
+
+        let name = normalize_host(name);
+        let (map, key) = if name == "*" || name == "_" {
+            self.catch_all.get_or_insert(index);
+            return;
+        } else if let Some(suffix) = name.strip_prefix("*.") {
+            (&mut self.leading, format!(".{}", suffix))
+        } else if let Some(suffix) = name.strip_prefix('.') {
+            // ".example.com" is "example.com" plus "*.example.com"
+            self.exact.entry(suffix.to_string()).or_insert(index);
+            (&mut self.leading, name.clone())
+        } else if let Some(prefix) = name.strip_suffix(".*") {
+            (&mut self.trailing, format!("{}.", prefix))
+        } else {
+            (&mut self.exact, name.clone())
+        };
+
+        if let Some(existing) = map.get(&key) {
+            if *existing != index {
+                warn!(domain = %domain, "server name {} is already used, ignoring", name);
+            }
+            return;
+        }
+        map.insert(key, index);
+    }
+
+    /// Virtual host for a Host header value (port already stripped)
+    pub fn lookup(&self, host: &str, listener: ListenerKind) -> Option<usize> {
+        let host = normalize_host(host);
+
+        if let Some(index) = self.exact.get(&host) {
+            return Some(*index);
+        }
+
+        // Longest suffix first: walk the dots from the left
+        if !self.leading.is_empty() {
+            for (pos, _) in host.match_indices('.') {
+                if let Some(index) = self.leading.get(&host[pos..]) {
+                    return Some(*index);
+                }
+            }
+        }
+
+        // Longest prefix first: walk the dots from the right
+        if !self.trailing.is_empty() {
+            for (pos, _) in host.rmatch_indices('.') {
+                if let Some(index) = self.trailing.get(&host[..=pos]) {
+                    return Some(*index);
+                }
+            }
+        }
+
+        if let Some((_, index)) = self.regex.iter().find(|(regex, _)| regex.is_match(&host)) {
+            return Some(*index);
+        }
+
+        let default = match listener {
+            ListenerKind::Http => self.default_http,
+            ListenerKind::Https => self.default_https,
+        };
+        default.or(self.catch_all)
+    }
+}
+
+/// Lowercase, without a trailing dot
+pub fn normalize_host(host: &str) -> String {
+    host.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn test_match_precedence() {
+        let config = config(
+            r#"
+[[virtualhost]]
+domain = "example.com"
+aliases = ["www.example.com"]
+root = "/srv/a"
+
+[[virtualhost]]
+domain = "*.example.com"
+root = "/srv/b"
+
+[[virtualhost]]
+domain = "*.shop.example.com"
+aliases = ["mail.*"]
+root = "/srv/c"
+
+[[virtualhost]]
+domain = "~^api\\d+\\.example\\.org$"
+root = "/srv/d"
+
+[[virtualhost]]
+domain = ".example.net"
+root = "/srv/e"
+"#,
+        );
+        let table = VhostTable::new(&config);
+        let lookup = |host: &str| table.lookup(host, ListenerKind::Http);
+
+        assert_eq!(lookup("example.com"), Some(0));
+        assert_eq!(lookup("WWW.Example.COM."), Some(0));
+        assert_eq!(lookup("blog.example.com"), Some(1));
+        assert_eq!(lookup("a.b.example.com"), Some(1));
+        assert_eq!(lookup("eu.shop.example.com"), Some(2));
+        assert_eq!(lookup("mail.example.org"), Some(2));
+        assert_eq!(lookup("API7.example.org"), Some(3));
+        assert_eq!(lookup("example.net"), Some(4));
+        assert_eq!(lookup("x.example.net"), Some(4));
+        assert_eq!(lookup("unknown.test"), None);
+    }
+
+    #[test]
+    fn test_default_server_per_listener() {
+        let config = config(
+            r#"
+[[virtualhost]]
+domain = "*"
+root = "/srv/any"
+
+[[virtualhost]]
+domain = "a.test"
+root = "/srv/a"
+default_server = ["https"]
+
+[[virtualhost]]
+domain = "b.test"
+root = "/srv/b"
+default_server = true
+"#,
+        );
+        let table = VhostTable::new(&config);
+
+        assert_eq!(table.lookup("other.test", ListenerKind::Https), Some(1));
+        assert_eq!(table.lookup("other.test", ListenerKind::Http), Some(2));
+        assert_eq!(table.lookup("b.test", ListenerKind::Https), Some(2));
+    }
+
+    #[test]
+    fn test_many_vhosts() {
+        let mut toml = String::new();
+        for i in 0..2500 {
+            toml.push_str(&format!(
+                "[[virtualhost]]\ndomain = \"site{}.example.com\"\nroot = \"/srv/{}\"\n\n",
+                i, i
+            ));
+        }
+        let table = VhostTable::new(&config(&toml));
+
+        assert_eq!(
+            table.lookup("site2499.example.com", ListenerKind::Http),
+            Some(2499)
+        );
+        assert_eq!(
+            table.lookup("site2500.example.com", ListenerKind::Http),
+            None
+        );
+    }
+}
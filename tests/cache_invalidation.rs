@@ -1,4 +1,5 @@
 use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
 use std::process::{Child, Command, Stdio};
 use std::time::Duration;
 
@@ -13,9 +14,51 @@ use serde_json::{json, Value};
 use tempfile::TempDir;
 use tokio::time::sleep;
 
+/// Stand-in PHP binary: answers `-v` and runs the script (last argument) with sh
+const FAKE_PHP: &str = r#"#!/bin/sh
+if [ "$1" = "-v" ]; then
+    echo "PHP 8.3.0 (cli)"
+    exit 0
+fi
+for arg; do script="$arg"; done
+exec sh "$script"
+"#;
+
+const MOVED_PAGE: &str = r#"printf 'Status: 301 Moved Permanently\r\n'
+printf 'Location: /origin/new-home\r\n'
+printf 'Content-Type: text/html\r\n'
+printf '\r\n'
+printf 'moved'
+"#;
+
+const GONE_PAGE: &str = r#"printf 'Status: 404 Not Found\r\n'
+printf 'Content-Type: text/html\r\n'
+printf '\r\n'
+printf 'no such product'
+"#;
+
+const TAGGED_PAGE: &str = r#"printf 'Content-Type: text/html\r\n'
+printf 'Surrogate-Key: foo bar\r\n'
+printf '\r\n'
+printf 'tagged'
+"#;
+
+const LINKED_PAGE: &str = r#"printf 'Content-Type: text/html\r\n'
+printf 'Link: </app.css>; rel=preload; as=style\r\n'
+printf 'Access-Control-Allow-Origin: *\r\n'
+printf '\r\n'
+printf 'linked'
+"#;
+
+const VARY_ANY_PAGE: &str = r#"printf 'Content-Type: text/html\r\n'
+printf 'Vary: *\r\n'
+printf '\r\n'
+printf 'varies'
+"#;
+
 struct TestServer {
     addr: SocketAddr,
-    _docroot: TempDir,
+    docroot: TempDir,
     _config_dir: TempDir,
     child: Child,
 }
@@ -28,14 +71,74 @@ impl TestServer {
             .context("write a.html")?;
         std::fs::write(docroot.path().join("catalog").join("b.html"), "<h1>B</h1>")
             .context("write b.html")?;
+        std::fs::write(docroot.path().join("catalog").join("style.css"), "h1{}")
+            .context("write style.css")?;
+        std::fs::write(
+            docroot.path().join("catalog").join("long.html"),
+            "<p>compressible catalog text</p>\n".repeat(100),
+        )
+        .context("write long.html")?;
+        std::fs::create_dir_all(docroot.path().join("esi")).context("create esi dir")?;
+        for (name, body) in [
+            (
+                "page.html",
+                "<p>Shell</p><esi:include src=\"/esi/greeting.html\"/>\
+                 <esi:remove><p>No ESI</p></esi:remove>\
+                 <!--esi <esi:include src=\"/esi/cart.html\" scope=\"private\"/> -->\
+                 <esi:include src=\"/esi/missing.html\" alt=\"/esi/greeting.html\"/>\
+                 <esi:include src=\"/esi/missing.html\" onerror=\"continue\"/>",
+            ),
+            (
+                "broken.html",
+                "<p>Shell</p><esi:include src=\"/esi/missing.html\"/>",
+            ),
+            ("greeting.html", "Hello"),
+            ("cart.html", "cart-1"),
+            (
+                "member.html",
+                "<p>Member</p><esi:include src=\"/esi/news.html\"/>",
+            ),
+            ("news.html", "news-1"),
+            (
+                "guarded.html",
+                "<p>Guarded</p>\
+                 <esi:include src=\"/esi/private/token.html\" onerror=\"continue\"/>\
+                 <esi:include src=\"/esi/%70rivate/token.html\" onerror=\"continue\"/>",
+            ),
+        ] {
+            std::fs::write(docroot.path().join("esi").join(name), body)
+                .with_context(|| format!("write {}", name))?;
+        }
+
+        std::fs::create_dir_all(docroot.path().join("esi/private"))
+            .context("create esi private dir")?;
+        std::fs::write(docroot.path().join("esi/private/token.html"), "token")
+            .context("write token.html")?;
+
+        std::fs::create_dir_all(docroot.path().join("origin")).context("create origin dir")?;
+        std::fs::write(docroot.path().join("origin/moved.php"), MOVED_PAGE)
+            .context("write moved.php")?;
+        std::fs::write(docroot.path().join("origin/gone.php"), GONE_PAGE)
+            .context("write gone.php")?;
+        std::fs::write(docroot.path().join("origin/tagged.php"), TAGGED_PAGE)
+            .context("write tagged.php")?;
+        std::fs::write(docroot.path().join("origin/linked.php"), LINKED_PAGE)
+            .context("write linked.php")?;
+        std::fs::write(docroot.path().join("origin/vary-any.php"), VARY_ANY_PAGE)
+            .context("write vary-any.php")?;
 
         let addr = reserve_local_addr().context("reserve local port")?;
         let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let php_path = config_dir.path().join("php");
+        std::fs::write(&php_path, FAKE_PHP).context("write fake php")?;
+        std::fs::set_permissions(&php_path, std::fs::Permissions::from_mode(0o755))
+            .context("make fake php executable")?;
         let config_path = config_dir.path().join("veloserve.toml");
         let config_toml = format!(
-            "[server]\nlisten = \"{}\"\n\n[php]\nenable = false\n\n[cache]\nenable = true\nl1_enabled = true\nl2_enabled = false\ndefault_ttl = 3600\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.html\"]\n",
-            addr,
-            docroot.path().to_string_lossy()
+            "[server]\nlisten = \"{addr}\"\n\n[php]\nenable = true\nmode = \"cgi\"\nbinary_path = \"{php}\"\n\n[cache]\nenable = true\nl1_enabled = true\nl2_enabled = false\ndefault_ttl = 3600\ndebug_headers = true\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{root}\"\nindex = [\"index.html\"]\n\n[[virtualhost]]\ndomain = \"stale.test\"\nroot = \"{root}\"\n\n[virtualhost.cache]\nttl = 1\nstale_while_revalidate = 60\n\n[[virtualhost]]\ndomain = \"vary.test\"\nroot = \"{root}\"\n\n[virtualhost.cache]\nvary = [\"Accept-Language\", \"cookie:currency\"]\n\n[[virtualhost]]\ndomain = \"origin.test\"\nroot = \"{root}\"\n\n[virtualhost.cache]\ncache_redirects = true\nnegative_ttl = 1\nstale_while_revalidate = 0\n\n[[virtualhost]]\ndomain = \"esi.test\"\nroot = \"{root}\"\n\n[virtualhost.cache]\nesi = true\n\n[[virtualhost.location]]\npath = \"/esi/private/\"\naccess = [\"deny all\"]\n",
+            addr = addr,
+            root = docroot.path().to_string_lossy(),
+            php = php_path.to_string_lossy()
         );
         std::fs::write(&config_path, config_toml).context("write config file")?;
 
@@ -52,7 +155,7 @@ impl TestServer {
 
         Ok(Self {
             addr,
-            _docroot: docroot,
+            docroot,
             _config_dir: config_dir,
             child,
         })
@@ -163,6 +266,36 @@ async fn magento_style_invalidation_contract_works() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn origin_surrogate_keys_tag_cached_pages() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    warm_path(&client, server.addr, "/origin/tagged.php").await?;
+
+    let invalidate_tag = json!({
+        "scope": "tag",
+        "tags": ["foo"]
+    });
+    let response = post_json(
+        &client,
+        server.addr,
+        "/api/v1/cache/invalidate",
+        &invalidate_tag,
+        &[],
+    )
+    .await?;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["affected_keys"], 1);
+
+    let page = get_path(&client, server.addr, "/origin/tagged.php").await?;
+    assert_eq!(page.status, StatusCode::OK);
+    assert_eq!(page.cache_header.as_deref(), Some("MISS"));
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn cache_warm_endpoint_processes_queue_and_populates_cache() -> Result<()> {
     let server = TestServer::start().await?;
@@ -212,6 +345,595 @@ async fn cache_warm_endpoint_processes_queue_and_populates_cache() -> Result<()>
     Ok(())
 }
 
+#[tokio::test]
+async fn revalidation_returns_not_modified() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    let request = |path: &str, headers: &[(&str, &str)]| {
+        let mut builder = Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{}{}", server.addr, path))
+            .header("Host", "example.test");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder
+            .body(Full::new(Bytes::new()))
+            .context("build conditional request")
+    };
+
+    // Page cache: the MISS already carries the validators later hits use
+    let miss = client.request(request("/catalog/a.html", &[])?).await?;
+    assert_eq!(miss.headers()["x-cache"], "MISS");
+    let etag = miss.headers()["etag"].to_str()?.to_string();
+    assert!(miss.headers().contains_key("last-modified"));
+
+    let hit = client
+        .request(request("/catalog/a.html", &[("If-None-Match", &etag)])?)
+        .await?;
+    assert_eq!(hit.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(hit.headers()["x-cache"], "HIT");
+    assert_eq!(hit.headers()["etag"], etag.as_str());
+    let body = hit.into_body().collect().await?.to_bytes();
+    assert!(body.is_empty());
+
+    let changed = client
+        .request(request(
+            "/catalog/a.html",
+            &[("If-None-Match", "\"other\"")],
+        )?)
+        .await?;
+    assert_eq!(changed.status(), StatusCode::OK);
+
+    let failed = client
+        .request(request("/catalog/a.html", &[("If-Match", "\"other\"")])?)
+        .await?;
+    assert_eq!(failed.status(), StatusCode::PRECONDITION_FAILED);
+
+    // Static files go through the same checks
+    let css = client.request(request("/catalog/style.css", &[])?).await?;
+    assert_eq!(css.status(), StatusCode::OK);
+    let etag = css.headers()["etag"].to_str()?.to_string();
+    let last_modified = css.headers()["last-modified"].to_str()?.to_string();
+
+    let css = client
+        .request(request("/catalog/style.css", &[("If-None-Match", &etag)])?)
+        .await?;
+    assert_eq!(css.status(), StatusCode::NOT_MODIFIED);
+
+    let css = client
+        .request(request(
+            "/catalog/style.css",
+            &[("If-Modified-Since", &last_modified)],
+        )?)
+        .await?;
+    assert_eq!(css.status(), StatusCode::NOT_MODIFIED);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn cache_hits_replay_stored_headers() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    let request = || {
+        Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{}/catalog/b.html", server.addr))
+            .header("Host", "example.test")
+            .body(Full::new(Bytes::new()))
+            .context("build page request")
+    };
+
+    let miss = client.request(request()?).await?;
+    assert_eq!(miss.headers()["x-cache"], "MISS");
+    let cache_control = miss.headers()["cache-control"].clone();
+
+    let hit = client.request(request()?).await?;
+    assert_eq!(hit.status(), StatusCode::OK);
+    assert_eq!(hit.headers()["x-cache"], "HIT");
+    assert_eq!(hit.headers()["cache-control"], cache_control);
+    assert_eq!(hit.headers()["x-content-type-options"], "nosniff");
+    // Regenerated per hit rather than replayed
+    assert_eq!(hit.headers().get_all("server").iter().count(), 1);
+    assert!(!hit.headers().contains_key("accept-ranges"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn php_response_headers_are_cached() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    let request = || {
+        Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{}/origin/linked.php", server.addr))
+            .header("Host", "example.test")
+            .body(Full::new(Bytes::new()))
+            .context("build page request")
+    };
+
+    let miss = client.request(request()?).await?;
+    assert_eq!(miss.headers()["x-cache"], "MISS");
+
+    let hit = client.request(request()?).await?;
+    assert_eq!(hit.headers()["x-cache"], "HIT");
+    assert_eq!(hit.headers()["link"], "</app.css>; rel=preload; as=style");
+    assert_eq!(hit.headers()["access-control-allow-origin"], "*");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn configured_vary_rules_key_the_cache() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    let x_cache = |host: &'static str, headers: &'static [(&'static str, &'static str)]| {
+        let client = client.clone();
+        let addr = server.addr;
+        async move {
+            let mut builder = Request::builder()
+                .method(Method::GET)
+                .uri(format!("http://{}/catalog/a.html", addr))
+                .header("Host", host);
+            for (name, value) in headers {
+                builder = builder.header(*name, *value);
+            }
+            let response = client
+                .request(builder.body(Full::new(Bytes::new()))?)
+                .await?;
+            let vary = response
+                .headers()
+                .get_all("vary")
+                .iter()
+                .map(|value| value.to_str().unwrap_or("").to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            Ok::<_, anyhow::Error>((response.headers()["x-cache"].to_str()?.to_string(), vary))
+        }
+    };
+
+    // Only the primary language tag is part of the key
+    let (cache, vary) = x_cache("vary.test", &[("Accept-Language", "de-DE,de;q=0.9")]).await?;
+    assert_eq!(cache, "MISS");
+    assert!(vary.contains("accept-language"));
+    assert!(vary.contains("cookie"));
+    let (cache, vary) = x_cache("vary.test", &[("Accept-Language", "de-AT")]).await?;
+    assert_eq!(cache, "HIT");
+    assert!(vary.contains("accept-language"));
+    let (cache, _) = x_cache("vary.test", &[("Accept-Language", "en-US")]).await?;
+    assert_eq!(cache, "MISS");
+
+    // Cookies only count when named in the rules
+    let (cache, _) = x_cache(
+        "vary.test",
+        &[
+            ("Accept-Language", "de"),
+            ("Cookie", "currency=EUR; theme=dark"),
+        ],
+    )
+    .await?;
+    assert_eq!(cache, "MISS");
+    let (cache, _) = x_cache(
+        "vary.test",
+        &[
+            ("Accept-Language", "de"),
+            ("Cookie", "theme=light; currency=EUR"),
+        ],
+    )
+    .await?;
+    assert_eq!(cache, "HIT");
+
+    // Without rules, Accept-Language does not split the cache
+    let (cache, _) = x_cache("example.test", &[("Accept-Language", "fr")]).await?;
+    assert_eq!(cache, "MISS");
+    let (cache, _) = x_cache("example.test", &[("Accept-Language", "en")]).await?;
+    assert_eq!(cache, "HIT");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn origin_vary_star_is_never_cached() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    // Not stored, so neither request carries an X-Cache header
+    for _ in 0..2 {
+        let page = get_path(&client, server.addr, "/origin/vary-any.php").await?;
+        assert_eq!(page.status, StatusCode::OK);
+        assert_eq!(page.cache_header, None);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_strings_are_normalized_in_cache_keys() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    let cache = |path: &'static str| {
+        let client = client.clone();
+        let addr = server.addr;
+        async move { Ok::<_, anyhow::Error>(get_path(&client, addr, path).await?.cache_header) }
+    };
+
+    // Tracking parameters share the plain page's entry
+    assert_eq!(cache("/catalog/b.html").await?.as_deref(), Some("MISS"));
+    assert_eq!(
+        cache("/catalog/b.html?utm_source=news&fbclid=abc")
+            .await?
+            .as_deref(),
+        Some("HIT")
+    );
+
+    // Other parameters are keyed, in any order
+    assert_eq!(
+        cache("/catalog/b.html?page=2&sort=asc").await?.as_deref(),
+        Some("MISS")
+    );
+    assert_eq!(
+        cache("/catalog/b.html?sort=asc&gclid=x&page=2")
+            .await?
+            .as_deref(),
+        Some("HIT")
+    );
+
+    // Purging the URL covers its query variants; a query purges one variant
+    let invalidate = json!({
+        "scope": "url",
+        "domain": "example.test",
+        "paths": ["/catalog/b.html?utm_medium=mail&sort=asc&page=2"]
+    });
+    let response = post_json(
+        &client,
+        server.addr,
+        "/api/v1/cache/invalidate",
+        &invalidate,
+        &[],
+    )
+    .await?;
+    assert_eq!(response.body["affected_keys"], 1);
+    assert_eq!(cache("/catalog/b.html").await?.as_deref(), Some("HIT"));
+    assert_eq!(
+        cache("/catalog/b.html?page=2&sort=asc").await?.as_deref(),
+        Some("MISS")
+    );
+
+    let invalidate = json!({
+        "scope": "url",
+        "domain": "example.test",
+        "paths": ["/catalog/b.html"]
+    });
+    let response = post_json(
+        &client,
+        server.addr,
+        "/api/v1/cache/invalidate",
+        &invalidate,
+        &[],
+    )
+    .await?;
+    assert_eq!(response.body["affected_keys"], 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn bypass_rules_match_cookie_names() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    let request = |cookie: &'static str| {
+        let client = client.clone();
+        let addr = server.addr;
+        async move {
+            let request = Request::builder()
+                .method(Method::GET)
+                .uri(format!("http://{}/catalog/b.html", addr))
+                .header("Host", "example.test")
+                .header("Cookie", cookie)
+                .body(Full::new(Bytes::new()))?;
+            let response = client.request(request).await?;
+            let header = |name: &str| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string)
+            };
+            Ok::<_, anyhow::Error>((header("x-cache"), header("x-cache-bypass")))
+        }
+    };
+
+    // Substrings of other cookie names no longer disable caching
+    let (cache, bypass) = request("_auth_pref=1; session_theme=dark").await?;
+    assert_eq!((cache.as_deref(), bypass), (Some("MISS"), None));
+    let (cache, _) = request("_auth_pref=1").await?;
+    assert_eq!(cache.as_deref(), Some("HIT"));
+
+    let (cache, bypass) = request("theme=dark; PHPSESSID=abc").await?;
+    assert_eq!(cache, None);
+    assert_eq!(bypass.as_deref(), Some("cookie=PHPSESSID"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn esi_fragments_are_assembled_per_response() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    let get = |path: &'static str, cookie: Option<&'static str>| {
+        let client = client.clone();
+        let addr = server.addr;
+        async move {
+            let mut builder = Request::builder()
+                .method(Method::GET)
+                .uri(format!("http://{}{}", addr, path))
+                .header("Host", "esi.test");
+            if let Some(cookie) = cookie {
+                builder = builder.header("Cookie", cookie);
+            }
+            let response = client
+                .request(builder.body(Full::new(Bytes::new()))?)
+                .await?;
+            let status = response.status();
+            let headers = response.headers().clone();
+            let body = response.into_body().collect().await?.to_bytes();
+            Ok::<_, anyhow::Error>((status, headers, String::from_utf8(body.to_vec())?))
+        }
+    };
+
+    let (status, headers, body) = get("/esi/page.html", None).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["x-cache"], "MISS");
+    assert_eq!(body, "<p>Shell</p>Hello cart-1 Hello");
+    assert!(headers.get("etag").is_none());
+    assert_eq!(headers["cache-control"], "private");
+
+    // Public fragments stay cached, private ones are keyed per visitor
+    std::fs::write(server.docroot.path().join("esi/greeting.html"), "Bye")?;
+    std::fs::write(server.docroot.path().join("esi/cart.html"), "cart-2")?;
+    let (_, headers, body) = get("/esi/page.html", Some("sid=a")).await?;
+    assert_eq!(headers["x-cache"], "HIT");
+    assert_eq!(body, "<p>Shell</p>Hello cart-2 Hello");
+    let (_, _, body) = get("/esi/page.html", None).await?;
+    assert_eq!(body, "<p>Shell</p>Hello cart-1 Hello");
+
+    let (status, _, _) = get("/esi/broken.html", None).await?;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn esi_fragments_respect_bypass_and_access_rules() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    let get = |path: &'static str, cookie: Option<&'static str>| {
+        let client = client.clone();
+        let addr = server.addr;
+        async move {
+            let mut builder = Request::builder()
+                .method(Method::GET)
+                .uri(format!("http://{}{}", addr, path))
+                .header("Host", "esi.test");
+            if let Some(cookie) = cookie {
+                builder = builder.header("Cookie", cookie);
+            }
+            let response = client
+                .request(builder.body(Full::new(Bytes::new()))?)
+                .await?;
+            let status = response.status();
+            let body = response.into_body().collect().await?.to_bytes();
+            Ok::<_, anyhow::Error>((status, String::from_utf8(body.to_vec())?))
+        }
+    };
+
+    // A logged-in render neither reads nor fills the shared fragment cache
+    let (_, body) = get("/esi/member.html", Some("PHPSESSID=abc")).await?;
+    assert_eq!(body, "<p>Member</p>news-1");
+    std::fs::write(server.docroot.path().join("esi/news.html"), "news-2")?;
+    let (_, body) = get("/esi/member.html", None).await?;
+    assert_eq!(body, "<p>Member</p>news-2");
+    std::fs::write(server.docroot.path().join("esi/news.html"), "news-3")?;
+    let (_, body) = get("/esi/member.html", None).await?;
+    assert_eq!(body, "<p>Member</p>news-2");
+    let (_, body) = get("/esi/member.html", Some("PHPSESSID=abc")).await?;
+    assert_eq!(body, "<p>Member</p>news-3");
+
+    // Fragments go through the same location rules as pages
+    let (status, _) = get("/esi/private/token.html", None).await?;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, body) = get("/esi/guarded.html", None).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "<p>Guarded</p>");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn redirects_and_not_found_pages_are_cached_when_configured() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    let get = |host: &'static str, path: &'static str| {
+        let client = client.clone();
+        let addr = server.addr;
+        async move {
+            let request = Request::builder()
+                .method(Method::GET)
+                .uri(format!("http://{}{}", addr, path))
+                .header("Host", host)
+                .body(Full::new(Bytes::new()))?;
+            let response = client.request(request).await?;
+            let header = |name: &str| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string)
+            };
+            let result = (response.status(), header("x-cache"), header("location"));
+            let _ = response.into_body().collect().await?;
+            Ok::<_, anyhow::Error>(result)
+        }
+    };
+
+    // Redirects are not cached by default (no X-Cache: never stored)
+    for _ in 0..2 {
+        let (status, cache, _) = get("example.test", "/origin/moved.php").await?;
+        assert_eq!(status, StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(cache, None);
+    }
+
+    // With cache_redirects they are, and a hit keeps its Location
+    let (status, cache, location) = get("origin.test", "/origin/moved.php").await?;
+    assert_eq!(status, StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(cache.as_deref(), Some("MISS"));
+    assert_eq!(location.as_deref(), Some("/origin/new-home"));
+    let (status, cache, location) = get("origin.test", "/origin/moved.php").await?;
+    assert_eq!(status, StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(cache.as_deref(), Some("HIT"));
+    assert_eq!(location.as_deref(), Some("/origin/new-home"));
+
+    // Not-found pages are cached for negative_ttl, then rendered again
+    let (status, cache, _) = get("origin.test", "/origin/gone.php").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(cache.as_deref(), Some("MISS"));
+    let (status, cache, _) = get("origin.test", "/origin/gone.php").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(cache.as_deref(), Some("HIT"));
+    sleep(Duration::from_millis(2100)).await;
+    let (status, cache, _) = get("origin.test", "/origin/gone.php").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(cache.as_deref(), Some("MISS"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn cache_hits_serve_compressed_variants() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    let request = |accept_encoding: &str| {
+        Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{}/catalog/long.html", server.addr))
+            .header("Host", "example.test")
+            .header("Accept-Encoding", accept_encoding)
+            .body(Full::new(Bytes::new()))
+            .context("build encoded request")
+    };
+    let expected = "<p>compressible catalog text</p>\n".repeat(100);
+
+    let miss = client.request(request("identity")?).await?;
+    assert_eq!(miss.headers()["x-cache"], "MISS");
+    let identity_size = get_json(&client, server.addr, "/api/v1/cache/stats")
+        .await?
+        .body["cache"]["size_bytes"]
+        .as_u64()
+        .unwrap_or(0);
+    // Body plus the few stored response headers
+    assert!(identity_size >= expected.len() as u64);
+    assert!(identity_size < expected.len() as u64 + 256);
+
+    // The first brotli hit stores the variant, the second reuses it
+    for _ in 0..2 {
+        let hit = client.request(request("gzip;q=0.5, br")?).await?;
+        assert_eq!(hit.headers()["x-cache"], "HIT");
+        assert_eq!(hit.headers()["content-encoding"], "br");
+        assert_eq!(hit.headers()["vary"], "Accept-Encoding");
+        assert!(hit.headers()["etag"].to_str()?.ends_with("-br\""));
+        let body = hit.into_body().collect().await?.to_bytes();
+        let mut decoded = String::new();
+        std::io::Read::read_to_string(
+            &mut brotli::Decompressor::new(&body[..], 4096),
+            &mut decoded,
+        )?;
+        assert_eq!(decoded, expected);
+    }
+
+    let hit = client.request(request("gzip")?).await?;
+    assert_eq!(hit.headers()["content-encoding"], "gzip");
+    let gzip_len = hit.into_body().collect().await?.to_bytes().len();
+
+    let stats = get_json(&client, server.addr, "/api/v1/cache/stats").await?;
+    let size = stats.body["cache"]["size_bytes"].as_u64().unwrap_or(0);
+    assert!(size > identity_size + gzip_len as u64);
+
+    let plain = client.request(request("identity")?).await?;
+    assert!(!plain.headers().contains_key("content-encoding"));
+    let body = plain.into_body().collect().await?.to_bytes();
+    assert_eq!(&body[..], expected.as_bytes());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn stale_pages_are_served_while_revalidating() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    let page = server.docroot.path().join("catalog").join("news.html");
+    std::fs::write(&page, "old news").context("write news.html")?;
+    let get = || async {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{}/catalog/news.html", server.addr))
+            .header("Host", "stale.test")
+            .body(Full::new(Bytes::new()))
+            .context("build stale request")?;
+        let response = client.request(request).await?;
+        let cache = response.headers()["x-cache"].to_str()?.to_string();
+        let body = response.into_body().collect().await?.to_bytes();
+        Ok::<_, anyhow::Error>((cache, String::from_utf8_lossy(&body).to_string()))
+    };
+
+    assert_eq!(get().await?, ("MISS".to_string(), "old news".to_string()));
+    std::fs::write(&page, "new news").context("rewrite news.html")?;
+    sleep(Duration::from_millis(2100)).await;
+
+    // Past the 1s TTL: the old copy is served at once and refreshed behind it
+    assert_eq!(get().await?, ("STALE".to_string(), "old news".to_string()));
+
+    let mut refreshed = None;
+    for _ in 0..50 {
+        let (cache, body) = get().await?;
+        if cache == "HIT" {
+            refreshed = Some(body);
+            break;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(refreshed.as_deref(), Some("new news"));
+
+    let stats = get_json(&client, server.addr, "/api/v1/cache/stats").await?;
+    assert_eq!(stats.body["cache"]["stale"]["revalidations"], 1);
+
+    Ok(())
+}
+
 struct HttpResult {
     status: StatusCode,
     body: Value,
@@ -0,0 +1,247 @@
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+/// Stand-in PHP binary: answers `-v` and runs the script (last argument) with sh
+const FAKE_PHP: &str = r#"#!/bin/sh
+if [ "$1" = "-v" ]; then
+    echo "PHP 8.3.0 (cli)"
+    exit 0
+fi
+for arg; do script="$arg"; done
+exec sh "$script"
+"#;
+
+/// Counts renders, so hits and misses can be told apart by the body
+const ACCOUNT_PAGE: &str = r#"n=$(cat renders 2>/dev/null || echo 0)
+n=$((n + 1))
+echo "$n" > renders
+printf 'Content-Type: text/html\r\n'
+if [ -n "$HTTP_COOKIE" ]; then
+    printf 'X-LiteSpeed-Cache-Control: private, max-age=60\r\n'
+fi
+printf '\r\n'
+printf 'account %s render %s' "$HTTP_COOKIE" "$n"
+"#;
+
+const LOGOUT_PAGE: &str = r#"printf 'Content-Type: text/html\r\n'
+printf 'X-VeloServe-Purge-Private: 1\r\n'
+printf '\r\n'
+printf 'bye'
+"#;
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("account.php"), ACCOUNT_PAGE)
+            .context("write account.php")?;
+        std::fs::write(docroot.path().join("logout.php"), LOGOUT_PAGE)
+            .context("write logout.php")?;
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let php_path = config_dir.path().join("php");
+        std::fs::write(&php_path, FAKE_PHP).context("write fake php")?;
+        std::fs::set_permissions(&php_path, std::fs::Permissions::from_mode(0o755))
+            .context("make fake php executable")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            r#"[server]
+listen = "{addr}"
+
+[php]
+enable = true
+mode = "cgi"
+binary_path = "{php}"
+
+[cache]
+enable = true
+l1_enabled = true
+l2_enabled = false
+default_ttl = 3600
+debug_headers = true
+
+[[virtualhost]]
+domain = "*"
+root = "{root}"
+index = ["index.php"]
+
+[virtualhost.cache]
+private = true
+bypass_cookies = ["session"]
+"#,
+            addr = addr,
+            php = php_path.to_string_lossy(),
+            root = docroot.path().to_string_lossy(),
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        wait_until_ready(addr).await?;
+
+        Ok(Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        })
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn private_pages_are_cached_per_session() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    let alice = get_page(&client, server.addr, "/account.php", Some("session=alice")).await?;
+    assert_eq!(alice.status, StatusCode::OK);
+    assert_eq!(alice.cache_header.as_deref(), Some("MISS"));
+    assert_eq!(alice.scope_header.as_deref(), Some("private"));
+    assert_eq!(alice.body, "account session=alice render 1");
+
+    let alice_again = get_page(&client, server.addr, "/account.php", Some("session=alice")).await?;
+    assert_eq!(alice_again.cache_header.as_deref(), Some("HIT"));
+    assert_eq!(alice_again.scope_header.as_deref(), Some("private"));
+    assert_eq!(alice_again.body, alice.body);
+
+    // Another session never sees Alice's copy
+    let bob = get_page(&client, server.addr, "/account.php", Some("session=bob")).await?;
+    assert_eq!(bob.cache_header.as_deref(), Some("MISS"));
+    assert_eq!(bob.body, "account session=bob render 2");
+    let bob_again = get_page(&client, server.addr, "/account.php", Some("session=bob")).await?;
+    assert_eq!(bob_again.cache_header.as_deref(), Some("HIT"));
+    assert_eq!(bob_again.body, bob.body);
+
+    // Anonymous visitors are not served private pages
+    let anonymous = get_page(&client, server.addr, "/account.php", None).await?;
+    assert_eq!(anonymous.body, "account  render 3");
+    assert_eq!(anonymous.scope_header, None);
+
+    // The origin drops a session's pages on logout
+    let logout = get_page(&client, server.addr, "/logout.php", Some("session=alice")).await?;
+    assert_eq!(logout.status, StatusCode::OK);
+    let alice_after = get_page(&client, server.addr, "/account.php", Some("session=alice")).await?;
+    assert_eq!(alice_after.cache_header.as_deref(), Some("MISS"));
+    assert_eq!(alice_after.body, "account session=alice render 4");
+    let bob_after = get_page(&client, server.addr, "/account.php", Some("session=bob")).await?;
+    assert_eq!(bob_after.cache_header.as_deref(), Some("HIT"));
+
+    Ok(())
+}
+
+struct PageResult {
+    status: StatusCode,
+    cache_header: Option<String>,
+    scope_header: Option<String>,
+    body: String,
+}
+
+async fn get_page(
+    client: &Client<HttpConnector, Full<Bytes>>,
+    addr: SocketAddr,
+    path: &str,
+    cookie: Option<&str>,
+) -> Result<PageResult> {
+    let mut request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .header("Host", "example.test");
+    if let Some(cookie) = cookie {
+        request = request.header("Cookie", cookie);
+    }
+    let request = request
+        .body(Full::new(Bytes::new()))
+        .context("build page request")?;
+    let response = client
+        .request(request)
+        .await
+        .context("execute page request")?;
+    let status = response.status();
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+    let cache_header = header("X-Cache");
+    let scope_header = header("X-Cache-Scope");
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read page body")?
+        .to_bytes();
+    Ok(PageResult {
+        status,
+        cache_header,
+        scope_header,
+        body: String::from_utf8_lossy(&body).to_string(),
+    })
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+    let url = format!("http://{}/health", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(Full::new(Bytes::new()))
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,479 @@
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde_json::{json, Value};
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _assets: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("index.html"), "<h1>Home</h1>")
+            .context("write index.html")?;
+        std::fs::create_dir_all(docroot.path().join("uploads")).context("create uploads")?;
+        std::fs::write(docroot.path().join("uploads/shell.php"), "<?php echo 1;")
+            .context("write shell.php")?;
+        std::fs::create_dir_all(docroot.path().join("private")).context("create private dir")?;
+        std::fs::write(docroot.path().join("private/secret.txt"), "secret")
+            .context("write private secret")?;
+        std::fs::create_dir_all(docroot.path().join("app")).context("create app dir")?;
+        std::fs::write(docroot.path().join("app/index.html"), "<h1>SPA</h1>")
+            .context("write app index")?;
+
+        std::fs::create_dir_all(docroot.path().join("site")).context("create site dir")?;
+        std::fs::write(
+            docroot.path().join("site/.htaccess"),
+            r#"RewriteEngine On
+RewriteRule ^pretty/(\w+)$ page.html?name=$1 [L]
+Redirect 301 /site/moved /site/page.html
+Header set X-Htaccess "on"
+ErrorDocument 404 /site/missing.html
+<Files "secret.txt">
+    Require all denied
+</Files>
+php_flag engine off
+"#,
+        )
+        .context("write .htaccess")?;
+        std::fs::write(docroot.path().join("site/page.html"), "page").context("write page.html")?;
+        std::fs::write(docroot.path().join("site/missing.html"), "custom 404")
+            .context("write missing.html")?;
+        std::fs::write(docroot.path().join("site/secret.txt"), "secret")
+            .context("write secret.txt")?;
+
+        std::fs::create_dir_all(docroot.path().join("errors")).context("create errors dir")?;
+        std::fs::write(docroot.path().join("errors/404.html"), "custom not found")
+            .context("write 404.html")?;
+        std::fs::write(docroot.path().join("info.php"), "<?php phpinfo();")
+            .context("write info.php")?;
+
+        let assets = tempfile::tempdir().context("create temp assets dir")?;
+        std::fs::write(assets.path().join("site.css"), "body{}").context("write site.css")?;
+        std::fs::write(assets.path().join("503.html"), "back soon").context("write 503.html")?;
+        std::fs::write(
+            assets.path().join("app.js"),
+            "console.log('compress me');\n".repeat(100),
+        )
+        .context("write app.js")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            r#"[server]
+listen = "{addr}"
+error_pages = {{ 503 = "{assets}/503.html" }}
+
+[php]
+enable = false
+
+[[virtualhost]]
+domain = "*"
+root = "{root}"
+index = ["index.html"]
+htaccess = true
+error_pages = {{ 404 = "/errors/404.html", 403 = "/errors/403.html" }}
+rewrites = [
+    {{ pattern = "^/legacy/(.*)$", replacement = "/assets/$1", flags = "last" }},
+    {{ pattern = "^/blog/(\\d+)$", replacement = "/posts/$1", flags = "permanent" }},
+    {{ pattern = "^/beta$", replacement = "/app/", flags = "redirect", conditions = [
+        {{ type = "cookie", name = "beta", pattern = "^1$" }},
+    ] }},
+]
+
+[[virtualhost.location]]
+path = "/old-home"
+match = "="
+return_status = 301
+return_body = "/"
+
+[[virtualhost.location]]
+path = "/uploads/"
+php = false
+
+[[virtualhost.location]]
+path = "/assets/"
+alias = "{assets}"
+headers = {{ "X-Location" = "assets" }}
+
+[[virtualhost.location]]
+path = "/private/"
+access = ["deny 127.0.0.1", "allow all"]
+
+[[virtualhost.location]]
+path = "/app/"
+try_files = ["$uri", "/app/index.html"]
+"#,
+            addr = addr,
+            root = docroot.path().to_string_lossy(),
+            assets = assets.path().to_string_lossy(),
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        wait_until_ready(addr).await?;
+
+        Ok(Self {
+            addr,
+            _docroot: docroot,
+            _assets: assets,
+            _config_dir: config_dir,
+            child,
+        })
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn location_blocks_override_vhost_behaviour() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let get = |path: &str| {
+        Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{}{}", server.addr, path))
+            .header("Host", "example.test")
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build request")
+    };
+
+    let response = client.request(get("/old-home")?).await?;
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(response.headers()["location"], "/");
+
+    let response = client.request(get("/uploads/shell.php")?).await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = client.request(get("/assets/site.css")?).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-location"], "assets");
+    let body = response.into_body().collect().await?.to_bytes();
+    assert_eq!(&body[..], b"body{}");
+
+    let mut request = get("/assets/site.css")?;
+    request
+        .headers_mut()
+        .insert("Range", "bytes=-2".parse().context("range header")?);
+    let response = client.request(request).await?;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()["content-range"], "bytes 4-5/6");
+    let body = response.into_body().collect().await?.to_bytes();
+    assert_eq!(&body[..], b"{}");
+
+    let response = client.request(get("/private/secret.txt")?).await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = client.request(get("/app/settings/profile")?).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await?.to_bytes();
+    assert_eq!(&body[..], b"<h1>SPA</h1>");
+
+    // Vhost rewrites run before the location is picked.
+    let response = client.request(get("/legacy/site.css")?).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-location"], "assets");
+
+    let response = client.request(get("/blog/42?ref=feed")?).await?;
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(response.headers()["location"], "/posts/42?ref=feed");
+
+    let response = client.request(get("/beta")?).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Outside any location the vhost behaviour is unchanged.
+    let response = client.request(get("/missing")?).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn location_rules_match_the_normalized_path() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let get = |path: &str| {
+        Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{}{}", server.addr, path))
+            .header("Host", "example.test")
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build request")
+    };
+
+    for path in [
+        "/private/secret.txt",
+        "/%70rivate/secret.txt",
+        "//private/secret.txt",
+        "/private//secret.txt",
+        "/./private/secret.txt",
+        "/app/../private/secret.txt",
+        "/%2e%2e/private/secret.txt",
+    ] {
+        let response = client.request(get(path)?).await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", path);
+    }
+
+    // PHP is refused before the (disabled) pool would answer 503
+    for path in [
+        "/uploads/shell.php",
+        "/uploads/shell%2ephp",
+        "/uploads/./shell.php",
+        "//uploads/shell.php",
+        "/%75ploads/shell.php/extra",
+    ] {
+        let response = client.request(get(path)?).await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", path);
+    }
+
+    // Encoded paths outside protected locations are still served
+    let response = client.request(get("/%69ndex.html")?).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await?.to_bytes();
+    assert_eq!(&body[..], b"<h1>Home</h1>");
+
+    let response = client.request(get("//assets/site.css")?).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-location"], "assets");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn rewrite_test_api_reports_fired_rule() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    let payload = json!({
+        "url": "http://example.test/beta",
+        "headers": { "Cookie": "beta=1" }
+    });
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("http://{}/api/v1/rewrite/test", server.addr))
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(payload.to_string())))
+        .context("build rewrite test request")?;
+
+    let response = client.request(request).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await?.to_bytes();
+    let report: Value = serde_json::from_slice(&body)?;
+
+    assert_eq!(report["vhost"], "*");
+    assert_eq!(report["fired"][0]["rule"], 2);
+    assert_eq!(report["result"]["action"], "redirect");
+    assert_eq!(report["result"]["status"], 302);
+    assert_eq!(report["result"]["location"], "/app/");
+
+    Ok(())
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/health", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
+
+#[tokio::test]
+async fn htaccess_files_are_honoured() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let get = |path: &str| {
+        Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{}{}", server.addr, path))
+            .header("Host", "example.test")
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build request")
+    };
+
+    let response = client.request(get("/site/pretty/home")?).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-htaccess"], "on");
+    let body = response.into_body().collect().await?.to_bytes();
+    assert_eq!(&body[..], b"page");
+
+    let response = client.request(get("/site/moved")?).await?;
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(response.headers()["location"], "/site/page.html");
+
+    let response = client.request(get("/site/secret.txt")?).await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = client.request(get("/site/.htaccess")?).await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = client.request(get("/site/nothing-here")?).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers()["x-htaccess"], "on");
+    let body = response.into_body().collect().await?.to_bytes();
+    assert_eq!(&body[..], b"custom 404");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn error_pages_keep_the_original_status() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let get = |path: &str, accept: &str| {
+        Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{}{}", server.addr, path))
+            .header("Host", "example.test")
+            .header("Accept", accept)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build request")
+    };
+
+    let response = client.request(get("/missing", "text/html")?).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body = response.into_body().collect().await?.to_bytes();
+    assert_eq!(&body[..], b"custom not found");
+
+    // The configured 403 page does not exist: fall back to the built-in page.
+    let response = client
+        .request(get("/private/secret.txt", "text/html")?)
+        .await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let body = response.into_body().collect().await?.to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("403 Forbidden"));
+
+    // PHP is disabled: 503 from the server-wide set.
+    let response = client.request(get("/info.php", "text/html")?).await?;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body = response.into_body().collect().await?.to_bytes();
+    assert_eq!(&body[..], b"back soon");
+
+    let response = client.request(get("/missing", "application/json")?).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers()["content-type"], "application/json");
+    let body = response.into_body().collect().await?.to_bytes();
+    let error: Value = serde_json::from_slice(&body)?;
+    assert_eq!(error["error"]["status"], 404);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn responses_are_compressed_when_accepted() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let get = |path: &str, accept_encoding: &str| {
+        Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{}{}", server.addr, path))
+            .header("Host", "example.test")
+            .header("Accept-Encoding", accept_encoding)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build request")
+    };
+
+    let response = client
+        .request(get("/assets/app.js", "br;q=0.5, gzip")?)
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-encoding"], "gzip");
+    assert_eq!(response.headers()["vary"], "Accept-Encoding");
+    let etag = response.headers()["etag"].clone();
+    assert!(etag.to_str()?.ends_with("-gzip\""));
+    let body = response.into_body().collect().await?.to_bytes();
+    let mut decoded = String::new();
+    std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(&body[..]), &mut decoded)?;
+    assert_eq!(decoded, "console.log('compress me');\n".repeat(100));
+
+    // Revalidating the compressed representation still matches
+    let mut request = get("/assets/app.js", "gzip")?;
+    request.headers_mut().insert("If-None-Match", etag);
+    let response = client.request(request).await?;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    // Below min_size or not accepted: identity
+    let response = client.request(get("/assets/site.css", "gzip")?).await?;
+    assert!(!response.headers().contains_key("content-encoding"));
+    let response = client.request(get("/assets/app.js", "identity")?).await?;
+    assert!(!response.headers().contains_key("content-encoding"));
+    assert_eq!(response.headers()["vary"], "Accept-Encoding");
+
+    Ok(())
+}